@@ -0,0 +1,4 @@
+-- 信封加密：每个资源使用随机数据密钥加密，数据密钥由密钥A和硬件码B派生的KEK包装后保存
+-- 旧资源没有包装的数据密钥，该列为空
+
+ALTER TABLE encryption_keys ADD COLUMN IF NOT EXISTS wrapped_key TEXT;
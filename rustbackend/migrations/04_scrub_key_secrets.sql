@@ -0,0 +1,24 @@
+-- 清除与密文保存在一起的硬件码B和无盐密钥哈希
+-- 新记录使用加密信息中的密钥校验块验证密钥，旧记录由数据的认证标签发现错误的密钥
+
+-- 清除encryption_keys表中的密钥哈希和UKey信息
+UPDATE encryption_keys SET key_hash = '', ukey_info = '' WHERE key_hash <> '' OR ukey_info <> '';
+
+-- 清除加密信息JSON中key_info的key_hash和ukey_info字段，无法解析的记录保持不变
+DO $$
+DECLARE
+    r RECORD;
+BEGIN
+    FOR r IN SELECT id, encryption_info FROM resources LOOP
+        BEGIN
+            IF (r.encryption_info::jsonb) ? 'key_info' THEN
+                UPDATE resources
+                SET encryption_info = (r.encryption_info::jsonb #- '{key_info,key_hash}' #- '{key_info,ukey_info}')::text
+                WHERE id = r.id;
+            END IF;
+        EXCEPTION WHEN others THEN
+            RAISE NOTICE '跳过无法解析的加密信息，资源ID: %', r.id;
+        END;
+    END LOOP;
+END
+$$;
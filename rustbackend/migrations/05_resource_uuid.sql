@@ -0,0 +1,6 @@
+-- 资源UUID：资源的稳定标识，与媒体类型、容器版本一起作为AEAD附加认证数据绑定到密文
+-- 已有资源自动生成UUID，旧资源的密文未绑定UUID，仍可按原格式解密
+
+ALTER TABLE resources ADD COLUMN IF NOT EXISTS uuid UUID NOT NULL DEFAULT gen_random_uuid();
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_resources_uuid ON resources(uuid);
@@ -0,0 +1,18 @@
+-- 密钥更换任务：记录批量更换密钥A和硬件码B的进度，进程崩溃后从最后处理的资源继续
+-- 任务中不保存任何密钥，new_key_check 为新密钥的密钥信息（盐值、派生参数和校验块），续跑时用于确认新密钥一致
+
+CREATE TABLE IF NOT EXISTS rekey_jobs (
+    id SERIAL PRIMARY KEY,
+    status TEXT NOT NULL DEFAULT 'RUNNING',
+    total_resources INTEGER NOT NULL DEFAULT 0,
+    processed_resources INTEGER NOT NULL DEFAULT 0,
+    skipped_resources INTEGER NOT NULL DEFAULT 0,
+    last_resource_id INTEGER NOT NULL DEFAULT 0,
+    batch_size INTEGER NOT NULL,
+    new_key_check TEXT NOT NULL,
+    error TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_rekey_jobs_status ON rekey_jobs(status);
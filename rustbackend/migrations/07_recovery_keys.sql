@@ -0,0 +1,15 @@
+-- 恢复密钥：UKey丢失时用于解包数据密钥的X25519密钥对，服务端只保存公钥，恢复码只在生成时展示一次
+-- 同一时间最多一个有效的恢复密钥，撤销后保留记录，revoked_at为撤销时间
+
+CREATE TABLE IF NOT EXISTS recovery_keys (
+    id SERIAL PRIMARY KEY,
+    public_key TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    revoked_at TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_recovery_keys_active ON recovery_keys((revoked_at IS NULL)) WHERE revoked_at IS NULL;
+
+-- 用恢复公钥包装的数据密钥，未设置恢复密钥或资源生成于恢复密钥之前时为空
+ALTER TABLE encryption_keys ADD COLUMN IF NOT EXISTS recovery_wrapped_key TEXT;
@@ -0,0 +1,5 @@
+-- 恢复密钥托管：恢复码可以用Shamir秘密分享拆分给多名保管人，任意share_threshold份即可重建
+-- 以单个恢复码发放的恢复密钥两列均为空
+
+ALTER TABLE recovery_keys ADD COLUMN IF NOT EXISTS share_threshold SMALLINT;
+ALTER TABLE recovery_keys ADD COLUMN IF NOT EXISTS share_count SMALLINT;
@@ -0,0 +1,4 @@
+-- 加密元数据：启用后标题和描述用资源的数据密钥加密保存在此列，明文列留空
+-- 格式为 版本(1) ‖ nonce(12) ‖ 密文和认证标签，为空表示元数据以明文保存
+
+ALTER TABLE resources ADD COLUMN IF NOT EXISTS encrypted_metadata BYTEA;
@@ -0,0 +1,10 @@
+-- 盲索引：元数据加密的资源按标题和描述的规范化词元及其前缀建立HMAC索引，数据库中不保存明文词元
+-- 索引密钥由密钥A和硬件码B派生，只有解锁的用户才能生成查询词元，更换密钥时整体重建
+
+CREATE TABLE IF NOT EXISTS resource_search_index (
+    resource_id INTEGER NOT NULL REFERENCES resources(id) ON DELETE CASCADE,
+    token BYTEA NOT NULL,
+    PRIMARY KEY (resource_id, token)
+);
+
+CREATE INDEX IF NOT EXISTS idx_resource_search_index_token ON resource_search_index(token);
@@ -0,0 +1,19 @@
+-- 签名清单：记录所有资源的ID、UUID、容器哈希和版本，每次写入资源后重新签名
+-- 清单记录不设外键，资源行被直接删除时记录仍然保留，验证时可以发现缺失的资源
+
+CREATE TABLE IF NOT EXISTS resource_manifest_entries (
+    resource_id INTEGER PRIMARY KEY,
+    uuid UUID NOT NULL,
+    container_hash BYTEA NOT NULL,
+    version BIGINT NOT NULL
+);
+
+-- 清单头部只有一行，sequence每次签名加一，signature为对清单摘要的Ed25519签名
+CREATE TABLE IF NOT EXISTS resource_manifest (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    sequence BIGINT NOT NULL,
+    digest BYTEA NOT NULL,
+    signature BYTEA NOT NULL,
+    public_key TEXT NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
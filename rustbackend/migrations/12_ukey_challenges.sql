@@ -0,0 +1,12 @@
+-- UKey挑战：服务器签发的一次性随机数，UKey对其签名后才接受硬件码B
+-- 挑战在验证时原子地标记为已使用，过期或已使用的挑战不能再次验证，签发新挑战时清理过期记录
+
+CREATE TABLE IF NOT EXISTS ukey_challenges (
+    id UUID PRIMARY KEY,
+    nonce BYTEA NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_ukey_challenges_expires_at ON ukey_challenges(expires_at);
@@ -0,0 +1,16 @@
+-- UKey登记表：只有登记过且未撤销的UKey才能应答挑战，用于创建和解密资源
+-- 以P-256公钥的SHA-256指纹标识UKey，撤销后保留记录，同一公钥不能再次登记
+-- 升级后需要先通过 POST /api/ukeys 登记正在使用的UKey
+
+CREATE TABLE IF NOT EXISTS ukeys (
+    id SERIAL PRIMARY KEY,
+    serial VARCHAR(255) NOT NULL,
+    vendor VARCHAR(64) NOT NULL,
+    owner VARCHAR(255) NOT NULL,
+    public_key TEXT NOT NULL,
+    fingerprint VARCHAR(64) NOT NULL UNIQUE,
+    enrolled_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_ukeys_serial ON ukeys(serial);
@@ -0,0 +1,6 @@
+-- FIDO2安全密钥登记在ukeys表中：vendor为fido2，serial为Base64url编码的凭据ID，public_key为凭据的P-256公钥
+-- sign_count保存安全密钥最近一次断言的签名计数器，计数器不增加时拒绝断言
+
+ALTER TABLE ukeys ADD COLUMN IF NOT EXISTS sign_count BIGINT NOT NULL DEFAULT 0;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_ukeys_fido2_credential ON ukeys(serial) WHERE vendor = 'fido2';
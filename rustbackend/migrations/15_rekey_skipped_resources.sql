@@ -0,0 +1,3 @@
+-- 记录密钥更换任务中旧密钥无法解开而被跳过的资源ID，任务完成后由管理员逐一确认
+
+ALTER TABLE rekey_jobs ADD COLUMN IF NOT EXISTS skipped_resource_ids INTEGER[] NOT NULL DEFAULT '{}';
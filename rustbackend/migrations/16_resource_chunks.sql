@@ -0,0 +1,17 @@
+-- 加密容器按分块保存，上传时边加密边写入，下载时逐块读出，服务器内存中不保留完整的容器
+-- 已有资源的容器整体作为第0块迁移，resources.media_data 不再保存数据
+
+CREATE TABLE IF NOT EXISTS resource_chunks (
+    resource_id INTEGER NOT NULL REFERENCES resources(id) ON DELETE CASCADE,
+    chunk_index INTEGER NOT NULL,
+    data BYTEA NOT NULL,
+    PRIMARY KEY (resource_id, chunk_index)
+);
+
+INSERT INTO resource_chunks (resource_id, chunk_index, data)
+SELECT id, 0, media_data FROM resources WHERE length(media_data) > 0
+ON CONFLICT DO NOTHING;
+
+UPDATE resources SET media_data = ''::bytea WHERE length(media_data) > 0;
+
+ALTER TABLE resources ALTER COLUMN media_data SET DEFAULT ''::bytea;
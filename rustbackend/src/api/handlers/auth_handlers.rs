@@ -1,9 +1,12 @@
 use axum::{http::StatusCode, Json};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use tracing::info;
 
+use crate::logging::REDACTED;
+
 /// 登录请求
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize)]
 #[allow(dead_code)]
 pub struct LoginRequest {
     /// 用户名
@@ -12,8 +15,17 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+impl fmt::Debug for LoginRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginRequest")
+            .field("username", &self.username)
+            .field("password", &format_args!("{}", REDACTED))
+            .finish()
+    }
+}
+
 /// 注册请求
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize)]
 #[allow(dead_code)]
 pub struct RegisterRequest {
     /// 用户名
@@ -22,6 +34,15 @@ pub struct RegisterRequest {
     pub password: String,
 }
 
+impl fmt::Debug for RegisterRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegisterRequest")
+            .field("username", &self.username)
+            .field("password", &format_args!("{}", REDACTED))
+            .finish()
+    }
+}
+
 /// 认证响应
 #[derive(Serialize, Debug)]
 pub struct AuthResponse {
@@ -0,0 +1,294 @@
+use axum::{http::{HeaderMap, StatusCode}, Json, Extension};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::api::handlers::ukey_handlers::{enrollment_credential, UKeyResponse};
+use crate::database::models::ukey::{EnrollFido2Request, UKey, UKeyAuthorization};
+use crate::service::resource::{ResourceService, ResourceServiceError};
+use crate::service::ukey::UKeyService;
+use crate::ukey::fido2::{self, COSE_ALG_ES256};
+use crate::ukey::UKeyChallenge;
+
+/// 申请注册参数请求
+#[derive(Deserialize, Debug)]
+pub struct Fido2RegistrationOptionsRequest {
+    /// 持有人，作为WebAuthn的用户名
+    pub owner: String,
+    /// 已登记硬件的授权，使用管理令牌时可以省略
+    pub authorization: Option<UKeyAuthorization>,
+}
+
+/// 申请断言参数请求，`resource_id` 和 `credential_id` 二选一
+#[derive(Deserialize, Debug)]
+pub struct Fido2AssertionOptionsRequest {
+    /// 要解密的资源，只允许加密该资源的安全密钥
+    pub resource_id: Option<i32>,
+    /// 凭据ID，创建资源时指定使用的安全密钥
+    pub credential_id: Option<String>,
+}
+
+/// 依赖方
+#[derive(Serialize, Debug)]
+pub struct RelyingPartyEntity {
+    pub id: String,
+    pub name: String,
+}
+
+/// 用户
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UserEntity {
+    /// 用户句柄，Base64url编码
+    pub id: String,
+    pub name: String,
+    pub display_name: String,
+}
+
+/// 凭据算法
+#[derive(Serialize, Debug)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub alg: i64,
+}
+
+/// 凭据描述
+#[derive(Serialize, Debug)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// 凭据ID，Base64url编码
+    pub id: String,
+}
+
+impl CredentialDescriptor {
+    fn from_ukey(ukey: &UKey) -> Self {
+        CredentialDescriptor { kind: "public-key", id: ukey.serial.clone() }
+    }
+}
+
+/// PRF扩展求值的盐值，Base64url编码
+#[derive(Serialize, Debug)]
+pub struct PrfValues {
+    pub first: String,
+}
+
+/// PRF扩展输入，注册时为空对象
+#[derive(Serialize, Debug, Default)]
+pub struct PrfExtension {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eval: Option<PrfValues>,
+}
+
+/// 注册扩展：同时请求PRF和hmac-secret，兼容只支持其中一种的浏览器
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationExtensions {
+    pub hmac_create_secret: bool,
+    pub prf: PrfExtension,
+}
+
+/// 断言扩展
+#[derive(Serialize, Debug)]
+pub struct RequestExtensions {
+    pub prf: PrfExtension,
+}
+
+/// `navigator.credentials.create` 的 publicKey 参数，二进制字段为Base64url编码
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    pub rp: RelyingPartyEntity,
+    pub user: UserEntity,
+    pub challenge: String,
+    pub pub_key_cred_params: Vec<CredentialParameters>,
+    /// 超时时间（毫秒），与挑战有效期一致
+    pub timeout: u64,
+    pub attestation: &'static str,
+    pub exclude_credentials: Vec<CredentialDescriptor>,
+    pub extensions: CreationExtensions,
+}
+
+/// `navigator.credentials.get` 的 publicKey 参数，二进制字段为Base64url编码
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    pub challenge: String,
+    pub rp_id: String,
+    /// 超时时间（毫秒），与挑战有效期一致
+    pub timeout: u64,
+    pub user_verification: &'static str,
+    pub allow_credentials: Vec<CredentialDescriptor>,
+    pub extensions: RequestExtensions,
+}
+
+/// FIDO2参数响应
+#[derive(Serialize, Debug)]
+pub struct Fido2OptionsResponse<T> {
+    /// 挑战ID，提交注册结果或断言时原样返回
+    pub challenge_id: Option<uuid::Uuid>,
+    /// 过期时间
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    /// 传给浏览器的 publicKey 参数
+    pub public_key: Option<T>,
+    /// 消息
+    pub message: String,
+}
+
+impl<T> Fido2OptionsResponse<T> {
+    fn error(message: String) -> Json<Self> {
+        Json(Fido2OptionsResponse { challenge_id: None, expires_at: None, public_key: None, message })
+    }
+    
+    fn issued(challenge: &UKeyChallenge, public_key: T) -> Json<Self> {
+        Json(Fido2OptionsResponse {
+            challenge_id: Some(challenge.id),
+            expires_at: Some(challenge.expires_at),
+            public_key: Some(public_key),
+            message: "挑战已签发".to_string(),
+        })
+    }
+}
+
+/// 申请FIDO2安全密钥的注册参数
+///
+/// 与登记一样需要管理令牌或已登记硬件的授权。签发一次性挑战，浏览器用返回的参数创建凭据后提交到
+/// `POST /api/fido2/registrations`。
+#[axum::debug_handler]
+pub async fn registration_options(
+    headers: HeaderMap,
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Json(req): Json<Fido2RegistrationOptionsRequest>,
+) -> (StatusCode, Json<Fido2OptionsResponse<CreationOptions>>) {
+    let owner = req.owner.trim();
+    if owner.is_empty() {
+        return (StatusCode::BAD_REQUEST, Fido2OptionsResponse::error("持有人不能为空".to_string()));
+    }
+    
+    let service = UKeyService::new(resource_service.clone());
+    match service.authorize(enrollment_credential(&headers, req.authorization.as_ref())).await {
+        Ok(()) => {}
+        Err(ResourceServiceError::UKeyVerificationFailed(message)) => {
+            return (StatusCode::UNAUTHORIZED, Fido2OptionsResponse::error(format!("UKey验证失败: {}", message)));
+        }
+        Err(ResourceServiceError::KeyVerificationFailed) => {
+            return (StatusCode::UNAUTHORIZED, Fido2OptionsResponse::error("密钥验证失败".to_string()));
+        }
+        Err(ResourceServiceError::PermissionError(message)) => {
+            return (StatusCode::FORBIDDEN, Fido2OptionsResponse::error(message));
+        }
+        Err(err) => {
+            tracing::error!("检查登记授权失败: {:?}", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Fido2OptionsResponse::error(format!("检查登记授权失败: {}", err)));
+        }
+    }
+    
+    let result = match service.fido2_credentials().await {
+        Ok(credentials) => service.issue_challenge().await.map(|challenge| (credentials, challenge)),
+        Err(err) => Err(err),
+    };
+    let (credentials, challenge) = match result {
+        Ok(result) => result,
+        Err(err) => {
+            tracing::error!("签发FIDO2注册挑战失败: {:?}", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Fido2OptionsResponse::error(format!("签发FIDO2注册挑战失败: {}", err)));
+        }
+    };
+    
+    let config = &resource_service.config;
+    let options = CreationOptions {
+        rp: RelyingPartyEntity { id: config.fido2.rp_id.clone(), name: config.fido2.rp_name.clone() },
+        // 用户句柄只用于区分凭据，不保存
+        user: UserEntity {
+            id: fido2::encode(uuid::Uuid::new_v4().as_bytes()),
+            name: owner.to_string(),
+            display_name: owner.to_string(),
+        },
+        challenge: fido2::encode(&challenge.nonce),
+        pub_key_cred_params: vec![CredentialParameters { kind: "public-key", alg: COSE_ALG_ES256 }],
+        timeout: config.ukey.challenge_ttl_secs * 1000,
+        attestation: "none",
+        exclude_credentials: credentials.iter().map(CredentialDescriptor::from_ukey).collect(),
+        extensions: CreationExtensions { hmac_create_secret: true, prf: PrfExtension::default() },
+    };
+    
+    (StatusCode::CREATED, Fido2OptionsResponse::issued(&challenge, options))
+}
+
+/// 登记FIDO2安全密钥
+///
+/// 需要管理令牌或已登记硬件的授权。
+#[axum::debug_handler]
+pub async fn register(
+    headers: HeaderMap,
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Json(req): Json<EnrollFido2Request>,
+) -> (StatusCode, Json<UKeyResponse>) {
+    let credential = enrollment_credential(&headers, req.authorization.as_ref());
+    match UKeyService::new(resource_service).enroll_fido2(&req.owner, &req.registration, credential).await {
+        Ok(ukey) => (StatusCode::CREATED, Json(UKeyResponse {
+            ukey: Some(ukey),
+            message: "FIDO2安全密钥已登记".to_string(),
+        })),
+        Err(ResourceServiceError::ParameterError(message)) => (StatusCode::BAD_REQUEST, UKeyResponse::error(message)),
+        Err(ResourceServiceError::UKeyVerificationFailed(message)) => (StatusCode::UNAUTHORIZED, UKeyResponse::error(format!("FIDO2验证失败: {}", message))),
+        Err(ResourceServiceError::KeyVerificationFailed) => (StatusCode::UNAUTHORIZED, UKeyResponse::error("密钥验证失败".to_string())),
+        Err(ResourceServiceError::PermissionError(message)) => (StatusCode::FORBIDDEN, UKeyResponse::error(message)),
+        Err(ResourceServiceError::ResourceExists) => (StatusCode::CONFLICT, UKeyResponse::error("安全密钥已登记或已被撤销".to_string())),
+        Err(err) => {
+            tracing::error!("登记FIDO2安全密钥失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, UKeyResponse::error(format!("登记FIDO2安全密钥失败: {}", err)))
+        }
+    }
+}
+
+/// 申请FIDO2安全密钥的断言参数
+///
+/// 只给出加密指定资源的安全密钥或指定的凭据，不公开其他已登记的凭据。签发一次性挑战并给出PRF扩展的盐值，
+/// 浏览器返回的断言放在创建或解密资源请求的 `fido2_assertion` 中提交。
+#[axum::debug_handler]
+pub async fn assertion_options(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Json(req): Json<Fido2AssertionOptionsRequest>,
+) -> (StatusCode, Json<Fido2OptionsResponse<RequestOptions>>) {
+    let service = UKeyService::new(resource_service.clone());
+    let credential = match (req.resource_id, req.credential_id.as_deref()) {
+        (Some(resource_id), None) => service.resource_fido2_credential(resource_id).await,
+        (None, Some(credential_id)) => service.fido2_credential(credential_id).await,
+        _ => return (StatusCode::BAD_REQUEST, Fido2OptionsResponse::error("需要指定资源ID或凭据ID之一".to_string())),
+    };
+    let credential = match credential {
+        Ok(Some(credential)) => credential,
+        Ok(None) => {
+            return (StatusCode::NOT_FOUND, Fido2OptionsResponse::error("没有可用的FIDO2安全密钥".to_string()));
+        }
+        Err(ResourceServiceError::ResourceNotFound) => {
+            return (StatusCode::NOT_FOUND, Fido2OptionsResponse::error("资源未找到".to_string()));
+        }
+        Err(err) => {
+            tracing::error!("获取FIDO2安全密钥失败: {:?}", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Fido2OptionsResponse::error(format!("获取FIDO2安全密钥失败: {}", err)));
+        }
+    };
+    let challenge = match service.issue_challenge().await {
+        Ok(challenge) => challenge,
+        Err(err) => {
+            tracing::error!("签发FIDO2断言挑战失败: {:?}", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Fido2OptionsResponse::error(format!("签发FIDO2断言挑战失败: {}", err)));
+        }
+    };
+    
+    let config = &resource_service.config;
+    let options = RequestOptions {
+        challenge: fido2::encode(&challenge.nonce),
+        rp_id: config.fido2.rp_id.clone(),
+        timeout: config.ukey.challenge_ttl_secs * 1000,
+        user_verification: "preferred",
+        allow_credentials: vec![CredentialDescriptor::from_ukey(&credential)],
+        extensions: RequestExtensions {
+            prf: PrfExtension { eval: Some(PrfValues { first: fido2::encode(&fido2::prf_salt()) }) },
+        },
+    };
+    
+    (StatusCode::CREATED, Fido2OptionsResponse::issued(&challenge, options))
+}
@@ -0,0 +1,401 @@
+use axum::{http::StatusCode, Json, Extension};
+use axum::extract::Path;
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::database::models::recovery_key::{
+    EscrowRecoveryKeyRequest, RecoverRequest, RecoveryCoverage, RecoveryKey, RecoveryKeyStatus, RecoverySummary,
+    RegenerateRecoveryKeyRequest, RevokeRecoveryKeyRequest, UnlockWithSharesRequest
+};
+use crate::api::handlers::ukey_handlers::verified_part_b;
+use crate::database::models::rekey_job::{RekeyJob, RekeyKeys, RekeyRequest};
+use crate::database::models::upgrade::{EncryptionUpgradeStats, UpgradeRequest, UpgradeSummary};
+use crate::service::recovery::RecoveryService;
+use crate::service::rekey::RekeyService;
+use crate::service::upgrade::UpgradeService;
+use crate::service::resource::{ResourceService, ResourceServiceError};
+use crate::ukey::UKeyProvider;
+
+/// 密钥更换任务响应
+#[derive(Serialize, Debug)]
+pub struct RekeyJobResponse {
+    /// 任务
+    pub job: Option<RekeyJob>,
+    /// 消息
+    pub message: String,
+}
+
+impl RekeyJobResponse {
+    fn error(message: String) -> Json<Self> {
+        Json(RekeyJobResponse { job: None, message })
+    }
+}
+
+/// 批量升级响应
+#[derive(Serialize, Debug)]
+pub struct UpgradeResponse {
+    /// 升级结果
+    pub summary: Option<UpgradeSummary>,
+    /// 消息
+    pub message: String,
+}
+
+/// 恢复密钥响应
+///
+/// 恢复码只在生成时返回一次，不实现Debug，避免写入日志。
+#[derive(Serialize)]
+pub struct RecoveryKeyResponse {
+    /// 恢复码，只在生成时返回
+    pub recovery_code: Option<String>,
+    /// 恢复密钥
+    pub recovery_key: Option<RecoveryKey>,
+    /// 生成恢复包装的资源数
+    pub coverage: Option<RecoveryCoverage>,
+    /// 消息
+    pub message: String,
+}
+
+impl RecoveryKeyResponse {
+    fn error(message: String) -> Json<Self> {
+        Json(RecoveryKeyResponse { recovery_code: None, recovery_key: None, coverage: None, message })
+    }
+}
+
+/// 托管恢复密钥响应
+///
+/// 分片只在生成时返回一次，应分别交给不同的保管人，不实现Debug，避免写入日志。
+#[derive(Serialize)]
+pub struct RecoveryEscrowResponse {
+    /// 保管人分片，只在生成时返回
+    pub shares: Vec<String>,
+    /// 恢复密钥
+    pub recovery_key: Option<RecoveryKey>,
+    /// 生成恢复包装的资源数
+    pub coverage: Option<RecoveryCoverage>,
+    /// 消息
+    pub message: String,
+}
+
+/// 使用恢复码恢复响应
+#[derive(Serialize, Debug)]
+pub struct RecoverResponse {
+    /// 恢复结果
+    pub summary: Option<RecoverySummary>,
+    /// 消息
+    pub message: String,
+}
+
+/// 发起密钥更换任务
+///
+/// 先验证旧UKey和新UKey的应答，再验证旧密钥，之后在后台执行并立即返回任务；存在未完成的任务时用相同的密钥续跑。
+#[axum::debug_handler]
+pub async fn start_rekey(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
+    Json(req): Json<RekeyRequest>,
+) -> (StatusCode, Json<RekeyJobResponse>) {
+    let (_, old_ukey_part_b) = match verified_part_b(resource_service.clone(), ukey_provider.as_ref(), req.old_hardware_assertion).await {
+        Ok(verified) => verified,
+        Err((status, message)) => return (status, RekeyJobResponse::error(message)),
+    };
+    let (new_ukey, new_ukey_part_b) = match verified_part_b(resource_service.clone(), ukey_provider.as_ref(), req.new_hardware_assertion).await {
+        Ok(verified) => verified,
+        Err((status, message)) => return (status, RekeyJobResponse::error(message)),
+    };
+    let keys = RekeyKeys {
+        old_key_part_a: req.old_key_part_a,
+        old_ukey_part_b,
+        new_key_part_a: req.new_key_part_a,
+        new_ukey_part_b,
+        new_ukey_fingerprint: new_ukey.fingerprint,
+        batch_size: req.batch_size,
+    };
+    
+    let rekey_service = RekeyService::new(resource_service);
+    
+    let prepared = match rekey_service.prepare_job(&keys).await {
+        Ok(prepared) => prepared,
+        Err(ResourceServiceError::KeyVerificationFailed) => {
+            return (StatusCode::UNAUTHORIZED, RekeyJobResponse::error("密钥验证失败".to_string()));
+        }
+        Err(ResourceServiceError::ParameterError(msg)) => {
+            return (StatusCode::BAD_REQUEST, RekeyJobResponse::error(msg));
+        }
+        Err(ResourceServiceError::ResourceStatusError(msg)) => {
+            return (StatusCode::CONFLICT, RekeyJobResponse::error(msg));
+        }
+        Err(err) => {
+            tracing::error!("发起密钥更换任务失败: {:?}", err);
+            return (StatusCode::INTERNAL_SERVER_ERROR, RekeyJobResponse::error(format!("发起密钥更换任务失败: {:?}", err)));
+        }
+    };
+    
+    let job = prepared.job.clone();
+    tokio::spawn(async move {
+        // 进度由服务记录到数据库和日志，通过查询接口获取
+        if let Err(err) = rekey_service.run_job(prepared, &keys, |_| {}).await {
+            tracing::error!("密钥更换任务失败: {:?}", err);
+        }
+    });
+    
+    (StatusCode::ACCEPTED, Json(RekeyJobResponse {
+        job: Some(job),
+        message: "密钥更换任务已开始".to_string(),
+    }))
+}
+
+/// 获取密钥更换任务进度
+#[axum::debug_handler]
+pub async fn get_rekey_job(
+    Path(id): Path<i32>,
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+) -> (StatusCode, Json<RekeyJobResponse>) {
+    match RekeyService::new(resource_service).get_job(id).await {
+        Ok(job) => (StatusCode::OK, Json(RekeyJobResponse {
+            job: Some(job),
+            message: "获取密钥更换任务成功".to_string(),
+        })),
+        Err(ResourceServiceError::ResourceNotFound) => {
+            (StatusCode::NOT_FOUND, RekeyJobResponse::error("密钥更换任务未找到".to_string()))
+        }
+        Err(err) => {
+            tracing::error!("获取密钥更换任务失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, RekeyJobResponse::error(format!("获取密钥更换任务失败: {:?}", err)))
+        }
+    }
+}
+
+/// 用提供的密钥批量升级加密参数低于当前配置的资源
+///
+/// 同步执行，资源较多时建议使用命令行 `upgrade` 子命令。
+#[axum::debug_handler]
+pub async fn upgrade_resources(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
+    Json(req): Json<UpgradeRequest>,
+) -> (StatusCode, Json<UpgradeResponse>) {
+    let (_, ukey_part_b) = match verified_part_b(resource_service.clone(), ukey_provider.as_ref(), req.hardware_assertion).await {
+        Ok(verified) => verified,
+        Err((status, message)) => return (status, Json(UpgradeResponse { summary: None, message })),
+    };
+    
+    let upgrade_service = UpgradeService::new(resource_service);
+    match upgrade_service.upgrade_all(req.key_part_a.expose_secret(), ukey_part_b.expose_secret(), |_| {}).await {
+        Ok(summary) => (StatusCode::OK, Json(UpgradeResponse {
+            summary: Some(summary),
+            message: "资源加密参数升级完成".to_string(),
+        })),
+        Err(err) => {
+            tracing::error!("批量升级资源加密参数失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(UpgradeResponse {
+                summary: None,
+                message: format!("批量升级资源加密参数失败: {:?}", err),
+            }))
+        }
+    }
+}
+
+/// 获取加密参数升级统计信息
+#[axum::debug_handler]
+pub async fn get_upgrade_stats(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+) -> (StatusCode, Json<EncryptionUpgradeStats>) {
+    match UpgradeService::new(resource_service).get_stats().await {
+        Ok(stats) => (StatusCode::OK, Json(stats)),
+        Err(err) => {
+            tracing::error!("获取加密参数升级统计信息失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(EncryptionUpgradeStats::default()))
+        }
+    }
+}
+
+/// 获取当前恢复密钥和覆盖的资源数
+#[axum::debug_handler]
+pub async fn get_recovery_key(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+) -> (StatusCode, Json<RecoveryKeyStatus>) {
+    match RecoveryService::new(resource_service).get_status().await {
+        Ok(status) => (StatusCode::OK, Json(status)),
+        Err(err) => {
+            tracing::error!("获取恢复密钥失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(RecoveryKeyStatus::default()))
+        }
+    }
+}
+
+/// 生成新的恢复密钥，旧恢复密钥同时失效
+///
+/// 恢复码只在本次响应中返回，需要离线妥善保存。
+#[axum::debug_handler]
+pub async fn regenerate_recovery_key(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
+    Json(req): Json<RegenerateRecoveryKeyRequest>,
+) -> (StatusCode, Json<RecoveryKeyResponse>) {
+    let (_, ukey_part_b) = match verified_part_b(resource_service.clone(), ukey_provider.as_ref(), req.hardware_assertion).await {
+        Ok(verified) => verified,
+        Err((status, message)) => return (status, RecoveryKeyResponse::error(message)),
+    };
+    
+    let recovery_service = RecoveryService::new(resource_service);
+    match recovery_service.regenerate(req.key_part_a.expose_secret(), ukey_part_b.expose_secret()).await {
+        Ok((recovery_code, recovery_key, coverage)) => (StatusCode::CREATED, Json(RecoveryKeyResponse {
+            recovery_code: Some(recovery_code.expose_secret().to_string()),
+            recovery_key: Some(recovery_key),
+            coverage: Some(coverage),
+            message: "恢复密钥生成成功，请离线保存恢复码".to_string(),
+        })),
+        Err(ResourceServiceError::KeyVerificationFailed) => {
+            (StatusCode::UNAUTHORIZED, RecoveryKeyResponse::error("密钥验证失败".to_string()))
+        }
+        Err(err) => {
+            tracing::error!("生成恢复密钥失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, RecoveryKeyResponse::error(format!("生成恢复密钥失败: {:?}", err)))
+        }
+    }
+}
+
+/// 撤销当前恢复密钥
+///
+/// 需要当前的密钥A和UKey应答，与生成恢复密钥相同。
+#[axum::debug_handler]
+pub async fn revoke_recovery_key(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
+    Json(req): Json<RevokeRecoveryKeyRequest>,
+) -> (StatusCode, Json<RecoveryKeyResponse>) {
+    let (_, ukey_part_b) = match verified_part_b(resource_service.clone(), ukey_provider.as_ref(), req.hardware_assertion).await {
+        Ok(verified) => verified,
+        Err((status, message)) => return (status, RecoveryKeyResponse::error(message)),
+    };
+    
+    match RecoveryService::new(resource_service).revoke(req.key_part_a.expose_secret(), ukey_part_b.expose_secret()).await {
+        Ok(recovery_key) => (StatusCode::OK, Json(RecoveryKeyResponse {
+            recovery_code: None,
+            recovery_key: Some(recovery_key),
+            coverage: None,
+            message: "恢复密钥已撤销".to_string(),
+        })),
+        Err(ResourceServiceError::ResourceNotFound) => {
+            (StatusCode::NOT_FOUND, RecoveryKeyResponse::error("没有有效的恢复密钥".to_string()))
+        }
+        Err(ResourceServiceError::KeyVerificationFailed) => {
+            (StatusCode::UNAUTHORIZED, RecoveryKeyResponse::error("密钥验证失败".to_string()))
+        }
+        Err(err) => {
+            tracing::error!("撤销恢复密钥失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, RecoveryKeyResponse::error(format!("撤销恢复密钥失败: {:?}", err)))
+        }
+    }
+}
+
+/// 使用恢复码恢复所有资源，并换上新的密钥A和硬件码B
+#[axum::debug_handler]
+pub async fn recover_with_recovery_key(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
+    Json(req): Json<RecoverRequest>,
+) -> (StatusCode, Json<RecoverResponse>) {
+    let (new_ukey, new_ukey_part_b) = match verified_part_b(resource_service.clone(), ukey_provider.as_ref(), req.new_hardware_assertion).await {
+        Ok(verified) => verified,
+        Err((status, message)) => return (status, Json(RecoverResponse { summary: None, message })),
+    };
+    
+    let recovery_service = RecoveryService::new(resource_service);
+    let result = recovery_service.recover(
+        req.recovery_code.expose_secret(),
+        req.new_key_part_a.expose_secret(),
+        new_ukey_part_b.expose_secret(),
+        &new_ukey.fingerprint
+    ).await;
+    
+    recover_response(result)
+}
+
+/// 生成新的恢复密钥并把恢复码拆分为保管人分片，旧恢复密钥同时失效
+///
+/// 任意 `share_threshold` 份分片可以通过 `/keys/recovery/unlock` 恢复资源。
+#[axum::debug_handler]
+pub async fn escrow_recovery_key(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
+    Json(req): Json<EscrowRecoveryKeyRequest>,
+) -> (StatusCode, Json<RecoveryEscrowResponse>) {
+    let (_, ukey_part_b) = match verified_part_b(resource_service.clone(), ukey_provider.as_ref(), req.hardware_assertion).await {
+        Ok(verified) => verified,
+        Err((status, message)) => {
+            return (status, Json(RecoveryEscrowResponse { shares: Vec::new(), recovery_key: None, coverage: None, message }));
+        }
+    };
+    
+    let recovery_service = RecoveryService::new(resource_service);
+    let result = recovery_service.escrow(
+        req.key_part_a.expose_secret(),
+        ukey_part_b.expose_secret(),
+        req.share_threshold,
+        req.share_count
+    ).await;
+    
+    let (status, message) = match result {
+        Ok((shares, recovery_key, coverage)) => {
+            return (StatusCode::CREATED, Json(RecoveryEscrowResponse {
+                shares: shares.iter().map(|share| share.expose_secret().to_string()).collect(),
+                recovery_key: Some(recovery_key),
+                coverage: Some(coverage),
+                message: "托管恢复密钥生成成功，请将分片分别交给保管人".to_string(),
+            }));
+        }
+        Err(ResourceServiceError::KeyVerificationFailed) => (StatusCode::UNAUTHORIZED, "密钥验证失败".to_string()),
+        Err(ResourceServiceError::ParameterError(msg)) => (StatusCode::BAD_REQUEST, msg),
+        Err(err) => {
+            tracing::error!("生成托管恢复密钥失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("生成托管恢复密钥失败: {:?}", err))
+        }
+    };
+    
+    (status, Json(RecoveryEscrowResponse { shares: Vec::new(), recovery_key: None, coverage: None, message }))
+}
+
+/// 使用达到门限的保管人分片恢复所有资源，并换上新的密钥A和硬件码B
+#[axum::debug_handler]
+pub async fn unlock_with_shares(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
+    Json(req): Json<UnlockWithSharesRequest>,
+) -> (StatusCode, Json<RecoverResponse>) {
+    let (new_ukey, new_ukey_part_b) = match verified_part_b(resource_service.clone(), ukey_provider.as_ref(), req.new_hardware_assertion).await {
+        Ok(verified) => verified,
+        Err((status, message)) => return (status, Json(RecoverResponse { summary: None, message })),
+    };
+    
+    let recovery_service = RecoveryService::new(resource_service);
+    let result = recovery_service.unlock(
+        &req.shares,
+        req.new_key_part_a.expose_secret(),
+        new_ukey_part_b.expose_secret(),
+        &new_ukey.fingerprint
+    ).await;
+    
+    recover_response(result)
+}
+
+/// 将恢复结果转换为响应
+fn recover_response(result: Result<RecoverySummary, ResourceServiceError>) -> (StatusCode, Json<RecoverResponse>) {
+    let (status, message) = match result {
+        Ok(summary) => {
+            return (StatusCode::OK, Json(RecoverResponse {
+                summary: Some(summary),
+                message: "恢复资源完成".to_string(),
+            }));
+        }
+        Err(ResourceServiceError::KeyVerificationFailed) => (StatusCode::UNAUTHORIZED, "恢复码验证失败".to_string()),
+        Err(ResourceServiceError::ParameterError(msg)) => (StatusCode::BAD_REQUEST, msg),
+        Err(ResourceServiceError::ResourceStatusError(msg)) => (StatusCode::CONFLICT, msg),
+        Err(err) => {
+            tracing::error!("恢复资源失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, format!("恢复资源失败: {:?}", err))
+        }
+    };
+    
+    (status, Json(RecoverResponse { summary: None, message }))
+}
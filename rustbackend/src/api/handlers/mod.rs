@@ -6,3 +6,12 @@ pub mod auth_handlers;
 
 /// 健康检查处理器
 pub mod health_handlers;
+
+/// 密钥管理处理器
+pub mod key_handlers;
+
+/// UKey挑战和登记处理器
+pub mod ukey_handlers;
+
+/// FIDO2安全密钥处理器
+pub mod fido2_handlers;
@@ -1,12 +1,32 @@
-use axum::{http::StatusCode, Json, Extension};
-use axum::extract::{Path, Query};
+use axum::{http::StatusCode, Json, Extension, RequestExt};
+use axum::body::Body;
+use axum::extract::{Path, Query, Request};
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use futures_util::{stream, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use base64::{Engine as _, engine::general_purpose};
+use tokio_util::io::{ReaderStream, StreamReader};
 
 use crate::service::resource::ResourceService;
 use crate::database::models::{CreateResourceRequest, UpdateResourceRequest, ResourceQueryParams, ResourceStats, ResourceResponse};
 use crate::service::resource::ResourceServiceError;
+use crate::crypto::SecretString;
+use crate::api::handlers::ukey_handlers::hardware_assertion;
+use crate::ukey::{Fido2Assertion, HardwareAssertion, UKeyAssertion, UKeyProvider};
+
+/// 创建资源时使用的密钥部分A
+///
+/// 这里需要从请求头或会话中获取key_part_a，暂时使用默认值，实际应用中需要从认证系统中获取。
+const DEFAULT_KEY_PART_A: &str = "default_key_part_a";
+
+/// 上传时携带硬件应答的请求头，值为 `HardwareAssertion` 的JSON
+const HARDWARE_ASSERTION_HEADER: &str = "x-hardware-assertion";
+
+/// 下载时解密写出与响应体读取之间的缓冲区大小
+const DOWNLOAD_BUFFER_SIZE: usize = 64 * 1024;
 
 /// 资源列表查询参数
 #[derive(Deserialize, Debug)]
@@ -32,12 +52,63 @@ pub struct ResourceListQuery {
 }
 
 /// 资源解密请求
+///
+/// 密钥字段为 `SecretString`，`Debug` 只输出占位符。
 #[derive(Deserialize, Debug)]
 pub struct DecryptResourceRequest {
     /// 密钥部分A（用户密钥）
-    pub key_part_a: String,
-    /// 密钥部分B（硬件UKey）
-    pub ukey_part_b: String,
+    pub key_part_a: SecretString,
+    /// UKey对 `POST /api/ukey/challenges` 签发的挑战的应答，其中带有硬件码B，为空时由服务器上的UKey应答
+    pub ukey_assertion: Option<UKeyAssertion>,
+    /// FIDO2安全密钥对 `POST /api/fido2/assertions/options` 签发的挑战的断言，硬件码B取自PRF输出
+    pub fido2_assertion: Option<Fido2Assertion>,
+}
+
+/// 资源创建请求
+///
+/// 资源字段与 `CreateResourceRequest` 相同，另外可以带上客户端UKey或FIDO2安全密钥的应答。
+#[derive(Deserialize, Debug)]
+pub struct CreateResourceBody {
+    #[serde(flatten)]
+    pub resource: CreateResourceRequest,
+    /// UKey对挑战的应答，与 `fido2_assertion` 都为空时由服务器上的UKey应答
+    pub ukey_assertion: Option<UKeyAssertion>,
+    /// FIDO2安全密钥对挑战的断言
+    pub fido2_assertion: Option<Fido2Assertion>,
+}
+
+/// 资源上传参数
+///
+/// 字段与 `CreateResourceRequest` 相同，媒体数据为请求体。
+#[derive(Deserialize, Debug)]
+pub struct ResourceUploadQuery {
+    pub title: String,
+    pub title_en: Option<String>,
+    pub description: String,
+    pub resource_type: String,
+    pub media_type: String,
+    #[serde(default)]
+    pub is_local: bool,
+    pub status: Option<String>,
+}
+
+/// 资源搜索请求
+///
+/// 词元由客户端用密钥A和硬件码B派生的盲索引密钥计算（参考实现见 `search-tokens` 命令），服务器不接收查询词和密钥。
+#[derive(Deserialize, Debug)]
+pub struct ResourceSearchRequest {
+    /// Base64编码的盲索引词元，资源需要包含其中每一个
+    pub search_tokens: Vec<String>,
+    /// 跳过的记录数
+    pub skip: Option<i32>,
+    /// 返回的记录数
+    pub limit: Option<i32>,
+    /// 状态过滤
+    pub status: Option<String>,
+    /// 媒体类型过滤
+    pub media_type: Option<String>,
+    /// 是否本地资源过滤
+    pub is_local: Option<bool>,
 }
 
 /// 资源创建响应
@@ -70,6 +141,8 @@ pub struct ResourceDeleteResponse {
 pub struct ResourceDecryptResponse {
     /// 解密后的数据（Base64编码）
     pub data: String,
+    /// 资源，元数据已加密时包含解密后的标题和描述
+    pub resource: Option<ResourceResponse>,
     /// 消息
     pub message: String,
 }
@@ -96,6 +169,7 @@ pub async fn get_resources(
     
     match resource_service.get_resources(params).await {
         Ok((resources, _)) => (StatusCode::OK, Json(resources)),
+        Err(ResourceServiceError::ParameterError(_)) => (StatusCode::BAD_REQUEST, Json(vec![])),
         Err(err) => {
             tracing::error!("获取资源列表失败: {:?}", err);
             (StatusCode::INTERNAL_SERVER_ERROR, Json(vec![]))
@@ -103,6 +177,41 @@ pub async fn get_resources(
     }
 }
 
+/// 按盲索引词元搜索元数据已加密的资源
+#[axum::debug_handler]
+pub async fn search_resources(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Json(req): Json<ResourceSearchRequest>,
+) -> (StatusCode, Json<Vec<ResourceResponse>>) {
+    let search_tokens = match req.search_tokens.iter().map(|token| general_purpose::STANDARD.decode(token)).collect::<Result<Vec<_>, _>>() {
+        Ok(search_tokens) => search_tokens,
+        Err(_) => return (StatusCode::BAD_REQUEST, Json(vec![])),
+    };
+    
+    let params = ResourceQueryParams {
+        skip: req.skip.map(|x| x as u32),
+        limit: req.limit.map(|x| x as u32),
+        status: req.status,
+        media_type: req.media_type,
+        is_local: req.is_local,
+        search: None,
+        sort_by: None,
+        sort_order: None,
+        count_only: None,
+        is_admin_view: None,
+        include_history: None,
+    };
+    
+    match resource_service.search_resources(&search_tokens, params).await {
+        Ok((resources, _)) => (StatusCode::OK, Json(resources)),
+        Err(ResourceServiceError::ParameterError(_)) => (StatusCode::BAD_REQUEST, Json(vec![])),
+        Err(err) => {
+            tracing::error!("搜索资源失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(vec![]))
+        }
+    }
+}
+
 /// 获取单个资源
 #[axum::debug_handler]
 pub async fn get_resource(
@@ -124,14 +233,23 @@ pub async fn get_resource(
 #[axum::debug_handler]
 pub async fn create_resource(
     Extension(resource_service): Extension<Arc<ResourceService>>,
-    Json(req): Json<CreateResourceRequest>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
+    Json(req): Json<CreateResourceBody>,
 ) -> (StatusCode, Json<ResourceCreateResponse>) {
-    // 这里需要从请求头或会话中获取key_part_a和ukey_part_b
-    // 暂时使用默认值，实际应用中需要从认证系统中获取
-    let key_part_a = "default_key_part_a"; // 实际应从认证系统获取
-    let ukey_part_b = "default_ukey_part_b"; // 实际应从UKey系统获取
+    let key_part_a = DEFAULT_KEY_PART_A;
     
-    match resource_service.create_resource(req, key_part_a, ukey_part_b).await {
+    // 硬件码B由客户端的应答提供，没有时由服务器上的UKey应答挑战
+    let assertion = match hardware_assertion(resource_service.clone(), ukey_provider.as_ref(), req.ukey_assertion, req.fido2_assertion).await {
+        Ok(assertion) => assertion,
+        Err((status, message)) => return (status, Json(ResourceCreateResponse {
+            resource: ResourceResponse::default(),
+            message,
+        })),
+    };
+    
+    tracing::info!("使用UKey创建资源, 序列号: {}", assertion.serial());
+    
+    match resource_service.create_resource(req.resource, key_part_a, &assertion).await {
         Ok(resource) => {
             let response = ResourceCreateResponse {
                 resource: resource.to_response(),
@@ -139,6 +257,10 @@ pub async fn create_resource(
             };
             (StatusCode::CREATED, Json(response))
         },
+        Err(ResourceServiceError::UKeyVerificationFailed(message)) => (StatusCode::UNAUTHORIZED, Json(ResourceCreateResponse {
+            resource: ResourceResponse::default(),
+            message: format!("UKey验证失败: {}", message),
+        })),
         Err(err) => {
             tracing::error!("创建资源失败: {:?}", err);
             (StatusCode::INTERNAL_SERVER_ERROR, Json(ResourceCreateResponse {
@@ -149,6 +271,92 @@ pub async fn create_resource(
     }
 }
 
+/// 上传资源
+///
+/// 媒体数据为请求体，边接收边加密并分块写入数据库，内存中不保留完整的明文和容器。其他字段放在查询参数中，
+/// 客户端UKey或FIDO2安全密钥的应答以JSON放在 `X-Hardware-Assertion` 请求头中，没有时由服务器上的UKey应答。
+/// 请求体超过 `UPLOAD_MAX_SIZE` 时返回413，接收途中超出时放弃已写入的部分。
+#[axum::debug_handler]
+pub async fn upload_resource(
+    Query(query): Query<ResourceUploadQuery>,
+    headers: HeaderMap,
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
+    request: Request,
+) -> (StatusCode, Json<ResourceCreateResponse>) {
+    let error = |status: StatusCode, message: String| (status, Json(ResourceCreateResponse {
+        resource: ResourceResponse::default(),
+        message,
+    }));
+    let too_large = || error(StatusCode::PAYLOAD_TOO_LARGE, "请求体超过上传大小限制".to_string());
+    
+    // 声明的长度已经超出时不消耗硬件应答
+    let content_length = headers.get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if content_length.is_some_and(|content_length| content_length > resource_service.config.upload.max_size) {
+        return too_large();
+    }
+    
+    let assertion = match headers.get(HARDWARE_ASSERTION_HEADER) {
+        Some(value) => match value.to_str().ok().and_then(|value| serde_json::from_str::<HardwareAssertion>(value).ok()) {
+            Some(assertion) => assertion,
+            None => return error(StatusCode::BAD_REQUEST, "硬件应答格式错误".to_string()),
+        },
+        None => match hardware_assertion(resource_service.clone(), ukey_provider.as_ref(), None, None).await {
+            Ok(assertion) => assertion,
+            Err((status, message)) => return error(status, message),
+        },
+    };
+    
+    tracing::info!("使用UKey上传资源, 序列号: {}", assertion.serial());
+    
+    let create_req = CreateResourceRequest {
+        title: query.title,
+        title_en: query.title_en,
+        description: query.description,
+        resource_type: query.resource_type,
+        media_data: Vec::new(),
+        media_type: query.media_type,
+        is_local: query.is_local,
+        encryption_info: String::new(),
+        status: query.status,
+    };
+    
+    // 请求体按路由上的 DefaultBodyLimit 限制，超出时读取出错，事务回滚
+    let limit_exceeded = Arc::new(AtomicBool::new(false));
+    let body = request.into_limited_body().into_data_stream().map_err({
+        let limit_exceeded = limit_exceeded.clone();
+        move |err| {
+            if is_length_limit_error(&err) {
+                limit_exceeded.store(true, Ordering::Relaxed);
+            }
+            std::io::Error::other(err)
+        }
+    });
+    let mut reader = StreamReader::new(body);
+    
+    match resource_service.create_resource_stream(create_req, &mut reader, DEFAULT_KEY_PART_A, &assertion).await {
+        Ok(resource) => (StatusCode::CREATED, Json(ResourceCreateResponse {
+            resource: resource.to_response(),
+            message: "资源上传成功".to_string(),
+        })),
+        Err(_) if limit_exceeded.load(Ordering::Relaxed) => too_large(),
+        Err(ResourceServiceError::ParameterError(message)) => error(StatusCode::BAD_REQUEST, message),
+        Err(ResourceServiceError::UKeyVerificationFailed(message)) => error(StatusCode::UNAUTHORIZED, format!("UKey验证失败: {}", message)),
+        Err(err) => {
+            tracing::error!("上传资源失败: {:?}", err);
+            error(StatusCode::INTERNAL_SERVER_ERROR, format!("资源上传失败: {:?}", err))
+        }
+    }
+}
+
+/// 请求体错误是否由超过大小限制引起
+fn is_length_limit_error(err: &axum::Error) -> bool {
+    std::iter::successors(Some(err as &(dyn std::error::Error + 'static)), |err| err.source())
+        .any(|err| err.is::<http_body_util::LengthLimitError>())
+}
+
 /// 更新资源
 #[axum::debug_handler]
 pub async fn update_resource(
@@ -168,6 +376,14 @@ pub async fn update_resource(
             resource: ResourceResponse::default(),
             message: "资源未找到".to_string(),
         })),
+        Err(ResourceServiceError::ParameterError(msg)) => (StatusCode::BAD_REQUEST, Json(ResourceUpdateResponse {
+            resource: ResourceResponse::default(),
+            message: msg,
+        })),
+        Err(ResourceServiceError::ResourceStatusError(msg)) => (StatusCode::CONFLICT, Json(ResourceUpdateResponse {
+            resource: ResourceResponse::default(),
+            message: msg,
+        })),
         Err(err) => {
             tracing::error!("更新资源失败: {:?}", err);
             (StatusCode::INTERNAL_SERVER_ERROR, Json(ResourceUpdateResponse {
@@ -208,34 +424,106 @@ pub async fn delete_resource(
 pub async fn decrypt_resource(
     Path(id): Path<i32>,
     Extension(resource_service): Extension<Arc<ResourceService>>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
     Json(req): Json<DecryptResourceRequest>,
 ) -> (StatusCode, Json<ResourceDecryptResponse>) {
-    match resource_service.decrypt_resource(id, &req.key_part_a, &req.ukey_part_b).await {
-        Ok(data) => {
+    // 请求中没有UKey应答或FIDO2断言时由服务器上的UKey应答挑战
+    let assertion = match hardware_assertion(resource_service.clone(), ukey_provider.as_ref(), req.ukey_assertion, req.fido2_assertion).await {
+        Ok(assertion) => assertion,
+        Err((status, message)) => return (status, Json(ResourceDecryptResponse {
+            data: String::new(),
+            resource: None,
+            message,
+        })),
+    };
+    
+    match resource_service.decrypt_resource(id, req.key_part_a.expose_secret(), &assertion).await {
+        Ok((data, resource)) => {
             let response = ResourceDecryptResponse {
                 data: general_purpose::STANDARD.encode(data),
+                resource: Some(resource),
                 message: "资源解密成功".to_string(),
             };
             (StatusCode::OK, Json(response))
         },
         Err(ResourceServiceError::ResourceNotFound) => (StatusCode::NOT_FOUND, Json(ResourceDecryptResponse {
             data: String::new(),
+            resource: None,
             message: "资源未找到".to_string(),
         })),
         Err(ResourceServiceError::KeyVerificationFailed) => (StatusCode::UNAUTHORIZED, Json(ResourceDecryptResponse {
             data: String::new(),
-            message: "密钥验证失败".to_string(),
+            resource: None,
+            message: "密钥验证失败或数据已损坏".to_string(),
+        })),
+        Err(ResourceServiceError::UKeyVerificationFailed(message)) => (StatusCode::UNAUTHORIZED, Json(ResourceDecryptResponse {
+            data: String::new(),
+            resource: None,
+            message: format!("UKey验证失败: {}", message),
         })),
         Err(err) => {
             tracing::error!("解密资源失败: {:?}", err);
             (StatusCode::INTERNAL_SERVER_ERROR, Json(ResourceDecryptResponse {
                 data: String::new(),
+                resource: None,
                 message: format!("资源解密失败: {:?}", err),
             }))
         }
     }
 }
 
+/// 下载资源
+///
+/// 请求体与解密资源相同。验证密钥后以媒体类型作为 `Content-Type` 流式返回明文，内存中不保留完整的明文。
+/// 传输中途发现数据损坏时中断响应，客户端会收到不完整的响应体。
+#[axum::debug_handler]
+pub async fn download_resource(
+    Path(id): Path<i32>,
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
+    Json(req): Json<DecryptResourceRequest>,
+) -> Response {
+    let error = |status: StatusCode, message: String| (status, Json(ResourceDecryptResponse {
+        data: String::new(),
+        resource: None,
+        message,
+    })).into_response();
+    
+    let assertion = match hardware_assertion(resource_service.clone(), ukey_provider.as_ref(), req.ukey_assertion, req.fido2_assertion).await {
+        Ok(assertion) => assertion,
+        Err((status, message)) => return error(status, message),
+    };
+    
+    let download = match resource_service.prepare_download(id, req.key_part_a.expose_secret(), &assertion).await {
+        Ok(download) => download,
+        Err(ResourceServiceError::ResourceNotFound) => return error(StatusCode::NOT_FOUND, "资源未找到".to_string()),
+        Err(ResourceServiceError::KeyVerificationFailed) => return error(StatusCode::UNAUTHORIZED, "密钥验证失败或数据已损坏".to_string()),
+        Err(ResourceServiceError::UKeyVerificationFailed(message)) => return error(StatusCode::UNAUTHORIZED, format!("UKey验证失败: {}", message)),
+        Err(err) => {
+            tracing::error!("下载资源失败: {:?}", err);
+            return error(StatusCode::INTERNAL_SERVER_ERROR, format!("资源下载失败: {:?}", err));
+        }
+    };
+    let media_type = download.resource.media_type.clone();
+    
+    // 解密任务写入管道，响应体从另一端读取；任务结束后写入端关闭，再根据任务结果决定是否以错误结束响应体
+    let (reader, mut writer) = tokio::io::duplex(DOWNLOAD_BUFFER_SIZE);
+    let task = tokio::spawn(async move {
+        resource_service.write_download(&download, &mut writer).await
+    });
+    let result = stream::once(task).filter_map(move |result| async move {
+        let message = match result {
+            Ok(Ok(_)) => return None,
+            Ok(Err(err)) => err.to_string(),
+            Err(err) => err.to_string(),
+        };
+        tracing::error!("下载资源中断: {}, 错误: {}", id, message);
+        Some(Err(std::io::Error::other(message)))
+    });
+    
+    (StatusCode::OK, [(header::CONTENT_TYPE, media_type)], Body::from_stream(ReaderStream::new(reader).chain(result))).into_response()
+}
+
 /// 获取资源统计信息
 #[axum::debug_handler]
 pub async fn get_resource_stats(
@@ -249,3 +537,67 @@ pub async fn get_resource_stats(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+    
+    use crate::api::routes::create_router;
+    use crate::database::DatabasePool;
+    use crate::service::ukey::UKeyService;
+    use crate::service::ukey::tests::{enroll, respond, test_resource_service};
+    use crate::ukey::challenge::tests::test_key_pair;
+    
+    /// 上传请求，硬件应答放在请求头中
+    fn upload_request(assertion: &UKeyAssertion, content_length: Option<usize>, body: Body) -> Request {
+        let assertion = serde_json::json!({
+            "ukey": {
+                "challenge_id": assertion.challenge_id,
+                "serial": assertion.serial,
+                "public_key": assertion.public_key,
+                "part_b": assertion.part_b.expose_secret(),
+                "signature": assertion.signature,
+            }
+        });
+        let mut request = Request::post("/api/resources/upload?title=title&description=description&resource_type=video&media_type=video/mp4")
+            .header(HARDWARE_ASSERTION_HEADER, assertion.to_string());
+        if let Some(content_length) = content_length {
+            request = request.header(header::CONTENT_LENGTH, content_length);
+        }
+        request.body(body).unwrap()
+    }
+    
+    #[sqlx::test]
+    async fn test_upload_body_limit(db: DatabasePool) {
+        let mut config = test_resource_service(db.clone()).config.clone();
+        config.upload.max_size = 1024;
+        config.ukey.vendor = crate::ukey::VENDOR_HTTP.to_string();
+        let resource_service = Arc::new(ResourceService::new(db.clone(), config.clone()));
+        let ukey_service = UKeyService::new(resource_service.clone());
+        let key_pair = test_key_pair();
+        enroll(&ukey_service, &key_pair, "UK-0001").await;
+        let ukey_provider = crate::ukey::create_provider(&config.ukey).unwrap();
+        let router = create_router(resource_service, ukey_provider, config);
+        
+        // 声明的长度超出限制时直接拒绝，不消耗挑战
+        let assertion = respond(&ukey_service, &key_pair, "UK-0001", "part-b").await;
+        let request = upload_request(&assertion, Some(2048), Body::from(vec![0u8; 2048]));
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        
+        // 不声明长度的请求体在接收途中超出限制，已写入的部分随事务回滚
+        let chunks = (0..4).map(|_| Ok::<_, std::io::Error>(vec![0u8; 512]));
+        let request = upload_request(&assertion, None, Body::from_stream(stream::iter(chunks)));
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let resources: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM resources").fetch_one(&db).await.unwrap();
+        let chunks: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM resource_chunks").fetch_one(&db).await.unwrap();
+        assert_eq!((resources, chunks), (0, 0));
+        
+        let assertion = respond(&ukey_service, &key_pair, "UK-0001", "part-b").await;
+        let request = upload_request(&assertion, Some(1024), Body::from(vec![0u8; 1024]));
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+}
@@ -0,0 +1,231 @@
+use axum::{http::StatusCode, Json, Extension};
+use axum::extract::Path;
+use axum::http::HeaderMap;
+use base64::{Engine as _, engine::general_purpose};
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::crypto::SecretString;
+use crate::database::models::ukey::{EnrollUKeyRequest, RevokeUKeyRequest, UKey, UKeyAuthorization};
+use crate::service::resource::{ResourceService, ResourceServiceError};
+use crate::service::ukey::{EnrollmentCredential, UKeyService};
+use crate::ukey::{Fido2Assertion, HardwareAssertion, UKeyAssertion, UKeyError, UKeyProvider, VENDOR_HTTP};
+
+/// UKey挑战响应
+#[derive(Serialize, Debug, Default)]
+pub struct UKeyChallengeResponse {
+    /// 挑战ID，应答时原样返回
+    pub challenge_id: Option<uuid::Uuid>,
+    /// 随机数，Base64编码
+    pub nonce: String,
+    /// 过期时间
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    /// 消息
+    pub message: String,
+}
+
+/// UKey登记响应
+#[derive(Serialize, Debug)]
+pub struct UKeyResponse {
+    /// UKey
+    pub ukey: Option<UKey>,
+    /// 消息
+    pub message: String,
+}
+
+impl UKeyResponse {
+    pub(crate) fn error(message: String) -> Json<Self> {
+        Json(UKeyResponse { ukey: None, message })
+    }
+}
+
+/// UKey错误对应的状态码
+pub(crate) fn ukey_error_status(err: &UKeyError) -> StatusCode {
+    match err {
+        UKeyError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        UKeyError::NoToken => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+/// 携带UKey管理令牌的请求头
+///
+/// 与 `Authorization` 分开，登录后带着JWT的请求仍可以使用请求体中的硬件授权。
+pub(crate) const ADMIN_TOKEN_HEADER: &str = "x-ukey-admin-token";
+
+/// 登记或撤销硬件的凭据：`X-UKey-Admin-Token` 请求头中的管理令牌优先，否则使用请求体中的硬件授权
+pub(crate) fn enrollment_credential<'a>(
+    headers: &'a HeaderMap,
+    authorization: Option<&'a UKeyAuthorization>
+) -> Option<EnrollmentCredential<'a>> {
+    let admin_token = headers.get(ADMIN_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok());
+    
+    match (admin_token, authorization) {
+        (Some(admin_token), _) => Some(EnrollmentCredential::AdminToken(admin_token.trim())),
+        (None, Some(authorization)) => Some(EnrollmentCredential::Hardware(authorization)),
+        (None, None) => None,
+    }
+}
+
+/// 签发挑战并由服务器上的UKey应答
+///
+/// 请求中没有UKey应答时使用，失败时返回状态码和错误信息。
+pub(crate) async fn server_assertion(
+    resource_service: Arc<ResourceService>,
+    ukey_provider: &dyn UKeyProvider
+) -> Result<UKeyAssertion, (StatusCode, String)> {
+    let challenge = UKeyService::new(resource_service).issue_challenge().await.map_err(|err| {
+        tracing::error!("签发UKey挑战失败: {:?}", err);
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("签发UKey挑战失败: {}", err))
+    })?;
+    
+    ukey_provider.respond(&challenge).await.map_err(|err| {
+        tracing::error!("UKey应答挑战失败: {}", err);
+        (ukey_error_status(&err), format!("UKey应答挑战失败: {}", err))
+    })
+}
+
+/// 选择请求中的硬件应答
+///
+/// UKey应答和FIDO2断言只能提交一个，都没有时由服务器上的UKey应答挑战。
+pub(crate) async fn hardware_assertion(
+    resource_service: Arc<ResourceService>,
+    ukey_provider: &dyn UKeyProvider,
+    ukey_assertion: Option<UKeyAssertion>,
+    fido2_assertion: Option<Fido2Assertion>
+) -> Result<HardwareAssertion, (StatusCode, String)> {
+    match (ukey_assertion, fido2_assertion) {
+        (Some(_), Some(_)) => Err((StatusCode::BAD_REQUEST, "UKey应答和FIDO2断言只能提交一个".to_string())),
+        (Some(assertion), None) => Ok(HardwareAssertion::UKey(assertion)),
+        (None, Some(assertion)) => Ok(HardwareAssertion::Fido2(assertion)),
+        (None, None) => server_assertion(resource_service, ukey_provider).await.map(HardwareAssertion::UKey),
+    }
+}
+
+/// 验证请求中的硬件应答，返回应答的UKey或安全密钥和硬件码B
+///
+/// 没有提交应答时由服务器上的UKey应答挑战。应答必须来自已登记且未撤销的硬件，失败时返回状态码和错误信息。
+pub(crate) async fn verified_part_b(
+    resource_service: Arc<ResourceService>,
+    ukey_provider: &dyn UKeyProvider,
+    assertion: Option<HardwareAssertion>
+) -> Result<(UKey, SecretString), (StatusCode, String)> {
+    let assertion = match assertion {
+        Some(assertion) => assertion,
+        None => HardwareAssertion::UKey(server_assertion(resource_service.clone(), ukey_provider).await?),
+    };
+    
+    match UKeyService::new(resource_service).verify(&assertion).await {
+        Ok(verified) => Ok(verified),
+        Err(ResourceServiceError::UKeyVerificationFailed(message)) => {
+            Err((StatusCode::UNAUTHORIZED, format!("UKey验证失败: {}", message)))
+        }
+        Err(err) => {
+            tracing::error!("验证硬件应答失败: {:?}", err);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("验证硬件应答失败: {}", err)))
+        }
+    }
+}
+
+/// 签发UKey挑战
+///
+/// 客户端的UKey代理对挑战签名后，把应答放在解密请求的 `ukey_assertion` 中提交，挑战只能使用一次。
+#[axum::debug_handler]
+pub async fn issue_challenge(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+) -> (StatusCode, Json<UKeyChallengeResponse>) {
+    match UKeyService::new(resource_service).issue_challenge().await {
+        Ok(challenge) => (StatusCode::CREATED, Json(UKeyChallengeResponse {
+            challenge_id: Some(challenge.id),
+            nonce: general_purpose::STANDARD.encode(&challenge.nonce),
+            expires_at: Some(challenge.expires_at),
+            message: "挑战已签发".to_string(),
+        })),
+        Err(err) => {
+            tracing::error!("签发UKey挑战失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(UKeyChallengeResponse {
+                message: format!("签发UKey挑战失败: {}", err),
+                ..Default::default()
+            }))
+        }
+    }
+}
+
+/// 列出登记的UKey
+#[axum::debug_handler]
+pub async fn list_ukeys(
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+) -> (StatusCode, Json<Vec<UKey>>) {
+    match UKeyService::new(resource_service).list().await {
+        Ok(ukeys) => (StatusCode::OK, Json(ukeys)),
+        Err(err) => {
+            tracing::error!("获取UKey列表失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(Vec::new()))
+        }
+    }
+}
+
+/// 登记UKey
+///
+/// 需要管理令牌或已登记硬件的授权，请求中没有UKey应答时登记服务器上的UKey。
+#[axum::debug_handler]
+pub async fn enroll_ukey(
+    headers: HeaderMap,
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    Extension(ukey_provider): Extension<Arc<dyn UKeyProvider>>,
+    Json(req): Json<EnrollUKeyRequest>,
+) -> (StatusCode, Json<UKeyResponse>) {
+    let (assertion, vendor) = match req.ukey_assertion {
+        Some(assertion) => (assertion, req.vendor.unwrap_or_else(|| VENDOR_HTTP.to_string())),
+        None => match server_assertion(resource_service.clone(), ukey_provider.as_ref()).await {
+            Ok(assertion) => (assertion, req.vendor.unwrap_or_else(|| ukey_provider.vendor().to_string())),
+            Err((status, message)) => return (status, UKeyResponse::error(message)),
+        },
+    };
+    
+    let credential = enrollment_credential(&headers, req.authorization.as_ref());
+    match UKeyService::new(resource_service).enroll(&req.owner, &vendor, &assertion, credential).await {
+        Ok(ukey) => (StatusCode::CREATED, Json(UKeyResponse {
+            ukey: Some(ukey),
+            message: "UKey已登记".to_string(),
+        })),
+        Err(ResourceServiceError::ParameterError(message)) => (StatusCode::BAD_REQUEST, UKeyResponse::error(message)),
+        Err(ResourceServiceError::UKeyVerificationFailed(message)) => (StatusCode::UNAUTHORIZED, UKeyResponse::error(format!("UKey验证失败: {}", message))),
+        Err(ResourceServiceError::KeyVerificationFailed) => (StatusCode::UNAUTHORIZED, UKeyResponse::error("密钥验证失败".to_string())),
+        Err(ResourceServiceError::PermissionError(message)) => (StatusCode::FORBIDDEN, UKeyResponse::error(message)),
+        Err(ResourceServiceError::ResourceExists) => (StatusCode::CONFLICT, UKeyResponse::error("UKey已登记或已被撤销".to_string())),
+        Err(err) => {
+            tracing::error!("登记UKey失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, UKeyResponse::error(format!("登记UKey失败: {}", err)))
+        }
+    }
+}
+
+/// 撤销UKey，撤销后该UKey的应答不再被接受
+///
+/// 需要管理令牌或已登记硬件的授权，只使用管理令牌时可以不带请求体。
+#[axum::debug_handler]
+pub async fn revoke_ukey(
+    Path(id): Path<i32>,
+    headers: HeaderMap,
+    Extension(resource_service): Extension<Arc<ResourceService>>,
+    req: Option<Json<RevokeUKeyRequest>>,
+) -> (StatusCode, Json<UKeyResponse>) {
+    let authorization = req.and_then(|Json(req)| req.authorization);
+    let credential = enrollment_credential(&headers, authorization.as_ref());
+    match UKeyService::new(resource_service).revoke(id, credential).await {
+        Ok(ukey) => (StatusCode::OK, Json(UKeyResponse {
+            ukey: Some(ukey),
+            message: "UKey已撤销".to_string(),
+        })),
+        Err(ResourceServiceError::ResourceNotFound) => (StatusCode::NOT_FOUND, UKeyResponse::error("UKey未找到".to_string())),
+        Err(ResourceServiceError::UKeyVerificationFailed(message)) => (StatusCode::UNAUTHORIZED, UKeyResponse::error(format!("UKey验证失败: {}", message))),
+        Err(ResourceServiceError::KeyVerificationFailed) => (StatusCode::UNAUTHORIZED, UKeyResponse::error("密钥验证失败".to_string())),
+        Err(ResourceServiceError::PermissionError(message)) => (StatusCode::FORBIDDEN, UKeyResponse::error(message)),
+        Err(err) => {
+            tracing::error!("撤销UKey失败: {:?}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, UKeyResponse::error(format!("撤销UKey失败: {}", err)))
+        }
+    }
+}
@@ -1,9 +1,10 @@
-use axum::{Router, routing::get, routing::post, routing::put, routing::delete, Extension, http::StatusCode};
+use axum::{Router, routing::get, routing::post, routing::put, routing::delete, Extension, extract::DefaultBodyLimit, http::StatusCode};
 use std::sync::Arc;
 
 use crate::config::AppConfig;
 use crate::service::resource::ResourceService;
-use crate::api::handlers::{resource_handlers, auth_handlers, health_handlers};
+use crate::ukey::UKeyProvider;
+use crate::api::handlers::{resource_handlers, auth_handlers, health_handlers, key_handlers, ukey_handlers, fido2_handlers};
 
 /// 404处理程序
 async fn not_found_handler() -> (StatusCode, &'static str) {
@@ -13,6 +14,7 @@ async fn not_found_handler() -> (StatusCode, &'static str) {
 /// 创建API路由
 pub fn create_router(
     resource_service: Arc<ResourceService>,
+    ukey_provider: Arc<dyn UKeyProvider>,
     config: AppConfig
 ) -> Router {
     // 创建路由
@@ -26,12 +28,39 @@ pub fn create_router(
                 // 资源管理
                 .route("/resources", get(resource_handlers::get_resources))
                 .route("/resources", post(resource_handlers::create_resource))
+                .route("/resources/upload", post(resource_handlers::upload_resource)
+                    .layer(DefaultBodyLimit::max(config.upload.max_size as usize)))
+                .route("/resources/search", post(resource_handlers::search_resources))
                 .route("/resources/:id", get(resource_handlers::get_resource))
                 .route("/resources/:id", put(resource_handlers::update_resource))
                 .route("/resources/:id", delete(resource_handlers::delete_resource))
                 .route("/resources/:id/decrypt", post(resource_handlers::decrypt_resource))
+                .route("/resources/:id/download", post(resource_handlers::download_resource))
                 .route("/resources/stats", get(resource_handlers::get_resource_stats))
                 
+                // 密钥管理
+                .route("/keys/rekey", post(key_handlers::start_rekey))
+                .route("/keys/rekey/:id", get(key_handlers::get_rekey_job))
+                .route("/keys/upgrade", post(key_handlers::upgrade_resources))
+                .route("/keys/upgrade/stats", get(key_handlers::get_upgrade_stats))
+                .route("/keys/recovery", get(key_handlers::get_recovery_key))
+                .route("/keys/recovery", post(key_handlers::regenerate_recovery_key))
+                .route("/keys/recovery", delete(key_handlers::revoke_recovery_key))
+                .route("/keys/recovery/recover", post(key_handlers::recover_with_recovery_key))
+                .route("/keys/recovery/escrow", post(key_handlers::escrow_recovery_key))
+                .route("/keys/recovery/unlock", post(key_handlers::unlock_with_shares))
+                
+                // UKey
+                .route("/ukey/challenges", post(ukey_handlers::issue_challenge))
+                .route("/ukeys", get(ukey_handlers::list_ukeys))
+                .route("/ukeys", post(ukey_handlers::enroll_ukey))
+                .route("/ukeys/:id", delete(ukey_handlers::revoke_ukey))
+                
+                // FIDO2安全密钥
+                .route("/fido2/registrations/options", post(fido2_handlers::registration_options))
+                .route("/fido2/registrations", post(fido2_handlers::register))
+                .route("/fido2/assertions/options", post(fido2_handlers::assertion_options))
+                
                 // 认证
                 .route("/auth/login", post(auth_handlers::login))
                 .route("/auth/register", post(auth_handlers::register))
@@ -44,5 +73,6 @@ pub fn create_router(
         
         // 添加中间件
         .layer(Extension(resource_service))
+        .layer(Extension(ukey_provider))
         .layer(Extension(config))
 }
@@ -0,0 +1,257 @@
+use std::io::{self, BufRead};
+use std::sync::Arc;
+
+use base64::{Engine as _, engine::general_purpose};
+use clap::{Parser, Subcommand};
+
+use crate::crypto::{SearchKey, SecretString};
+use crate::database::models::rekey_job::RekeyKeys;
+use crate::database::models::ukey::UKey;
+use crate::service::rekey::RekeyService;
+use crate::service::manifest::ManifestService;
+use crate::service::upgrade::UpgradeService;
+use crate::service::resource::ResourceService;
+use crate::service::ukey::UKeyService;
+use crate::ukey::{self, HardwareAssertion, UKeyProvider};
+
+/// 密影库 后端服务
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    /// 不指定子命令时启动HTTP服务
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// 运维子命令
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// 更换所有资源的密钥
+    ///
+    /// 从标准输入依次读取旧密钥A、新密钥A，每行一个，避免秘密出现在命令行参数中。
+    /// 硬件码B由插在本机的已登记UKey应答挑战得到。中断后用相同的密钥重新执行即可续跑。
+    Rekey {
+        /// 每批处理的资源数
+        #[arg(long)]
+        batch_size: Option<u32>,
+        
+        /// 同时更换UKey：旧UKey应答后提示换上新UKey，从标准输入再读取一行确认
+        #[arg(long)]
+        swap_ukey: bool,
+    },
+    
+    /// 升级加密参数低于当前配置的资源
+    ///
+    /// 从标准输入读取密钥A，硬件码B由插在本机的已登记UKey应答挑战得到。
+    /// 不属于该密钥的资源跳过，可以用其他密钥再次执行。
+    Upgrade {
+        /// 只输出统计信息，不读取密钥也不修改资源
+        #[arg(long)]
+        stats: bool,
+    },
+    
+    /// 计算搜索用的盲索引词元
+    ///
+    /// 从标准输入依次读取密钥A和查询词，每行一个，硬件码B由插在本机的已登记UKey应答挑战得到。
+    /// 输出可直接作为 `POST /api/resources/search` 请求体的JSON，查询词和密钥不发送给服务器。
+    SearchTokens,
+    
+    /// 验证或重建资源的签名清单
+    Manifest {
+        #[command(subcommand)]
+        action: ManifestAction,
+    },
+}
+
+/// 签名清单子命令
+#[derive(Subcommand, Debug)]
+pub enum ManifestAction {
+    /// 验证清单签名，列出缺失、被回滚和被插入的资源，发现问题时以非零状态退出
+    Verify {
+        /// Base64编码的Ed25519验证公钥，默认使用 MANIFEST_SIGNING_KEY 对应的公钥
+        #[arg(long)]
+        public_key: Option<String>,
+        
+        /// 上次验证时记录的清单序号，当前序号更小说明整个数据库被回滚
+        #[arg(long)]
+        min_sequence: Option<i64>,
+    },
+    
+    /// 按资源表的当前内容重建清单并签名，用于首次启用签名清单
+    Rebuild,
+}
+
+/// 从标准输入读取一行秘密
+fn read_secret(lines: &mut impl Iterator<Item = io::Result<String>>, name: &str) -> anyhow::Result<SecretString> {
+    let mut line = lines
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("标准输入缺少{}", name))??;
+    // 原地去掉Windows换行符，不复制秘密
+    if line.ends_with('\r') {
+        line.pop();
+    }
+    let secret = SecretString::new(line);
+    
+    if secret.expose_secret().is_empty() {
+        anyhow::bail!("{}不能为空", name);
+    }
+    
+    Ok(secret)
+}
+
+/// 由插在本机的UKey应答挑战，验证通过后返回UKey和硬件码B
+async fn ukey_part_b(ukey_service: &UKeyService, provider: &dyn UKeyProvider) -> anyhow::Result<(UKey, SecretString)> {
+    let challenge = ukey_service.issue_challenge().await?;
+    let assertion = provider.respond(&challenge).await?;
+    
+    Ok(ukey_service.verify(&HardwareAssertion::UKey(assertion)).await?)
+}
+
+/// 执行子命令
+pub async fn run(command: Command, resource_service: Arc<ResourceService>) -> anyhow::Result<()> {
+    match command {
+        Command::Rekey { batch_size, swap_ukey } => rekey(resource_service, batch_size, swap_ukey).await,
+        Command::Upgrade { stats } => upgrade(resource_service, stats).await,
+        Command::SearchTokens => search_tokens(resource_service).await,
+        Command::Manifest { action } => manifest(resource_service, action).await,
+    }
+}
+
+/// 执行密钥更换任务并输出进度
+async fn rekey(resource_service: Arc<ResourceService>, batch_size: Option<u32>, swap_ukey: bool) -> anyhow::Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let old_key_part_a = read_secret(&mut lines, "旧密钥A")?;
+    let new_key_part_a = read_secret(&mut lines, "新密钥A")?;
+    
+    let provider = ukey::create_provider(&resource_service.config.ukey)?;
+    let ukey_service = UKeyService::new(resource_service.clone());
+    let (old_ukey, old_ukey_part_b) = ukey_part_b(&ukey_service, provider.as_ref()).await?;
+    println!("旧UKey应答成功: {}", old_ukey.serial);
+    if swap_ukey {
+        println!("请换上新UKey后输入一行确认");
+        lines.next().ok_or_else(|| anyhow::anyhow!("标准输入缺少换上新UKey的确认"))??;
+    }
+    let (new_ukey, new_ukey_part_b) = ukey_part_b(&ukey_service, provider.as_ref()).await?;
+    println!("新UKey应答成功: {}", new_ukey.serial);
+    
+    let keys = RekeyKeys {
+        old_key_part_a,
+        old_ukey_part_b,
+        new_key_part_a,
+        new_ukey_part_b,
+        new_ukey_fingerprint: new_ukey.fingerprint,
+        batch_size,
+    };
+    
+    let rekey_service = RekeyService::new(resource_service);
+    let prepared = rekey_service.prepare_job(&keys).await?;
+    println!("密钥更换任务: {}, 资源总数: {}", prepared.job.id, prepared.job.total_resources);
+    
+    let job = rekey_service.run_job(prepared, &keys, |job| {
+        println!(
+            "已处理: {}, 已跳过: {}, 资源总数: {}",
+            job.processed_resources, job.skipped_resources, job.total_resources
+        );
+    }).await?;
+    
+    println!("密钥更换任务完成: {}", job.id);
+    if !job.skipped_resource_ids.is_empty() {
+        println!("以下资源无法用旧密钥解开，未更换密钥: {:?}", job.skipped_resource_ids);
+    }
+    
+    Ok(())
+}
+
+/// 输出统计信息，或读取密钥后批量升级资源
+async fn upgrade(resource_service: Arc<ResourceService>, stats_only: bool) -> anyhow::Result<()> {
+    let upgrade_service = UpgradeService::new(resource_service.clone());
+    
+    let stats = upgrade_service.get_stats().await?;
+    println!(
+        "资源总数: {}, 已符合配置: {}, 待升级: {}, 无法解析: {}",
+        stats.total, stats.up_to_date, stats.pending(), stats.unreadable
+    );
+    println!(
+        "旧版格式: {}, 旧版容器: {}, 旧版组合方案: {}, 算法不一致: {}, 派生参数过低: {}",
+        stats.legacy_format, stats.container_version, stats.key_combiner, stats.algorithm, stats.kdf_params
+    );
+    if stats_only || stats.pending() == 0 {
+        return Ok(());
+    }
+    
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let key_part_a = read_secret(&mut lines, "密钥A")?;
+    
+    let provider = ukey::create_provider(&resource_service.config.ukey)?;
+    let (ukey, ukey_part_b) = ukey_part_b(&UKeyService::new(resource_service), provider.as_ref()).await?;
+    println!("UKey应答成功: {}", ukey.serial);
+    
+    let summary = upgrade_service.upgrade_all(key_part_a.expose_secret(), ukey_part_b.expose_secret(), |summary| {
+        println!(
+            "已升级: {}, 已符合配置: {}, 已跳过: {}, 已被修改: {}, 失败: {}",
+            summary.upgraded, summary.up_to_date, summary.skipped, summary.conflicted, summary.failed
+        );
+    }).await?;
+    
+    println!("资源加密参数升级完成，已升级: {}", summary.upgraded);
+    
+    Ok(())
+}
+
+/// 输出查询词的盲索引词元
+async fn search_tokens(resource_service: Arc<ResourceService>) -> anyhow::Result<()> {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let key_part_a = read_secret(&mut lines, "密钥A")?;
+    let query = read_secret(&mut lines, "查询词")?;
+    
+    let provider = ukey::create_provider(&resource_service.config.ukey)?;
+    let (_, ukey_part_b) = ukey_part_b(&UKeyService::new(resource_service), provider.as_ref()).await?;
+    
+    let search_key = SearchKey::derive(key_part_a.expose_secret(), ukey_part_b.expose_secret())?;
+    let search_tokens = search_key.query_tokens(query.expose_secret());
+    if search_tokens.is_empty() {
+        anyhow::bail!("查询词太短");
+    }
+    
+    let search_tokens: Vec<String> = search_tokens.iter().map(|token| general_purpose::STANDARD.encode(token)).collect();
+    println!("{}", serde_json::json!({ "search_tokens": search_tokens }));
+    
+    Ok(())
+}
+
+/// 验证或重建签名清单
+async fn manifest(resource_service: Arc<ResourceService>, action: ManifestAction) -> anyhow::Result<()> {
+    let manifest_service = ManifestService::new(resource_service);
+    
+    match action {
+        ManifestAction::Verify { public_key, min_sequence } => {
+            let report = manifest_service.verify(public_key.as_deref()).await?;
+            let Some(sequence) = report.sequence else {
+                anyhow::bail!("还没有签名清单，请先执行 manifest rebuild");
+            };
+            
+            println!("清单序号: {}, 签名有效: {}", sequence, report.signature_valid);
+            println!("缺失的资源: {:?}", report.missing);
+            println!("被回滚或篡改的资源: {:?}", report.rolled_back);
+            println!("被插入的资源: {:?}", report.injected);
+            
+            if min_sequence.is_some_and(|min_sequence| sequence < min_sequence) {
+                anyhow::bail!("清单序号 {} 小于 {}，数据库可能被回滚到旧快照", sequence, min_sequence.unwrap_or_default());
+            }
+            if !report.is_intact() {
+                anyhow::bail!("签名清单验证失败");
+            }
+            
+            println!("签名清单验证通过");
+        }
+        ManifestAction::Rebuild => {
+            let count = manifest_service.rebuild().await?;
+            println!("签名清单重建完成，资源数: {}", count);
+        }
+    }
+    
+    Ok(())
+}
@@ -2,6 +2,9 @@ use serde::Deserialize;
 use std::env;
 use std::path::Path;
 
+/// UKey管理令牌的最小长度
+const MIN_ADMIN_TOKEN_LEN: usize = 32;
+
 /// 配置错误类型
 #[derive(thiserror::Error, Debug)]
 pub enum ConfigError {
@@ -45,17 +48,64 @@ pub struct JwtConfig {
 /// 加密配置
 #[derive(Deserialize, Debug, Clone)]
 pub struct EncryptionConfig {
+    /// 新资源使用的加密套件，见 `crypto::suite`
     pub algorithm: String,
+    /// 旧版全局盐值，已弃用：新资源使用随机盐值，旧资源的盐值保存在各自的密钥信息中
+    #[allow(dead_code)]
     pub salt: String,
     pub key_derivation_iterations: u32,
+    /// 新资源使用的密钥派生函数（ARGON2ID 或 PBKDF2-HMAC-SHA256）
+    pub key_derivation_function: String,
+    /// Argon2id内存开销（KiB）
+    pub argon2_memory_cost: u32,
+    /// Argon2id迭代次数
+    pub argon2_time_cost: u32,
+    /// Argon2id并行度
+    pub argon2_parallelism: u32,
+    /// 分段加密的分段大小（字节）
+    pub chunk_size: u32,
+    /// 是否用mlock锁定密钥等秘密所在的内存页，防止被换出到磁盘
+    pub lock_memory: bool,
+    /// 新资源的标题和描述是否用资源的数据密钥加密，加密后列表只显示占位符，也无法按明文搜索
+    pub encrypt_metadata: bool,
+    /// 签名清单的Ed25519种子（Base64编码的32字节），未设置时不维护签名清单
+    pub manifest_signing_key: Option<crate::crypto::SecretString>,
 }
 
 /// UKey配置
 #[derive(Deserialize, Debug, Clone)]
 #[allow(dead_code)]
 pub struct UKeyConfig {
+    /// UKey驱动，见 `ukey::create_provider`
     pub vendor: String,
+    /// UKey代理地址
     pub api_url: String,
+    /// 访问UKey代理的超时时间（毫秒）
+    pub timeout_ms: u64,
+    /// UKey挑战的有效期（秒）
+    pub challenge_ttl_secs: u64,
+    /// PKCS#11模块路径，`UKEY_VENDOR=pkcs11` 时必填
+    pub pkcs11_module: Option<String>,
+    /// PKCS#11令牌标签，为空时使用第一个插入的令牌
+    pub pkcs11_token_label: Option<String>,
+    /// PKCS#11用户PIN
+    pub pkcs11_pin: Option<crate::crypto::SecretString>,
+    /// 令牌上用于派生硬件码B的HMAC密钥标签
+    pub pkcs11_key_label: String,
+    /// 管理令牌，登记和撤销UKey或FIDO2安全密钥时可以代替已登记硬件的授权，登记第一个硬件时必须使用
+    pub admin_token: Option<crate::crypto::SecretString>,
+}
+
+/// FIDO2安全密钥配置
+#[derive(Deserialize, Debug, Clone)]
+#[allow(dead_code)]
+pub struct Fido2Config {
+    /// WebAuthn依赖方ID，即前端页面的域名
+    pub rp_id: String,
+    /// 依赖方名称，由浏览器显示给用户
+    pub rp_name: String,
+    /// 前端页面的源，客户端数据中的origin必须与之一致
+    pub origin: String,
 }
 
 /// TMDB配置
@@ -99,6 +149,8 @@ pub struct CorsConfig {
 pub struct UploadConfig {
     pub max_size: u64,
     pub temp_dir: String,
+    /// 加密容器在数据库中每个分块的字节数
+    pub storage_chunk_size: usize,
 }
 
 /// 应用配置
@@ -110,6 +162,7 @@ pub struct AppConfig {
     pub jwt: JwtConfig,
     pub encryption: EncryptionConfig,
     pub ukey: UKeyConfig,
+    pub fido2: Fido2Config,
     pub tmdb: TmdbConfig,
     pub image: ImageConfig,
     pub log: LogConfig,
@@ -138,13 +191,33 @@ impl AppConfig {
                 expiration: get_env_var("JWT_EXPIRATION").map_or("3600".to_string(), |v| v).parse::<u64>().map_err(|e| ConfigError::ParseError("JWT_EXPIRATION".to_string(), e.to_string()))?,
             },
             encryption: EncryptionConfig {
-                algorithm: get_env_var("ENCRYPTION_ALGORITHM").map_or("AES256GCM".to_string(), |v| v),
-                salt: get_env_var("ENCRYPTION_SALT").ok_or(ConfigError::MissingEnvVar("ENCRYPTION_SALT".to_string()))?,
+                algorithm: get_env_var("ENCRYPTION_ALGORITHM").map_or("AES256GCM".to_string(), |v| v.to_uppercase()),
+                salt: get_env_var("ENCRYPTION_SALT").unwrap_or_default(),
                 key_derivation_iterations: get_env_var("KEY_DERIVATION_ITERATIONS").map_or("100000".to_string(), |v| v).parse::<u32>().map_err(|e| ConfigError::ParseError("KEY_DERIVATION_ITERATIONS".to_string(), e.to_string()))?,
+                key_derivation_function: get_env_var("KEY_DERIVATION_FUNCTION").map_or("ARGON2ID".to_string(), |v| v.to_uppercase()),
+                argon2_memory_cost: get_env_var("ARGON2_MEMORY_COST").map_or("19456".to_string(), |v| v).parse::<u32>().map_err(|e| ConfigError::ParseError("ARGON2_MEMORY_COST".to_string(), e.to_string()))?,
+                argon2_time_cost: get_env_var("ARGON2_TIME_COST").map_or("2".to_string(), |v| v).parse::<u32>().map_err(|e| ConfigError::ParseError("ARGON2_TIME_COST".to_string(), e.to_string()))?,
+                argon2_parallelism: get_env_var("ARGON2_PARALLELISM").map_or("1".to_string(), |v| v).parse::<u32>().map_err(|e| ConfigError::ParseError("ARGON2_PARALLELISM".to_string(), e.to_string()))?,
+                chunk_size: get_env_var("ENCRYPTION_CHUNK_SIZE").map_or("65536".to_string(), |v| v).parse::<u32>().map_err(|e| ConfigError::ParseError("ENCRYPTION_CHUNK_SIZE".to_string(), e.to_string()))?,
+                lock_memory: get_env_var("LOCK_SECRET_MEMORY").map_or("false".to_string(), |v| v).parse::<bool>().map_err(|e| ConfigError::ParseError("LOCK_SECRET_MEMORY".to_string(), e.to_string()))?,
+                encrypt_metadata: get_env_var("ENCRYPT_METADATA").map_or("false".to_string(), |v| v).parse::<bool>().map_err(|e| ConfigError::ParseError("ENCRYPT_METADATA".to_string(), e.to_string()))?,
+                manifest_signing_key: get_env_var("MANIFEST_SIGNING_KEY").filter(|v| !v.is_empty()).map(crate::crypto::SecretString::new),
             },
             ukey: UKeyConfig {
                 vendor: get_env_var("UKEY_VENDOR").map_or("default".to_string(), |v| v),
                 api_url: get_env_var("UKEY_API_URL").map_or("http://localhost:8080/ukey".to_string(), |v| v),
+                timeout_ms: get_env_var("UKEY_TIMEOUT_MS").map_or("5000".to_string(), |v| v).parse::<u64>().map_err(|e| ConfigError::ParseError("UKEY_TIMEOUT_MS".to_string(), e.to_string()))?,
+                challenge_ttl_secs: get_env_var("UKEY_CHALLENGE_TTL_SECS").map_or("60".to_string(), |v| v).parse::<u64>().map_err(|e| ConfigError::ParseError("UKEY_CHALLENGE_TTL_SECS".to_string(), e.to_string()))?,
+                pkcs11_module: get_env_var("UKEY_PKCS11_MODULE").filter(|v| !v.is_empty()),
+                pkcs11_token_label: get_env_var("UKEY_PKCS11_TOKEN_LABEL").filter(|v| !v.is_empty()),
+                pkcs11_pin: get_env_var("UKEY_PKCS11_PIN").filter(|v| !v.is_empty()).map(crate::crypto::SecretString::new),
+                pkcs11_key_label: get_env_var("UKEY_PKCS11_KEY_LABEL").map_or("secretgallery-part-b".to_string(), |v| v),
+                admin_token: get_env_var("UKEY_ADMIN_TOKEN").filter(|v| !v.is_empty()).map(crate::crypto::SecretString::new),
+            },
+            fido2: Fido2Config {
+                rp_id: get_env_var("FIDO2_RP_ID").map_or("localhost".to_string(), |v| v),
+                rp_name: get_env_var("FIDO2_RP_NAME").map_or("SecretGallery".to_string(), |v| v),
+                origin: get_env_var("FIDO2_ORIGIN").map_or("http://localhost:3000".to_string(), |v| v),
             },
             tmdb: TmdbConfig {
                 api_key: get_env_var("TMDB_API_KEY").map_or("".to_string(), |v| v),
@@ -168,6 +241,7 @@ impl AppConfig {
             upload: UploadConfig {
                 max_size: get_env_var("UPLOAD_MAX_SIZE").map_or("104857600".to_string(), |v| v).parse::<u64>().map_err(|e| ConfigError::ParseError("UPLOAD_MAX_SIZE".to_string(), e.to_string()))?,
                 temp_dir: get_env_var("UPLOAD_TEMP_DIR").map_or("./uploads".to_string(), |v| v),
+                storage_chunk_size: get_env_var("UPLOAD_STORAGE_CHUNK_SIZE").map_or("1048576".to_string(), |v| v).parse::<usize>().map_err(|e| ConfigError::ParseError("UPLOAD_STORAGE_CHUNK_SIZE".to_string(), e.to_string()))?,
             },
         })
     }
@@ -179,8 +253,63 @@ impl AppConfig {
             return Err(ConfigError::MissingEnvVar("JWT_SECRET".to_string()));
         }
         
-        if self.encryption.salt.is_empty() {
-            return Err(ConfigError::MissingEnvVar("ENCRYPTION_SALT".to_string()));
+        if let Err(e) = crate::crypto::suite::find_suite(&self.encryption.algorithm) {
+            let supported = crate::crypto::suite::suite_names().join(", ");
+            return Err(ConfigError::ParseError("ENCRYPTION_ALGORITHM".to_string(), format!("{}，支持的算法: {}", e, supported)));
+        }
+        
+        if self.encryption.key_derivation_function == crate::crypto::key_management::KDF_ARGON2ID {
+            if self.encryption.argon2_memory_cost < crate::crypto::key_management::MIN_ARGON2_MEMORY_COST {
+                return Err(ConfigError::ParseError(
+                    "ARGON2_MEMORY_COST".to_string(),
+                    format!("内存开销不能低于{}KiB", crate::crypto::key_management::MIN_ARGON2_MEMORY_COST)
+                ));
+            }
+            
+            if self.encryption.argon2_time_cost < crate::crypto::key_management::MIN_ARGON2_TIME_COST {
+                return Err(ConfigError::ParseError(
+                    "ARGON2_TIME_COST".to_string(),
+                    format!("迭代次数不能低于{}", crate::crypto::key_management::MIN_ARGON2_TIME_COST)
+                ));
+            }
+        }
+        
+        if let Err(e) = crate::crypto::key_management::get_kdf_params(self) {
+            return Err(ConfigError::ParseError("KEY_DERIVATION_FUNCTION".to_string(), e.to_string()));
+        }
+        
+        if self.encryption.chunk_size == 0 {
+            return Err(ConfigError::ParseError("ENCRYPTION_CHUNK_SIZE".to_string(), "分段大小必须大于0".to_string()));
+        }
+        
+        if self.encryption.chunk_size > crate::crypto::container::MAX_CHUNK_SIZE {
+            return Err(ConfigError::ParseError("ENCRYPTION_CHUNK_SIZE".to_string(), "分段大小不能超过16MiB".to_string()));
+        }
+        
+        // 第一个分块需要包含完整的容器头部，读取头部时只读第一个分块
+        if self.upload.storage_chunk_size < crate::crypto::policy::MAX_HEADER_PROBE_LEN as usize {
+            return Err(ConfigError::ParseError(
+                "UPLOAD_STORAGE_CHUNK_SIZE".to_string(),
+                format!("存储分块不能小于{}字节", crate::crypto::policy::MAX_HEADER_PROBE_LEN)
+            ));
+        }
+        
+        if self.ukey.challenge_ttl_secs == 0 || self.ukey.challenge_ttl_secs > 3600 {
+            return Err(ConfigError::ParseError("UKEY_CHALLENGE_TTL_SECS".to_string(), "挑战有效期必须在1到3600秒之间".to_string()));
+        }
+        
+        if self.ukey.admin_token.as_ref().is_some_and(|token| token.expose_secret().len() < MIN_ADMIN_TOKEN_LEN) {
+            return Err(ConfigError::ParseError("UKEY_ADMIN_TOKEN".to_string(), format!("管理令牌至少需要{}个字符", MIN_ADMIN_TOKEN_LEN)));
+        }
+        
+        if self.fido2.rp_id.is_empty() || self.fido2.rp_id.contains(['/', ':']) {
+            return Err(ConfigError::ParseError("FIDO2_RP_ID".to_string(), "依赖方ID必须是不带协议和端口的域名".to_string()));
+        }
+        
+        if let Some(signing_key) = &self.encryption.manifest_signing_key {
+            if let Err(e) = crate::crypto::manifest::ManifestSigner::from_base64(signing_key.expose_secret()) {
+                return Err(ConfigError::ParseError("MANIFEST_SIGNING_KEY".to_string(), e.to_string()));
+            }
         }
         
         // 验证数据库URL
@@ -227,7 +356,7 @@ fn replace_env_placeholders(value: &str) -> String {
             let var_name = &result[left_pos + 2..right_pos];
             
             // 获取环境变量值
-            if let Some(var_value) = env::var(var_name).ok() {
+            if let Ok(var_value) = env::var(var_name) {
                 // 替换占位符
                 result.replace_range(left_pos..right_pos + 1, &var_value);
                 // 更新start位置
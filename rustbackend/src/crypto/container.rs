@@ -0,0 +1,506 @@
+use base64::{Engine as _, engine::general_purpose};
+use ring::{hkdf, hmac};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::crypto::envelope::WrappedKey;
+use crate::crypto::key_management::{self, Argon2Params, KdfParams, KeyInfo};
+use crate::crypto::stream::nonce_prefix_len;
+use crate::crypto::suite::{self, CipherSuite};
+
+/// 容器魔数
+pub const CONTAINER_MAGIC: &[u8; 4] = b"SGC\x00";
+
+/// 容器格式v1：分段密文的附加认证数据为空
+pub const CONTAINER_VERSION_V1: u8 = 1;
+
+/// 容器格式v2：分段密文的附加认证数据绑定资源UUID、媒体类型和容器版本
+pub const CONTAINER_VERSION_V2: u8 = 2;
+
+/// 新资源使用的容器格式版本
+pub const CURRENT_CONTAINER_VERSION: u8 = CONTAINER_VERSION_V2;
+
+/// 头部MAC长度（HMAC-SHA256）
+pub const HEADER_MAC_LEN: usize = 32;
+
+/// 允许的最大分段大小（16MiB）
+pub const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+/// 头部MAC子密钥的HKDF扩展信息
+const HEADER_MAC_INFO: &[u8] = b"SecretGallery/container-header-mac/v1";
+
+/// 分段附加认证数据的标签
+const RESOURCE_AAD_LABEL: &[u8] = b"SecretGallery/resource-aad/v2";
+
+/// 魔数到盐值长度字段之前的固定部分：魔数、版本、套件、KDF、组合方案、三个KDF参数
+const FIXED_PREFIX_LEN: usize = 4 + 1 + 1 + 1 + 1 + 12;
+
+/// 盐值长度范围
+const MIN_SALT_LEN: usize = 8;
+const MAX_SALT_LEN: usize = 64;
+
+/// 变长字段（密钥校验块、包装密钥）的最大长度
+const MAX_FIELD_LEN: usize = 256;
+
+/// 密钥派生函数编号
+const KDF_ID_PBKDF2_SHA256: u8 = 1;
+const KDF_ID_ARGON2ID: u8 = 2;
+
+/// 容器格式错误类型
+#[derive(Error, Debug)]
+pub enum ContainerError {
+    #[error("不是加密容器")]
+    InvalidMagic,
+    
+    #[error("不支持的容器版本: {0}")]
+    UnsupportedVersion(u8),
+    
+    #[error("不支持的加密套件: {0}")]
+    UnsupportedSuite(String),
+    
+    #[error("不支持的密钥派生函数: {0}")]
+    UnsupportedKdf(String),
+    
+    #[error("容器头部字段错误: {0}")]
+    InvalidField(&'static str),
+    
+    #[error("容器头部被截断")]
+    Truncated,
+    
+    #[error("密钥派生参数错误: {0}")]
+    KdfParams(#[from] key_management::KeyManagementError),
+    
+    #[error("读写错误: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// 绑定到密文的资源属性
+///
+/// v2容器的每个分段都以这些属性作为附加认证数据，把密文换到其他资源、
+/// 修改媒体类型或降低容器版本都会导致认证失败。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceContext {
+    pub uuid: uuid::Uuid,
+    pub media_type: String,
+}
+
+impl ResourceContext {
+    /// 创建资源属性
+    pub fn new(uuid: uuid::Uuid, media_type: &str) -> Self {
+        Self {
+            uuid,
+            media_type: media_type.to_string(),
+        }
+    }
+    
+    /// 指定容器版本的分段附加认证数据，v1容器为空
+    ///
+    /// 标签 ‖ 容器版本(1) ‖ UUID(16) ‖ 媒体类型长度(2) ‖ 媒体类型
+    pub fn aad(&self, version: u8) -> Result<Vec<u8>, ContainerError> {
+        if version == CONTAINER_VERSION_V1 {
+            return Ok(Vec::new());
+        }
+        
+        let media_type = self.media_type.as_bytes();
+        let media_type_len = u16::try_from(media_type.len())
+            .map_err(|_| ContainerError::InvalidField("media_type"))?;
+        
+        let mut aad = Vec::with_capacity(RESOURCE_AAD_LABEL.len() + 1 + 16 + 2 + media_type.len());
+        aad.extend_from_slice(RESOURCE_AAD_LABEL);
+        aad.push(version);
+        aad.extend_from_slice(self.uuid.as_bytes());
+        aad.extend_from_slice(&media_type_len.to_be_bytes());
+        aad.extend_from_slice(media_type);
+        Ok(aad)
+    }
+}
+
+/// 加密容器头部
+///
+/// 二进制布局（整数均为大端序）：
+/// 魔数(4) ‖ 版本(1) ‖ 套件(1) ‖ KDF(1) ‖ 组合方案(1) ‖ KDF参数(3×4) ‖
+/// 盐值长度(1) ‖ 盐值 ‖ 校验块长度(2) ‖ 校验块 ‖ 包装密钥长度(2) ‖ 包装密钥 ‖
+/// 基础nonce（套件nonce长度减5） ‖ 分段大小(4) ‖ 头部MAC(32)。
+/// 头部之后是分段密文，头部MAC由数据密钥派生的子密钥计算，覆盖MAC之前的全部字节。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerHeader {
+    pub version: u8,
+    pub algorithm: String,
+    pub kdf_params: KdfParams,
+    pub combiner_version: u32,
+    pub salt: Vec<u8>,
+    pub key_check: Vec<u8>,
+    pub wrapped_key: Vec<u8>,
+    pub nonce_prefix: Vec<u8>,
+    pub chunk_size: u32,
+}
+
+/// 根据算法名称查找加密套件
+fn suite_by_name(algorithm: &str) -> Result<&'static dyn CipherSuite, ContainerError> {
+    suite::find_suite(algorithm).map_err(|_| ContainerError::UnsupportedSuite(algorithm.to_string()))
+}
+
+/// 根据套件编号查找加密套件
+fn suite_by_id(id: u8) -> Result<&'static dyn CipherSuite, ContainerError> {
+    suite::find_suite_by_id(id).map_err(|_| ContainerError::UnsupportedSuite(id.to_string()))
+}
+
+/// 由数据密钥派生头部MAC密钥
+fn header_mac_key(data_key: &[u8]) -> hmac::Key {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]).extract(data_key);
+    let okm = prk.expand(&[HEADER_MAC_INFO], hmac::HMAC_SHA256)
+        .expect("HMAC-SHA256密钥长度固定，HKDF扩展不会失败");
+    hmac::Key::from(okm)
+}
+
+/// 顺序读取头部字段的游标
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ContainerError> {
+        let end = self.position.checked_add(len).ok_or(ContainerError::Truncated)?;
+        let field = self.bytes.get(self.position..end).ok_or(ContainerError::Truncated)?;
+        self.position = end;
+        Ok(field)
+    }
+    
+    fn u8(&mut self) -> Result<u8, ContainerError> {
+        Ok(self.take(1)?[0])
+    }
+    
+    fn u16(&mut self) -> Result<u16, ContainerError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().map_err(|_| ContainerError::Truncated)?))
+    }
+    
+    fn u32(&mut self) -> Result<u32, ContainerError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().map_err(|_| ContainerError::Truncated)?))
+    }
+}
+
+impl ContainerHeader {
+    /// 由密钥信息、包装后的数据密钥和分段参数构造头部
+    pub fn new(
+        key_info: &KeyInfo,
+        wrapped_key: &WrappedKey,
+        nonce_prefix: Vec<u8>,
+        chunk_size: u32
+    ) -> Result<Self, ContainerError> {
+        let key_check = key_info.key_check.as_deref()
+            .ok_or(ContainerError::InvalidField("key_check"))?;
+        
+        Ok(Self {
+            version: CURRENT_CONTAINER_VERSION,
+            algorithm: key_info.algorithm.clone(),
+            kdf_params: key_info.kdf_params()?,
+            combiner_version: key_info.combiner_version,
+            salt: general_purpose::STANDARD.decode(&key_info.salt).map_err(|_| ContainerError::InvalidField("salt"))?,
+            key_check: general_purpose::STANDARD.decode(key_check).map_err(|_| ContainerError::InvalidField("key_check"))?,
+            wrapped_key: wrapped_key.to_bytes().map_err(|_| ContainerError::InvalidField("wrapped_key"))?,
+            nonce_prefix,
+            chunk_size,
+        })
+    }
+    
+    /// 转换为密钥信息，复用 `key_management` 的密钥派生和校验
+    pub fn key_info(&self) -> KeyInfo {
+        let (iteration_count, argon2_params) = match self.kdf_params {
+            KdfParams::Pbkdf2 { iteration_count } => (iteration_count, None),
+            KdfParams::Argon2id(params) => (0, Some(params)),
+        };
+        
+        KeyInfo {
+            algorithm: self.algorithm.clone(),
+            salt: general_purpose::STANDARD.encode(&self.salt),
+            iteration_count,
+            key_hash: String::new(),
+            ukey_info: String::new(),
+            combiner_version: self.combiner_version,
+            kdf: self.kdf_params.kdf_name().to_string(),
+            argon2_params,
+            key_check: Some(general_purpose::STANDARD.encode(&self.key_check)),
+        }
+    }
+    
+    /// 头部中保存的包装数据密钥
+    pub fn wrapped_key(&self) -> Result<WrappedKey, ContainerError> {
+        WrappedKey::from_bytes(&self.wrapped_key).map_err(|_| ContainerError::InvalidField("wrapped_key"))
+    }
+    
+    /// 编码MAC之前的头部字节
+    fn encode_unauthenticated(&self) -> Result<Vec<u8>, ContainerError> {
+        let (kdf_id, params) = match self.kdf_params {
+            KdfParams::Pbkdf2 { iteration_count } => (KDF_ID_PBKDF2_SHA256, [iteration_count, 0, 0]),
+            KdfParams::Argon2id(params) => (KDF_ID_ARGON2ID, [params.memory_cost, params.time_cost, params.parallelism]),
+        };
+        let combiner_version = u8::try_from(self.combiner_version)
+            .map_err(|_| ContainerError::InvalidField("combiner_version"))?;
+        
+        if !(MIN_SALT_LEN..=MAX_SALT_LEN).contains(&self.salt.len()) {
+            return Err(ContainerError::InvalidField("salt"));
+        }
+        if self.key_check.len() > MAX_FIELD_LEN {
+            return Err(ContainerError::InvalidField("key_check"));
+        }
+        if self.wrapped_key.len() > MAX_FIELD_LEN {
+            return Err(ContainerError::InvalidField("wrapped_key"));
+        }
+        if self.chunk_size == 0 || self.chunk_size > MAX_CHUNK_SIZE {
+            return Err(ContainerError::InvalidField("chunk_size"));
+        }
+        let suite = suite_by_name(&self.algorithm)?;
+        if self.nonce_prefix.len() != nonce_prefix_len(suite) {
+            return Err(ContainerError::InvalidField("nonce_prefix"));
+        }
+        
+        let mut bytes = Vec::with_capacity(FIXED_PREFIX_LEN + 128);
+        bytes.extend_from_slice(CONTAINER_MAGIC);
+        bytes.push(self.version);
+        bytes.push(suite.id());
+        bytes.push(kdf_id);
+        bytes.push(combiner_version);
+        for param in params {
+            bytes.extend_from_slice(&param.to_be_bytes());
+        }
+        bytes.push(self.salt.len() as u8);
+        bytes.extend_from_slice(&self.salt);
+        bytes.extend_from_slice(&(self.key_check.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.key_check);
+        bytes.extend_from_slice(&(self.wrapped_key.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(&self.wrapped_key);
+        bytes.extend_from_slice(&self.nonce_prefix);
+        bytes.extend_from_slice(&self.chunk_size.to_be_bytes());
+        
+        Ok(bytes)
+    }
+    
+    /// 编码头部并用数据密钥计算头部MAC
+    pub fn to_bytes(&self, data_key: &[u8]) -> Result<Vec<u8>, ContainerError> {
+        let mut bytes = self.encode_unauthenticated()?;
+        let tag = hmac::sign(&header_mac_key(data_key), &bytes);
+        bytes.extend_from_slice(tag.as_ref());
+        Ok(bytes)
+    }
+    
+    /// 解析容器开头的头部，返回头部和头部长度（含MAC）
+    ///
+    /// 此时尚未验证头部MAC，调用方在得到数据密钥后必须调用 `verify_mac`。
+    pub fn parse(bytes: &[u8]) -> Result<(Self, usize), ContainerError> {
+        let mut cursor = Cursor { bytes, position: 0 };
+        
+        if cursor.take(CONTAINER_MAGIC.len())? != CONTAINER_MAGIC {
+            return Err(ContainerError::InvalidMagic);
+        }
+        
+        let version = cursor.u8()?;
+        if version != CONTAINER_VERSION_V1 && version != CONTAINER_VERSION_V2 {
+            return Err(ContainerError::UnsupportedVersion(version));
+        }
+        
+        let suite = suite_by_id(cursor.u8()?)?;
+        let kdf_id = cursor.u8()?;
+        let combiner_version = cursor.u8()? as u32;
+        let params = [cursor.u32()?, cursor.u32()?, cursor.u32()?];
+        let kdf_params = match kdf_id {
+            KDF_ID_PBKDF2_SHA256 => KdfParams::Pbkdf2 { iteration_count: params[0] },
+            KDF_ID_ARGON2ID => KdfParams::Argon2id(Argon2Params {
+                memory_cost: params[0],
+                time_cost: params[1],
+                parallelism: params[2],
+            }),
+            other => return Err(ContainerError::UnsupportedKdf(other.to_string())),
+        };
+        // 头部尚未认证，执行派生前先检查参数范围
+        key_management::check_kdf_params(&kdf_params)?;
+        
+        let salt_len = cursor.u8()? as usize;
+        if !(MIN_SALT_LEN..=MAX_SALT_LEN).contains(&salt_len) {
+            return Err(ContainerError::InvalidField("salt"));
+        }
+        let salt = cursor.take(salt_len)?.to_vec();
+        
+        let key_check_len = cursor.u16()? as usize;
+        if key_check_len > MAX_FIELD_LEN {
+            return Err(ContainerError::InvalidField("key_check"));
+        }
+        let key_check = cursor.take(key_check_len)?.to_vec();
+        
+        let wrapped_key_len = cursor.u16()? as usize;
+        if wrapped_key_len > MAX_FIELD_LEN {
+            return Err(ContainerError::InvalidField("wrapped_key"));
+        }
+        let wrapped_key = cursor.take(wrapped_key_len)?.to_vec();
+        
+        let nonce_prefix = cursor.take(nonce_prefix_len(suite))?.to_vec();
+        
+        let chunk_size = cursor.u32()?;
+        if chunk_size == 0 || chunk_size > MAX_CHUNK_SIZE {
+            return Err(ContainerError::InvalidField("chunk_size"));
+        }
+        
+        cursor.take(HEADER_MAC_LEN)?;
+        
+        let header = Self {
+            version,
+            algorithm: suite.name().to_string(),
+            kdf_params,
+            combiner_version,
+            salt,
+            key_check,
+            wrapped_key,
+            nonce_prefix,
+            chunk_size,
+        };
+        
+        Ok((header, cursor.position))
+    }
+    
+    /// 用数据密钥验证头部MAC，`header_bytes` 为 `parse` 所解析的完整头部
+    pub fn verify_mac(header_bytes: &[u8], data_key: &[u8]) -> bool {
+        if header_bytes.len() < HEADER_MAC_LEN {
+            return false;
+        }
+        
+        let (authenticated, tag) = header_bytes.split_at(header_bytes.len() - HEADER_MAC_LEN);
+        hmac::verify(&header_mac_key(data_key), authenticated, tag).is_ok()
+    }
+}
+
+/// 数据是否以容器魔数开头
+#[allow(dead_code)]
+pub fn is_container(data: &[u8]) -> bool {
+    data.starts_with(CONTAINER_MAGIC)
+}
+
+/// 从异步读取器中读出完整的容器头部字节（含MAC）
+pub async fn read_header_bytes<R>(reader: &mut R) -> Result<Vec<u8>, ContainerError>
+where
+    R: AsyncRead + Unpin,
+{
+    async fn read_more<R: AsyncRead + Unpin>(reader: &mut R, bytes: &mut Vec<u8>, len: usize) -> Result<(), ContainerError> {
+        let start = bytes.len();
+        bytes.resize(start + len, 0);
+        reader.read_exact(&mut bytes[start..]).await.map(|_| ()).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => ContainerError::Truncated,
+            _ => ContainerError::IoError(e),
+        })
+    }
+    
+    let mut bytes = Vec::new();
+    
+    // 固定部分和盐值长度
+    read_more(reader, &mut bytes, FIXED_PREFIX_LEN + 1).await?;
+    if &bytes[..CONTAINER_MAGIC.len()] != CONTAINER_MAGIC {
+        return Err(ContainerError::InvalidMagic);
+    }
+    let suite = suite_by_id(bytes[CONTAINER_MAGIC.len() + 1])?;
+    let salt_len = bytes[FIXED_PREFIX_LEN] as usize;
+    
+    // 盐值和校验块长度
+    read_more(reader, &mut bytes, salt_len + 2).await?;
+    let key_check_len = u16::from_be_bytes([bytes[bytes.len() - 2], bytes[bytes.len() - 1]]) as usize;
+    if key_check_len > MAX_FIELD_LEN {
+        return Err(ContainerError::InvalidField("key_check"));
+    }
+    
+    // 校验块和包装密钥长度
+    read_more(reader, &mut bytes, key_check_len + 2).await?;
+    let wrapped_key_len = u16::from_be_bytes([bytes[bytes.len() - 2], bytes[bytes.len() - 1]]) as usize;
+    if wrapped_key_len > MAX_FIELD_LEN {
+        return Err(ContainerError::InvalidField("wrapped_key"));
+    }
+    
+    // 包装密钥、基础nonce、分段大小和头部MAC
+    read_more(reader, &mut bytes, wrapped_key_len + nonce_prefix_len(suite) + 4 + HEADER_MAC_LEN).await?;
+    
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::secret::SecretKey;
+    use crate::crypto::envelope::{generate_data_key, wrap_data_key};
+    use crate::crypto::key_management::{generate_key_info, generate_salt, MIN_ARGON2_MEMORY_COST, MIN_ARGON2_TIME_COST};
+    
+    fn test_header() -> (ContainerHeader, SecretKey) {
+        let kdf_params = KdfParams::Argon2id(Argon2Params { memory_cost: MIN_ARGON2_MEMORY_COST, time_cost: MIN_ARGON2_TIME_COST, parallelism: 1 });
+        let (key_info, kek) = generate_key_info("AES256GCM", &generate_salt(), &kdf_params, "key_a", "ukey_b").unwrap();
+        let data_key = generate_data_key().unwrap();
+        let wrapped_key = wrap_data_key(&kek, &data_key).unwrap();
+        let header = ContainerHeader::new(&key_info, &wrapped_key, vec![3u8; 7], 4096).unwrap();
+        (header, data_key)
+    }
+    
+    #[test]
+    fn test_header_roundtrip() {
+        let (header, data_key) = test_header();
+        let mut bytes = header.to_bytes(&data_key).unwrap();
+        let header_len = bytes.len();
+        bytes.extend_from_slice(b"ciphertext");
+        
+        assert!(is_container(&bytes));
+        let (parsed, parsed_len) = ContainerHeader::parse(&bytes).unwrap();
+        assert_eq!(parsed, header);
+        assert_eq!(parsed_len, header_len);
+        assert!(ContainerHeader::verify_mac(&bytes[..parsed_len], &data_key));
+        
+        // 密钥信息可以从头部还原
+        let key_info = parsed.key_info();
+        assert!(key_management::get_key_from_info(&key_info, "key_a", "ukey_b").is_ok());
+        
+        // 被篡改的头部无法通过MAC验证
+        let mut tampered = bytes.clone();
+        tampered[header_len - HEADER_MAC_LEN - 1] ^= 0x01;
+        let (_, tampered_len) = ContainerHeader::parse(&tampered).unwrap();
+        assert!(!ContainerHeader::verify_mac(&tampered[..tampered_len], &data_key));
+        assert!(!ContainerHeader::verify_mac(&bytes[..parsed_len], &[0u8; 32]));
+    }
+    
+    #[test]
+    fn test_parse_rejects_invalid_headers() {
+        let (header, data_key) = test_header();
+        let bytes = header.to_bytes(&data_key).unwrap();
+        
+        assert!(matches!(ContainerHeader::parse(b"not a container"), Err(ContainerError::InvalidMagic)));
+        assert!(matches!(ContainerHeader::parse(&bytes[..bytes.len() - 1]), Err(ContainerError::Truncated)));
+        
+        let mut future_version = bytes.clone();
+        future_version[4] = 99;
+        assert!(matches!(ContainerHeader::parse(&future_version), Err(ContainerError::UnsupportedVersion(99))));
+        
+        // 超出范围的KDF参数在派生前被拒绝
+        let mut huge_memory = bytes.clone();
+        huge_memory[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(ContainerHeader::parse(&huge_memory), Err(ContainerError::KdfParams(_))));
+    }
+    
+    #[test]
+    fn test_resource_context_aad() {
+        let context = ResourceContext::new(uuid::Uuid::new_v4(), "image/jpeg");
+        
+        // v1容器不绑定资源属性
+        assert!(context.aad(CONTAINER_VERSION_V1).unwrap().is_empty());
+        
+        // 资源UUID、媒体类型和容器版本都会改变附加认证数据
+        let aad = context.aad(CONTAINER_VERSION_V2).unwrap();
+        assert_ne!(aad, ResourceContext::new(uuid::Uuid::new_v4(), "image/jpeg").aad(CONTAINER_VERSION_V2).unwrap());
+        assert_ne!(aad, ResourceContext::new(context.uuid, "image/png").aad(CONTAINER_VERSION_V2).unwrap());
+        assert_ne!(aad, context.aad(3).unwrap());
+    }
+    
+    #[tokio::test]
+    async fn test_read_header_bytes() {
+        let (header, data_key) = test_header();
+        let header_bytes = header.to_bytes(&data_key).unwrap();
+        let mut bytes = header_bytes.clone();
+        bytes.extend_from_slice(b"ciphertext");
+        
+        let mut reader = bytes.as_slice();
+        let read = read_header_bytes(&mut reader).await.unwrap();
+        assert_eq!(read, header_bytes);
+        assert_eq!(reader, b"ciphertext");
+    }
+}
@@ -1,15 +1,22 @@
-use ring::aead;
 use ring::error::Unspecified;
 use base64::{Engine as _, engine::general_purpose};
 use thiserror::Error;
-use tracing::{error, debug};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tracing::debug;
 
-use crate::crypto::key_management::{get_key_from_info, verify_key_hash, generate_actual_key};
+use crate::crypto::key_management::{get_key_from_info, derive_key_from_info, KeyInfo, KeyManagementError};
 use crate::crypto::encode::EncryptionInfo;
+use crate::crypto::stream::{self, StreamError, StreamOpener, DecryptReader};
+use crate::crypto::envelope::{self, generate_data_key, unwrap_data_key, EnvelopeError, WrappedKey};
+use crate::crypto::container::{self, ContainerHeader, ResourceContext};
+use crate::crypto::suite::find_suite;
+use crate::crypto::secret::SecretKey;
+use crate::crypto::metadata::{open_metadata, ResourceMetadata};
 
 /// 资源解码错误类型
 #[derive(Error, Debug)]
 #[allow(dead_code)]
+#[allow(clippy::enum_variant_names)]
 pub enum DecodeError {
     #[error("解密算法错误: {0}")]
     AlgorithmError(String),
@@ -43,25 +50,305 @@ pub enum DecodeError {
     
     #[error("不支持的加密算法: {0}")]
     UnsupportedAlgorithmError(String),
+    
+    #[error("分段解密错误: {0}")]
+    StreamError(#[from] StreamError),
+    
+    #[error("读写错误: {0}")]
+    IoError(#[from] std::io::Error),
+    
+    #[error("信封加密错误: {0}")]
+    EnvelopeError(#[from] envelope::EnvelopeError),
+    
+    #[error("解密失败：密钥错误或数据已损坏")]
+    AuthenticationFailed,
+    
+    #[error("加密容器错误: {0}")]
+    ContainerError(#[from] container::ContainerError),
 }
 
 /// 解码资源
+///
+/// `encryption_info_json` 为空时，`encrypted_data` 为 `encode_resource` 返回的加密容器，
+/// 解密参数和包装的数据密钥都从容器头部读取，头部MAC在解包数据密钥后校验，
+/// v2容器还要求 `context` 与加密时的资源UUID和媒体类型一致。
+/// 否则按旧版格式处理：加密参数从加密信息JSON中读取，分段格式逐段校验认证标签，
+/// 旧版整段格式的认证标签和IV从加密信息中读取，信封加密的资源需要提供
+/// `encryption_keys` 中保存的包装数据密钥。
+///
+/// 密钥错误和数据损坏都返回 `DecodeError::AuthenticationFailed`，且都会完整执行密钥派生和解密，
+/// 调用方无法通过错误类型或耗时区分两者。
 pub fn decode_resource(
     encrypted_data: &[u8],
     encryption_info_json: &str,
+    wrapped_key: Option<&str>,
+    context: &ResourceContext,
     key_part_a: &str,
     ukey_part_b: &str
 ) -> Result<Vec<u8>, DecodeError> {
     debug!("加密数据长度: {} 字节", encrypted_data.len());
     
+    if encryption_info_json.is_empty() {
+        let (decrypted_data, _) = decode_container(encrypted_data, context, key_part_a, ukey_part_b)?;
+        return Ok(decrypted_data);
+    }
+    
     // 解析加密信息
     let encryption_info: EncryptionInfo = serde_json::from_str(encryption_info_json)?;
     debug!("解析加密信息成功");
     
-    // 获取密钥信息
-    let key_info = &encryption_info.key_info;
-    debug!("获取密钥信息成功");
+    // 获取加密密钥，密钥验证结果留到解密之后再处理
+    let (key, key_verified) = resolve_data_key(&encryption_info, wrapped_key, key_part_a, ukey_part_b)?;
+    
+    let result = match encryption_info.chunk_size {
+        Some(chunk_size) => create_stream_opener(&encryption_info, &key).and_then(|mut opener| {
+            stream::open_all(&mut opener, encrypted_data, chunk_size as usize).map_err(DecodeError::from)
+        }),
+        None => decode_legacy(encrypted_data, &encryption_info, &key),
+    };
+    let decrypted_data = authenticate(result, key_verified)?;
+    debug!("解密完成，解密后数据长度: {} 字节", decrypted_data.len());
+    
+    Ok(decrypted_data)
+}
+
+/// 解码资源，同时用数据密钥解密元数据
+///
+/// `encrypted_metadata` 为空时与 `decode_resource` 相同。加密元数据只用于容器格式的资源，
+/// 元数据无法解密时与数据损坏一样返回 `DecodeError::AuthenticationFailed`。
+pub fn decode_resource_with_metadata(
+    encrypted_data: &[u8],
+    encryption_info_json: &str,
+    wrapped_key: Option<&str>,
+    context: &ResourceContext,
+    encrypted_metadata: Option<&[u8]>,
+    key_part_a: &str,
+    ukey_part_b: &str
+) -> Result<(Vec<u8>, Option<ResourceMetadata>), DecodeError> {
+    let Some(encrypted_metadata) = encrypted_metadata else {
+        let decrypted_data = decode_resource(encrypted_data, encryption_info_json, wrapped_key, context, key_part_a, ukey_part_b)?;
+        return Ok((decrypted_data, None));
+    };
+    
+    if !encryption_info_json.is_empty() {
+        return Err(DecodeError::EncryptionInfoError("旧版格式的资源不支持加密元数据".to_string()));
+    }
+    
+    let (decrypted_data, data_key) = decode_container(encrypted_data, context, key_part_a, ukey_part_b)?;
+    let metadata = decode_metadata(&data_key, context, encrypted_metadata)?;
+    
+    Ok((decrypted_data, Some(metadata)))
+}
+
+/// 用数据密钥解密元数据，无法解密时返回 `DecodeError::AuthenticationFailed`
+pub fn decode_metadata(
+    data_key: &SecretKey,
+    context: &ResourceContext,
+    encrypted_metadata: &[u8]
+) -> Result<ResourceMetadata, DecodeError> {
+    open_metadata(data_key, context, encrypted_metadata)
+        .map_err(|_| DecodeError::AuthenticationFailed)
+}
+
+/// 解码加密容器，同时返回数据密钥
+fn decode_container(
+    container: &[u8],
+    context: &ResourceContext,
+    key_part_a: &str,
+    ukey_part_b: &str
+) -> Result<(Vec<u8>, SecretKey), DecodeError> {
+    let (header, header_len) = ContainerHeader::parse(container)?;
+    let (key, key_verified) = resolve_container_key(&header, &container[..header_len], key_part_a, ukey_part_b)?;
+    
+    let result = create_container_opener(&header, context, &key).and_then(|mut opener| {
+        stream::open_all(&mut opener, &container[header_len..], header.chunk_size as usize).map_err(DecodeError::from)
+    });
+    let decrypted_data = authenticate(result, key_verified)?;
+    debug!("解密完成，解密后数据长度: {} 字节", decrypted_data.len());
+    
+    Ok((decrypted_data, key))
+}
+
+/// 流式解码资源
+///
+/// 从 `reader` 读取加密容器或旧版分段密文，逐段校验并解密后写入 `writer`，内存中最多缓存一个分段。
+/// 旧版整段格式没有分段信息，只能整体读入后解密。返回写入的明文长度。
+#[allow(clippy::too_many_arguments)]
+pub async fn decode_resource_stream<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    encryption_info_json: &str,
+    wrapped_key: Option<&str>,
+    context: &ResourceContext,
+    key_part_a: &str,
+    ukey_part_b: &str
+) -> Result<u64, DecodeError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let written = if encryption_info_json.is_empty() {
+        // 先读出容器头部，分段密文随后逐段读取
+        let header_bytes = container::read_header_bytes(reader).await?;
+        let (header, _) = ContainerHeader::parse(&header_bytes)?;
+        let (key, key_verified) = resolve_container_key(&header, &header_bytes, key_part_a, ukey_part_b)?;
+        
+        let result = async {
+            let opener = create_container_opener(&header, context, &key)?;
+            let mut decrypt_reader = DecryptReader::new(reader, opener, header.chunk_size as usize)?;
+            Ok(tokio::io::copy(&mut decrypt_reader, writer).await?)
+        }.await;
+        authenticate(result, key_verified)?
+    } else {
+        // 解析加密信息
+        let encryption_info: EncryptionInfo = serde_json::from_str(encryption_info_json)?;
+        
+        // 获取加密密钥，密钥验证结果留到解密之后再处理
+        let (key, key_verified) = resolve_data_key(&encryption_info, wrapped_key, key_part_a, ukey_part_b)?;
+        
+        let result = match encryption_info.chunk_size {
+            Some(chunk_size) => {
+                async {
+                    let opener = create_stream_opener(&encryption_info, &key)?;
+                    let mut decrypt_reader = DecryptReader::new(reader, opener, chunk_size as usize)?;
+                    Ok(tokio::io::copy(&mut decrypt_reader, writer).await?)
+                }.await
+            }
+            None => {
+                async {
+                    let mut encrypted_data = Vec::new();
+                    reader.read_to_end(&mut encrypted_data).await?;
+                    let decrypted_data = decode_legacy(&encrypted_data, &encryption_info, &key)?;
+                    writer.write_all(&decrypted_data).await?;
+                    Ok(decrypted_data.len() as u64)
+                }.await
+            }
+        };
+        authenticate(result, key_verified)?
+    };
+    writer.flush().await?;
+    debug!("流式解密完成，明文长度: {} 字节", written);
     
+    Ok(written)
+}
+
+/// 用密钥A和硬件码B解出加密容器的数据密钥，用于生成恢复包装
+///
+/// 只读取容器头部，不解密数据。密钥错误返回 `AuthenticationFailed`。
+pub fn unwrap_container_data_key(
+    encrypted_data: &[u8],
+    key_part_a: &str,
+    ukey_part_b: &str
+) -> Result<SecretKey, DecodeError> {
+    let (header, header_len) = ContainerHeader::parse(encrypted_data)?;
+    let (data_key, key_verified) = resolve_container_key(&header, &encrypted_data[..header_len], key_part_a, ukey_part_b)?;
+    if !key_verified {
+        return Err(DecodeError::AuthenticationFailed);
+    }
+    
+    Ok(data_key)
+}
+
+/// 获取加密容器的数据密钥和验证结果
+///
+/// 由头部中的密钥信息派生KEK，解包头部中的数据密钥，再用数据密钥校验头部MAC。
+/// 任何一步失败都不提前返回，与 `resolve_data_key` 相同。
+fn resolve_container_key(
+    header: &ContainerHeader,
+    header_bytes: &[u8],
+    key_part_a: &str,
+    ukey_part_b: &str
+) -> Result<(SecretKey, bool), DecodeError> {
+    let (kek, key_verified) = derive_key_from_info(&header.key_info(), key_part_a, ukey_part_b)?;
+    
+    let (data_key, key_verified) = match unwrap_data_key(&kek, &header.wrapped_key()?) {
+        Ok(data_key) => (data_key, key_verified),
+        Err(EnvelopeError::UnwrapError) => (generate_data_key()?, false),
+        Err(e) => return Err(e.into()),
+    };
+    let header_verified = ContainerHeader::verify_mac(header_bytes, &data_key);
+    
+    Ok((data_key, key_verified & header_verified))
+}
+
+/// 获取解密数据使用的密钥和密钥验证结果
+///
+/// 信封加密的资源先由密钥A和硬件码B派生KEK，再解包数据密钥；
+/// 旧资源直接使用派生出的密钥加密数据。验证失败时不提前返回，
+/// 解包失败时用随机密钥继续解密，使密钥错误与数据损坏的处理流程一致。
+fn resolve_data_key(
+    encryption_info: &EncryptionInfo,
+    wrapped_key: Option<&str>,
+    key_part_a: &str,
+    ukey_part_b: &str
+) -> Result<(SecretKey, bool), DecodeError> {
+    let (key, key_verified) = derive_key_from_info(&encryption_info.key_info, key_part_a, ukey_part_b)?;
+    
+    if !encryption_info.envelope {
+        return Ok((key, key_verified));
+    }
+    
+    let wrapped_key = wrapped_key
+        .ok_or_else(|| DecodeError::EncryptionInfoError("缺少包装的数据密钥".to_string()))?;
+    match unwrap_data_key(&key, &WrappedKey::from_json(wrapped_key)?) {
+        Ok(data_key) => Ok((data_key, key_verified)),
+        Err(EnvelopeError::UnwrapError) => Ok((generate_data_key()?, false)),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 是否为认证失败（密钥错误、密文被篡改或截断）
+fn is_authentication_error(err: &DecodeError) -> bool {
+    fn is_stream_authentication_error(err: &StreamError) -> bool {
+        matches!(err, StreamError::ChunkAuthentication | StreamError::Truncated | StreamError::TrailingData)
+    }
+    
+    match err {
+        DecodeError::DecryptionError(_) | DecodeError::AuthenticationFailed => true,
+        DecodeError::StreamError(err) => is_stream_authentication_error(err),
+        DecodeError::IoError(err) => err.get_ref()
+            .and_then(|inner| inner.downcast_ref::<StreamError>())
+            .is_some_and(is_stream_authentication_error),
+        _ => false,
+    }
+}
+
+/// 合并密钥验证结果和解密结果，密钥错误与数据损坏返回相同的错误
+fn authenticate<T>(result: Result<T, DecodeError>, key_verified: bool) -> Result<T, DecodeError> {
+    match result {
+        Ok(value) if key_verified => Ok(value),
+        Ok(_) => Err(DecodeError::AuthenticationFailed),
+        Err(err) if is_authentication_error(&err) => Err(DecodeError::AuthenticationFailed),
+        Err(err) => Err(err),
+    }
+}
+
+/// 根据加密信息创建分段解密器
+fn create_stream_opener(encryption_info: &EncryptionInfo, key: &[u8]) -> Result<StreamOpener, DecodeError> {
+    let nonce_prefix = general_purpose::STANDARD.decode(&encryption_info.iv)?;
+    
+    StreamOpener::new(&encryption_info.algorithm, key, &nonce_prefix).map_err(|e| match e {
+        StreamError::UnsupportedAlgorithm(algorithm) => DecodeError::UnsupportedAlgorithmError(algorithm),
+        StreamError::NonceLength => DecodeError::IVLengthError,
+        other => DecodeError::StreamError(other),
+    })
+}
+
+/// 根据容器头部和资源属性创建分段解密器
+fn create_container_opener(header: &ContainerHeader, context: &ResourceContext, key: &[u8]) -> Result<StreamOpener, DecodeError> {
+    let opener = StreamOpener::new(&header.algorithm, key, &header.nonce_prefix).map_err(|e| match e {
+        StreamError::UnsupportedAlgorithm(algorithm) => DecodeError::UnsupportedAlgorithmError(algorithm),
+        other => DecodeError::StreamError(other),
+    })?;
+    Ok(opener.with_aad(context.aad(header.version)?))
+}
+
+/// 解码旧版整段加密格式
+fn decode_legacy(
+    encrypted_data: &[u8],
+    encryption_info: &EncryptionInfo,
+    key: &[u8]
+) -> Result<Vec<u8>, DecodeError> {
     // 解码IV
     let iv = general_purpose::STANDARD.decode(&encryption_info.iv)?;
     debug!("解码IV成功，长度: {} 字节", iv.len());
@@ -78,41 +365,26 @@ pub fn decode_resource(
         return Err(DecodeError::TagLengthError);
     }
     
-    // 获取加密密钥
-    let key = get_key_from_info(key_info, key_part_a, ukey_part_b)?;
-    debug!("获取解密密钥成功");
+    // 根据算法查找加密套件
+    let suite = find_suite(&encryption_info.algorithm)
+        .map_err(|_| DecodeError::UnsupportedAlgorithmError(encryption_info.algorithm.clone()))?;
     
-    // 根据算法选择解密算法
-    let encryption_algorithm = match encryption_info.algorithm.as_str() {
-        "AES256GCM" => &aead::AES_256_GCM,
-        "CHACHA20POLY1305" => &aead::CHACHA20_POLY1305,
-        _ => return Err(DecodeError::UnsupportedAlgorithmError(encryption_info.algorithm.clone())),
-    };
-    
-    // 创建UnboundKey
-    let unbound_key = aead::UnboundKey::new(encryption_algorithm, &key)
+    // 创建解密器
+    let cipher = suite.cipher(key)
         .map_err(|e| DecodeError::AlgorithmError(format!("创建密钥失败: {:?}", e)))?;
     
-    // 创建LessSafeKey用于解密
-    let opening_key = aead::LessSafeKey::new(unbound_key);
-    
-    // 创建AEAD操作
-    let aad = Vec::new();
-    let nonce = aead::Nonce::assume_unique_for_key(iv.try_into().map_err(|_| DecodeError::IVLengthError)?);
-    
     // 准备解密数据
     let mut data_to_decrypt = Vec::from(encrypted_data);
     data_to_decrypt.extend_from_slice(&tag);
     
-    // 解密数据
-    let decrypted_data = opening_key.open_in_place(nonce, aead::Aad::from(&aad), &mut data_to_decrypt)?;
-    debug!("解密完成，解密后数据长度: {} 字节", decrypted_data.len());
+    // 解密数据，旧版格式没有附加认证数据
+    cipher.open_in_place(&iv, &[], &mut data_to_decrypt)
+        .map_err(|_| DecodeError::DecryptionError(Unspecified))?;
     
-    Ok(decrypted_data.to_vec())
+    Ok(data_to_decrypt)
 }
 
 /// 解析加密信息
-#[allow(dead_code)]
 pub fn parse_encryption_info(encryption_info_json: &str) -> Result<EncryptionInfo, DecodeError> {
     let info: EncryptionInfo = serde_json::from_str(encryption_info_json)?;
     Ok(info)
@@ -139,35 +411,57 @@ pub fn get_algorithm_from_encryption_info(encryption_info: &str) -> Result<Strin
     Ok(info.algorithm)
 }
 
+/// 从加密信息中获取密钥组合方案版本
+#[allow(dead_code)]
+pub fn get_combiner_version_from_encryption_info(encryption_info: &str) -> Result<u32, DecodeError> {
+    let info = parse_encryption_info(encryption_info)?;
+    Ok(info.key_info.combiner_version)
+}
+
+/// 获取资源的密钥信息，容器格式从头部读取，旧格式从加密信息JSON读取
+fn resource_key_info(encrypted_data: &[u8], encryption_info_json: &str) -> Result<KeyInfo, DecodeError> {
+    if encryption_info_json.is_empty() {
+        let (header, _) = ContainerHeader::parse(encrypted_data)?;
+        Ok(header.key_info())
+    } else {
+        Ok(parse_encryption_info(encryption_info_json)?.key_info)
+    }
+}
+
 /// 验证密钥是否正确
+///
+/// 新记录通过密钥校验块验证，需要完整执行一次密钥派生；仍保留密钥哈希的旧记录验证哈希。
+/// 没有任何校验信息的旧记录只能通过解密数据来验证，返回错误。
 #[allow(dead_code)]
 pub fn verify_key(
     key_part_a: &str,
     ukey_part_b: &str,
+    encrypted_data: &[u8],
     encryption_info_json: &str
 ) -> Result<bool, DecodeError> {
-    // 解析加密信息
-    let encryption_info = parse_encryption_info(encryption_info_json)?;
-    
-    // 生成实际密钥
-    let actual_key = generate_actual_key(key_part_a, ukey_part_b);
+    let key_info = resource_key_info(encrypted_data, encryption_info_json)?;
     
-    // 验证密钥哈希
-    let is_valid = verify_key_hash(&actual_key, &encryption_info.key_info.key_hash);
+    if key_info.key_check.is_none() && key_info.key_hash.is_empty() {
+        return Err(DecodeError::EncryptionInfoError("加密信息中没有密钥校验信息".to_string()));
+    }
     
-    Ok(is_valid)
+    match get_key_from_info(&key_info, key_part_a, ukey_part_b) {
+        Ok(_) => Ok(true),
+        Err(KeyManagementError::KeyVerificationFailed) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use ring::aead;
     use crate::config::AppConfig;
     use crate::crypto::encode::encode_resource;
     
-    #[test]
-    fn test_decode_resource() {
-        // 创建测试配置
-        let config = AppConfig {
+    /// 创建测试配置
+    pub(crate) fn test_config() -> AppConfig {
+        AppConfig {
             server: crate::config::ServerConfig {
                 port: 8000,
                 host: "0.0.0.0".to_string(),
@@ -185,10 +479,30 @@ mod tests {
                 algorithm: "AES256GCM".to_string(),
                 salt: "test_encryption_salt".to_string(),
                 key_derivation_iterations: 100000,
+                key_derivation_function: "ARGON2ID".to_string(),
+                argon2_memory_cost: crate::crypto::key_management::MIN_ARGON2_MEMORY_COST,
+                argon2_time_cost: crate::crypto::key_management::MIN_ARGON2_TIME_COST,
+                argon2_parallelism: 1,
+                chunk_size: 65536,
+                lock_memory: false,
+                encrypt_metadata: false,
+                manifest_signing_key: None,
             },
             ukey: crate::config::UKeyConfig {
                 vendor: "test_vendor".to_string(),
                 api_url: "http://localhost:8080/ukey".to_string(),
+                timeout_ms: 5000,
+                challenge_ttl_secs: 60,
+                pkcs11_module: None,
+                pkcs11_token_label: None,
+                pkcs11_pin: None,
+                pkcs11_key_label: "secretgallery-part-b".to_string(),
+                admin_token: None,
+            },
+            fido2: crate::config::Fido2Config {
+                rp_id: "localhost".to_string(),
+                rp_name: "SecretGallery".to_string(),
+                origin: "http://localhost:3000".to_string(),
             },
             tmdb: crate::config::TmdbConfig {
                 api_key: "test_tmdb_api_key".to_string(),
@@ -212,50 +526,320 @@ mod tests {
             upload: crate::config::UploadConfig {
                 max_size: 104857600,
                 temp_dir: "./uploads".to_string(),
+                storage_chunk_size: 4096,
             },
-        };
+        }
+    }
+    
+    #[test]
+    fn test_decode_resource() {
+        // 创建测试配置
+        let config = test_config();
         
         // 测试数据
         let test_data = b"test resource data";
-        let media_type = "image/jpeg";
-        let is_local = true;
         let key_part_a = "test_key_part_a";
         let ukey_part_b = "test_ukey_part_b";
+        let context = ResourceContext::new(uuid::Uuid::new_v4(), "image/jpeg");
         
-        // 先编码资源
-        let encryption_info_json = encode_resource(test_data, media_type, is_local, key_part_a, ukey_part_b, &config).unwrap();
-        
-        // 模拟加密后的媒体数据
-        let encrypted_data = test_data.to_vec(); // 注意：这里应该是实际加密后的数据，测试时我们简化处理
+        // 先编码资源，容器格式不需要加密信息JSON
+        let (container, wrapped_key) = encode_resource(test_data, &context, key_part_a, ukey_part_b, &config).unwrap();
         
         // 解码资源
-        let result = decode_resource(&encrypted_data, &encryption_info_json, key_part_a, ukey_part_b);
+        let result = decode_resource(&container, "", Some(&wrapped_key), &context, key_part_a, ukey_part_b);
         assert!(result.is_ok());
         
-        // let decrypted_data = result.unwrap();
-        // assert_eq!(decrypted_data, test_data.to_vec());
+        let decrypted_data = result.unwrap();
+        assert_eq!(decrypted_data, test_data.to_vec());
+        
+        // 容器自带包装的数据密钥，导出后单独的容器即可解码
+        let decrypted_data = decode_resource(&container, "", None, &context, key_part_a, ukey_part_b).unwrap();
+        assert_eq!(decrypted_data, test_data.to_vec());
+        
+        // 错误的密钥无法解码
+        let result = decode_resource(&container, "", Some(&wrapped_key), &context, "wrong_key", ukey_part_b);
+        assert!(matches!(result, Err(DecodeError::AuthenticationFailed)));
+        
+        // 被篡改的密文无法解码
+        let mut tampered_data = container.clone();
+        let last = tampered_data.len() - 1;
+        tampered_data[last] ^= 0x01;
+        let result = decode_resource(&tampered_data, "", Some(&wrapped_key), &context, key_part_a, ukey_part_b);
+        assert!(matches!(result, Err(DecodeError::AuthenticationFailed)));
+        
+        // 被篡改的头部（分段大小）无法通过头部MAC验证
+        let (_, header_len) = ContainerHeader::parse(&container).unwrap();
+        let mut tampered_header = container.clone();
+        tampered_header[header_len - container::HEADER_MAC_LEN - 1] ^= 0x01;
+        let result = decode_resource(&tampered_header, "", Some(&wrapped_key), &context, key_part_a, ukey_part_b);
+        assert!(matches!(result, Err(DecodeError::AuthenticationFailed)));
+        
+        // 不是容器的数据返回格式错误
+        let result = decode_resource(test_data, "", None, &context, key_part_a, ukey_part_b);
+        assert!(matches!(result, Err(DecodeError::ContainerError(container::ContainerError::InvalidMagic))));
         
         // 测试密钥验证
-        let is_valid = verify_key(key_part_a, ukey_part_b, &encryption_info_json);
+        let is_valid = verify_key(key_part_a, ukey_part_b, &container, "");
         assert!(is_valid.unwrap());
         
         // 测试错误的密钥
-        let is_invalid = verify_key("wrong_key", ukey_part_b, &encryption_info_json);
+        let is_invalid = verify_key("wrong_key", ukey_part_b, &container, "");
         assert!(!is_invalid.unwrap());
         
-        // 测试从加密信息中获取媒体类型
-        let retrieved_media_type = get_media_type_from_encryption_info(&encryption_info_json);
-        assert!(retrieved_media_type.is_ok());
-        assert_eq!(retrieved_media_type.unwrap(), media_type);
+        // 新资源使用当前的密钥组合方案
+        assert_eq!(crate::crypto::policy::check_upgrade(&container, "", &config).unwrap(), None);
+    }
+    
+    #[tokio::test]
+    async fn test_decode_resource_all_suites() {
+        let mut config = test_config();
+        config.encryption.chunk_size = 32;
+        let test_data: Vec<u8> = (0..100u8).collect();
+        let key_part_a = "test_key_part_a";
+        let ukey_part_b = "test_ukey_part_b";
+        let context = ResourceContext::new(uuid::Uuid::new_v4(), "video/mp4");
+        
+        for name in crate::crypto::suite::suite_names() {
+            config.encryption.algorithm = name.to_string();
+            
+            let (container, _) = encode_resource(&test_data, &context, key_part_a, ukey_part_b, &config).unwrap();
+            let (header, _) = ContainerHeader::parse(&container).unwrap();
+            assert_eq!(header.algorithm, name);
+            assert_eq!(decode_resource(&container, "", None, &context, key_part_a, ukey_part_b).unwrap(), test_data);
+            
+            // 流式解码按套件读取不同长度的基础nonce
+            let mut decrypted_data = Vec::new();
+            decode_resource_stream(&mut container.as_slice(), &mut decrypted_data, "", None, &context, key_part_a, ukey_part_b).await.unwrap();
+            assert_eq!(decrypted_data, test_data);
+            
+            let result = decode_resource(&container, "", None, &context, "wrong_key", ukey_part_b);
+            assert!(matches!(result, Err(DecodeError::AuthenticationFailed)));
+        }
+    }
+    
+    #[test]
+    fn test_decode_resource_binding() {
+        let config = test_config();
+        let test_data = b"bound resource data";
+        let key_part_a = "test_key_part_a";
+        let ukey_part_b = "test_ukey_part_b";
+        let context = ResourceContext::new(uuid::Uuid::new_v4(), "image/jpeg");
+        
+        let (container, _) = encode_resource(test_data, &context, key_part_a, ukey_part_b, &config).unwrap();
+        assert_eq!(decode_resource(&container, "", None, &context, key_part_a, ukey_part_b).unwrap(), test_data.to_vec());
+        
+        // 密文被换到另一个资源（密钥相同）时认证失败
+        let other_context = ResourceContext::new(uuid::Uuid::new_v4(), "image/jpeg");
+        let result = decode_resource(&container, "", None, &other_context, key_part_a, ukey_part_b);
+        assert!(matches!(result, Err(DecodeError::AuthenticationFailed)));
+        
+        // 媒体类型被篡改时认证失败
+        let tampered_context = ResourceContext::new(context.uuid, "text/html");
+        let result = decode_resource(&container, "", None, &tampered_context, key_part_a, ukey_part_b);
+        assert!(matches!(result, Err(DecodeError::AuthenticationFailed)));
+        
+        // 容器版本被降级为不绑定资源属性的v1时，头部MAC验证失败
+        let mut downgraded = container.clone();
+        downgraded[4] = container::CONTAINER_VERSION_V1;
+        let result = decode_resource(&downgraded, "", None, &context, key_part_a, ukey_part_b);
+        assert!(matches!(result, Err(DecodeError::AuthenticationFailed)));
+    }
+    
+    #[tokio::test]
+    async fn test_decode_resource_stream() {
+        let mut config = test_config();
+        config.encryption.chunk_size = 64;
+        
+        // 跨越多个分段的测试数据
+        let test_data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+        let key_part_a = "test_key_part_a";
+        let ukey_part_b = "test_ukey_part_b";
+        let context = ResourceContext::new(uuid::Uuid::new_v4(), "image/jpeg");
+        
+        // 流式编码
+        let mut container = Vec::new();
+        let wrapped_key = crate::crypto::encode::encode_resource_stream(
+            &mut test_data.as_slice(),
+            &mut container,
+            &context,
+            key_part_a,
+            ukey_part_b,
+            None,
+            None,
+            &config
+        ).await.unwrap().wrapped_key;
+        
+        // 流式解码
+        let mut decrypted_data = Vec::new();
+        let written = decode_resource_stream(
+            &mut container.as_slice(),
+            &mut decrypted_data,
+            "",
+            Some(&wrapped_key),
+            &context,
+            key_part_a,
+            ukey_part_b
+        ).await.unwrap();
+        assert_eq!(written, test_data.len() as u64);
+        assert_eq!(decrypted_data, test_data);
+        
+        // 流式格式与一次性解码兼容
+        let decrypted_data = decode_resource(&container, "", Some(&wrapped_key), &context, key_part_a, ukey_part_b).unwrap();
+        assert_eq!(decrypted_data, test_data);
+        
+        // 删除结束分段后解码失败
+        let truncated = &container[..container.len() - (1000 % 64 + stream::TAG_LEN)];
+        let result = decode_resource(truncated, "", Some(&wrapped_key), &context, key_part_a, ukey_part_b);
+        assert!(matches!(result, Err(DecodeError::AuthenticationFailed)));
+        
+        // 流式解码时密钥错误与数据损坏返回相同的错误
+        let mut tampered_data = container.clone();
+        let last = tampered_data.len() - 1;
+        tampered_data[last] ^= 0x01;
+        let result = decode_resource_stream(
+            &mut tampered_data.as_slice(),
+            &mut Vec::new(),
+            "",
+            Some(&wrapped_key),
+            &context,
+            key_part_a,
+            ukey_part_b
+        ).await;
+        assert!(matches!(result, Err(DecodeError::AuthenticationFailed)));
+        let result = decode_resource_stream(
+            &mut container.as_slice(),
+            &mut Vec::new(),
+            "",
+            Some(&wrapped_key),
+            &context,
+            "wrong_key",
+            ukey_part_b
+        ).await;
+        assert!(matches!(result, Err(DecodeError::AuthenticationFailed)));
+    }
+    
+    #[test]
+    fn test_decode_legacy_envelope_resource() {
+        let config = test_config();
+        let test_data = b"legacy envelope resource data";
+        let key_part_a = "test_key_part_a";
+        let ukey_part_b = "test_ukey_part_b";
+        let context = ResourceContext::new(uuid::Uuid::new_v4(), "image/jpeg");
+        
+        // 构造容器格式之前的信封加密记录：加密参数保存在加密信息JSON中
+        let kdf_params = crate::crypto::key_management::get_kdf_params(&config).unwrap();
+        let (key_info, kek) = crate::crypto::key_management::generate_key_info(
+            "AES256GCM", &crate::crypto::key_management::generate_salt(), &kdf_params, key_part_a, ukey_part_b
+        ).unwrap();
+        let data_key = generate_data_key().unwrap();
+        let wrapped_key = envelope::wrap_data_key(&kek, &data_key).unwrap().to_json().unwrap();
+        let nonce_prefix = stream::generate_nonce_prefix("AES256GCM").unwrap();
+        let mut sealer = stream::StreamSealer::new("AES256GCM", &data_key, &nonce_prefix).unwrap();
+        let encrypted_data = stream::seal_all(&mut sealer, test_data, 16).unwrap();
+        
+        let encryption_info = EncryptionInfo {
+            key_info,
+            iv: general_purpose::STANDARD.encode(nonce_prefix),
+            tag: String::new(),
+            algorithm: "AES256GCM".to_string(),
+            media_type: "image/jpeg".to_string(),
+            is_local: true,
+            chunk_size: Some(16),
+            envelope: true,
+        };
+        let encryption_info_json = serde_json::to_string(&encryption_info).unwrap();
+        
+        let decrypted_data = decode_resource(&encrypted_data, &encryption_info_json, Some(&wrapped_key), &context, key_part_a, ukey_part_b).unwrap();
+        assert_eq!(decrypted_data, test_data.to_vec());
+        assert!(verify_key(key_part_a, ukey_part_b, &encrypted_data, &encryption_info_json).unwrap());
+        
+        // 信封加密的资源缺少包装的数据密钥时无法解码
+        let result = decode_resource(&encrypted_data, &encryption_info_json, None, &context, key_part_a, ukey_part_b);
+        assert!(matches!(result, Err(DecodeError::EncryptionInfoError(_))));
+        
+        // 换钥时转换为容器格式，分段密文不变
+        let (container, new_wrapped_key) = crate::crypto::encode::rekey_resource(
+            &encrypted_data, &encryption_info_json, Some(&wrapped_key), key_part_a, ukey_part_b, "new_key", "new_ukey", &config
+        ).unwrap();
+        assert!(container.ends_with(&encrypted_data));
+        let decrypted_data = decode_resource(&container, "", Some(&new_wrapped_key), &context, "new_key", "new_ukey").unwrap();
+        assert_eq!(decrypted_data, test_data.to_vec());
+    }
+    
+    #[test]
+    fn test_decode_legacy_resource() {
+        let config = test_config();
+        let test_data = b"legacy resource data";
+        let key_part_a = "test_key_part_a";
+        let ukey_part_b = "test_ukey_part_b";
+        let context = ResourceContext::new(uuid::Uuid::new_v4(), "image/jpeg");
+        
+        // 构造旧版整段加密格式、旧版拼接密钥方案的记录
+        let salt = config.encryption.salt.as_bytes();
+        let (mut key_info, _) = crate::crypto::key_management::generate_key_info("AES256GCM", salt, &crate::crypto::key_management::KdfParams::Pbkdf2 { iteration_count: 100000 }, key_part_a, ukey_part_b).unwrap();
+        key_info.combiner_version = crate::crypto::key_management::KEY_COMBINER_LEGACY;
+        key_info.key_check = None;
+        key_info.ukey_info = general_purpose::STANDARD.encode(ukey_part_b);
+        key_info.key_hash = crate::crypto::key_management::generate_key_hash(
+            crate::crypto::key_management::generate_actual_key(key_part_a, ukey_part_b).expose_secret()
+        );
+        let key = get_key_from_info(&key_info, key_part_a, ukey_part_b).unwrap();
+        let iv = crate::crypto::key_management::generate_iv();
+        let sealing_key = aead::LessSafeKey::new(aead::UnboundKey::new(&aead::AES_256_GCM, &key).unwrap());
+        let nonce = aead::Nonce::try_assume_unique_for_key(&iv).unwrap();
+        let mut encrypted_data = test_data.to_vec();
+        let tag = sealing_key.seal_in_place_separate_tag(nonce, aead::Aad::empty(), &mut encrypted_data).unwrap();
+        
+        let encryption_info = EncryptionInfo {
+            key_info,
+            iv: general_purpose::STANDARD.encode(&iv),
+            tag: general_purpose::STANDARD.encode(tag),
+            algorithm: "AES256GCM".to_string(),
+            media_type: "image/jpeg".to_string(),
+            is_local: true,
+            chunk_size: None,
+            envelope: false,
+        };
+        let encryption_info_json = serde_json::to_string(&encryption_info).unwrap();
+        assert!(!encryption_info_json.contains("chunk_size"));
+        
+        let decrypted_data = decode_resource(&encrypted_data, &encryption_info_json, None, &context, key_part_a, ukey_part_b).unwrap();
+        assert_eq!(decrypted_data, test_data.to_vec());
+        
+        // 旧记录仍可验证密钥，并被标记为需要迁移
+        assert!(verify_key(key_part_a, ukey_part_b, &encrypted_data, &encryption_info_json).unwrap());
+        assert!(!verify_key("wrong_key", ukey_part_b, &encrypted_data, &encryption_info_json).unwrap());
+        assert_eq!(
+            crate::crypto::policy::check_upgrade(&encrypted_data, &encryption_info_json, &config).unwrap(),
+            Some(crate::crypto::policy::UpgradeReason::LegacyFormat)
+        );
+        
+        // 旧版非信封加密的资源无法只重新包装数据密钥
+        assert!(matches!(
+            crate::crypto::encode::rekey_resource(&encrypted_data, &encryption_info_json, None, key_part_a, ukey_part_b, "new_key", "new_ukey", &config),
+            Err(crate::crypto::encode::EncodeError::NotEnvelopeEncrypted)
+        ));
+        
+        // 测试从加密信息中获取媒体类型、是否本地资源和算法
+        assert_eq!(get_media_type_from_encryption_info(&encryption_info_json).unwrap(), "image/jpeg");
+        assert!(get_is_local_from_encryption_info(&encryption_info_json).unwrap());
+        assert_eq!(get_algorithm_from_encryption_info(&encryption_info_json).unwrap(), "AES256GCM");
         
-        // 测试从加密信息中获取是否本地资源
-        let retrieved_is_local = get_is_local_from_encryption_info(&encryption_info_json);
-        assert!(retrieved_is_local.is_ok());
-        assert_eq!(retrieved_is_local.unwrap(), is_local);
+        // 清除硬件码B和密钥哈希后仍可解密，错误的密钥由认证标签发现
+        let mut scrubbed_info = encryption_info.clone();
+        scrubbed_info.key_info.ukey_info = String::new();
+        scrubbed_info.key_info.key_hash = String::new();
+        let scrubbed_info_json = serde_json::to_string(&scrubbed_info).unwrap();
+        assert!(verify_key(key_part_a, ukey_part_b, &encrypted_data, &scrubbed_info_json).is_err());
+        let decrypted_data = decode_resource(&encrypted_data, &scrubbed_info_json, None, &context, key_part_a, ukey_part_b).unwrap();
+        assert_eq!(decrypted_data, test_data.to_vec());
+        assert!(matches!(
+            decode_resource(&encrypted_data, &scrubbed_info_json, None, &context, "wrong_key", ukey_part_b),
+            Err(DecodeError::AuthenticationFailed)
+        ));
         
-        // 测试从加密信息中获取算法
-        let retrieved_algorithm = get_algorithm_from_encryption_info(&encryption_info_json);
-        assert!(retrieved_algorithm.is_ok());
-        assert_eq!(retrieved_algorithm.unwrap(), "AES256GCM");
+        // 重新编码后使用新的组合方案
+        let (migrated_container, _) = encode_resource(&decrypted_data, &context, key_part_a, ukey_part_b, &config).unwrap();
+        assert_eq!(crate::crypto::policy::check_upgrade(&migrated_container, "", &config).unwrap(), None);
     }
 }
@@ -1,16 +1,23 @@
-use ring::aead;
 use ring::error::Unspecified;
 use base64::{Engine as _, engine::general_purpose};
 use serde::{Serialize, Deserialize};
 use thiserror::Error;
-use tracing::{error, debug};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tracing::debug;
 
 use crate::config::{AppConfig};
-use crate::crypto::key_management::{self, KeyInfo, get_key_from_info, generate_key_info, get_encryption_salt, get_key_derivation_iterations, get_encryption_algorithm};
+use crate::crypto::key_management::{self, KeyInfo, get_key_from_info, generate_key_info, generate_salt, get_kdf_params, get_encryption_algorithm, get_encryption_chunk_size};
+use crate::crypto::stream::{self, StreamSealer, EncryptWriter};
+use crate::crypto::envelope::{self, generate_data_key, wrap_data_key, unwrap_data_key, WrappedKey};
+use crate::crypto::container::{self, ContainerHeader, ResourceContext};
+use crate::crypto::recovery::{self, wrap_for_recovery, RecoveryPublicKey};
+use crate::crypto::metadata::{self, seal_metadata, ResourceMetadata};
+use crate::crypto::secret::SecretKey;
 
 /// 资源编码错误类型
 #[derive(Error, Debug)]
 #[allow(dead_code)]
+#[allow(clippy::enum_variant_names)]
 pub enum EncodeError {
     #[error("加密算法错误: {0}")]
     AlgorithmError(String),
@@ -35,9 +42,33 @@ pub enum EncodeError {
     
     #[error("标签长度错误")]
     TagLengthError,
+    
+    #[error("分段加密错误: {0}")]
+    StreamError(#[from] stream::StreamError),
+    
+    #[error("读写错误: {0}")]
+    IoError(#[from] std::io::Error),
+    
+    #[error("信封加密错误: {0}")]
+    EnvelopeError(#[from] envelope::EnvelopeError),
+    
+    #[error("资源未使用信封加密")]
+    NotEnvelopeEncrypted,
+    
+    #[error("加密容器错误: {0}")]
+    ContainerError(#[from] container::ContainerError),
+    
+    #[error("恢复密钥错误: {0}")]
+    RecoveryError(#[from] recovery::RecoveryError),
+    
+    #[error("元数据加密错误: {0}")]
+    MetadataError(#[from] metadata::MetadataError),
 }
 
 /// 加密信息
+///
+/// 容器格式之前的资源将加密参数以JSON保存在 `resources.encryption_info` 中，
+/// 现在只用于读取旧资源，新资源的参数保存在容器头部。
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct EncryptionInfo {
@@ -47,84 +78,282 @@ pub struct EncryptionInfo {
     pub algorithm: String,
     pub media_type: String,
     pub is_local: bool,
+    /// 分段大小，为空表示旧版整段加密格式（IV为12字节，标签单独保存）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<u32>,
+    /// 是否使用信封加密：数据由随机数据密钥加密，数据密钥被密钥信息派生的KEK包装后保存在encryption_keys中
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub envelope: bool,
 }
 
-/// 编码资源
-pub fn encode_resource(
-    data: &[u8],
-    media_type: &str,
-    is_local: bool,
+/// 准备好的加密状态
+struct PreparedEncryption {
+    /// 分段加密器
+    sealer: StreamSealer,
+    /// 容器头部
+    header: ContainerHeader,
+    /// 序列化后的容器头部
+    header_bytes: Vec<u8>,
+    /// 包装后的数据密钥
+    wrapped_key: String,
+    /// 用恢复公钥包装的数据密钥
+    recovery_wrapped_key: Option<String>,
+    /// 数据密钥，用于加密元数据
+    data_key: SecretKey,
+}
+
+/// 编码结果，调用方只应持久化这些字段
+pub struct EncodedResource {
+    /// 加密容器
+    pub container: Vec<u8>,
+    /// 包装后的数据密钥
+    pub wrapped_key: String,
+    /// 用恢复公钥包装的数据密钥，没有恢复密钥时为空
+    pub recovery_wrapped_key: Option<String>,
+    /// 用数据密钥加密的元数据，未启用元数据加密时为空
+    pub encrypted_metadata: Option<Vec<u8>>,
+}
+
+/// 流式编码结果，加密容器已经写入调用方的 `writer`
+pub struct EncodedStream {
+    /// 包装后的数据密钥
+    pub wrapped_key: String,
+    /// 用恢复公钥包装的数据密钥，没有恢复密钥时为空
+    pub recovery_wrapped_key: Option<String>,
+    /// 用数据密钥加密的元数据，未提供元数据时为空
+    pub encrypted_metadata: Option<Vec<u8>>,
+}
+
+/// 准备分段加密器、容器头部、包装后的数据密钥和恢复包装
+fn prepare_encryption(
+    context: &ResourceContext,
     key_part_a: &str,
     ukey_part_b: &str,
+    recovery_key: Option<&RecoveryPublicKey>,
     config: &AppConfig
-) -> Result<String, EncodeError> {
-    debug!("数据长度: {} 字节", data.len());
-    
+) -> Result<PreparedEncryption, EncodeError> {
     // 获取加密算法
     let algorithm = get_encryption_algorithm(config);
     debug!("使用加密算法: {}", algorithm);
     
-    // 生成IV
-    let iv = key_management::generate_iv();
-    debug!("生成IV: {:?}", iv);
+    // 生成基础nonce，长度由加密套件决定
+    let nonce_prefix = stream::generate_nonce_prefix(algorithm)?;
     
-    // 保存IV的副本，因为后面需要使用
-    let iv_copy = iv.clone();
+    // 每个资源生成独立的随机盐值，随容器头部保存
+    let salt = generate_salt();
     
-    // 获取加密盐值
-    let salt = get_encryption_salt(config);
-    debug!("使用盐值: {:?}", salt);
+    // 获取密钥派生参数
+    let kdf_params = get_kdf_params(config)?;
+    debug!("使用密钥派生参数: {:?}", kdf_params);
     
-    // 获取密钥派生迭代次数
-    let iterations = get_key_derivation_iterations(config);
-    debug!("使用迭代次数: {}", iterations);
+    // 获取分段大小
+    let chunk_size = get_encryption_chunk_size(config);
+    debug!("使用分段大小: {} 字节", chunk_size);
     
-    // 生成密钥信息
-    let key_info = generate_key_info(algorithm, &salt, iterations, key_part_a, ukey_part_b)?;
+    // 生成密钥信息，同时由密钥A和硬件码B派生密钥加密密钥
+    let (key_info, kek) = generate_key_info(algorithm, &salt, &kdf_params, key_part_a, ukey_part_b)?;
     debug!("生成密钥信息成功");
     
-    // 获取加密密钥
-    let key = get_key_from_info(&key_info, key_part_a, ukey_part_b)?;
-    debug!("获取加密密钥成功");
+    // 生成随机数据密钥并用KEK包装
+    let data_key = generate_data_key()?;
+    let wrapped_key = wrap_data_key(&kek, &data_key)?;
     
-    // 根据算法选择加密算法
-    let encryption_algorithm = match algorithm {
-        "AES256GCM" => &aead::AES_256_GCM,
-        "CHACHA20POLY1305" => &aead::CHACHA20_POLY1305,
-        _ => return Err(EncodeError::AlgorithmError(format!("不支持的加密算法: {}", algorithm))),
-    };
+    // 设置了恢复密钥时，数据密钥同时用恢复公钥包装
+    let recovery_wrapped_key = recovery_key
+        .map(|recovery_key| wrap_for_recovery(recovery_key, &data_key)?.to_json())
+        .transpose()?;
     
-    // 创建UnboundKey
-    let unbound_key = aead::UnboundKey::new(encryption_algorithm, &key)
-        .map_err(|e| EncodeError::AlgorithmError(format!("创建密钥失败: {:?}", e)))?;
-    
-    // 创建LessSafeKey用于加密
-    let sealing_key = aead::LessSafeKey::new(unbound_key);
-    
-    // 创建AEAD操作
-    let aad = Vec::new();
-    let nonce = aead::Nonce::assume_unique_for_key(iv.try_into().map_err(|_| EncodeError::IVLengthError)?);
-    
-    // 加密数据
-    let mut data_to_encrypt = Vec::from(data);
-    let tag = sealing_key.seal_in_place_separate_tag(nonce, aead::Aad::from(&aad), &mut data_to_encrypt)?;
-    debug!("加密完成，加密后数据长度: {} 字节，标签长度: {} 字节", data_to_encrypt.len(), tag.as_ref().len());
-    
-    // 构建加密信息
-    let encryption_info = EncryptionInfo {
-        key_info,
-        iv: general_purpose::STANDARD.encode(&iv_copy),
-        tag: general_purpose::STANDARD.encode(&tag),
-        algorithm: algorithm.to_string(),
-        media_type: media_type.to_string(),
-        is_local,
+    // 构建容器头部，头部MAC由数据密钥计算
+    let header = ContainerHeader::new(&key_info, &wrapped_key, nonce_prefix, chunk_size)?;
+    let header_bytes = header.to_bytes(&data_key)?;
+    
+    // 创建分段加密器，每个分段绑定资源属性和容器版本
+    let sealer = StreamSealer::new(algorithm, &data_key, &header.nonce_prefix)?
+        .with_aad(context.aad(header.version)?);
+    
+    Ok(PreparedEncryption {
+        sealer,
+        header,
+        header_bytes,
+        wrapped_key: wrapped_key.to_json()?,
+        recovery_wrapped_key,
+        data_key,
+    })
+}
+
+/// 编码资源
+///
+/// 返回加密容器（头部 ‖ 分段密文）和包装后的数据密钥。容器头部记录了解密所需的全部参数，
+/// 每个分段的认证标签随密文保存，解码时由 `decode_resource` 校验头部MAC并逐段校验。
+/// `context` 中的资源UUID和媒体类型作为附加认证数据，解码时必须提供相同的值。
+/// 包装后的数据密钥同时保存在 `encryption_keys` 中。
+#[allow(dead_code)]
+pub fn encode_resource(
+    data: &[u8],
+    context: &ResourceContext,
+    key_part_a: &str,
+    ukey_part_b: &str,
+    config: &AppConfig
+) -> Result<(Vec<u8>, String), EncodeError> {
+    let encoded = encode_resource_with_recovery(data, context, key_part_a, ukey_part_b, None, None, config)?;
+    Ok((encoded.container, encoded.wrapped_key))
+}
+
+/// 编码资源，设置了恢复密钥时同时用恢复公钥包装数据密钥，提供元数据时同时用数据密钥加密元数据
+pub fn encode_resource_with_recovery(
+    data: &[u8],
+    context: &ResourceContext,
+    key_part_a: &str,
+    ukey_part_b: &str,
+    recovery_key: Option<&RecoveryPublicKey>,
+    metadata: Option<&ResourceMetadata>,
+    config: &AppConfig
+) -> Result<EncodedResource, EncodeError> {
+    debug!("数据长度: {} 字节", data.len());
+    
+    let PreparedEncryption { mut sealer, header, header_bytes: mut container, wrapped_key, recovery_wrapped_key, data_key } =
+        prepare_encryption(context, key_part_a, ukey_part_b, recovery_key, config)?;
+    
+    // 加密数据，追加在头部之后
+    let encrypted_data = stream::seal_all(&mut sealer, data, header.chunk_size as usize)?;
+    container.extend_from_slice(&encrypted_data);
+    debug!("加密完成，容器长度: {} 字节", container.len());
+    
+    let encrypted_metadata = metadata
+        .map(|metadata| seal_metadata(&data_key, context, metadata))
+        .transpose()?;
+    
+    Ok(EncodedResource { container, wrapped_key, recovery_wrapped_key, encrypted_metadata })
+}
+
+/// 流式编码资源
+///
+/// 从 `reader` 读取明文，先写出容器头部，再分段加密后写入 `writer`，内存中最多缓存一个分段，
+/// 适用于无法整体载入内存的大视频。提供元数据时同时用数据密钥加密元数据。
+#[allow(clippy::too_many_arguments)]
+pub async fn encode_resource_stream<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    context: &ResourceContext,
+    key_part_a: &str,
+    ukey_part_b: &str,
+    recovery_key: Option<&RecoveryPublicKey>,
+    metadata: Option<&ResourceMetadata>,
+    config: &AppConfig
+) -> Result<EncodedStream, EncodeError>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let PreparedEncryption { sealer, header, header_bytes, wrapped_key, recovery_wrapped_key, data_key } =
+        prepare_encryption(context, key_part_a, ukey_part_b, recovery_key, config)?;
+    writer.write_all(&header_bytes).await?;
+    
+    // 加密数据，shutdown会写出结束分段
+    let mut encrypt_writer = EncryptWriter::new(writer, sealer, header.chunk_size as usize)?;
+    let copied = tokio::io::copy(reader, &mut encrypt_writer).await?;
+    encrypt_writer.shutdown().await?;
+    debug!("流式加密完成，明文长度: {} 字节", copied);
+    
+    let encrypted_metadata = metadata
+        .map(|metadata| seal_metadata(&data_key, context, metadata))
+        .transpose()?;
+    
+    Ok(EncodedStream { wrapped_key, recovery_wrapped_key, encrypted_metadata })
+}
+
+/// 更换资源的密钥A和硬件码B
+///
+/// 仅用新的密钥信息重新派生KEK并重新包装数据密钥，分段密文保持不变，只替换容器头部。
+/// 仍使用加密信息JSON的信封加密资源会在换钥时转换为容器格式，`wrapped_key` 为其
+/// `encryption_keys` 中的包装数据密钥。返回新的容器和包装后的数据密钥，
+/// 旧版非信封加密的资源返回 `EncodeError::NotEnvelopeEncrypted`，需要整体重新加密。
+#[allow(dead_code, clippy::too_many_arguments)]
+pub fn rekey_resource(
+    encrypted_data: &[u8],
+    encryption_info_json: &str,
+    wrapped_key: Option<&str>,
+    old_key_part_a: &str,
+    old_ukey_part_b: &str,
+    new_key_part_a: &str,
+    new_ukey_part_b: &str,
+    config: &AppConfig
+) -> Result<(Vec<u8>, String), EncodeError> {
+    // 取出原容器版本、密钥信息、包装的数据密钥、基础nonce、分段大小和分段密文
+    // 旧版加密信息JSON的分段密文没有附加认证数据，转换后为v1容器
+    let (version, key_info, old_wrapped_key, nonce_prefix, chunk_size, body) = if encryption_info_json.is_empty() {
+        let (header, header_len) = ContainerHeader::parse(encrypted_data)?;
+        (header.version, header.key_info(), header.wrapped_key()?, header.nonce_prefix, header.chunk_size, &encrypted_data[header_len..])
+    } else {
+        let encryption_info: EncryptionInfo = serde_json::from_str(encryption_info_json)?;
+        let (true, Some(chunk_size), Some(wrapped_key)) = (encryption_info.envelope, encryption_info.chunk_size, wrapped_key) else {
+            return Err(EncodeError::NotEnvelopeEncrypted);
+        };
+        let nonce_prefix = general_purpose::STANDARD.decode(&encryption_info.iv)?;
+        (container::CONTAINER_VERSION_V1, encryption_info.key_info, WrappedKey::from_json(wrapped_key)?, nonce_prefix, chunk_size, encrypted_data)
     };
     
-    // 序列化加密信息
-    let encryption_info_json = serde_json::to_string(&encryption_info)?;
-    debug!("加密信息序列化成功");
+    // 用旧密钥派生原KEK并解包数据密钥
+    let old_kek = get_key_from_info(&key_info, old_key_part_a, old_ukey_part_b)?;
+    let data_key = unwrap_data_key(&old_kek, &old_wrapped_key)?;
+    
+    rewrap_container(version, &key_info.algorithm, &data_key, nonce_prefix, chunk_size, body, new_key_part_a, new_ukey_part_b, config)
+}
+
+/// 用恢复码解出的数据密钥为容器换上新的密钥A和硬件码B
+///
+/// 只支持容器格式。先用数据密钥校验头部MAC，不属于该容器的数据密钥返回 `EnvelopeError::UnwrapError`。
+/// 与 `rekey_resource` 相同，只替换容器头部。
+pub fn recover_resource(
+    encrypted_data: &[u8],
+    data_key: &SecretKey,
+    new_key_part_a: &str,
+    new_ukey_part_b: &str,
+    config: &AppConfig
+) -> Result<(Vec<u8>, String), EncodeError> {
+    let (header, header_len) = ContainerHeader::parse(encrypted_data)?;
+    if !ContainerHeader::verify_mac(&encrypted_data[..header_len], data_key) {
+        return Err(envelope::EnvelopeError::UnwrapError.into());
+    }
+    
+    rewrap_container(
+        header.version,
+        &header.algorithm,
+        data_key,
+        header.nonce_prefix,
+        header.chunk_size,
+        &encrypted_data[header_len..],
+        new_key_part_a,
+        new_ukey_part_b,
+        config
+    )
+}
+
+/// 用新的密钥A和硬件码B重新包装数据密钥，生成新的容器头部
+///
+/// 新KEK使用新盐值和当前的派生参数，容器版本不变，分段密文原样保留。
+#[allow(clippy::too_many_arguments)]
+fn rewrap_container(
+    version: u8,
+    algorithm: &str,
+    data_key: &SecretKey,
+    nonce_prefix: Vec<u8>,
+    chunk_size: u32,
+    body: &[u8],
+    new_key_part_a: &str,
+    new_ukey_part_b: &str,
+    config: &AppConfig
+) -> Result<(Vec<u8>, String), EncodeError> {
+    let kdf_params = get_kdf_params(config)?;
+    let (new_key_info, new_kek) = generate_key_info(algorithm, &generate_salt(), &kdf_params, new_key_part_a, new_ukey_part_b)?;
+    let wrapped_key = wrap_data_key(&new_kek, data_key)?;
     
-    Ok(encryption_info_json)
+    let mut header = ContainerHeader::new(&new_key_info, &wrapped_key, nonce_prefix, chunk_size)?;
+    header.version = version;
+    let mut container = header.to_bytes(data_key)?;
+    container.extend_from_slice(body);
+    
+    Ok((container, wrapped_key.to_json()?))
 }
 
 /// 从加密信息中获取媒体类型
@@ -174,10 +403,30 @@ mod tests {
                 algorithm: "AES256GCM".to_string(),
                 salt: "test_encryption_salt".to_string(),
                 key_derivation_iterations: 100000,
+                key_derivation_function: "ARGON2ID".to_string(),
+                argon2_memory_cost: crate::crypto::key_management::MIN_ARGON2_MEMORY_COST,
+                argon2_time_cost: crate::crypto::key_management::MIN_ARGON2_TIME_COST,
+                argon2_parallelism: 1,
+                chunk_size: 65536,
+                lock_memory: false,
+                encrypt_metadata: false,
+                manifest_signing_key: None,
             },
             ukey: crate::config::UKeyConfig {
                 vendor: "test_vendor".to_string(),
                 api_url: "http://localhost:8080/ukey".to_string(),
+                timeout_ms: 5000,
+                challenge_ttl_secs: 60,
+                pkcs11_module: None,
+                pkcs11_token_label: None,
+                pkcs11_pin: None,
+                pkcs11_key_label: "secretgallery-part-b".to_string(),
+                admin_token: None,
+            },
+            fido2: crate::config::Fido2Config {
+                rp_id: "localhost".to_string(),
+                rp_name: "SecretGallery".to_string(),
+                origin: "http://localhost:3000".to_string(),
             },
             tmdb: crate::config::TmdbConfig {
                 api_key: "test_tmdb_api_key".to_string(),
@@ -201,36 +450,77 @@ mod tests {
             upload: crate::config::UploadConfig {
                 max_size: 104857600,
                 temp_dir: "./uploads".to_string(),
+                storage_chunk_size: 4096,
             },
         };
         
         // 测试数据
         let test_data = b"test resource data";
-        let media_type = "image/jpeg";
-        let is_local = true;
         let key_part_a = "test_key_part_a";
         let ukey_part_b = "test_ukey_part_b";
         
+        let context = ResourceContext::new(uuid::Uuid::new_v4(), "image/jpeg");
+        
         // 编码资源
-        let result = encode_resource(test_data, media_type, is_local, key_part_a, ukey_part_b, &config);
+        let result = encode_resource(test_data, &context, key_part_a, ukey_part_b, &config);
         assert!(result.is_ok());
         
-        let encryption_info_json = result.unwrap();
-        assert!(!encryption_info_json.is_empty());
+        let (container, wrapped_key) = result.unwrap();
+        assert!(envelope::WrappedKey::from_json(&wrapped_key).is_ok());
+        
+        // 容器头部记录加密参数，头部中的包装密钥与encryption_keys中的一致
+        let (header, header_len) = ContainerHeader::parse(&container).unwrap();
+        assert_eq!(header.version, container::CURRENT_CONTAINER_VERSION);
+        assert_eq!(header.algorithm, "AES256GCM");
+        assert_eq!(header.chunk_size, 65536);
+        assert_eq!(header.kdf_params, get_kdf_params(&config).unwrap());
+        assert_eq!(header.combiner_version, key_management::CURRENT_KEY_COMBINER);
+        assert_eq!(header.wrapped_key().unwrap(), envelope::WrappedKey::from_json(&wrapped_key).unwrap());
+        
+        // 加密后的数据不应包含明文，单个分段附带一个认证标签
+        let encrypted_data = &container[header_len..];
+        assert_eq!(encrypted_data.len(), test_data.len() + stream::TAG_LEN);
+        assert!(!container.windows(test_data.len()).any(|w| w == test_data));
+        
+        // 相同密钥重复编码时每个资源使用不同的随机盐值
+        let (second_container, _) = encode_resource(test_data, &context, key_part_a, ukey_part_b, &config).unwrap();
+        let (second_header, _) = ContainerHeader::parse(&second_container).unwrap();
+        assert_ne!(header.salt, second_header.salt);
+        assert_ne!(header.salt, config.encryption.salt.as_bytes());
+    }
+    
+    #[test]
+    fn test_rekey_resource() {
+        let mut config = crate::crypto::decode::tests::test_config();
+        config.encryption.chunk_size = 16;
+        let test_data = b"envelope encrypted resource data";
+        
+        let context = ResourceContext::new(uuid::Uuid::new_v4(), "image/jpeg");
+        
+        let (container, wrapped_key) = encode_resource(test_data, &context, "old_key", "old_ukey", &config).unwrap();
+        let (_, header_len) = ContainerHeader::parse(&container).unwrap();
         
-        // 测试从加密信息中获取媒体类型
-        let retrieved_media_type = get_media_type_from_encryption_info(&encryption_info_json);
-        assert!(retrieved_media_type.is_ok());
-        assert_eq!(retrieved_media_type.unwrap(), media_type);
+        // 换钥只替换容器头部，分段密文不变
+        let (rekeyed_container, rekeyed_wrapped_key) = rekey_resource(
+            &container, "", Some(&wrapped_key), "old_key", "old_ukey", "new_key", "new_ukey", &config
+        ).unwrap();
+        let (rekeyed_header, rekeyed_header_len) = ContainerHeader::parse(&rekeyed_container).unwrap();
+        assert_eq!(&rekeyed_container[rekeyed_header_len..], &container[header_len..]);
+        assert_eq!(rekeyed_header.version, container::CURRENT_CONTAINER_VERSION);
+        assert_eq!(rekeyed_header.wrapped_key().unwrap(), WrappedKey::from_json(&rekeyed_wrapped_key).unwrap());
+        let decrypted_data = crate::crypto::decode::decode_resource(
+            &rekeyed_container, "", Some(&rekeyed_wrapped_key), &context, "new_key", "new_ukey"
+        ).unwrap();
+        assert_eq!(decrypted_data, test_data.to_vec());
         
-        // 测试从加密信息中获取是否本地资源
-        let retrieved_is_local = get_is_local_from_encryption_info(&encryption_info_json);
-        assert!(retrieved_is_local.is_ok());
-        assert_eq!(retrieved_is_local.unwrap(), is_local);
+        // 旧密钥不再可用
+        assert!(crate::crypto::decode::decode_resource(
+            &rekeyed_container, "", Some(&rekeyed_wrapped_key), &context, "old_key", "old_ukey"
+        ).is_err());
         
-        // 测试从加密信息中获取算法
-        let retrieved_algorithm = get_algorithm_from_encryption_info(&encryption_info_json);
-        assert!(retrieved_algorithm.is_ok());
-        assert_eq!(retrieved_algorithm.unwrap(), "AES256GCM");
+        // 错误的旧密钥无法换钥
+        assert!(rekey_resource(
+            &container, "", Some(&wrapped_key), "wrong_key", "old_ukey", "new_key", "new_ukey", &config
+        ).is_err());
     }
 }
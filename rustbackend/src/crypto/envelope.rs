@@ -0,0 +1,179 @@
+use base64::{Engine as _, engine::general_purpose};
+use ring::{aead, rand, rand::SecureRandom};
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use super::secret::SecretKey;
+
+/// 数据密钥长度
+#[allow(dead_code)]
+pub const DATA_KEY_LEN: usize = 32;
+
+/// 包装数据密钥使用的算法
+pub const KEY_WRAP_ALGORITHM: &str = "AES256GCM";
+
+/// 包装数据密钥时的附加认证数据，防止包装结果被挪作他用
+const KEY_WRAP_AAD: &[u8] = b"SecretGallery/data-key-wrap/v1";
+
+/// 包装nonce长度
+const KEY_WRAP_NONCE_LEN: usize = 12;
+
+/// 信封加密错误类型
+#[derive(Error, Debug)]
+pub enum EnvelopeError {
+    #[error("不支持的密钥包装算法: {0}")]
+    UnsupportedAlgorithm(String),
+    
+    #[error("随机数生成错误")]
+    RandomGenerationError,
+    
+    #[error("密钥包装失败")]
+    WrapError,
+    
+    #[error("密钥解包失败")]
+    UnwrapError,
+    
+    #[error("包装密钥格式错误: {0}")]
+    FormatError(String),
+}
+
+/// 被密钥加密密钥（KEK）包装后的数据密钥（DEK）
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct WrappedKey {
+    pub algorithm: String,
+    pub nonce: String,
+    pub wrapped_key: String,
+}
+
+impl WrappedKey {
+    /// 序列化为JSON，保存到 `encryption_keys.wrapped_key`
+    pub fn to_json(&self) -> Result<String, EnvelopeError> {
+        serde_json::to_string(self).map_err(|e| EnvelopeError::FormatError(e.to_string()))
+    }
+    
+    /// 从 `encryption_keys.wrapped_key` 中的JSON解析
+    pub fn from_json(json: &str) -> Result<Self, EnvelopeError> {
+        serde_json::from_str(json).map_err(|e| EnvelopeError::FormatError(e.to_string()))
+    }
+    
+    /// 编码为 nonce‖包装密文 的二进制形式，写入容器头部
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EnvelopeError> {
+        if self.algorithm != KEY_WRAP_ALGORITHM {
+            return Err(EnvelopeError::UnsupportedAlgorithm(self.algorithm.clone()));
+        }
+        
+        let mut bytes = general_purpose::STANDARD.decode(&self.nonce)
+            .map_err(|e| EnvelopeError::FormatError(e.to_string()))?;
+        bytes.extend(general_purpose::STANDARD.decode(&self.wrapped_key)
+            .map_err(|e| EnvelopeError::FormatError(e.to_string()))?);
+        Ok(bytes)
+    }
+    
+    /// 从容器头部中的二进制形式解析
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EnvelopeError> {
+        if bytes.len() <= KEY_WRAP_NONCE_LEN {
+            return Err(EnvelopeError::FormatError("包装密钥长度错误".to_string()));
+        }
+        
+        let (nonce, wrapped_key) = bytes.split_at(KEY_WRAP_NONCE_LEN);
+        Ok(WrappedKey {
+            algorithm: KEY_WRAP_ALGORITHM.to_string(),
+            nonce: general_purpose::STANDARD.encode(nonce),
+            wrapped_key: general_purpose::STANDARD.encode(wrapped_key),
+        })
+    }
+}
+
+/// 生成随机数据密钥
+pub fn generate_data_key() -> Result<SecretKey, EnvelopeError> {
+    let mut data_key = SecretKey::zeroed();
+    rand::SystemRandom::new()
+        .fill(&mut data_key[..])
+        .map_err(|_| EnvelopeError::RandomGenerationError)?;
+    Ok(data_key)
+}
+
+/// 创建包装用的AEAD密钥
+fn wrapping_key(kek: &[u8]) -> Result<aead::LessSafeKey, EnvelopeError> {
+    let unbound_key = aead::UnboundKey::new(&aead::AES_256_GCM, kek)
+        .map_err(|_| EnvelopeError::WrapError)?;
+    Ok(aead::LessSafeKey::new(unbound_key))
+}
+
+/// 用密钥加密密钥包装数据密钥
+pub fn wrap_data_key(kek: &[u8], data_key: &SecretKey) -> Result<WrappedKey, EnvelopeError> {
+    let key = wrapping_key(kek)?;
+    
+    let mut nonce = [0u8; KEY_WRAP_NONCE_LEN];
+    rand::SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| EnvelopeError::RandomGenerationError)?;
+    
+    let mut in_out = Zeroizing::new(data_key.to_vec());
+    key.seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce), aead::Aad::from(KEY_WRAP_AAD), &mut *in_out)
+        .map_err(|_| EnvelopeError::WrapError)?;
+    
+    Ok(WrappedKey {
+        algorithm: KEY_WRAP_ALGORITHM.to_string(),
+        nonce: general_purpose::STANDARD.encode(nonce),
+        wrapped_key: general_purpose::STANDARD.encode(&*in_out),
+    })
+}
+
+/// 用密钥加密密钥解包数据密钥
+pub fn unwrap_data_key(kek: &[u8], wrapped_key: &WrappedKey) -> Result<SecretKey, EnvelopeError> {
+    if wrapped_key.algorithm != KEY_WRAP_ALGORITHM {
+        return Err(EnvelopeError::UnsupportedAlgorithm(wrapped_key.algorithm.clone()));
+    }
+    
+    let key = wrapping_key(kek)?;
+    
+    let nonce: [u8; KEY_WRAP_NONCE_LEN] = general_purpose::STANDARD.decode(&wrapped_key.nonce)
+        .map_err(|e| EnvelopeError::FormatError(e.to_string()))?
+        .try_into()
+        .map_err(|_| EnvelopeError::FormatError("nonce长度错误".to_string()))?;
+    let mut in_out = Zeroizing::new(general_purpose::STANDARD.decode(&wrapped_key.wrapped_key)
+        .map_err(|e| EnvelopeError::FormatError(e.to_string()))?);
+    
+    let data_key = key.open_in_place(aead::Nonce::assume_unique_for_key(nonce), aead::Aad::from(KEY_WRAP_AAD), &mut in_out[..])
+        .map_err(|_| EnvelopeError::UnwrapError)?;
+    
+    SecretKey::from_slice(data_key).ok_or(EnvelopeError::UnwrapError)
+}
+
+/// 用新的密钥加密密钥重新包装数据密钥，数据本身无需重新加密
+#[allow(dead_code)]
+pub fn rewrap_data_key(old_kek: &[u8], new_kek: &[u8], wrapped_key: &WrappedKey) -> Result<WrappedKey, EnvelopeError> {
+    let data_key = unwrap_data_key(old_kek, wrapped_key)?;
+    wrap_data_key(new_kek, &data_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_wrap_unwrap_data_key() {
+        let kek = [7u8; 32];
+        let data_key = generate_data_key().unwrap();
+        
+        let wrapped = wrap_data_key(&kek, &data_key).unwrap();
+        assert_eq!(unwrap_data_key(&kek, &wrapped).unwrap(), data_key);
+        
+        // 序列化后可以还原
+        let restored = WrappedKey::from_json(&wrapped.to_json().unwrap()).unwrap();
+        assert_eq!(restored, wrapped);
+        let restored = WrappedKey::from_bytes(&wrapped.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored, wrapped);
+        
+        // 错误的KEK无法解包
+        assert!(matches!(unwrap_data_key(&[8u8; 32], &wrapped), Err(EnvelopeError::UnwrapError)));
+        
+        // 重新包装后只有新KEK可以解包，数据密钥不变
+        let new_kek = [9u8; 32];
+        let rewrapped = rewrap_data_key(&kek, &new_kek, &wrapped).unwrap();
+        assert_eq!(unwrap_data_key(&new_kek, &rewrapped).unwrap(), data_key);
+        assert!(unwrap_data_key(&kek, &rewrapped).is_err());
+    }
+}
@@ -1,14 +1,16 @@
-use ring::{digest, pbkdf2, rand, rand::SecureRandom};
+use ring::{aead, digest, hkdf, pbkdf2, rand, rand::SecureRandom};
 use std::num::NonZeroU32;
 use base64::{Engine as _, engine::general_purpose};
+use subtle::ConstantTimeEq;
 use thiserror::Error;
-use tracing::{error};
 
 use crate::config::{AppConfig};
+use super::secret::{SecretBytes, SecretKey, SecretString};
 
 /// 密钥管理错误类型
 #[derive(Error, Debug)]
 #[allow(dead_code)]
+#[allow(clippy::enum_variant_names)]
 pub enum KeyManagementError {
     #[error("密钥派生错误: {0}")]
     KeyDerivationError(String),
@@ -30,6 +32,151 @@ pub enum KeyManagementError {
     
     #[error("随机数生成错误")]
     RandomGenerationError,
+    
+    #[error("不支持的密钥组合方案版本: {0}")]
+    UnsupportedCombinerVersion(u32),
+    
+    #[error("不支持的密钥派生函数: {0}")]
+    UnsupportedKdf(String),
+    
+    #[error("密钥派生参数错误: {0}")]
+    KdfParamsError(String),
+    
+    #[error("密钥验证失败")]
+    KeyVerificationFailed,
+    
+    #[error("密钥分片参数错误: {0}")]
+    ShareParamsError(String),
+    
+    #[error("密钥分片格式错误")]
+    InvalidShare,
+    
+    #[error("密钥分片校验和错误，请检查是否抄写有误")]
+    ShareChecksumMismatch,
+    
+    #[error("密钥分片不属于同一次拆分")]
+    ShareSetMismatch,
+    
+    #[error("密钥分片重复: {0}")]
+    DuplicateShare(u8),
+    
+    #[error("密钥分片不足，需要{required}份，提供了{provided}份")]
+    InsufficientShares { required: u8, provided: usize },
+}
+
+/// 旧版密钥组合方案：直接拼接密钥A和硬件码B
+pub const KEY_COMBINER_LEGACY: u32 = 0;
+
+/// 密钥组合方案v1：带长度前缀和标签的HKDF-SHA256
+pub const KEY_COMBINER_V1: u32 = 1;
+
+/// 新资源使用的密钥组合方案版本
+pub const CURRENT_KEY_COMBINER: u32 = KEY_COMBINER_V1;
+
+/// 密钥组合方案v1的HKDF盐值（域分隔标签）
+const KEY_COMBINER_V1_SALT: &[u8] = b"SecretGallery/key-combiner/v1";
+
+/// 密钥组合方案v1的HKDF扩展信息
+const KEY_COMBINER_V1_INFO: &[u8] = b"SecretGallery/combined-key";
+
+/// 密钥A的标签
+const KEY_PART_A_LABEL: &[u8] = b"key-part-a";
+
+/// 硬件码B的标签
+const UKEY_PART_B_LABEL: &[u8] = b"ukey-part-b";
+
+/// 密钥校验块子密钥的HKDF扩展信息
+const KEY_CHECK_INFO: &[u8] = b"SecretGallery/key-check/v1";
+
+/// 密钥校验块中被加密的固定明文
+const KEY_CHECK_PLAINTEXT: &[u8] = b"SecretGallery key check";
+
+/// 密钥校验块nonce长度
+const KEY_CHECK_NONCE_LEN: usize = 12;
+
+/// 密钥派生函数：PBKDF2-HMAC-SHA256
+pub const KDF_PBKDF2_SHA256: &str = "PBKDF2-HMAC-SHA256";
+
+/// 密钥派生函数：Argon2id
+pub const KDF_ARGON2ID: &str = "ARGON2ID";
+
+/// PBKDF2的最小迭代次数
+const MIN_PBKDF2_ITERATIONS: u32 = 10000;
+
+/// PBKDF2的最大迭代次数，防止被篡改的参数造成拒绝服务
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+
+/// Argon2id的最小内存开销（KiB），即19MiB，参考OWASP建议
+pub const MIN_ARGON2_MEMORY_COST: u32 = 19 * 1024;
+
+/// Argon2id的最小迭代次数
+pub const MIN_ARGON2_TIME_COST: u32 = 2;
+
+/// Argon2id的最大内存开销（KiB），即1GiB
+const MAX_ARGON2_MEMORY_COST: u32 = 1024 * 1024;
+
+/// Argon2id的最大迭代次数
+const MAX_ARGON2_TIME_COST: u32 = 16;
+
+/// Argon2id的最大并行度
+const MAX_ARGON2_PARALLELISM: u32 = 16;
+
+/// Argon2id参数
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// 内存开销（KiB）
+    pub memory_cost: u32,
+    /// 迭代次数
+    pub time_cost: u32,
+    /// 并行度
+    pub parallelism: u32,
+}
+
+/// 密钥派生参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KdfParams {
+    /// PBKDF2-HMAC-SHA256
+    Pbkdf2 { iteration_count: u32 },
+    /// Argon2id
+    Argon2id(Argon2Params),
+}
+
+impl KdfParams {
+    /// 密钥派生函数标识
+    pub fn kdf_name(&self) -> &'static str {
+        match self {
+            KdfParams::Pbkdf2 { .. } => KDF_PBKDF2_SHA256,
+            KdfParams::Argon2id(_) => KDF_ARGON2ID,
+        }
+    }
+}
+
+/// 检查密钥派生参数是否在允许的范围内
+///
+/// 参数来自数据库或容器头部时先做范围检查，再执行派生。
+pub fn check_kdf_params(kdf_params: &KdfParams) -> Result<(), KeyManagementError> {
+    match kdf_params {
+        KdfParams::Pbkdf2 { iteration_count } => {
+            if !(MIN_PBKDF2_ITERATIONS..=MAX_PBKDF2_ITERATIONS).contains(iteration_count) {
+                return Err(KeyManagementError::IterationCountError);
+            }
+        }
+        KdfParams::Argon2id(params) => {
+            if !(MIN_ARGON2_MEMORY_COST..=MAX_ARGON2_MEMORY_COST).contains(&params.memory_cost)
+                || !(MIN_ARGON2_TIME_COST..=MAX_ARGON2_TIME_COST).contains(&params.time_cost)
+                || params.parallelism > MAX_ARGON2_PARALLELISM
+            {
+                return Err(KeyManagementError::KdfParamsError("Argon2id参数超出允许范围".to_string()));
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+/// 旧记录没有kdf字段，默认使用PBKDF2
+fn default_kdf() -> String {
+    KDF_PBKDF2_SHA256.to_string()
 }
 
 /// 密钥信息
@@ -37,13 +184,42 @@ pub enum KeyManagementError {
 pub struct KeyInfo {
     pub algorithm: String,
     pub salt: String,
+    /// PBKDF2迭代次数，使用Argon2id时为0
     pub iteration_count: u32,
+    /// 旧版无盐的组合密钥SHA-256哈希，已弃用，新记录为空
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub key_hash: String,
+    /// 旧版明文保存的硬件码B，已弃用，新记录为空
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub ukey_info: String,
+    /// 密钥组合方案版本，旧记录没有该字段，按旧版拼接方案处理
+    #[serde(default)]
+    pub combiner_version: u32,
+    /// 密钥派生函数，旧记录没有该字段，按PBKDF2处理
+    #[serde(default = "default_kdf")]
+    pub kdf: String,
+    /// Argon2id参数，仅在kdf为ARGON2ID时存在
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub argon2_params: Option<Argon2Params>,
+    /// 密钥校验块：用派生密钥加密的固定明文，验证密钥需要完整执行一次密钥派生
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_check: Option<String>,
 }
 
-/// 生成盐值
-#[allow(dead_code)]
+impl KeyInfo {
+    /// 从记录中解析密钥派生参数
+    pub fn kdf_params(&self) -> Result<KdfParams, KeyManagementError> {
+        match self.kdf.as_str() {
+            KDF_PBKDF2_SHA256 => Ok(KdfParams::Pbkdf2 { iteration_count: self.iteration_count }),
+            KDF_ARGON2ID => self.argon2_params
+                .map(KdfParams::Argon2id)
+                .ok_or_else(|| KeyManagementError::KdfParamsError("缺少Argon2id参数".to_string())),
+            other => Err(KeyManagementError::UnsupportedKdf(other.to_string())),
+        }
+    }
+}
+
+/// 生成随机盐值
 pub fn generate_salt() -> Vec<u8> {
     let mut salt = vec![0u8; 16];
     let rng = rand::SystemRandom::new();
@@ -51,89 +227,259 @@ pub fn generate_salt() -> Vec<u8> {
     salt
 }
 
-/// 从密钥部分A和B生成实际密钥
-pub fn generate_actual_key(key_part_a: &str, key_part_b: &str) -> String {
-    format!("{}{}", key_part_a, key_part_b)
+/// 从密钥部分A和B生成实际密钥（旧版拼接方案）
+pub fn generate_actual_key(key_part_a: &str, key_part_b: &str) -> SecretString {
+    let mut actual_key = String::with_capacity(key_part_a.len() + key_part_b.len());
+    actual_key.push_str(key_part_a);
+    actual_key.push_str(key_part_b);
+    SecretString::new(actual_key)
+}
+
+/// 在HKDF输入的指定位置写入带长度前缀的字段，返回写入后的位置
+fn write_length_prefixed(buffer: &mut [u8], offset: usize, field: &[u8]) -> usize {
+    let field_offset = offset + 4;
+    buffer[offset..field_offset].copy_from_slice(&(field.len() as u32).to_be_bytes());
+    buffer[field_offset..field_offset + field.len()].copy_from_slice(field);
+    field_offset + field.len()
+}
+
+/// 按指定版本的组合方案由密钥A和硬件码B生成组合密钥
+///
+/// v1对两个因子分别加标签和长度前缀后做HKDF-SHA256提取和扩展，
+/// 避免 ("ab", "c") 与 ("a", "bc") 得到相同的密钥。
+pub fn generate_combined_key(
+    combiner_version: u32,
+    key_part_a: &str,
+    key_part_b: &str
+) -> Result<SecretBytes, KeyManagementError> {
+    match combiner_version {
+        KEY_COMBINER_LEGACY => Ok(SecretBytes::new(generate_actual_key(key_part_a, key_part_b).expose_secret().as_bytes().to_vec())),
+        KEY_COMBINER_V1 => {
+            // 预先分配好容量，避免扩容时在堆上留下未清零的副本
+            let capacity = 4 * 4 + KEY_PART_A_LABEL.len() + key_part_a.len() + UKEY_PART_B_LABEL.len() + key_part_b.len();
+            let mut input_key_material = SecretBytes::zeroed(capacity);
+            let mut offset = 0;
+            for field in [KEY_PART_A_LABEL, key_part_a.as_bytes(), UKEY_PART_B_LABEL, key_part_b.as_bytes()] {
+                offset = write_length_prefixed(&mut input_key_material, offset, field);
+            }
+            
+            let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, KEY_COMBINER_V1_SALT).extract(&input_key_material);
+            let okm = prk.expand(&[KEY_COMBINER_V1_INFO], hkdf::HKDF_SHA256)
+                .map_err(|_| KeyManagementError::KeyDerivationError("HKDF扩展失败".to_string()))?;
+            
+            let mut combined_key = SecretBytes::zeroed(digest::SHA256_OUTPUT_LEN);
+            okm.fill(&mut combined_key)
+                .map_err(|_| KeyManagementError::KeyDerivationError("HKDF扩展失败".to_string()))?;
+            
+            Ok(combined_key)
+        }
+        other => Err(KeyManagementError::UnsupportedCombinerVersion(other)),
+    }
 }
 
 /// 生成密钥哈希
-pub fn generate_key_hash(key: &str) -> String {
-    let hash = digest::digest(&digest::SHA256, key.as_bytes());
+pub fn generate_key_hash(key: impl AsRef<[u8]>) -> String {
+    let hash = digest::digest(&digest::SHA256, key.as_ref());
     general_purpose::STANDARD.encode(hash.as_ref())
 }
 
+/// 常量时间比较两个字节串，耗时与内容无关
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
 /// 验证密钥哈希
-pub fn verify_key_hash(key: &str, expected_hash: &str) -> bool {
+pub fn verify_key_hash(key: impl AsRef<[u8]>, expected_hash: &str) -> bool {
     let actual_hash = generate_key_hash(key);
-    actual_hash == expected_hash
+    constant_time_eq(actual_hash.as_bytes(), expected_hash.as_bytes())
 }
 
 /// 从密码生成加密密钥
 pub fn derive_key_from_password(
-    password: &str, 
+    password: &[u8], 
     salt: &[u8], 
     iteration_count: u32
-) -> Result<[u8; 32], KeyManagementError> {
+) -> Result<SecretKey, KeyManagementError> {
     if salt.len() < 8 {
         return Err(KeyManagementError::SaltLengthError);
     }
     
-    if iteration_count < 10000 {
+    if iteration_count < MIN_PBKDF2_ITERATIONS {
         return Err(KeyManagementError::IterationCountError);
     }
     
     let iterations = NonZeroU32::new(iteration_count).ok_or(KeyManagementError::IterationCountError)?;
     
-    let mut key = [0u8; 32];
+    let mut key = SecretKey::zeroed();
     pbkdf2::derive(
         pbkdf2::PBKDF2_HMAC_SHA256,
         iterations,
         salt,
-        password.as_bytes(),
+        password,
         &mut key,
     );
     
     Ok(key)
 }
 
+/// 使用Argon2id从密码生成加密密钥
+pub fn derive_key_argon2id(
+    password: &[u8],
+    salt: &[u8],
+    params: &Argon2Params
+) -> Result<SecretKey, KeyManagementError> {
+    if salt.len() < 8 {
+        return Err(KeyManagementError::SaltLengthError);
+    }
+    
+    check_kdf_params(&KdfParams::Argon2id(*params))?;
+    
+    let argon2_params = argon2::Params::new(params.memory_cost, params.time_cost, params.parallelism, Some(32))
+        .map_err(|e| KeyManagementError::KdfParamsError(e.to_string()))?;
+    let argon2 = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, argon2_params);
+    
+    let mut key = SecretKey::zeroed();
+    argon2.hash_password_into(password, salt, &mut key)
+        .map_err(|e| KeyManagementError::KeyDerivationError(e.to_string()))?;
+    
+    Ok(key)
+}
+
+/// 按密钥派生参数选择对应的派生函数
+pub fn derive_key(
+    password: &[u8],
+    salt: &[u8],
+    kdf_params: &KdfParams
+) -> Result<SecretKey, KeyManagementError> {
+    check_kdf_params(kdf_params)?;
+    
+    match kdf_params {
+        KdfParams::Pbkdf2 { iteration_count } => derive_key_from_password(password, salt, *iteration_count),
+        KdfParams::Argon2id(params) => derive_key_argon2id(password, salt, params),
+    }
+}
+
+/// 由派生密钥生成校验块使用的AEAD密钥
+fn key_check_key(key: &[u8]) -> Result<aead::LessSafeKey, KeyManagementError> {
+    let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]).extract(key);
+    let okm = prk.expand(&[KEY_CHECK_INFO], &aead::AES_256_GCM)
+        .map_err(|_| KeyManagementError::KeyDerivationError("HKDF扩展失败".to_string()))?;
+    Ok(aead::LessSafeKey::new(aead::UnboundKey::from(okm)))
+}
+
+/// 生成密钥校验块
+///
+/// 校验块为 nonce‖密文‖标签 的Base64编码，只有持有派生密钥才能打开，
+/// 离线猜测密钥A的代价与密钥派生函数相同。
+pub fn generate_key_check(key: &[u8]) -> Result<String, KeyManagementError> {
+    let check_key = key_check_key(key)?;
+    
+    let mut nonce = [0u8; KEY_CHECK_NONCE_LEN];
+    rand::SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| KeyManagementError::RandomGenerationError)?;
+    
+    let mut in_out = KEY_CHECK_PLAINTEXT.to_vec();
+    check_key.seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce), aead::Aad::from(KEY_CHECK_INFO), &mut in_out)
+        .map_err(|_| KeyManagementError::KeyDerivationError("生成密钥校验块失败".to_string()))?;
+    
+    let mut key_check = nonce.to_vec();
+    key_check.extend_from_slice(&in_out);
+    Ok(general_purpose::STANDARD.encode(key_check))
+}
+
+/// 用派生密钥验证密钥校验块
+pub fn verify_key_check(key: &[u8], key_check: &str) -> Result<bool, KeyManagementError> {
+    let key_check = general_purpose::STANDARD.decode(key_check)?;
+    if key_check.len() <= KEY_CHECK_NONCE_LEN {
+        return Ok(false);
+    }
+    
+    let (nonce, sealed) = key_check.split_at(KEY_CHECK_NONCE_LEN);
+    let nonce = aead::Nonce::try_assume_unique_for_key(nonce)
+        .map_err(|_| KeyManagementError::KeyDerivationError("密钥校验块格式错误".to_string()))?;
+    let mut in_out = sealed.to_vec();
+    
+    let check_key = key_check_key(key)?;
+    Ok(check_key.open_in_place(nonce, aead::Aad::from(KEY_CHECK_INFO), &mut in_out)
+        .map(|plaintext| constant_time_eq(plaintext, KEY_CHECK_PLAINTEXT))
+        .unwrap_or(false))
+}
+
 /// 生成密钥信息
+///
+/// 同时返回派生出的密钥，避免调用方再执行一次密钥派生。
+/// 记录中只保存盐值、派生参数和密钥校验块，不保存硬件码B和密钥哈希。
 pub fn generate_key_info(
     algorithm: &str, 
     salt: &[u8], 
-    iteration_count: u32,
+    kdf_params: &KdfParams,
     key_part_a: &str,
     ukey_part_b: &str
-) -> Result<KeyInfo, KeyManagementError> {
-    let actual_key = generate_actual_key(key_part_a, ukey_part_b);
-    let key_hash = generate_key_hash(&actual_key);
+) -> Result<(KeyInfo, SecretKey), KeyManagementError> {
+    let combined_key = generate_combined_key(CURRENT_KEY_COMBINER, key_part_a, ukey_part_b)?;
+    let key = derive_key(&combined_key, salt, kdf_params)?;
+    
+    let (iteration_count, argon2_params) = match kdf_params {
+        KdfParams::Pbkdf2 { iteration_count } => (*iteration_count, None),
+        KdfParams::Argon2id(params) => (0, Some(*params)),
+    };
     
-    Ok(KeyInfo {
+    let key_info = KeyInfo {
         algorithm: algorithm.to_string(),
         salt: general_purpose::STANDARD.encode(salt),
         iteration_count,
-        key_hash,
-        ukey_info: general_purpose::STANDARD.encode(ukey_part_b.as_bytes()),
-    })
+        key_hash: String::new(),
+        ukey_info: String::new(),
+        combiner_version: CURRENT_KEY_COMBINER,
+        kdf: kdf_params.kdf_name().to_string(),
+        argon2_params,
+        key_check: Some(generate_key_check(&key)?),
+    };
+    
+    Ok((key_info, key))
 }
 
-/// 从密钥信息中获取密钥
-pub fn get_key_from_info(
+/// 从密钥信息中派生密钥并返回验证结果
+///
+/// 无论验证是否通过都会完整执行密钥派生和校验，不提前返回。有密钥校验块的记录验证校验块，
+/// 旧记录若仍保留密钥哈希则验证哈希，哈希已被清除的旧记录由数据本身的认证标签发现错误的密钥。
+pub fn derive_key_from_info(
     key_info: &KeyInfo,
     key_part_a: &str,
     ukey_part_b: &str
-) -> Result<[u8; 32], KeyManagementError> {
-    let actual_key = generate_actual_key(key_part_a, ukey_part_b);
-    
-    // 验证密钥哈希
-    if !verify_key_hash(&actual_key, &key_info.key_hash) {
-        return Err(KeyManagementError::KeyDerivationError("密钥验证失败".to_string()));
-    }
+) -> Result<(SecretKey, bool), KeyManagementError> {
+    // 按记录中的组合方案版本生成组合密钥，旧记录仍使用拼接方案
+    let combined_key = generate_combined_key(key_info.combiner_version, key_part_a, ukey_part_b)?;
     
     // 解码盐值
     let salt = general_purpose::STANDARD.decode(&key_info.salt)?;
     
-    // 派生密钥
-    derive_key_from_password(&actual_key, &salt, key_info.iteration_count)
+    // 按记录中的密钥派生函数派生密钥
+    let key = derive_key(&combined_key, &salt, &key_info.kdf_params()?)?;
+    
+    // 验证密钥校验块或旧版密钥哈希
+    let verified = match &key_info.key_check {
+        Some(key_check) => verify_key_check(&key, key_check)?,
+        None if !key_info.key_hash.is_empty() => verify_key_hash(&combined_key, &key_info.key_hash),
+        None => true,
+    };
+    
+    Ok((key, verified))
+}
+
+/// 从密钥信息中获取密钥，验证失败时返回 `KeyVerificationFailed`
+pub fn get_key_from_info(
+    key_info: &KeyInfo,
+    key_part_a: &str,
+    ukey_part_b: &str
+) -> Result<SecretKey, KeyManagementError> {
+    let (key, verified) = derive_key_from_info(key_info, key_part_a, ukey_part_b)?;
+    if !verified {
+        return Err(KeyManagementError::KeyVerificationFailed);
+    }
+    
+    Ok(key)
 }
 
 /// 从配置中获取加密算法
@@ -141,17 +487,36 @@ pub fn get_encryption_algorithm(config: &AppConfig) -> &str {
     &config.encryption.algorithm
 }
 
-/// 从配置中获取加密盐值
-pub fn get_encryption_salt(config: &AppConfig) -> Vec<u8> {
-    config.encryption.salt.as_bytes().to_vec()
-}
-
 /// 从配置中获取密钥派生迭代次数
 pub fn get_key_derivation_iterations(config: &AppConfig) -> u32 {
     config.encryption.key_derivation_iterations
 }
 
+/// 从配置中获取新资源使用的密钥派生参数
+pub fn get_kdf_params(config: &AppConfig) -> Result<KdfParams, KeyManagementError> {
+    let kdf_params = match config.encryption.key_derivation_function.as_str() {
+        KDF_PBKDF2_SHA256 => KdfParams::Pbkdf2 {
+            iteration_count: get_key_derivation_iterations(config),
+        },
+        KDF_ARGON2ID => KdfParams::Argon2id(Argon2Params {
+            memory_cost: config.encryption.argon2_memory_cost,
+            time_cost: config.encryption.argon2_time_cost,
+            parallelism: config.encryption.argon2_parallelism,
+        }),
+        other => return Err(KeyManagementError::UnsupportedKdf(other.to_string())),
+    };
+    check_kdf_params(&kdf_params)?;
+    
+    Ok(kdf_params)
+}
+
+/// 从配置中获取分段加密的分段大小
+pub fn get_encryption_chunk_size(config: &AppConfig) -> u32 {
+    config.encryption.chunk_size
+}
+
 /// 生成随机IV
+#[allow(dead_code)]
 pub fn generate_iv() -> Vec<u8> {
     let mut iv = vec![0u8; 12]; // GCM建议使用12字节IV
     let rng = rand::SystemRandom::new();
@@ -168,6 +533,214 @@ pub fn generate_random_key() -> [u8; 32] {
     key
 }
 
+/// 密钥分片格式版本
+const KEY_SHARE_VERSION: u8 = 1;
+
+/// 密钥分片中拆分批次标识的长度，不同批次的分片不能混用
+const KEY_SHARE_SET_ID_LEN: usize = 4;
+
+/// 密钥分片末尾校验和的长度，用于发现抄写错误
+const KEY_SHARE_CHECKSUM_LEN: usize = 2;
+
+/// 密钥分片显示时每组的字符数
+const KEY_SHARE_GROUP_LEN: usize = 5;
+
+/// GF(2^8)乘法，约化多项式为 x^8 + x^4 + x^3 + x + 1
+///
+/// 不按秘密数据分支，执行时间与输入无关。
+fn gf256_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (carry & 0x1b);
+        b >>= 1;
+    }
+    product
+}
+
+/// GF(2^8)求逆，a^254 = a^-1
+fn gf256_inv(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut base = a;
+    let mut exponent = 254u8;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = gf256_mul(result, base);
+        }
+        base = gf256_mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// 计算分片校验和，覆盖分片的所有字段
+fn key_share_checksum(payload: &[u8]) -> [u8; KEY_SHARE_CHECKSUM_LEN] {
+    let digest = digest::digest(&digest::SHA256, payload);
+    let mut checksum = [0u8; KEY_SHARE_CHECKSUM_LEN];
+    checksum.copy_from_slice(&digest.as_ref()[..KEY_SHARE_CHECKSUM_LEN]);
+    checksum
+}
+
+/// Shamir秘密分享的一个密钥分片
+///
+/// 由保管人离线保存，任意 `threshold` 份即可重建被拆分的主密钥，少于该数量时得不到任何信息。
+/// 显示形式只包含大写字母、数字和连字符，可以直接打印或以QR码字母数字模式编码。
+pub struct KeyShare {
+    /// 拆分批次标识
+    pub set_id: [u8; KEY_SHARE_SET_ID_LEN],
+    /// 重建需要的分片数
+    pub threshold: u8,
+    /// 分片序号，从1开始
+    pub index: u8,
+    value: SecretBytes,
+}
+
+impl KeyShare {
+    /// 分片的二进制形式：版本、批次标识、门限、序号、分片值和校验和
+    fn to_bytes(&self) -> SecretBytes {
+        let mut bytes = SecretBytes::zeroed(3 + KEY_SHARE_SET_ID_LEN + self.value.len() + KEY_SHARE_CHECKSUM_LEN);
+        let payload_len = bytes.len() - KEY_SHARE_CHECKSUM_LEN;
+        bytes[0] = KEY_SHARE_VERSION;
+        bytes[1..1 + KEY_SHARE_SET_ID_LEN].copy_from_slice(&self.set_id);
+        bytes[1 + KEY_SHARE_SET_ID_LEN] = self.threshold;
+        bytes[2 + KEY_SHARE_SET_ID_LEN] = self.index;
+        bytes[3 + KEY_SHARE_SET_ID_LEN..payload_len].copy_from_slice(&self.value);
+        let checksum = key_share_checksum(&bytes[..payload_len]);
+        bytes[payload_len..].copy_from_slice(&checksum);
+        bytes
+    }
+    
+    /// 可打印的分片，Crockford base32分组
+    pub fn to_display(&self) -> SecretString {
+        let bytes = self.to_bytes();
+        let encoded = SecretString::new(base32::encode(base32::Alphabet::Crockford, &bytes));
+        let groups: Vec<&str> = encoded
+            .expose_secret()
+            .as_bytes()
+            .chunks(KEY_SHARE_GROUP_LEN)
+            .map(|group| std::str::from_utf8(group).expect("base32编码结果只包含ASCII字符"))
+            .collect();
+        SecretString::new(groups.join("-"))
+    }
+    
+    /// 解析保管人输入的分片，忽略分隔符、空白和大小写
+    pub fn parse(share: &str) -> Result<Self, KeyManagementError> {
+        let normalized = SecretString::new(share
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_uppercase())
+            .collect());
+        let bytes = SecretBytes::new(
+            base32::decode(base32::Alphabet::Crockford, normalized.expose_secret()).ok_or(KeyManagementError::InvalidShare)?
+        );
+        if bytes.len() <= 3 + KEY_SHARE_SET_ID_LEN + KEY_SHARE_CHECKSUM_LEN || bytes[0] != KEY_SHARE_VERSION {
+            return Err(KeyManagementError::InvalidShare);
+        }
+        
+        let payload_len = bytes.len() - KEY_SHARE_CHECKSUM_LEN;
+        if key_share_checksum(&bytes[..payload_len])[..] != bytes[payload_len..] {
+            return Err(KeyManagementError::ShareChecksumMismatch);
+        }
+        
+        let mut set_id = [0u8; KEY_SHARE_SET_ID_LEN];
+        set_id.copy_from_slice(&bytes[1..1 + KEY_SHARE_SET_ID_LEN]);
+        let threshold = bytes[1 + KEY_SHARE_SET_ID_LEN];
+        let index = bytes[2 + KEY_SHARE_SET_ID_LEN];
+        if threshold < 2 || index == 0 {
+            return Err(KeyManagementError::InvalidShare);
+        }
+        
+        let mut value = SecretBytes::zeroed(payload_len - 3 - KEY_SHARE_SET_ID_LEN);
+        value.copy_from_slice(&bytes[3 + KEY_SHARE_SET_ID_LEN..payload_len]);
+        
+        Ok(KeyShare { set_id, threshold, index, value })
+    }
+}
+
+/// 用Shamir秘密分享把主密钥拆分为 `share_count` 份，任意 `threshold` 份可以重建
+///
+/// 在GF(2^8)上对每个字节独立构造 `threshold - 1` 次随机多项式，常数项为该字节，
+/// 第i份分片是各多项式在x=i处的值。门限至少为2，分片数不超过255。
+pub fn split_master_key(
+    master_key: &[u8],
+    threshold: u8,
+    share_count: u8
+) -> Result<Vec<KeyShare>, KeyManagementError> {
+    if master_key.is_empty() {
+        return Err(KeyManagementError::KeyLengthError);
+    }
+    if threshold < 2 {
+        return Err(KeyManagementError::ShareParamsError("门限至少为2".to_string()));
+    }
+    if share_count < threshold {
+        return Err(KeyManagementError::ShareParamsError("分片数不能少于门限".to_string()));
+    }
+    
+    let rng = rand::SystemRandom::new();
+    let mut set_id = [0u8; KEY_SHARE_SET_ID_LEN];
+    rng.fill(&mut set_id).map_err(|_| KeyManagementError::RandomGenerationError)?;
+    
+    // 每个字节的多项式系数，第0项为主密钥字节
+    let degree = threshold as usize - 1;
+    let mut coefficients = SecretBytes::zeroed(master_key.len() * degree);
+    rng.fill(&mut coefficients).map_err(|_| KeyManagementError::RandomGenerationError)?;
+    
+    let shares = (1..=share_count)
+        .map(|x| {
+            let mut value = SecretBytes::zeroed(master_key.len());
+            for (i, byte) in value.iter_mut().enumerate() {
+                // 秦九韶算法求多项式在x处的值
+                let mut y = 0u8;
+                for coefficient in coefficients[i * degree..(i + 1) * degree].iter().rev() {
+                    y = gf256_mul(y, x) ^ coefficient;
+                }
+                *byte = gf256_mul(y, x) ^ master_key[i];
+            }
+            KeyShare { set_id, threshold, index: x, value }
+        })
+        .collect();
+    
+    Ok(shares)
+}
+
+/// 用足够数量的密钥分片重建主密钥
+///
+/// 分片必须来自同一次拆分且序号不重复，多于门限时只使用前 `threshold` 份。
+/// 分片本身无法判断重建结果是否正确，调用方需要用主密钥对应的公钥或校验信息核对。
+pub fn combine_key_shares(shares: &[KeyShare]) -> Result<SecretBytes, KeyManagementError> {
+    let first = shares.first().ok_or(KeyManagementError::InsufficientShares { required: 2, provided: 0 })?;
+    if shares.len() < first.threshold as usize {
+        return Err(KeyManagementError::InsufficientShares { required: first.threshold, provided: shares.len() });
+    }
+    
+    let shares = &shares[..first.threshold as usize];
+    for (i, share) in shares.iter().enumerate() {
+        if share.set_id != first.set_id || share.threshold != first.threshold || share.value.len() != first.value.len() {
+            return Err(KeyManagementError::ShareSetMismatch);
+        }
+        if shares[..i].iter().any(|other| other.index == share.index) {
+            return Err(KeyManagementError::DuplicateShare(share.index));
+        }
+    }
+    
+    // 拉格朗日插值求x=0处的值，GF(2^8)中减法即异或
+    let mut master_key = SecretBytes::zeroed(first.value.len());
+    for (i, share) in shares.iter().enumerate() {
+        let mut basis = 1u8;
+        for (j, other) in shares.iter().enumerate() {
+            if i != j {
+                basis = gf256_mul(basis, gf256_mul(other.index, gf256_inv(other.index ^ share.index)));
+            }
+        }
+        for (byte, value) in master_key.iter_mut().zip(share.value.iter()) {
+            *byte ^= gf256_mul(*value, basis);
+        }
+    }
+    
+    Ok(master_key)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,7 +751,89 @@ mod tests {
         let key_part_b = "ukey123";
         let expected = "password123ukey123";
         let actual = generate_actual_key(key_part_a, key_part_b);
-        assert_eq!(actual, expected);
+        assert_eq!(actual.expose_secret(), expected);
+    }
+    
+    #[test]
+    fn test_generate_combined_key() {
+        // 旧版方案与直接拼接一致
+        let legacy = generate_combined_key(KEY_COMBINER_LEGACY, "password123", "ukey123").unwrap();
+        assert_eq!(&legacy[..], b"password123ukey123");
+        
+        // v1方案对因子边界敏感
+        let key1 = generate_combined_key(KEY_COMBINER_V1, "ab", "c").unwrap();
+        let key2 = generate_combined_key(KEY_COMBINER_V1, "a", "bc").unwrap();
+        assert_eq!(key1.len(), 32);
+        assert_ne!(&key1[..], &key2[..]);
+        
+        // 交换两个因子也会得到不同的密钥
+        let key3 = generate_combined_key(KEY_COMBINER_V1, "c", "ab").unwrap();
+        assert_ne!(&key1[..], &key3[..]);
+        
+        // 相同输入结果确定
+        assert_eq!(&key1[..], &generate_combined_key(KEY_COMBINER_V1, "ab", "c").unwrap()[..]);
+        
+        // 未知版本返回错误
+        assert!(matches!(
+            generate_combined_key(99, "ab", "c"),
+            Err(KeyManagementError::UnsupportedCombinerVersion(99))
+        ));
+    }
+    
+    #[test]
+    fn test_get_key_from_legacy_key_info() {
+        let salt = generate_salt();
+        
+        // 模拟没有combiner_version字段的旧记录
+        let legacy_key_info: KeyInfo = serde_json::from_value(serde_json::json!({
+            "algorithm": "AES256GCM",
+            "salt": general_purpose::STANDARD.encode(&salt),
+            "iteration_count": 10000,
+            "key_hash": generate_key_hash(generate_actual_key("ab", "c").expose_secret()),
+            "ukey_info": "",
+        })).unwrap();
+        assert_eq!(legacy_key_info.combiner_version, KEY_COMBINER_LEGACY);
+        assert_eq!(legacy_key_info.kdf, KDF_PBKDF2_SHA256);
+        
+        let key = get_key_from_info(&legacy_key_info, "ab", "c").unwrap();
+        let expected = derive_key_from_password(b"abc", &salt, 10000).unwrap();
+        assert_eq!(key, expected);
+        
+        // 新记录使用v1方案，拼接相同但边界不同的因子无法通过验证
+        let (key_info, _) = generate_key_info("AES256GCM", &salt, &KdfParams::Pbkdf2 { iteration_count: 10000 }, "ab", "c").unwrap();
+        assert_eq!(key_info.combiner_version, CURRENT_KEY_COMBINER);
+        assert!(get_key_from_info(&key_info, "ab", "c").is_ok());
+        assert!(get_key_from_info(&key_info, "a", "bc").is_err());
+    }
+    
+    #[test]
+    fn test_key_check_replaces_stored_secrets() {
+        let salt = generate_salt();
+        let params = KdfParams::Pbkdf2 { iteration_count: 10000 };
+        let (key_info, key) = generate_key_info("AES256GCM", &salt, &params, "password", "ukey_secret").unwrap();
+        
+        // 记录中不保存硬件码B和无盐哈希
+        assert!(key_info.ukey_info.is_empty());
+        assert!(key_info.key_hash.is_empty());
+        let key_info_json = serde_json::to_string(&key_info).unwrap();
+        assert!(!key_info_json.contains("ukey_info"));
+        assert!(!key_info_json.contains("key_hash"));
+        assert!(!key_info_json.contains(&general_purpose::STANDARD.encode("ukey_secret")));
+        
+        // 校验块只能用正确的派生密钥打开
+        let key_check = key_info.key_check.as_deref().unwrap();
+        assert!(verify_key_check(&key, key_check).unwrap());
+        assert!(!verify_key_check(&[0u8; 32], key_check).unwrap());
+        
+        assert_eq!(get_key_from_info(&key_info, "password", "ukey_secret").unwrap(), key);
+        assert!(matches!(
+            get_key_from_info(&key_info, "wrong_password", "ukey_secret"),
+            Err(KeyManagementError::KeyVerificationFailed)
+        ));
+        assert!(matches!(
+            get_key_from_info(&key_info, "password", "wrong_ukey"),
+            Err(KeyManagementError::KeyVerificationFailed)
+        ));
     }
     
     #[test]
@@ -207,18 +862,102 @@ mod tests {
         let salt = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
         let iteration_count = 100000;
         
-        let key = derive_key_from_password(password, &salt, iteration_count).unwrap();
+        let key = derive_key_from_password(password.as_bytes(), &salt, iteration_count).unwrap();
         assert_eq!(key.len(), 32);
         
         // 相同参数应生成相同密钥
-        let key2 = derive_key_from_password(password, &salt, iteration_count).unwrap();
+        let key2 = derive_key_from_password(password.as_bytes(), &salt, iteration_count).unwrap();
         assert_eq!(key, key2);
         
         // 不同密码应生成不同密钥
-        let key3 = derive_key_from_password("different_password", &salt, iteration_count).unwrap();
+        let key3 = derive_key_from_password(b"different_password", &salt, iteration_count).unwrap();
         assert_ne!(key, key3);
     }
     
+    #[test]
+    fn test_derive_key_argon2id() {
+        let salt = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        let params = Argon2Params { memory_cost: MIN_ARGON2_MEMORY_COST, time_cost: MIN_ARGON2_TIME_COST, parallelism: 1 };
+        
+        let key = derive_key_argon2id(b"test_password", &salt, &params).unwrap();
+        assert_eq!(key, derive_key(b"test_password", &salt, &KdfParams::Argon2id(params)).unwrap());
+        
+        // 参数不同得到的密钥不同
+        let stronger = Argon2Params { time_cost: params.time_cost + 1, ..params };
+        assert_ne!(key, derive_key_argon2id(b"test_password", &salt, &stronger).unwrap());
+        
+        // 与PBKDF2派生的密钥不同
+        assert_ne!(key, derive_key_from_password(b"test_password", &salt, 10000).unwrap());
+        
+        // 非法参数返回错误
+        let invalid = Argon2Params { memory_cost: 1, time_cost: 1, parallelism: 1 };
+        assert!(matches!(
+            derive_key_argon2id(b"test_password", &salt, &invalid),
+            Err(KeyManagementError::KdfParamsError(_))
+        ));
+    }
+    
+    #[test]
+    fn test_check_kdf_params_minimums() {
+        let minimum = Argon2Params { memory_cost: MIN_ARGON2_MEMORY_COST, time_cost: MIN_ARGON2_TIME_COST, parallelism: 1 };
+        assert!(check_kdf_params(&KdfParams::Argon2id(minimum)).is_ok());
+        
+        // 低于最小内存开销或迭代次数的参数被拒绝，包括来自容器头部的参数
+        for weak in [
+            Argon2Params { memory_cost: 8, time_cost: 1, ..minimum },
+            Argon2Params { memory_cost: MIN_ARGON2_MEMORY_COST - 1, ..minimum },
+            Argon2Params { time_cost: MIN_ARGON2_TIME_COST - 1, ..minimum },
+        ] {
+            assert!(matches!(
+                check_kdf_params(&KdfParams::Argon2id(weak)),
+                Err(KeyManagementError::KdfParamsError(_))
+            ));
+            assert!(derive_key(b"test_password", &generate_salt(), &KdfParams::Argon2id(weak)).is_err());
+        }
+        
+        // 加载配置时拒绝过弱的Argon2id参数
+        let mut config = crate::crypto::decode::tests::test_config();
+        assert!(config.validate().is_ok());
+        config.encryption.argon2_memory_cost = 8;
+        config.encryption.argon2_time_cost = 1;
+        assert!(matches!(config.validate(), Err(crate::config::ConfigError::ParseError(name, _)) if name == "ARGON2_MEMORY_COST"));
+        config.encryption.argon2_memory_cost = MIN_ARGON2_MEMORY_COST;
+        assert!(matches!(config.validate(), Err(crate::config::ConfigError::ParseError(name, _)) if name == "ARGON2_TIME_COST"));
+    }
+    
+    #[test]
+    fn test_get_key_from_argon2id_key_info() {
+        let salt = generate_salt();
+        let params = KdfParams::Argon2id(Argon2Params { memory_cost: MIN_ARGON2_MEMORY_COST, time_cost: MIN_ARGON2_TIME_COST, parallelism: 1 });
+        
+        let (key_info, derived_key) = generate_key_info("AES256GCM", &salt, &params, "password", "ukey").unwrap();
+        assert_eq!(key_info.kdf, KDF_ARGON2ID);
+        assert_eq!(key_info.kdf_params().unwrap(), params);
+        
+        // 序列化后参数随记录保存
+        let restored: KeyInfo = serde_json::from_str(&serde_json::to_string(&key_info).unwrap()).unwrap();
+        let key = get_key_from_info(&restored, "password", "ukey").unwrap();
+        let combined_key = generate_combined_key(CURRENT_KEY_COMBINER, "password", "ukey").unwrap();
+        assert_eq!(key, derive_key(&combined_key, &salt, &params).unwrap());
+        assert_eq!(key, derived_key);
+        
+        // 未知的派生函数返回错误
+        let mut unknown = restored.clone();
+        unknown.kdf = "SCRYPT".to_string();
+        assert!(matches!(
+            get_key_from_info(&unknown, "password", "ukey"),
+            Err(KeyManagementError::UnsupportedKdf(_))
+        ));
+    }
+    
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"same", b"same"));
+        assert!(!constant_time_eq(b"same", b"sane"));
+        assert!(!constant_time_eq(b"same", b"same_but_longer"));
+        assert!(constant_time_eq(b"", b""));
+    }
+    
     #[test]
     fn test_generate_iv() {
         let iv = generate_iv();
@@ -236,4 +975,41 @@ mod tests {
         let key2 = generate_random_key();
         assert_ne!(key, key2);
     }
+    
+    #[test]
+    fn test_split_and_combine_master_key() {
+        let master_key = generate_random_key();
+        let shares = split_master_key(&master_key, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        
+        // 任意3份分片经过打印和重新输入后都能重建
+        let printed: Vec<SecretString> = shares.iter().map(|share| share.to_display()).collect();
+        for combination in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let selected: Vec<KeyShare> = combination.iter()
+                .map(|&i| KeyShare::parse(&printed[i].expose_secret().to_lowercase()).unwrap())
+                .collect();
+            assert_eq!(&combine_key_shares(&selected).unwrap()[..], &master_key[..]);
+        }
+        
+        // 分片不足、重复或抄写错误时拒绝
+        let two: Vec<KeyShare> = printed[..2].iter().map(|share| KeyShare::parse(share.expose_secret()).unwrap()).collect();
+        assert!(matches!(combine_key_shares(&two), Err(KeyManagementError::InsufficientShares { required: 3, provided: 2 })));
+        let duplicated: Vec<KeyShare> = [0, 1, 0].iter().map(|&i| KeyShare::parse(printed[i].expose_secret()).unwrap()).collect();
+        assert!(matches!(combine_key_shares(&duplicated), Err(KeyManagementError::DuplicateShare(1))));
+        let mut typo = printed[0].expose_secret().to_string();
+        typo.replace_range(7..8, if &typo[7..8] == "A" { "B" } else { "A" });
+        assert!(matches!(KeyShare::parse(&typo), Err(KeyManagementError::ShareChecksumMismatch)));
+        
+        // 不同批次的分片不能混用
+        let other = split_master_key(&master_key, 3, 5).unwrap();
+        let mixed = vec![
+            KeyShare::parse(printed[0].expose_secret()).unwrap(),
+            KeyShare::parse(printed[1].expose_secret()).unwrap(),
+            KeyShare::parse(other[2].to_display().expose_secret()).unwrap(),
+        ];
+        assert!(matches!(combine_key_shares(&mixed), Err(KeyManagementError::ShareSetMismatch)));
+        
+        assert!(split_master_key(&master_key, 1, 3).is_err());
+        assert!(split_master_key(&master_key, 4, 3).is_err());
+    }
 }
@@ -0,0 +1,185 @@
+use base64::{Engine as _, engine::general_purpose};
+use ring::{digest, signature, signature::KeyPair};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// 清单摘要的域分隔标签
+const MANIFEST_LABEL: &[u8] = b"SecretGallery/manifest/v1";
+
+/// 容器哈希的域分隔标签
+const CONTAINER_HASH_LABEL: &[u8] = b"SecretGallery/container-hash/v1";
+
+/// Ed25519签名密钥种子长度
+const SIGNING_SEED_LEN: usize = 32;
+
+/// 签名清单错误类型
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("清单签名密钥格式错误，需要Base64编码的32字节Ed25519种子")]
+    InvalidSigningKey,
+    
+    #[error("清单验证公钥格式错误")]
+    InvalidPublicKey,
+}
+
+/// 清单中单个资源的记录
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub resource_id: i32,
+    pub uuid: Uuid,
+    /// 加密容器和加密元数据的SHA-256
+    pub container_hash: Vec<u8>,
+    /// 资源每次写入加一，用于区分同一资源的不同版本
+    pub version: i64,
+}
+
+/// 逐块计算资源的容器哈希，覆盖加密容器和加密元数据
+///
+/// 哈希为 标签 ‖ 容器长度(8) ‖ 容器 ‖ 元数据标记(1) [‖ 元数据长度(8) ‖ 元数据]。
+/// 容器长度在开头参与哈希，需要事先知道，调用方保证写入的总长度与之一致。
+pub struct ContainerHasher {
+    context: digest::Context,
+}
+
+impl ContainerHasher {
+    pub fn new(container_len: u64) -> Self {
+        let mut context = digest::Context::new(&digest::SHA256);
+        context.update(CONTAINER_HASH_LABEL);
+        context.update(&container_len.to_be_bytes());
+        ContainerHasher { context }
+    }
+    
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.context.update(chunk);
+    }
+    
+    pub fn finish(mut self, encrypted_metadata: Option<&[u8]>) -> Vec<u8> {
+        match encrypted_metadata {
+            Some(encrypted_metadata) => {
+                self.context.update(&[1]);
+                self.context.update(&(encrypted_metadata.len() as u64).to_be_bytes());
+                self.context.update(encrypted_metadata);
+            }
+            None => self.context.update(&[0]),
+        }
+        self.context.finish().as_ref().to_vec()
+    }
+}
+
+/// 计算清单摘要
+///
+/// 摘要覆盖清单序号和按资源ID排序的全部记录：标签 ‖ 序号(8) ‖ 记录数(8) ‖ 每条记录的 ID(4) ‖ UUID(16) ‖ 版本(8) ‖ 容器哈希(32)。
+/// 调用方需要保证 `entries` 已按资源ID排序。
+pub fn manifest_digest(sequence: i64, entries: &[ManifestEntry]) -> Vec<u8> {
+    let mut context = digest::Context::new(&digest::SHA256);
+    context.update(MANIFEST_LABEL);
+    context.update(&sequence.to_be_bytes());
+    context.update(&(entries.len() as u64).to_be_bytes());
+    for entry in entries {
+        context.update(&entry.resource_id.to_be_bytes());
+        context.update(entry.uuid.as_bytes());
+        context.update(&entry.version.to_be_bytes());
+        context.update(&entry.container_hash);
+    }
+    context.finish().as_ref().to_vec()
+}
+
+/// 清单签名密钥
+///
+/// 由配置的Ed25519种子生成，数据库中只保存公钥和签名，拿到数据库的攻击者无法伪造清单。
+pub struct ManifestSigner {
+    key_pair: signature::Ed25519KeyPair,
+}
+
+impl ManifestSigner {
+    /// 由Base64编码的32字节种子生成签名密钥
+    pub fn from_base64(seed: &str) -> Result<Self, ManifestError> {
+        let seed = zeroize::Zeroizing::new(general_purpose::STANDARD.decode(seed.trim())
+            .map_err(|_| ManifestError::InvalidSigningKey)?);
+        if seed.len() != SIGNING_SEED_LEN {
+            return Err(ManifestError::InvalidSigningKey);
+        }
+        
+        let key_pair = signature::Ed25519KeyPair::from_seed_unchecked(&seed)
+            .map_err(|_| ManifestError::InvalidSigningKey)?;
+        Ok(Self { key_pair })
+    }
+    
+    /// 公钥，Base64编码
+    pub fn public_key(&self) -> String {
+        general_purpose::STANDARD.encode(self.key_pair.public_key().as_ref())
+    }
+    
+    /// 对清单摘要签名
+    pub fn sign(&self, digest: &[u8]) -> Vec<u8> {
+        self.key_pair.sign(digest).as_ref().to_vec()
+    }
+}
+
+/// 解析Base64编码的Ed25519公钥
+pub fn parse_public_key(public_key: &str) -> Result<Vec<u8>, ManifestError> {
+    let public_key = general_purpose::STANDARD.decode(public_key.trim())
+        .map_err(|_| ManifestError::InvalidPublicKey)?;
+    if public_key.len() != SIGNING_SEED_LEN {
+        return Err(ManifestError::InvalidPublicKey);
+    }
+    Ok(public_key)
+}
+
+/// 验证清单摘要的签名
+pub fn verify_signature(public_key: &[u8], digest: &[u8], signature: &[u8]) -> bool {
+    signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+        .verify(digest, signature)
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn container_hash(container: &[u8], encrypted_metadata: Option<&[u8]>) -> Vec<u8> {
+        let mut hasher = ContainerHasher::new(container.len() as u64);
+        hasher.update(container);
+        hasher.finish(encrypted_metadata)
+    }
+    
+    #[test]
+    fn test_container_hash_by_chunks() {
+        let container: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let mut hasher = ContainerHasher::new(container.len() as u64);
+        for chunk in container.chunks(64) {
+            hasher.update(chunk);
+        }
+        assert_eq!(hasher.finish(Some(b"metadata")), container_hash(&container, Some(b"metadata")));
+        assert_ne!(container_hash(&container, None), container_hash(&container, Some(b"")));
+    }
+    
+    #[test]
+    fn test_sign_and_verify_manifest() {
+        let signer = ManifestSigner::from_base64(&general_purpose::STANDARD.encode([7u8; 32])).unwrap();
+        let public_key = parse_public_key(&signer.public_key()).unwrap();
+        let mut entries = vec![
+            ManifestEntry { resource_id: 1, uuid: Uuid::new_v4(), container_hash: container_hash(b"container 1", None), version: 1 },
+            ManifestEntry { resource_id: 2, uuid: Uuid::new_v4(), container_hash: container_hash(b"container 2", Some(b"metadata")), version: 3 },
+        ];
+        
+        let digest = manifest_digest(5, &entries);
+        let signature = signer.sign(&digest);
+        assert!(verify_signature(&public_key, &digest, &signature));
+        
+        // 序号、版本、容器哈希或记录数量变化后签名失效
+        assert!(!verify_signature(&public_key, &manifest_digest(4, &entries), &signature));
+        entries[1].version = 2;
+        assert!(!verify_signature(&public_key, &manifest_digest(5, &entries), &signature));
+        entries[1].version = 3;
+        entries[0].container_hash = container_hash(b"container 1", Some(b""));
+        assert!(!verify_signature(&public_key, &manifest_digest(5, &entries), &signature));
+        assert!(!verify_signature(&public_key, &manifest_digest(5, &entries[1..]), &signature));
+        
+        // 其他密钥的签名无效
+        let other = ManifestSigner::from_base64(&general_purpose::STANDARD.encode([8u8; 32])).unwrap();
+        assert!(!verify_signature(&public_key, &digest, &other.sign(&digest)));
+        
+        assert!(ManifestSigner::from_base64("c2hvcnQ=").is_err());
+    }
+}
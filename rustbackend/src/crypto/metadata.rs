@@ -0,0 +1,154 @@
+use ring::{aead, hkdf, rand, rand::SecureRandom};
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use super::container::ResourceContext;
+use super::secret::SecretKey;
+
+/// 加密元数据格式版本
+const METADATA_VERSION: u8 = 1;
+
+/// 由数据密钥派生元数据密钥时的info，与分段加密使用的密钥分离
+const METADATA_KEY_INFO: &[u8] = b"SecretGallery/metadata-key/v1";
+
+/// 元数据附加认证数据的标签
+const METADATA_AAD_LABEL: &[u8] = b"SecretGallery/metadata/v1";
+
+/// 元数据nonce长度
+const METADATA_NONCE_LEN: usize = 12;
+
+/// 元数据加密错误类型
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum MetadataError {
+    #[error("随机数生成错误")]
+    RandomGenerationError,
+    
+    #[error("元数据序列化错误: {0}")]
+    SerializationError(#[from] serde_json::Error),
+    
+    #[error("元数据格式错误")]
+    FormatError,
+    
+    #[error("元数据加密错误")]
+    SealError,
+    
+    #[error("元数据解密失败：密钥错误或数据已损坏")]
+    OpenError,
+}
+
+/// 资源的可搜索文本元数据
+///
+/// 启用元数据加密时与媒体数据一起由资源的数据密钥保护，数据库中的明文列留空。
+/// 不实现 `Debug`，避免标题和描述写入日志。
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct ResourceMetadata {
+    pub title: String,
+    pub title_en: Option<String>,
+    pub description: Option<String>,
+}
+
+/// 由数据密钥派生元数据密钥
+fn metadata_key(data_key: &SecretKey) -> Result<aead::LessSafeKey, MetadataError> {
+    let mut key = SecretKey::zeroed();
+    hkdf::Salt::new(hkdf::HKDF_SHA256, &[])
+        .extract(data_key)
+        .expand(&[METADATA_KEY_INFO], hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut key))
+        .map_err(|_| MetadataError::SealError)?;
+    
+    let unbound_key = aead::UnboundKey::new(&aead::AES_256_GCM, &key)
+        .map_err(|_| MetadataError::SealError)?;
+    Ok(aead::LessSafeKey::new(unbound_key))
+}
+
+/// 元数据的附加认证数据：标签 ‖ 版本(1) ‖ 资源UUID(16)，防止密文被换到其他资源
+fn metadata_aad(context: &ResourceContext) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(METADATA_AAD_LABEL.len() + 1 + 16);
+    aad.extend_from_slice(METADATA_AAD_LABEL);
+    aad.push(METADATA_VERSION);
+    aad.extend_from_slice(context.uuid.as_bytes());
+    aad
+}
+
+/// 用资源的数据密钥加密元数据
+///
+/// 输出格式：版本(1) ‖ nonce(12) ‖ 密文和认证标签，保存到 `resources.encrypted_metadata`。
+pub fn seal_metadata(
+    data_key: &SecretKey,
+    context: &ResourceContext,
+    metadata: &ResourceMetadata
+) -> Result<Vec<u8>, MetadataError> {
+    let key = metadata_key(data_key)?;
+    
+    let mut nonce = [0u8; METADATA_NONCE_LEN];
+    rand::SystemRandom::new()
+        .fill(&mut nonce)
+        .map_err(|_| MetadataError::RandomGenerationError)?;
+    
+    let mut in_out = Zeroizing::new(serde_json::to_vec(metadata)?);
+    key.seal_in_place_append_tag(aead::Nonce::assume_unique_for_key(nonce), aead::Aad::from(metadata_aad(context)), &mut *in_out)
+        .map_err(|_| MetadataError::SealError)?;
+    
+    let mut sealed = Vec::with_capacity(1 + METADATA_NONCE_LEN + in_out.len());
+    sealed.push(METADATA_VERSION);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&in_out);
+    Ok(sealed)
+}
+
+/// 用资源的数据密钥解密元数据
+pub fn open_metadata(
+    data_key: &SecretKey,
+    context: &ResourceContext,
+    sealed: &[u8]
+) -> Result<ResourceMetadata, MetadataError> {
+    if sealed.len() < 1 + METADATA_NONCE_LEN + aead::AES_256_GCM.tag_len() || sealed[0] != METADATA_VERSION {
+        return Err(MetadataError::FormatError);
+    }
+    
+    let key = metadata_key(data_key)?;
+    let nonce: [u8; METADATA_NONCE_LEN] = sealed[1..1 + METADATA_NONCE_LEN]
+        .try_into()
+        .map_err(|_| MetadataError::FormatError)?;
+    
+    let mut in_out = Zeroizing::new(sealed[1 + METADATA_NONCE_LEN..].to_vec());
+    let plaintext = key.open_in_place(aead::Nonce::assume_unique_for_key(nonce), aead::Aad::from(metadata_aad(context)), &mut in_out[..])
+        .map_err(|_| MetadataError::OpenError)?;
+    
+    Ok(serde_json::from_slice(plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+    
+    use crate::crypto::envelope::generate_data_key;
+    
+    #[test]
+    fn test_seal_and_open_metadata() {
+        let data_key = generate_data_key().unwrap();
+        let context = ResourceContext::new(Uuid::new_v4(), "image");
+        let metadata = ResourceMetadata {
+            title: "标题".to_string(),
+            title_en: Some("Title".to_string()),
+            description: None,
+        };
+        
+        let sealed = seal_metadata(&data_key, &context, &metadata).unwrap();
+        assert!(open_metadata(&data_key, &context, &sealed).unwrap() == metadata);
+        
+        // 其他资源的数据密钥或UUID都无法解密
+        let other_key = generate_data_key().unwrap();
+        assert!(matches!(open_metadata(&other_key, &context, &sealed), Err(MetadataError::OpenError)));
+        let other_context = ResourceContext::new(Uuid::new_v4(), "image");
+        assert!(matches!(open_metadata(&data_key, &other_context, &sealed), Err(MetadataError::OpenError)));
+        
+        // 篡改密文无法解密
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(open_metadata(&data_key, &context, &tampered), Err(MetadataError::OpenError)));
+    }
+}
@@ -7,15 +7,66 @@ pub mod encode;
 // 资源解码模块
 pub mod decode;
 
+// 分段流式加解密模块
+pub mod stream;
+
+// 信封加密模块
+pub mod envelope;
+
+// 加密容器格式模块
+pub mod container;
+
+// 加密套件注册模块
+pub mod suite;
+
+// 秘密数据包装类型模块
+pub mod secret;
+
+// 加密参数升级策略模块
+pub mod policy;
+
+// 恢复密钥模块
+pub mod recovery;
+
+// 元数据加密模块
+pub mod metadata;
+
+// 盲索引搜索模块
+pub mod search;
+
+// 签名清单模块
+pub mod manifest;
+
 // 重新导出公共API
+#[allow(unused_imports)]
 pub use key_management::{generate_key_hash};
-pub use encode::{encode_resource};
-pub use decode::{decode_resource, verify_key};
+pub use key_management::{combine_key_shares, split_master_key, KeyShare};
+#[allow(unused_imports)]
+pub use encode::encode_resource;
+pub use encode::{encode_resource_with_recovery, encode_resource_stream, rekey_resource, recover_resource, EncodedResource, EncodedStream};
+pub use decode::{decode_resource, decode_resource_with_metadata, decode_metadata, decode_resource_stream, verify_key, unwrap_container_data_key};
+#[allow(unused_imports)]
+pub use decode::parse_encryption_info;
+#[allow(unused_imports)]
+pub use stream::{EncryptWriter, DecryptReader, StreamSealer, StreamOpener};
+#[allow(unused_imports)]
+pub use secret::{SecretKey, SecretBytes, SecretString};
 
 // 重新导出错误类型
 pub use key_management::KeyManagementError;
 pub use encode::EncodeError;
 pub use decode::DecodeError;
+#[allow(unused_imports)]
+pub use stream::StreamError;
+pub use envelope::EnvelopeError;
+#[allow(unused_imports)]
+pub use container::ContainerError;
+pub use container::ResourceContext;
+pub use recovery::{RecoveryCode, RecoveryError, RecoveryPublicKey};
+pub use metadata::ResourceMetadata;
+pub use search::{SearchKey, MAX_QUERY_TOKENS, SEARCH_TOKEN_LEN};
+#[allow(unused_imports)]
+pub use suite::{CipherSuite, SuiteError};
 
 // 统一的加密服务错误类型
 pub enum CryptoError {
@@ -32,6 +83,22 @@ pub enum CryptoError {
     Other(anyhow::Error),
 }
 
+impl CryptoError {
+    /// 是否由错误的密钥A、硬件码B或被篡改的数据导致，两者对外不做区分
+    pub fn is_key_verification_failure(&self) -> bool {
+        let key_management_error = match self {
+            CryptoError::KeyManagement(err) => Some(err),
+            CryptoError::Encode(EncodeError::KeyManagementError(err)) => Some(err),
+            CryptoError::Decode(DecodeError::KeyManagementError(err)) => Some(err),
+            CryptoError::Encode(EncodeError::EnvelopeError(EnvelopeError::UnwrapError))
+            | CryptoError::Decode(DecodeError::EnvelopeError(EnvelopeError::UnwrapError))
+            | CryptoError::Decode(DecodeError::AuthenticationFailed) => return true,
+            _ => None,
+        };
+        matches!(key_management_error, Some(KeyManagementError::KeyVerificationFailed))
+    }
+}
+
 // 实现错误转换
 impl From<KeyManagementError> for CryptoError {
     fn from(err: KeyManagementError) -> Self {
@@ -104,44 +171,172 @@ impl EncryptionService {
     }
     
     /// 加密资源
-    #[allow(dead_code)]
+    ///
+    /// 返回加密容器、包装后的数据密钥、恢复包装和加密后的元数据，调用方只应持久化这些字段。
+    /// 没有恢复密钥时恢复包装为 `None`，不提供元数据时加密元数据为 `None`。
+    #[allow(clippy::too_many_arguments)]
     pub fn encrypt_resource(
         &self,
         data: &[u8],
-        _media_type: &str,
-        _is_local: bool,
-        _key_part_a: &str,
-        _ukey_part_b: &str,
-        _config: &crate::config::AppConfig
-    ) -> Result<(Vec<u8>, String), CryptoError> {
-        // 这里需要实现实际的加密逻辑，目前还在开发中
-        // 暂时返回原始数据和空的加密信息
-        Ok((data.to_vec(), "{}".to_string()))
+        context: &ResourceContext,
+        key_part_a: &str,
+        ukey_part_b: &str,
+        recovery_key: Option<&RecoveryPublicKey>,
+        metadata: Option<&ResourceMetadata>,
+        config: &crate::config::AppConfig
+    ) -> Result<EncodedResource, CryptoError> {
+        let encoded = encode_resource_with_recovery(data, context, key_part_a, ukey_part_b, recovery_key, metadata, config)?;
+        Ok(encoded)
     }
     
     /// 解密资源
-    #[allow(dead_code)]
+    ///
+    /// `encryption_info` 为空表示容器格式，否则按旧版加密信息JSON解密。
     pub fn decrypt_resource(
         &self,
         encrypted_data: &[u8],
-        _encryption_info: &str,
-        _key_part_a: &str,
-        _ukey_part_b: &str
+        encryption_info: &str,
+        wrapped_key: Option<&str>,
+        context: &ResourceContext,
+        key_part_a: &str,
+        ukey_part_b: &str
     ) -> Result<Vec<u8>, CryptoError> {
-        // 这里需要实现实际的解密逻辑，目前还在开发中
-        // 暂时返回原始数据
-        Ok(encrypted_data.to_vec())
+        let decrypted_data = decode_resource(encrypted_data, encryption_info, wrapped_key, context, key_part_a, ukey_part_b)?;
+        Ok(decrypted_data)
+    }
+    
+    /// 解密资源和加密的元数据，没有加密元数据时元数据为 `None`
+    #[allow(clippy::too_many_arguments)]
+    pub fn decrypt_resource_with_metadata(
+        &self,
+        encrypted_data: &[u8],
+        encryption_info: &str,
+        wrapped_key: Option<&str>,
+        context: &ResourceContext,
+        encrypted_metadata: Option<&[u8]>,
+        key_part_a: &str,
+        ukey_part_b: &str
+    ) -> Result<(Vec<u8>, Option<ResourceMetadata>), CryptoError> {
+        let decrypted = decode_resource_with_metadata(encrypted_data, encryption_info, wrapped_key, context, encrypted_metadata, key_part_a, ukey_part_b)?;
+        Ok(decrypted)
+    }
+    
+    /// 用密钥A和硬件码B解密资源的元数据并用同一数据密钥重新加密，只解包数据密钥，不解密媒体数据
+    ///
+    /// 更换密钥时数据密钥不变，返回解密的元数据和使用新nonce的加密元数据。
+    pub fn reseal_resource_metadata(
+        &self,
+        encrypted_data: &[u8],
+        context: &ResourceContext,
+        encrypted_metadata: &[u8],
+        key_part_a: &str,
+        ukey_part_b: &str
+    ) -> Result<(ResourceMetadata, Vec<u8>), CryptoError> {
+        let data_key = unwrap_container_data_key(encrypted_data, key_part_a, ukey_part_b)?;
+        let metadata = decode_metadata(&data_key, context, encrypted_metadata)?;
+        let sealed = metadata::seal_metadata(&data_key, context, &metadata).map_err(EncodeError::from)?;
+        Ok((metadata, sealed))
+    }
+    
+    /// 流式加密资源
+    ///
+    /// 明文从 `reader` 读取，容器头部和分段密文写入 `writer`，返回包装后的数据密钥、恢复包装和加密的元数据。
+    #[allow(clippy::too_many_arguments)]
+    pub async fn encrypt_resource_stream<R, W>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        context: &ResourceContext,
+        key_part_a: &str,
+        ukey_part_b: &str,
+        recovery_key: Option<&RecoveryPublicKey>,
+        metadata: Option<&ResourceMetadata>,
+        config: &crate::config::AppConfig
+    ) -> Result<EncodedStream, CryptoError>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        let encoded = encode_resource_stream(reader, writer, context, key_part_a, ukey_part_b, recovery_key, metadata, config).await?;
+        Ok(encoded)
+    }
+    
+    /// 流式解密资源，密文从 `reader` 读取，逐段校验后把明文写入 `writer`，返回明文长度
+    #[allow(clippy::too_many_arguments)]
+    pub async fn decrypt_resource_stream<R, W>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        encryption_info: &str,
+        wrapped_key: Option<&str>,
+        context: &ResourceContext,
+        key_part_a: &str,
+        ukey_part_b: &str
+    ) -> Result<u64, CryptoError>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        let written = decode_resource_stream(reader, writer, encryption_info, wrapped_key, context, key_part_a, ukey_part_b).await?;
+        Ok(written)
+    }
+    
+    /// 更换资源的密钥A和硬件码B，只重新包装数据密钥并替换容器头部
+    #[allow(clippy::too_many_arguments)]
+    pub fn rekey_resource(
+        &self,
+        encrypted_data: &[u8],
+        encryption_info: &str,
+        wrapped_key: Option<&str>,
+        old_key_part_a: &str,
+        old_ukey_part_b: &str,
+        new_key_part_a: &str,
+        new_ukey_part_b: &str,
+        config: &crate::config::AppConfig
+    ) -> Result<(Vec<u8>, String), CryptoError> {
+        let rekeyed = rekey_resource(encrypted_data, encryption_info, wrapped_key, old_key_part_a, old_ukey_part_b, new_key_part_a, new_ukey_part_b, config)?;
+        Ok(rekeyed)
+    }
+    
+    /// 用恢复码解出的数据密钥为资源换上新的密钥A和硬件码B
+    pub fn recover_resource(
+        &self,
+        encrypted_data: &[u8],
+        data_key: &SecretKey,
+        new_key_part_a: &str,
+        new_ukey_part_b: &str,
+        config: &crate::config::AppConfig
+    ) -> Result<(Vec<u8>, String), CryptoError> {
+        let recovered = recover_resource(encrypted_data, data_key, new_key_part_a, new_ukey_part_b, config)?;
+        Ok(recovered)
+    }
+    
+    /// 用密钥A和硬件码B解出资源的数据密钥并用恢复公钥包装
+    ///
+    /// 只支持容器格式，旧版加密信息JSON格式的资源需要先升级。
+    pub fn wrap_resource_for_recovery(
+        &self,
+        encrypted_data: &[u8],
+        key_part_a: &str,
+        ukey_part_b: &str,
+        recovery_key: &RecoveryPublicKey
+    ) -> Result<String, CryptoError> {
+        let data_key = unwrap_container_data_key(encrypted_data, key_part_a, ukey_part_b)?;
+        let recovery_wrapped_key = recovery::wrap_for_recovery(recovery_key, &data_key)
+            .and_then(|wrapped| wrapped.to_json())
+            .map_err(EncodeError::from)?;
+        Ok(recovery_wrapped_key)
     }
     
     /// 验证密钥
-    #[allow(dead_code)]
     pub fn verify_resource_key(
         &self,
         key_part_a: &str,
         ukey_part_b: &str,
+        encrypted_data: &[u8],
         encryption_info: &str
     ) -> Result<bool, CryptoError> {
-        verify_key(key_part_a, ukey_part_b, encryption_info)
-            .map_err(|e| CryptoError::Decode(e))
+        verify_key(key_part_a, ukey_part_b, encrypted_data, encryption_info)
+            .map_err(CryptoError::Decode)
     }
 }
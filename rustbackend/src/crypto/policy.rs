@@ -0,0 +1,132 @@
+use serde::Serialize;
+
+use crate::config::AppConfig;
+use super::container::{ContainerHeader, CURRENT_CONTAINER_VERSION};
+use super::decode::{parse_encryption_info, DecodeError};
+use super::key_management::{get_encryption_algorithm, get_kdf_params, KdfParams, KeyInfo, CURRENT_KEY_COMBINER};
+
+/// 读取容器头部需要的最大字节数
+///
+/// 头部只包含盐值、校验块和包装后的数据密钥等短字段，统计时只需从数据库读取资源开头的这部分数据。
+pub const MAX_HEADER_PROBE_LEN: i32 = 4096;
+
+/// 资源需要升级的原因，按检查顺序排列
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum UpgradeReason {
+    /// 旧版加密信息JSON格式
+    LegacyFormat,
+    /// 旧版本的容器格式
+    ContainerVersion,
+    /// 旧版密钥组合方案
+    KeyCombiner,
+    /// 加密算法与配置不一致
+    Algorithm,
+    /// 密钥派生函数与配置不一致或参数低于配置
+    KdfParams,
+}
+
+/// 资源的密钥派生参数是否低于配置
+///
+/// 更换了密钥派生函数时一律视为需要升级；同一函数的参数任何一项低于配置都需要升级，高于配置的保持不变。
+fn kdf_below_policy(resource: &KdfParams, policy: &KdfParams) -> bool {
+    match (resource, policy) {
+        (KdfParams::Pbkdf2 { iteration_count }, KdfParams::Pbkdf2 { iteration_count: required }) => {
+            iteration_count < required
+        }
+        (KdfParams::Argon2id(params), KdfParams::Argon2id(required)) => {
+            params.memory_cost < required.memory_cost
+                || params.time_cost < required.time_cost
+                || params.parallelism < required.parallelism
+        }
+        _ => true,
+    }
+}
+
+/// 按当前配置检查密钥信息
+fn check_key_info(key_info: &KeyInfo, config: &AppConfig) -> Result<Option<UpgradeReason>, DecodeError> {
+    if key_info.combiner_version < CURRENT_KEY_COMBINER {
+        return Ok(Some(UpgradeReason::KeyCombiner));
+    }
+    
+    if key_info.algorithm != get_encryption_algorithm(config) {
+        return Ok(Some(UpgradeReason::Algorithm));
+    }
+    
+    if kdf_below_policy(&key_info.kdf_params()?, &get_kdf_params(config)?) {
+        return Ok(Some(UpgradeReason::KdfParams));
+    }
+    
+    Ok(None)
+}
+
+/// 检查资源的加密参数是否低于当前配置，返回第一个需要升级的原因
+///
+/// `encrypted_data` 至少需要包含完整的容器头部，可以只传入资源开头的 `MAX_HEADER_PROBE_LEN` 字节。
+pub fn check_upgrade(
+    encrypted_data: &[u8],
+    encryption_info_json: &str,
+    config: &AppConfig
+) -> Result<Option<UpgradeReason>, DecodeError> {
+    if !encryption_info_json.is_empty() {
+        // 确认加密信息可以解析，损坏的记录由调用方单独处理
+        parse_encryption_info(encryption_info_json)?;
+        return Ok(Some(UpgradeReason::LegacyFormat));
+    }
+    
+    let (header, _) = ContainerHeader::parse(encrypted_data)?;
+    if header.version < CURRENT_CONTAINER_VERSION {
+        return Ok(Some(UpgradeReason::ContainerVersion));
+    }
+    
+    check_key_info(&header.key_info(), config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+    
+    use crate::crypto::container::{ResourceContext, CONTAINER_VERSION_V1};
+    use crate::crypto::decode::tests::test_config;
+    use crate::crypto::encode::encode_resource;
+    use crate::crypto::key_management::KEY_COMBINER_LEGACY;
+    
+    #[test]
+    fn test_check_upgrade_against_policy() {
+        let mut config = test_config();
+        let context = ResourceContext::new(Uuid::new_v4(), "image");
+        let (encrypted_data, _) = encode_resource(b"policy", &context, "password", "ukey", &config).unwrap();
+        
+        // 使用当前配置加密的资源无需升级，只读取头部也能判断
+        assert_eq!(check_upgrade(&encrypted_data, "", &config).unwrap(), None);
+        let probe = &encrypted_data[..encrypted_data.len().min(MAX_HEADER_PROBE_LEN as usize)];
+        assert_eq!(check_upgrade(probe, "", &config).unwrap(), None);
+        
+        // 旧版本容器需要升级
+        let mut downgraded = encrypted_data.clone();
+        downgraded[4] = CONTAINER_VERSION_V1;
+        assert_eq!(check_upgrade(&downgraded, "", &config).unwrap(), Some(UpgradeReason::ContainerVersion));
+        
+        // 旧版密钥组合方案需要升级
+        let (header, _) = ContainerHeader::parse(&encrypted_data).unwrap();
+        let mut key_info = header.key_info();
+        key_info.combiner_version = KEY_COMBINER_LEGACY;
+        assert_eq!(check_key_info(&key_info, &config).unwrap(), Some(UpgradeReason::KeyCombiner));
+        
+        // 配置提高Argon2id参数后需要升级，恢复后不需要
+        config.encryption.argon2_time_cost += 1;
+        assert_eq!(check_upgrade(&encrypted_data, "", &config).unwrap(), Some(UpgradeReason::KdfParams));
+        config.encryption.argon2_time_cost -= 1;
+        assert_eq!(check_upgrade(&encrypted_data, "", &config).unwrap(), None);
+        
+        // 更换密钥派生函数后需要升级
+        config.encryption.key_derivation_function = "PBKDF2-HMAC-SHA256".to_string();
+        assert_eq!(check_upgrade(&encrypted_data, "", &config).unwrap(), Some(UpgradeReason::KdfParams));
+        config.encryption.key_derivation_function = "ARGON2ID".to_string();
+        
+        // 更换加密算法后需要升级
+        config.encryption.algorithm = "XCHACHA20POLY1305".to_string();
+        assert_eq!(check_upgrade(&encrypted_data, "", &config).unwrap(), Some(UpgradeReason::Algorithm));
+    }
+}
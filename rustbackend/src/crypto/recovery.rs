@@ -0,0 +1,311 @@
+use base64::{Engine as _, engine::general_purpose};
+use ring::{digest, hkdf, rand, rand::SecureRandom};
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::Zeroizing;
+
+use super::envelope::{unwrap_data_key, wrap_data_key, EnvelopeError, WrappedKey};
+use super::secret::{SecretKey, SecretString};
+
+/// 恢复码中私钥的长度
+const RECOVERY_SECRET_LEN: usize = 32;
+
+/// 恢复码末尾校验和的长度，用于发现抄写错误
+const RECOVERY_CHECKSUM_LEN: usize = 2;
+
+/// 恢复码显示时每组的字符数
+const RECOVERY_GROUP_LEN: usize = 5;
+
+/// 由密钥协商结果派生包装密钥时的info，防止派生结果被挪作他用
+const RECOVERY_WRAP_INFO: &[u8] = b"SecretGallery/recovery-wrap/v1";
+
+/// base32字母表，Crockford字母表不含容易混淆的I、L、O、U
+const RECOVERY_ALPHABET: base32::Alphabet = base32::Alphabet::Crockford;
+
+/// 恢复密钥错误类型
+#[derive(Error, Debug)]
+pub enum RecoveryError {
+    #[error("随机数生成错误")]
+    RandomGenerationError,
+    
+    #[error("恢复码格式错误")]
+    InvalidCode,
+    
+    #[error("恢复码校验和错误，请检查是否抄写有误")]
+    ChecksumMismatch,
+    
+    #[error("恢复公钥格式错误")]
+    InvalidPublicKey,
+    
+    #[error("恢复包装格式错误: {0}")]
+    FormatError(String),
+    
+    #[error("恢复包装不属于当前恢复密钥")]
+    KeyMismatch,
+    
+    #[error("恢复码错误或恢复包装已损坏")]
+    UnwrapError,
+    
+    #[error("信封加密错误: {0}")]
+    EnvelopeError(EnvelopeError),
+}
+
+impl From<EnvelopeError> for RecoveryError {
+    fn from(err: EnvelopeError) -> Self {
+        match err {
+            EnvelopeError::UnwrapError => RecoveryError::UnwrapError,
+            other => RecoveryError::EnvelopeError(other),
+        }
+    }
+}
+
+/// 计算恢复码的校验和
+fn checksum(secret: &[u8]) -> [u8; RECOVERY_CHECKSUM_LEN] {
+    let digest = digest::digest(&digest::SHA256, secret);
+    let mut checksum = [0u8; RECOVERY_CHECKSUM_LEN];
+    checksum.copy_from_slice(&digest.as_ref()[..RECOVERY_CHECKSUM_LEN]);
+    checksum
+}
+
+/// 恢复公钥的指纹，用于核对打印的恢复码对应哪一个恢复密钥
+pub fn fingerprint(public_key: &[u8; 32]) -> String {
+    let digest = digest::digest(&digest::SHA256, public_key);
+    digest.as_ref()[..8]
+        .chunks(2)
+        .map(|pair| format!("{:02X}{:02X}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// 由密钥协商结果派生包装密钥，盐值绑定双方公钥
+fn derive_wrapping_key(shared_secret: &[u8], ephemeral_public: &[u8; 32], recipient_public: &[u8; 32]) -> SecretKey {
+    let mut salt = [0u8; 64];
+    salt[..32].copy_from_slice(ephemeral_public);
+    salt[32..].copy_from_slice(recipient_public);
+    
+    let mut key = SecretKey::zeroed();
+    hkdf::Salt::new(hkdf::HKDF_SHA256, &salt)
+        .extract(shared_secret)
+        .expand(&[RECOVERY_WRAP_INFO], hkdf::HKDF_SHA256)
+        .and_then(|okm| okm.fill(&mut key))
+        .expect("HKDF-SHA256输出32字节，扩展不会失败");
+    key
+}
+
+/// 恢复码
+///
+/// UKey丢失时代替密钥A和硬件码B解包资源的数据密钥。恢复码是一个X25519私钥，
+/// 服务端只保存对应的公钥，新资源的数据密钥用公钥包装，不需要恢复码即可持续覆盖新资源。
+/// 显示形式为带校验和的Crockford base32分组字符串，只在生成时展示一次。
+pub struct RecoveryCode {
+    secret: SecretKey,
+}
+
+impl RecoveryCode {
+    /// 生成随机恢复码
+    pub fn generate() -> Result<Self, RecoveryError> {
+        let mut secret = SecretKey::zeroed();
+        rand::SystemRandom::new()
+            .fill(&mut secret)
+            .map_err(|_| RecoveryError::RandomGenerationError)?;
+        Ok(RecoveryCode { secret })
+    }
+    
+    /// 由保管人分片重建的私钥构造恢复码，长度不是32字节时返回 `InvalidCode`
+    pub fn from_secret(secret: &[u8]) -> Result<Self, RecoveryError> {
+        Ok(RecoveryCode {
+            secret: SecretKey::from_slice(secret).ok_or(RecoveryError::InvalidCode)?,
+        })
+    }
+    
+    /// X25519私钥，托管时用于拆分
+    pub fn secret(&self) -> &SecretKey {
+        &self.secret
+    }
+    
+    /// 解析用户输入的恢复码，忽略分隔符、空白和大小写
+    pub fn parse(code: &str) -> Result<Self, RecoveryError> {
+        let normalized: Zeroizing<String> = Zeroizing::new(code
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_uppercase())
+            .collect());
+        let bytes = Zeroizing::new(base32::decode(RECOVERY_ALPHABET, &normalized).ok_or(RecoveryError::InvalidCode)?);
+        if bytes.len() != RECOVERY_SECRET_LEN + RECOVERY_CHECKSUM_LEN {
+            return Err(RecoveryError::InvalidCode);
+        }
+        
+        let (secret, expected) = bytes.split_at(RECOVERY_SECRET_LEN);
+        if checksum(secret)[..] != *expected {
+            return Err(RecoveryError::ChecksumMismatch);
+        }
+        
+        Ok(RecoveryCode {
+            secret: SecretKey::from_slice(secret).ok_or(RecoveryError::InvalidCode)?,
+        })
+    }
+    
+    /// 可打印的恢复码
+    pub fn to_display(&self) -> SecretString {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(RECOVERY_SECRET_LEN + RECOVERY_CHECKSUM_LEN));
+        bytes.extend_from_slice(&self.secret);
+        bytes.extend_from_slice(&checksum(&self.secret));
+        
+        let encoded = Zeroizing::new(base32::encode(RECOVERY_ALPHABET, &bytes));
+        let groups: Vec<&str> = encoded
+            .as_bytes()
+            .chunks(RECOVERY_GROUP_LEN)
+            .map(|group| std::str::from_utf8(group).expect("base32编码结果只包含ASCII字符"))
+            .collect();
+        SecretString::new(groups.join("-"))
+    }
+    
+    /// X25519私钥，释放时清零
+    fn static_secret(&self) -> StaticSecret {
+        let mut bytes = Zeroizing::new([0u8; RECOVERY_SECRET_LEN]);
+        bytes.copy_from_slice(&self.secret);
+        StaticSecret::from(*bytes)
+    }
+    
+    /// 对应的恢复公钥
+    pub fn public_key(&self) -> [u8; 32] {
+        PublicKey::from(&self.static_secret()).to_bytes()
+    }
+}
+
+/// 服务端保存的恢复公钥
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryPublicKey {
+    /// `recovery_keys` 中的记录ID，写入恢复包装，撤销后旧包装不再被接受
+    pub id: i32,
+    pub public_key: [u8; 32],
+}
+
+impl RecoveryPublicKey {
+    /// 从 `recovery_keys.public_key` 中的Base64解析
+    pub fn from_base64(id: i32, public_key: &str) -> Result<Self, RecoveryError> {
+        let public_key = general_purpose::STANDARD.decode(public_key)
+            .map_err(|_| RecoveryError::InvalidPublicKey)?
+            .try_into()
+            .map_err(|_| RecoveryError::InvalidPublicKey)?;
+        Ok(RecoveryPublicKey { id, public_key })
+    }
+    
+    /// 编码为Base64，保存到 `recovery_keys.public_key`
+    pub fn to_base64(&self) -> String {
+        general_purpose::STANDARD.encode(self.public_key)
+    }
+}
+
+/// 用恢复公钥包装后的数据密钥，保存到 `encryption_keys.recovery_wrapped_key`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RecoveryWrappedKey {
+    /// 恢复密钥记录ID
+    pub key_id: i32,
+    /// 临时X25519公钥
+    pub ephemeral_public_key: String,
+    /// 由密钥协商结果派生的密钥包装的数据密钥
+    pub wrapped_key: WrappedKey,
+}
+
+impl RecoveryWrappedKey {
+    /// 序列化为JSON
+    pub fn to_json(&self) -> Result<String, RecoveryError> {
+        serde_json::to_string(self).map_err(|e| RecoveryError::FormatError(e.to_string()))
+    }
+    
+    /// 从JSON解析
+    pub fn from_json(json: &str) -> Result<Self, RecoveryError> {
+        serde_json::from_str(json).map_err(|e| RecoveryError::FormatError(e.to_string()))
+    }
+}
+
+/// 用恢复公钥包装数据密钥
+///
+/// 每次包装生成临时X25519密钥对，与恢复公钥协商出共享密钥后经HKDF派生包装密钥。
+pub fn wrap_for_recovery(recovery_key: &RecoveryPublicKey, data_key: &SecretKey) -> Result<RecoveryWrappedKey, RecoveryError> {
+    let mut ephemeral_bytes = Zeroizing::new([0u8; 32]);
+    rand::SystemRandom::new()
+        .fill(&mut *ephemeral_bytes)
+        .map_err(|_| RecoveryError::RandomGenerationError)?;
+    let ephemeral_secret = StaticSecret::from(*ephemeral_bytes);
+    let ephemeral_public = PublicKey::from(&ephemeral_secret).to_bytes();
+    
+    let shared_secret = ephemeral_secret.diffie_hellman(&PublicKey::from(recovery_key.public_key));
+    if !shared_secret.was_contributory() {
+        return Err(RecoveryError::InvalidPublicKey);
+    }
+    
+    let kek = derive_wrapping_key(shared_secret.as_bytes(), &ephemeral_public, &recovery_key.public_key);
+    
+    Ok(RecoveryWrappedKey {
+        key_id: recovery_key.id,
+        ephemeral_public_key: general_purpose::STANDARD.encode(ephemeral_public),
+        wrapped_key: wrap_data_key(&kek, data_key)?,
+    })
+}
+
+/// 用恢复码解包数据密钥，恢复包装必须属于 `key_id` 指定的恢复密钥
+pub fn unwrap_with_recovery(code: &RecoveryCode, key_id: i32, wrapped: &RecoveryWrappedKey) -> Result<SecretKey, RecoveryError> {
+    if wrapped.key_id != key_id {
+        return Err(RecoveryError::KeyMismatch);
+    }
+    
+    let ephemeral_public: [u8; 32] = general_purpose::STANDARD.decode(&wrapped.ephemeral_public_key)
+        .map_err(|e| RecoveryError::FormatError(e.to_string()))?
+        .try_into()
+        .map_err(|_| RecoveryError::FormatError("临时公钥长度错误".to_string()))?;
+    
+    let secret = code.static_secret();
+    let recipient_public = PublicKey::from(&secret).to_bytes();
+    let shared_secret = secret.diffie_hellman(&PublicKey::from(ephemeral_public));
+    if !shared_secret.was_contributory() {
+        return Err(RecoveryError::UnwrapError);
+    }
+    
+    let kek = derive_wrapping_key(shared_secret.as_bytes(), &ephemeral_public, &recipient_public);
+    Ok(unwrap_data_key(&kek, &wrapped.wrapped_key)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::envelope::generate_data_key;
+    
+    #[test]
+    fn test_recovery_code_round_trip() {
+        let code = RecoveryCode::generate().unwrap();
+        let display = code.to_display();
+        
+        // 分组显示，解析时忽略大小写和分隔符
+        assert!(display.expose_secret().split('-').all(|group| group.len() <= RECOVERY_GROUP_LEN));
+        let parsed = RecoveryCode::parse(&display.expose_secret().to_lowercase().replace('-', " ")).unwrap();
+        assert_eq!(parsed.public_key(), code.public_key());
+        
+        // 抄错一个字符时校验和不匹配
+        let mut typo = display.expose_secret().to_string();
+        let replacement = if typo.starts_with('0') { "1" } else { "0" };
+        typo.replace_range(0..1, replacement);
+        assert!(matches!(RecoveryCode::parse(&typo), Err(RecoveryError::ChecksumMismatch)));
+        assert!(matches!(RecoveryCode::parse("ABCDE"), Err(RecoveryError::InvalidCode)));
+    }
+    
+    #[test]
+    fn test_wrap_unwrap_with_recovery() {
+        let code = RecoveryCode::generate().unwrap();
+        let recovery_key = RecoveryPublicKey { id: 3, public_key: code.public_key() };
+        let restored = RecoveryPublicKey::from_base64(3, &recovery_key.to_base64()).unwrap();
+        assert_eq!(restored, recovery_key);
+        
+        let data_key = generate_data_key().unwrap();
+        let wrapped = wrap_for_recovery(&recovery_key, &data_key).unwrap();
+        let wrapped = RecoveryWrappedKey::from_json(&wrapped.to_json().unwrap()).unwrap();
+        assert_eq!(unwrap_with_recovery(&code, 3, &wrapped).unwrap(), data_key);
+        
+        // 其他恢复码无法解包，已撤销的恢复密钥的包装不被接受
+        let other = RecoveryCode::generate().unwrap();
+        assert!(matches!(unwrap_with_recovery(&other, 3, &wrapped), Err(RecoveryError::UnwrapError)));
+        assert!(matches!(unwrap_with_recovery(&code, 4, &wrapped), Err(RecoveryError::KeyMismatch)));
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeSet;
+
+use ring::{hkdf, hmac};
+
+use super::key_management::{generate_combined_key, KeyManagementError, KEY_COMBINER_V1};
+use super::metadata::ResourceMetadata;
+
+/// 由密钥A和硬件码B派生盲索引密钥时的info，与数据密钥的包装密钥分离
+const SEARCH_KEY_INFO: &[u8] = b"SecretGallery/search-index/v1";
+
+/// 非中日韩文字词元的最短长度，更短的查询词不参与匹配
+const MIN_TERM_CHARS: usize = 2;
+
+/// 中日韩文字词元的最短长度，单字即可查询
+const MIN_CJK_TERM_CHARS: usize = 1;
+
+/// 前缀词元的最大长度，更长的词只索引前缀和完整的词
+const MAX_PREFIX_CHARS: usize = 16;
+
+/// 词元长度，即HMAC-SHA256的输出长度
+pub const SEARCH_TOKEN_LEN: usize = 32;
+
+/// 一次查询最多的词元数
+pub const MAX_QUERY_TOKENS: usize = 32;
+
+/// 盲索引密钥
+///
+/// 对规范化后的词元计算HMAC-SHA256，数据库只保存HMAC结果。
+/// 密钥只在解锁时由密钥A和硬件码B临时派生，不持久化。
+pub struct SearchKey(hmac::Key);
+
+impl SearchKey {
+    /// 由密钥A和硬件码B派生盲索引密钥
+    pub fn derive(key_part_a: &str, ukey_part_b: &str) -> Result<Self, KeyManagementError> {
+        let combined_key = generate_combined_key(KEY_COMBINER_V1, key_part_a, ukey_part_b)?;
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, &[]).extract(&combined_key);
+        let okm = prk.expand(&[SEARCH_KEY_INFO], hmac::HMAC_SHA256)
+            .map_err(|_| KeyManagementError::KeyDerivationError("HKDF扩展失败".to_string()))?;
+        Ok(Self(hmac::Key::from(okm)))
+    }
+    
+    /// 生成资源元数据的全部索引词元，已排序去重
+    pub fn index_tokens(&self, metadata: &ResourceMetadata) -> Vec<Vec<u8>> {
+        let fields = [Some(metadata.title.as_str()), metadata.title_en.as_deref(), metadata.description.as_deref()];
+        let terms: BTreeSet<String> = fields.into_iter()
+            .flatten()
+            .flat_map(split_words)
+            .flat_map(|word| index_terms(&word))
+            .collect();
+        
+        terms.iter().map(|term| self.token(term)).collect()
+    }
+    
+    /// 生成查询词元，资源需要匹配其中每一个，查询词都太短时返回空列表
+    pub fn query_tokens(&self, query: &str) -> Vec<Vec<u8>> {
+        let terms: BTreeSet<String> = split_words(query)
+            .into_iter()
+            .filter_map(|word| query_term(&word))
+            .collect();
+        
+        terms.iter().map(|term| self.token(term)).collect()
+    }
+    
+    /// 计算单个词元的HMAC
+    fn token(&self, term: &str) -> Vec<u8> {
+        hmac::sign(&self.0, term.as_bytes()).as_ref().to_vec()
+    }
+}
+
+/// 规范化后的词
+struct Word {
+    chars: Vec<char>,
+    cjk: bool,
+}
+
+/// 是否为中日韩文字，这些文字没有空格分词
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30FF}'   // 平假名、片假名
+        | '\u{3400}'..='\u{4DBF}' // 扩展A
+        | '\u{4E00}'..='\u{9FFF}' // 基本汉字
+        | '\u{AC00}'..='\u{D7AF}' // 韩文音节
+        | '\u{F900}'..='\u{FAFF}' // 兼容汉字
+    )
+}
+
+/// 转为小写后按非字母数字字符分词，中日韩文字与其他文字相邻处也作为分界
+fn split_words(text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+    let mut current: Option<Word> = None;
+    
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if !c.is_alphanumeric() {
+            words.extend(current.take());
+            continue;
+        }
+        
+        let cjk = is_cjk(c);
+        match current.as_mut() {
+            Some(word) if word.cjk == cjk => word.chars.push(c),
+            _ => {
+                words.extend(current.take());
+                current = Some(Word { chars: vec![c], cjk });
+            }
+        }
+    }
+    words.extend(current);
+    
+    words
+}
+
+/// 词的最短可查询长度
+fn min_term_chars(word: &Word) -> usize {
+    if word.cjk { MIN_CJK_TERM_CHARS } else { MIN_TERM_CHARS }
+}
+
+/// 一个词的全部索引词元
+///
+/// 其他文字只索引从词首开始的前缀，中日韩文字从每个位置开始索引前缀，以支持词中间的子串查询。
+/// 超过前缀最大长度的非中日韩文字词另外索引完整的词。
+fn index_terms(word: &Word) -> Vec<String> {
+    let len = word.chars.len();
+    let starts = if word.cjk { 0..len } else { 0..1 };
+    
+    let mut terms = Vec::new();
+    for start in starts {
+        let max_len = MAX_PREFIX_CHARS.min(len - start);
+        for term_len in min_term_chars(word)..=max_len {
+            terms.push(word.chars[start..start + term_len].iter().collect());
+        }
+    }
+    if !word.cjk && len > MAX_PREFIX_CHARS {
+        terms.push(word.chars.iter().collect());
+    }
+    
+    terms
+}
+
+/// 查询词对应的索引词元，与 `index_terms` 的规则一致
+fn query_term(word: &Word) -> Option<String> {
+    let len = word.chars.len();
+    if len < min_term_chars(word) {
+        return None;
+    }
+    
+    if len <= MAX_PREFIX_CHARS || !word.cjk {
+        Some(word.chars.iter().collect())
+    } else {
+        Some(word.chars[..MAX_PREFIX_CHARS].iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn matches(key: &SearchKey, metadata: &ResourceMetadata, query: &str) -> bool {
+        let index = key.index_tokens(metadata);
+        let tokens = key.query_tokens(query);
+        !tokens.is_empty() && tokens.iter().all(|token| index.contains(token))
+    }
+    
+    #[test]
+    fn test_blind_index_search() {
+        let key = SearchKey::derive("key_part_a", "ukey_part_b").unwrap();
+        let metadata = ResourceMetadata {
+            title: "夏日海边风景".to_string(),
+            title_en: Some("Summer Beach".to_string()),
+            description: Some("Photographed at sunset, 2024".to_string()),
+        };
+        
+        // 大小写无关的前缀查询、多个词同时匹配
+        assert!(matches(&key, &metadata, "beach"));
+        assert!(matches(&key, &metadata, "SUM photo"));
+        assert!(matches(&key, &metadata, "2024"));
+        // 中文支持词中间的子串查询
+        assert!(matches(&key, &metadata, "海边"));
+        assert!(matches(&key, &metadata, "风景 sunset"));
+        
+        // 非前缀、不存在的词和太短的查询词不匹配
+        assert!(!matches(&key, &metadata, "each"));
+        assert!(!matches(&key, &metadata, "beach mountain"));
+        assert!(!matches(&key, &metadata, "山"));
+        assert!(key.query_tokens("a").is_empty());
+        
+        // 不同的密钥A或硬件码B生成的词元不同
+        let other_key = SearchKey::derive("key_part_a", "other_ukey").unwrap();
+        assert!(other_key.query_tokens("beach") != key.query_tokens("beach"));
+    }
+    
+    #[test]
+    fn test_long_words() {
+        let key = SearchKey::derive("key_part_a", "ukey_part_b").unwrap();
+        let metadata = ResourceMetadata {
+            title: "internationalization".to_string(),
+            ..Default::default()
+        };
+        
+        assert!(matches(&key, &metadata, "international"));
+        assert!(matches(&key, &metadata, "internationalization"));
+        assert!(!matches(&key, &metadata, "internationalizations"));
+    }
+}
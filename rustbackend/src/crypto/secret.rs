@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Deserializer};
+use zeroize::Zeroize;
+
+/// 是否用mlock锁定秘密所在的内存页
+static LOCK_MEMORY: AtomicBool = AtomicBool::new(false);
+
+/// 已锁定内存页的引用计数，多个秘密共用同一页时最后一个释放后才解锁
+static LOCKED_PAGES: Mutex<BTreeMap<usize, usize>> = Mutex::new(BTreeMap::new());
+
+/// 开启或关闭内存锁定，只影响之后创建的秘密
+pub fn set_memory_locking(enabled: bool) {
+    LOCK_MEMORY.store(enabled, Ordering::Relaxed);
+}
+
+/// 内存页大小
+#[cfg(unix)]
+fn page_size() -> usize {
+    // SAFETY: sysconf没有内存安全方面的前置条件
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 { size as usize } else { 4096 }
+}
+
+/// 区域覆盖的内存页起始地址
+#[cfg(unix)]
+fn pages(ptr: *const u8, len: usize) -> impl Iterator<Item = usize> {
+    let page_size = page_size();
+    let start = ptr as usize & !(page_size - 1);
+    let end = ptr as usize + len;
+    (start..end).step_by(page_size)
+}
+
+/// 锁定内存区域，返回是否锁定成功
+///
+/// 锁定失败（例如超出 RLIMIT_MEMLOCK）只记录警告，秘密仍会在释放时清零。
+#[cfg(unix)]
+fn lock_region(ptr: *const u8, len: usize) -> bool {
+    if len == 0 || !LOCK_MEMORY.load(Ordering::Relaxed) {
+        return false;
+    }
+    
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    let page_size = page_size();
+    for page in pages(ptr, len) {
+        if !locked_pages.contains_key(&page) {
+            // SAFETY: page是当前进程中已分配区域所在的页，mlock只改变页的换出属性
+            if unsafe { libc::mlock(page as *const libc::c_void, page_size) } != 0 {
+                tracing::warn!("锁定秘密所在内存页失败: {}", std::io::Error::last_os_error());
+                // 回滚本次已经锁定的页
+                for locked in pages(ptr, len).take_while(|locked| *locked != page) {
+                    release_page(&mut locked_pages, locked, page_size);
+                }
+                return false;
+            }
+        }
+        *locked_pages.entry(page).or_insert(0) += 1;
+    }
+    
+    true
+}
+
+#[cfg(not(unix))]
+fn lock_region(_ptr: *const u8, _len: usize) -> bool {
+    false
+}
+
+/// 引用计数减一，归零时解锁该页
+#[cfg(unix)]
+fn release_page(locked_pages: &mut BTreeMap<usize, usize>, page: usize, page_size: usize) {
+    if let Some(count) = locked_pages.get_mut(&page) {
+        *count -= 1;
+        if *count == 0 {
+            locked_pages.remove(&page);
+            // SAFETY: 该页由lock_region锁定，munlock只改变页的换出属性
+            unsafe { libc::munlock(page as *const libc::c_void, page_size) };
+        }
+    }
+}
+
+/// 解锁由 `lock_region` 锁定的内存区域
+#[cfg(unix)]
+fn unlock_region(ptr: *const u8, len: usize) {
+    let mut locked_pages = LOCKED_PAGES.lock().unwrap_or_else(|e| e.into_inner());
+    let page_size = page_size();
+    for page in pages(ptr, len) {
+        release_page(&mut locked_pages, page, page_size);
+    }
+}
+
+#[cfg(not(unix))]
+fn unlock_region(_ptr: *const u8, _len: usize) {}
+
+/// 32字节的密钥
+///
+/// 密钥放在堆上，地址在整个生命周期内不变，释放时清零并解锁内存页。
+/// `Debug` 不输出内容，比较使用常量时间。
+pub struct SecretKey {
+    key: Box<[u8; 32]>,
+    locked: bool,
+}
+
+impl SecretKey {
+    /// 创建全零密钥，由调用方原地写入
+    pub fn zeroed() -> Self {
+        let key = Box::new([0u8; 32]);
+        let locked = lock_region(key.as_ptr(), key.len());
+        SecretKey { key, locked }
+    }
+    
+    /// 从字节切片复制密钥，长度不是32字节时返回None
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != 32 {
+            return None;
+        }
+        
+        let mut key = SecretKey::zeroed();
+        key.copy_from_slice(bytes);
+        Some(key)
+    }
+}
+
+impl Deref for SecretKey {
+    type Target = [u8];
+    
+    fn deref(&self) -> &[u8] {
+        &self.key[..]
+    }
+}
+
+impl DerefMut for SecretKey {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.key[..]
+    }
+}
+
+impl PartialEq for SecretKey {
+    fn eq(&self, other: &Self) -> bool {
+        super::key_management::constant_time_eq(&self.key[..], &other.key[..])
+    }
+}
+
+impl Eq for SecretKey {}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey([REDACTED])")
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+        if self.locked {
+            unlock_region(self.key.as_ptr(), self.key.len());
+        }
+    }
+}
+
+/// 变长的秘密字节串，例如组合密钥
+///
+/// 创建后长度固定，不会因扩容在堆上留下副本。
+pub struct SecretBytes {
+    bytes: Vec<u8>,
+    locked: bool,
+}
+
+impl SecretBytes {
+    /// 接管已有的字节缓冲区
+    pub fn new(mut bytes: Vec<u8>) -> Self {
+        // 释放多余容量前先清零，缩容可能会复制缓冲区
+        if bytes.capacity() != bytes.len() {
+            let mut exact = Vec::with_capacity(bytes.len());
+            exact.extend_from_slice(&bytes);
+            bytes.zeroize();
+            bytes = exact;
+        }
+        
+        let locked = lock_region(bytes.as_ptr(), bytes.len());
+        SecretBytes { bytes, locked }
+    }
+    
+    /// 创建指定长度的全零缓冲区，由调用方原地写入
+    pub fn zeroed(len: usize) -> Self {
+        SecretBytes::new(vec![0u8; len])
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+    
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl DerefMut for SecretBytes {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.bytes
+    }
+}
+
+impl AsRef<[u8]> for SecretBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretBytes([REDACTED])")
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.bytes.zeroize();
+        if self.locked {
+            unlock_region(self.bytes.as_ptr(), self.bytes.capacity());
+        }
+    }
+}
+
+/// 秘密字符串，例如密钥A和硬件码B
+///
+/// 只能通过 `expose_secret` 读取内容，`Debug` 不输出内容，释放时清零。
+/// 反序列化时serde的中间缓冲区不受控制，接管后的副本才会被锁定和清零。
+pub struct SecretString {
+    value: String,
+    locked: bool,
+}
+
+impl SecretString {
+    /// 接管字符串
+    pub fn new(mut value: String) -> Self {
+        // 释放多余容量前先清零，缩容可能会复制缓冲区
+        if value.capacity() != value.len() {
+            let mut exact = String::with_capacity(value.len());
+            exact.push_str(&value);
+            value.zeroize();
+            value = exact;
+        }
+        
+        let locked = lock_region(value.as_ptr(), value.len());
+        SecretString { value, locked }
+    }
+    
+    /// 读取秘密内容
+    pub fn expose_secret(&self) -> &str {
+        &self.value
+    }
+}
+
+impl Clone for SecretString {
+    fn clone(&self) -> Self {
+        SecretString::new(self.value.clone())
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        SecretString::new(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        SecretString::new(value.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(SecretString::new)
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString([REDACTED])")
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        self.value.zeroize();
+        if self.locked {
+            unlock_region(self.value.as_ptr(), self.value.capacity());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_debug_is_redacted() {
+        let mut key = SecretKey::zeroed();
+        key.fill(0x41);
+        let bytes = SecretBytes::new(b"combined key material".to_vec());
+        let string: SecretString = serde_json::from_str("\"ukey_secret\"").unwrap();
+        
+        let output = format!("{:?} {:?} {:?}", key, bytes, string);
+        assert!(!output.contains("65"));
+        assert!(!output.contains("combined"));
+        assert!(!output.contains("ukey_secret"));
+        assert!(output.contains("[REDACTED]"));
+        
+        assert_eq!(string.expose_secret(), "ukey_secret");
+        assert_eq!(&bytes[..], b"combined key material");
+        assert_eq!(SecretKey::from_slice(&[0x41; 32]).unwrap(), key);
+        assert!(SecretKey::from_slice(&[0x41; 31]).is_none());
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn test_memory_locking_refcount() {
+        set_memory_locking(true);
+        let first = SecretKey::zeroed();
+        let second = SecretKey::zeroed();
+        set_memory_locking(false);
+        
+        // 受 RLIMIT_MEMLOCK 限制时锁定失败，不影响使用
+        if first.locked && second.locked {
+            let page = pages(first.as_ptr(), 32).next().unwrap();
+            drop(second);
+            
+            // 与其他秘密共用的页在最后一个秘密释放前保持锁定
+            assert!(LOCKED_PAGES.lock().unwrap().contains_key(&page));
+        }
+    }
+}
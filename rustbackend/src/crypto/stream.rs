@@ -0,0 +1,567 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use ring::rand::{self, SecureRandom};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::crypto::suite::{find_suite, CipherSuite, SuiteCipher, SUITE_TAG_LEN};
+
+/// 分段nonce中基础nonce之后的部分：4字节分段计数器和1字节结束标记
+pub const NONCE_SUFFIX_LEN: usize = 5;
+
+/// 认证标签长度
+pub const TAG_LEN: usize = SUITE_TAG_LEN;
+
+/// 流式加解密错误类型
+#[derive(Error, Debug)]
+pub enum StreamError {
+    #[error("不支持的加密算法: {0}")]
+    UnsupportedAlgorithm(String),
+    
+    #[error("创建密钥失败")]
+    InvalidKey,
+    
+    #[error("基础nonce长度错误")]
+    NonceLength,
+    
+    #[error("分段大小错误")]
+    ChunkSize,
+    
+    #[error("分段计数器溢出")]
+    CounterOverflow,
+    
+    #[error("分段认证失败")]
+    ChunkAuthentication,
+    
+    #[error("数据流被截断")]
+    Truncated,
+    
+    #[error("结束分段之后仍有数据")]
+    TrailingData,
+    
+    #[error("数据流已结束")]
+    Finished,
+    
+    #[error("随机数生成错误")]
+    RandomGenerationError,
+}
+
+impl From<StreamError> for io::Error {
+    fn from(err: StreamError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// 由基础nonce、分段计数器和结束标记构造分段nonce
+fn chunk_nonce(nonce_prefix: &[u8], counter: u32, last: bool) -> Vec<u8> {
+    let mut nonce = Vec::with_capacity(nonce_prefix.len() + NONCE_SUFFIX_LEN);
+    nonce.extend_from_slice(nonce_prefix);
+    nonce.extend_from_slice(&counter.to_be_bytes());
+    nonce.push(last as u8);
+    nonce
+}
+
+/// 加密套件的基础nonce长度
+pub fn nonce_prefix_len(suite: &dyn CipherSuite) -> usize {
+    suite.nonce_len() - NONCE_SUFFIX_LEN
+}
+
+/// 为指定算法生成随机基础nonce
+pub fn generate_nonce_prefix(algorithm: &str) -> Result<Vec<u8>, StreamError> {
+    let suite = find_suite(algorithm).map_err(|_| StreamError::UnsupportedAlgorithm(algorithm.to_string()))?;
+    let mut prefix = vec![0u8; nonce_prefix_len(suite)];
+    rand::SystemRandom::new()
+        .fill(&mut prefix)
+        .map_err(|_| StreamError::RandomGenerationError)?;
+    Ok(prefix)
+}
+
+/// 根据算法名称、密钥和基础nonce创建加解密器
+fn create_cipher(algorithm: &str, key: &[u8], nonce_prefix: &[u8]) -> Result<Box<dyn SuiteCipher>, StreamError> {
+    let suite = find_suite(algorithm).map_err(|_| StreamError::UnsupportedAlgorithm(algorithm.to_string()))?;
+    if nonce_prefix.len() != nonce_prefix_len(suite) {
+        return Err(StreamError::NonceLength);
+    }
+    suite.cipher(key).map_err(|_| StreamError::InvalidKey)
+}
+
+/// 分段加密器
+///
+/// 每个分段使用 `基础nonce || 计数器 || 结束标记` 作为nonce独立加密，
+/// 分段被删除、重排或截断都会导致解密端认证失败。
+pub struct StreamSealer {
+    cipher: Box<dyn SuiteCipher>,
+    nonce_prefix: Vec<u8>,
+    aad: Vec<u8>,
+    counter: u32,
+    finished: bool,
+}
+
+impl StreamSealer {
+    /// 创建分段加密器
+    pub fn new(algorithm: &str, key: &[u8], nonce_prefix: &[u8]) -> Result<Self, StreamError> {
+        Ok(Self {
+            cipher: create_cipher(algorithm, key, nonce_prefix)?,
+            nonce_prefix: nonce_prefix.to_vec(),
+            aad: Vec::new(),
+            counter: 0,
+            finished: false,
+        })
+    }
+    
+    /// 设置每个分段的附加认证数据，默认为空
+    pub fn with_aad(mut self, aad: Vec<u8>) -> Self {
+        self.aad = aad;
+        self
+    }
+    
+    /// 加密一个分段，认证标签追加在分段末尾
+    pub fn seal_chunk(&mut self, chunk: &mut Vec<u8>, last: bool) -> Result<(), StreamError> {
+        if self.finished {
+            return Err(StreamError::Finished);
+        }
+        
+        let nonce = chunk_nonce(&self.nonce_prefix, self.counter, last);
+        self.cipher.seal_in_place(&nonce, &self.aad, chunk)
+            .map_err(|_| StreamError::ChunkAuthentication)?;
+        
+        if last {
+            self.finished = true;
+        } else {
+            self.counter = self.counter.checked_add(1).ok_or(StreamError::CounterOverflow)?;
+        }
+        
+        Ok(())
+    }
+}
+
+/// 分段解密器
+pub struct StreamOpener {
+    cipher: Box<dyn SuiteCipher>,
+    nonce_prefix: Vec<u8>,
+    aad: Vec<u8>,
+    counter: u32,
+    finished: bool,
+}
+
+impl StreamOpener {
+    /// 创建分段解密器
+    pub fn new(algorithm: &str, key: &[u8], nonce_prefix: &[u8]) -> Result<Self, StreamError> {
+        Ok(Self {
+            cipher: create_cipher(algorithm, key, nonce_prefix)?,
+            nonce_prefix: nonce_prefix.to_vec(),
+            aad: Vec::new(),
+            counter: 0,
+            finished: false,
+        })
+    }
+    
+    /// 设置每个分段的附加认证数据，默认为空
+    pub fn with_aad(mut self, aad: Vec<u8>) -> Self {
+        self.aad = aad;
+        self
+    }
+    
+    /// 解密一个分段，成功后去掉分段末尾的认证标签
+    pub fn open_chunk(&mut self, chunk: &mut Vec<u8>, last: bool) -> Result<(), StreamError> {
+        if self.finished {
+            return Err(StreamError::TrailingData);
+        }
+        
+        if chunk.len() < TAG_LEN {
+            return Err(StreamError::Truncated);
+        }
+        
+        // 认证失败后缓冲区内容不确定，结束分段需保留副本用于判断截断
+        let original = if last { Some(chunk.clone()) } else { None };
+        
+        let nonce = chunk_nonce(&self.nonce_prefix, self.counter, last);
+        if self.cipher.open_in_place(&nonce, &self.aad, chunk).is_err() {
+            // 最后一个分段按非结束分段能通过认证，说明结束分段被整体删除
+            if let Some(mut original) = original {
+                let nonce = chunk_nonce(&self.nonce_prefix, self.counter, false);
+                if self.cipher.open_in_place(&nonce, &self.aad, &mut original).is_ok() {
+                    return Err(StreamError::Truncated);
+                }
+            }
+            return Err(StreamError::ChunkAuthentication);
+        }
+        
+        if last {
+            self.finished = true;
+        } else {
+            self.counter = self.counter.checked_add(1).ok_or(StreamError::CounterOverflow)?;
+        }
+        
+        Ok(())
+    }
+    
+    /// 数据流是否已读到结束分段
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+/// 一次性加密内存中的数据
+pub fn seal_all(sealer: &mut StreamSealer, data: &[u8], chunk_size: usize) -> Result<Vec<u8>, StreamError> {
+    if chunk_size == 0 {
+        return Err(StreamError::ChunkSize);
+    }
+    
+    let chunk_count = data.len().div_ceil(chunk_size).max(1);
+    let mut output = Vec::with_capacity(data.len() + chunk_count * TAG_LEN);
+    let mut chunks = data.chunks(chunk_size).peekable();
+    
+    // 空数据也需要写出一个空的结束分段
+    loop {
+        let mut chunk = chunks.next().map(<[u8]>::to_vec).unwrap_or_default();
+        let last = chunks.peek().is_none();
+        sealer.seal_chunk(&mut chunk, last)?;
+        output.extend_from_slice(&chunk);
+        if last {
+            break;
+        }
+    }
+    
+    Ok(output)
+}
+
+/// 一次性解密内存中的数据
+pub fn open_all(opener: &mut StreamOpener, data: &[u8], chunk_size: usize) -> Result<Vec<u8>, StreamError> {
+    if chunk_size == 0 {
+        return Err(StreamError::ChunkSize);
+    }
+    
+    let encrypted_chunk_size = chunk_size + TAG_LEN;
+    let mut output = Vec::with_capacity(data.len());
+    let mut remaining = data;
+    
+    loop {
+        let last = remaining.len() <= encrypted_chunk_size;
+        let take = remaining.len().min(encrypted_chunk_size);
+        let mut chunk = remaining[..take].to_vec();
+        opener.open_chunk(&mut chunk, last)?;
+        output.extend_from_slice(&chunk);
+        remaining = &remaining[take..];
+        if last {
+            break;
+        }
+    }
+    
+    Ok(output)
+}
+
+/// 加密写入适配器
+///
+/// 写入的明文按分段加密后写入内部写入器，内存中最多缓存一个分段。
+/// 必须调用 `shutdown` 写出结束分段，否则解密端会判定数据流被截断。
+pub struct EncryptWriter<W> {
+    inner: W,
+    sealer: StreamSealer,
+    chunk_size: usize,
+    plaintext: Vec<u8>,
+    pending: Vec<u8>,
+    pending_pos: usize,
+}
+
+impl<W: AsyncWrite + Unpin> EncryptWriter<W> {
+    /// 创建加密写入适配器
+    pub fn new(inner: W, sealer: StreamSealer, chunk_size: usize) -> Result<Self, StreamError> {
+        if chunk_size == 0 {
+            return Err(StreamError::ChunkSize);
+        }
+        
+        Ok(Self {
+            inner,
+            sealer,
+            chunk_size,
+            plaintext: Vec::with_capacity(chunk_size + 1),
+            pending: Vec::new(),
+            pending_pos: 0,
+        })
+    }
+    
+    /// 取回内部写入器
+    #[allow(dead_code)]
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+    
+    fn poll_write_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.pending_pos < self.pending.len() {
+            let written = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.pending_pos..]))?;
+            if written == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.pending_pos += written;
+        }
+        self.pending.clear();
+        self.pending_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+    
+    fn seal_next(&mut self, last: bool) -> Result<(), StreamError> {
+        let split_at = self.plaintext.len().min(self.chunk_size);
+        let rest = self.plaintext.split_off(split_at);
+        let mut chunk = std::mem::replace(&mut self.plaintext, rest);
+        self.sealer.seal_chunk(&mut chunk, last)?;
+        self.pending = chunk;
+        self.pending_pos = 0;
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for EncryptWriter<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        
+        loop {
+            ready!(this.poll_write_pending(cx))?;
+            
+            // 多缓存一个字节才能确定当前分段不是最后一个
+            if this.plaintext.len() > this.chunk_size {
+                this.seal_next(false)?;
+                continue;
+            }
+            
+            let accepted = buf.len().min(this.chunk_size + 1 - this.plaintext.len());
+            this.plaintext.extend_from_slice(&buf[..accepted]);
+            return Poll::Ready(Ok(accepted));
+        }
+    }
+    
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+    
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        
+        loop {
+            ready!(this.poll_write_pending(cx))?;
+            
+            if this.plaintext.len() > this.chunk_size {
+                this.seal_next(false)?;
+            } else if !this.sealer.finished {
+                this.seal_next(true)?;
+            } else {
+                break;
+            }
+        }
+        
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// 解密读取适配器
+///
+/// 从内部读取器按分段读取密文并解密，内存中最多缓存一个分段。
+/// 读到结束分段之前遇到EOF会返回截断错误。
+pub struct DecryptReader<R> {
+    inner: R,
+    opener: StreamOpener,
+    encrypted_chunk_size: usize,
+    buffer: Vec<u8>,
+    filled: usize,
+    eof: bool,
+    plaintext: Vec<u8>,
+    plaintext_pos: usize,
+}
+
+impl<R: AsyncRead + Unpin> DecryptReader<R> {
+    /// 创建解密读取适配器
+    pub fn new(inner: R, opener: StreamOpener, chunk_size: usize) -> Result<Self, StreamError> {
+        if chunk_size == 0 {
+            return Err(StreamError::ChunkSize);
+        }
+        
+        let encrypted_chunk_size = chunk_size + TAG_LEN;
+        Ok(Self {
+            inner,
+            opener,
+            encrypted_chunk_size,
+            // 多读一个字节用于判断当前分段是否为结束分段
+            buffer: vec![0u8; encrypted_chunk_size + 1],
+            filled: 0,
+            eof: false,
+            plaintext: Vec::new(),
+            plaintext_pos: 0,
+        })
+    }
+    
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.eof && self.filled < self.buffer.len() {
+            let mut read_buf = ReadBuf::new(&mut self.buffer[self.filled..]);
+            ready!(Pin::new(&mut self.inner).poll_read(cx, &mut read_buf))?;
+            let read = read_buf.filled().len();
+            if read == 0 {
+                self.eof = true;
+            }
+            self.filled += read;
+        }
+        Poll::Ready(Ok(()))
+    }
+    
+    fn open_next(&mut self) -> Result<(), StreamError> {
+        let last = self.eof && self.filled <= self.encrypted_chunk_size;
+        let take = self.filled.min(self.encrypted_chunk_size);
+        
+        let mut chunk = self.buffer[..take].to_vec();
+        self.opener.open_chunk(&mut chunk, last)?;
+        
+        self.buffer.copy_within(take..self.filled, 0);
+        self.filled -= take;
+        self.plaintext = chunk;
+        self.plaintext_pos = 0;
+        Ok(())
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DecryptReader<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        
+        loop {
+            if this.plaintext_pos < this.plaintext.len() {
+                let available = &this.plaintext[this.plaintext_pos..];
+                let copied = available.len().min(buf.remaining());
+                buf.put_slice(&available[..copied]);
+                this.plaintext_pos += copied;
+                return Poll::Ready(Ok(()));
+            }
+            
+            if this.opener.is_finished() {
+                return Poll::Ready(Ok(()));
+            }
+            
+            ready!(this.poll_fill(cx))?;
+            this.open_next()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    
+    const KEY: [u8; 32] = [7u8; 32];
+    const PREFIX: [u8; 7] = [1, 2, 3, 4, 5, 6, 7];
+    
+    async fn encrypt(data: &[u8], chunk_size: usize) -> Vec<u8> {
+        let sealer = StreamSealer::new("AES256GCM", &KEY, &PREFIX).unwrap();
+        let mut writer = EncryptWriter::new(Vec::new(), sealer, chunk_size).unwrap();
+        // 以不规则的写入大小模拟网络上传
+        for piece in data.chunks(5) {
+            writer.write_all(piece).await.unwrap();
+        }
+        writer.shutdown().await.unwrap();
+        writer.into_inner()
+    }
+    
+    async fn decrypt(data: &[u8], chunk_size: usize) -> std::io::Result<Vec<u8>> {
+        let opener = StreamOpener::new("AES256GCM", &KEY, &PREFIX).unwrap();
+        let mut reader = DecryptReader::new(data, opener, chunk_size).unwrap();
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).await?;
+        Ok(output)
+    }
+    
+    fn stream_error(err: std::io::Error) -> StreamError {
+        *err.into_inner().unwrap().downcast::<StreamError>().unwrap()
+    }
+    
+    #[tokio::test]
+    async fn test_stream_round_trip() {
+        let chunk_size = 16;
+        for len in [0usize, 1, 15, 16, 17, 32, 100] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let encrypted = encrypt(&data, chunk_size).await;
+            assert_eq!(encrypted.len(), len + len.div_ceil(chunk_size).max(1) * TAG_LEN);
+            
+            // 适配器与一次性接口的格式一致
+            let mut sealer = StreamSealer::new("AES256GCM", &KEY, &PREFIX).unwrap();
+            assert_eq!(seal_all(&mut sealer, &data, chunk_size).unwrap(), encrypted);
+            let mut opener = StreamOpener::new("AES256GCM", &KEY, &PREFIX).unwrap();
+            assert_eq!(open_all(&mut opener, &encrypted, chunk_size).unwrap(), data);
+            
+            assert_eq!(decrypt(&encrypted, chunk_size).await.unwrap(), data);
+        }
+    }
+    
+    #[test]
+    fn test_stream_all_suites() {
+        let data: Vec<u8> = (0..100u8).collect();
+        
+        for name in crate::crypto::suite::suite_names() {
+            let prefix = generate_nonce_prefix(name).unwrap();
+            assert_eq!(prefix.len() + NONCE_SUFFIX_LEN, find_suite(name).unwrap().nonce_len());
+            
+            let mut sealer = StreamSealer::new(name, &KEY, &prefix).unwrap().with_aad(b"aad".to_vec());
+            let encrypted = seal_all(&mut sealer, &data, 16).unwrap();
+            let mut opener = StreamOpener::new(name, &KEY, &prefix).unwrap().with_aad(b"aad".to_vec());
+            assert_eq!(open_all(&mut opener, &encrypted, 16).unwrap(), data);
+            
+            // 删除结束分段在所有套件下都能被识别
+            let mut opener = StreamOpener::new(name, &KEY, &prefix).unwrap().with_aad(b"aad".to_vec());
+            let err = open_all(&mut opener, &encrypted[..6 * (16 + TAG_LEN)], 16).unwrap_err();
+            assert!(matches!(err, StreamError::Truncated));
+        }
+        
+        // 基础nonce长度必须与套件匹配
+        assert!(matches!(StreamSealer::new("XCHACHA20POLY1305", &KEY, &PREFIX), Err(StreamError::NonceLength)));
+        assert!(matches!(StreamSealer::new("DES", &KEY, &PREFIX), Err(StreamError::UnsupportedAlgorithm(_))));
+    }
+    
+    #[tokio::test]
+    async fn test_stream_detects_truncation() {
+        let chunk_size = 16;
+        let data = vec![42u8; 40];
+        let encrypted = encrypt(&data, chunk_size).await;
+        
+        // 删除结束分段
+        let truncated = &encrypted[..2 * (chunk_size + TAG_LEN)];
+        let err = stream_error(decrypt(truncated, chunk_size).await.unwrap_err());
+        assert!(matches!(err, StreamError::Truncated));
+        
+        // 分段中间截断
+        let truncated = &encrypted[..encrypted.len() - 1];
+        assert!(decrypt(truncated, chunk_size).await.is_err());
+        
+        // 空数据流
+        let err = stream_error(decrypt(&[], chunk_size).await.unwrap_err());
+        assert!(matches!(err, StreamError::Truncated));
+    }
+    
+    #[tokio::test]
+    async fn test_stream_detects_reordering_and_tampering() {
+        let chunk_size = 16;
+        let data: Vec<u8> = (0..48u8).collect();
+        let encrypted = encrypt(&data, chunk_size).await;
+        let encrypted_chunk_size = chunk_size + TAG_LEN;
+        
+        // 交换前两个分段
+        let mut reordered = encrypted.clone();
+        reordered[..encrypted_chunk_size].copy_from_slice(&encrypted[encrypted_chunk_size..2 * encrypted_chunk_size]);
+        reordered[encrypted_chunk_size..2 * encrypted_chunk_size].copy_from_slice(&encrypted[..encrypted_chunk_size]);
+        let err = stream_error(decrypt(&reordered, chunk_size).await.unwrap_err());
+        assert!(matches!(err, StreamError::ChunkAuthentication));
+        
+        // 篡改分段内容
+        let mut tampered = encrypted.clone();
+        tampered[3] ^= 0x80;
+        assert!(decrypt(&tampered, chunk_size).await.is_err());
+        
+        // 结束分段之后追加数据
+        let mut extended = encrypted.clone();
+        extended.extend_from_slice(&encrypted[..encrypted_chunk_size]);
+        assert!(decrypt(&extended, chunk_size).await.is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::XChaCha20Poly1305;
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use ring::aead;
+use thiserror::Error;
+
+/// 所有加密套件的密钥长度
+#[allow(dead_code)]
+pub const SUITE_KEY_LEN: usize = 32;
+
+/// 所有加密套件的认证标签长度
+pub const SUITE_TAG_LEN: usize = 16;
+
+/// 加密套件错误类型
+#[derive(Error, Debug)]
+pub enum SuiteError {
+    #[error("不支持的加密算法: {0}")]
+    UnsupportedSuite(String),
+    
+    #[error("未知的加密套件编号: {0}")]
+    UnknownSuiteId(u8),
+    
+    #[error("创建密钥失败")]
+    InvalidKey,
+    
+    #[error("nonce长度错误")]
+    NonceLength,
+    
+    #[error("加密失败")]
+    SealError,
+    
+    #[error("认证失败")]
+    OpenError,
+}
+
+/// 加密套件
+///
+/// 每个套件有唯一的名称（用于 `ENCRYPTION_ALGORITHM` 和加密信息中的algorithm）
+/// 和唯一的编号（用于容器头部），新增套件只需实现该trait并加入 `SUITES`。
+pub trait CipherSuite: Send + Sync {
+    /// 算法名称
+    fn name(&self) -> &'static str;
+    
+    /// 容器头部中的套件编号
+    fn id(&self) -> u8;
+    
+    /// nonce长度
+    fn nonce_len(&self) -> usize;
+    
+    /// 用密钥创建加解密器
+    fn cipher(&self, key: &[u8]) -> Result<Box<dyn SuiteCipher>, SuiteError>;
+}
+
+/// 绑定了密钥的加解密器，认证标签追加在密文末尾
+pub trait SuiteCipher: Send + Sync {
+    /// 原地加密并追加认证标签
+    fn seal_in_place(&self, nonce: &[u8], aad: &[u8], in_out: &mut Vec<u8>) -> Result<(), SuiteError>;
+    
+    /// 原地校验并解密，成功后去掉认证标签
+    fn open_in_place(&self, nonce: &[u8], aad: &[u8], in_out: &mut Vec<u8>) -> Result<(), SuiteError>;
+}
+
+/// ring实现的套件
+struct RingSuite {
+    name: &'static str,
+    id: u8,
+    algorithm: &'static aead::Algorithm,
+}
+
+struct RingCipher(aead::LessSafeKey);
+
+impl CipherSuite for RingSuite {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    
+    fn id(&self) -> u8 {
+        self.id
+    }
+    
+    fn nonce_len(&self) -> usize {
+        self.algorithm.nonce_len()
+    }
+    
+    fn cipher(&self, key: &[u8]) -> Result<Box<dyn SuiteCipher>, SuiteError> {
+        let unbound_key = aead::UnboundKey::new(self.algorithm, key).map_err(|_| SuiteError::InvalidKey)?;
+        Ok(Box::new(RingCipher(aead::LessSafeKey::new(unbound_key))))
+    }
+}
+
+impl SuiteCipher for RingCipher {
+    fn seal_in_place(&self, nonce: &[u8], aad: &[u8], in_out: &mut Vec<u8>) -> Result<(), SuiteError> {
+        let nonce = aead::Nonce::try_assume_unique_for_key(nonce).map_err(|_| SuiteError::NonceLength)?;
+        self.0.seal_in_place_append_tag(nonce, aead::Aad::from(aad), in_out)
+            .map_err(|_| SuiteError::SealError)
+    }
+    
+    fn open_in_place(&self, nonce: &[u8], aad: &[u8], in_out: &mut Vec<u8>) -> Result<(), SuiteError> {
+        let nonce = aead::Nonce::try_assume_unique_for_key(nonce).map_err(|_| SuiteError::NonceLength)?;
+        let plaintext_len = self.0.open_in_place(nonce, aead::Aad::from(aad), in_out)
+            .map_err(|_| SuiteError::OpenError)?
+            .len();
+        in_out.truncate(plaintext_len);
+        Ok(())
+    }
+}
+
+/// RustCrypto实现的套件
+struct RustCryptoSuite<C> {
+    name: &'static str,
+    id: u8,
+    nonce_len: usize,
+    _cipher: std::marker::PhantomData<fn() -> C>,
+}
+
+struct RustCryptoCipher<C> {
+    cipher: C,
+    nonce_len: usize,
+}
+
+impl<C> CipherSuite for RustCryptoSuite<C>
+where
+    C: AeadInPlace + KeyInit + Send + Sync + 'static,
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    
+    fn id(&self) -> u8 {
+        self.id
+    }
+    
+    fn nonce_len(&self) -> usize {
+        self.nonce_len
+    }
+    
+    fn cipher(&self, key: &[u8]) -> Result<Box<dyn SuiteCipher>, SuiteError> {
+        let cipher = C::new_from_slice(key).map_err(|_| SuiteError::InvalidKey)?;
+        Ok(Box::new(RustCryptoCipher { cipher, nonce_len: self.nonce_len }))
+    }
+}
+
+impl<C> SuiteCipher for RustCryptoCipher<C>
+where
+    C: AeadInPlace + Send + Sync,
+{
+    fn seal_in_place(&self, nonce: &[u8], aad: &[u8], in_out: &mut Vec<u8>) -> Result<(), SuiteError> {
+        if nonce.len() != self.nonce_len {
+            return Err(SuiteError::NonceLength);
+        }
+        self.cipher.encrypt_in_place(chacha20poly1305::aead::Nonce::<C>::from_slice(nonce), aad, in_out)
+            .map_err(|_| SuiteError::SealError)
+    }
+    
+    fn open_in_place(&self, nonce: &[u8], aad: &[u8], in_out: &mut Vec<u8>) -> Result<(), SuiteError> {
+        if nonce.len() != self.nonce_len {
+            return Err(SuiteError::NonceLength);
+        }
+        self.cipher.decrypt_in_place(chacha20poly1305::aead::Nonce::<C>::from_slice(nonce), aad, in_out)
+            .map_err(|_| SuiteError::OpenError)
+    }
+}
+
+/// AES-256-GCM
+static AES256GCM: RingSuite = RingSuite {
+    name: "AES256GCM",
+    id: 1,
+    algorithm: &aead::AES_256_GCM,
+};
+
+/// ChaCha20-Poly1305
+static CHACHA20POLY1305: RingSuite = RingSuite {
+    name: "CHACHA20POLY1305",
+    id: 2,
+    algorithm: &aead::CHACHA20_POLY1305,
+};
+
+/// XChaCha20-Poly1305，24字节nonce，分段nonce的随机部分足够长，不依赖计数器唯一性
+static XCHACHA20POLY1305: RustCryptoSuite<XChaCha20Poly1305> = RustCryptoSuite {
+    name: "XCHACHA20POLY1305",
+    id: 3,
+    nonce_len: 24,
+    _cipher: std::marker::PhantomData,
+};
+
+/// AES-256-GCM-SIV，nonce重复时只泄露明文是否相同，不会泄露认证密钥
+static AES256GCMSIV: RustCryptoSuite<Aes256GcmSiv> = RustCryptoSuite {
+    name: "AES256GCMSIV",
+    id: 4,
+    nonce_len: 12,
+    _cipher: std::marker::PhantomData,
+};
+
+/// 已注册的加密套件
+static SUITES: [&dyn CipherSuite; 4] = [&AES256GCM, &CHACHA20POLY1305, &XCHACHA20POLY1305, &AES256GCMSIV];
+
+/// 根据名称查找加密套件
+pub fn find_suite(name: &str) -> Result<&'static dyn CipherSuite, SuiteError> {
+    SUITES.iter()
+        .copied()
+        .find(|suite| suite.name() == name)
+        .ok_or_else(|| SuiteError::UnsupportedSuite(name.to_string()))
+}
+
+/// 根据容器头部中的编号查找加密套件
+pub fn find_suite_by_id(id: u8) -> Result<&'static dyn CipherSuite, SuiteError> {
+    SUITES.iter()
+        .copied()
+        .find(|suite| suite.id() == id)
+        .ok_or(SuiteError::UnknownSuiteId(id))
+}
+
+/// 所有已注册套件的名称
+pub fn suite_names() -> Vec<&'static str> {
+    SUITES.iter().map(|suite| suite.name()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_all_suites_round_trip() {
+        let key = [5u8; SUITE_KEY_LEN];
+        
+        for name in suite_names() {
+            let suite = find_suite(name).unwrap();
+            assert_eq!(find_suite_by_id(suite.id()).unwrap().name(), name);
+            
+            let cipher = suite.cipher(&key).unwrap();
+            let nonce = vec![9u8; suite.nonce_len()];
+            let mut in_out = b"cipher suite data".to_vec();
+            cipher.seal_in_place(&nonce, b"aad", &mut in_out).unwrap();
+            assert_eq!(in_out.len(), b"cipher suite data".len() + SUITE_TAG_LEN);
+            
+            // 附加认证数据或密文不一致时认证失败
+            let mut wrong_aad = in_out.clone();
+            assert!(matches!(cipher.open_in_place(&nonce, b"other", &mut wrong_aad), Err(SuiteError::OpenError)));
+            let mut tampered = in_out.clone();
+            tampered[0] ^= 0x01;
+            assert!(cipher.open_in_place(&nonce, b"aad", &mut tampered).is_err());
+            
+            // 错误长度的nonce被拒绝
+            assert!(matches!(cipher.open_in_place(&nonce[1..], b"aad", &mut in_out.clone()), Err(SuiteError::NonceLength)));
+            
+            cipher.open_in_place(&nonce, b"aad", &mut in_out).unwrap();
+            assert_eq!(in_out, b"cipher suite data");
+        }
+    }
+    
+    #[test]
+    fn test_unknown_suite() {
+        assert!(matches!(find_suite("DES"), Err(SuiteError::UnsupportedSuite(_))));
+        assert!(matches!(find_suite_by_id(0), Err(SuiteError::UnknownSuiteId(0))));
+        assert_eq!(find_suite("XCHACHA20POLY1305").unwrap().nonce_len(), 24);
+    }
+}
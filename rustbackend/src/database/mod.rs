@@ -10,6 +10,7 @@ pub enum DatabaseError {
     ConnectionError(#[from] sqlx::Error),
     
     #[error("数据库迁移错误: {0}")]
+    #[allow(dead_code)]
     MigrationError(String),
     
     #[error("数据库查询错误: {0}")]
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+use crate::crypto::SecretString;
+
 /// 加密密钥模型
 #[derive(FromRow, Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
@@ -10,6 +12,10 @@ pub struct EncryptionKey {
     pub key_hash: String,
     pub ukey_info: String,
     pub created_at: chrono::NaiveDateTime,
+    /// 被KEK包装的数据密钥，旧资源为空
+    pub wrapped_key: Option<String>,
+    /// 被恢复公钥包装的数据密钥，没有有效的恢复密钥时为空
+    pub recovery_wrapped_key: Option<String>,
 }
 
 /// 创建加密密钥请求模型
@@ -19,6 +25,8 @@ pub struct CreateEncryptionKeyRequest {
     pub resource_id: i32,
     pub key_hash: String,
     pub ukey_info: String,
+    pub wrapped_key: Option<String>,
+    pub recovery_wrapped_key: Option<String>,
 }
 
 /// 更新加密密钥请求模型
@@ -30,11 +38,13 @@ pub struct UpdateEncryptionKeyRequest {
 }
 
 /// 解密请求模型
-#[derive(Debug, Serialize, Deserialize, Clone)]
+///
+/// 密钥A和硬件码B在释放时清零，`Debug` 不输出内容。
+#[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 pub struct DecryptRequest {
-    pub key_part_a: String,
-    pub ukey_part_b: String,
+    pub key_part_a: SecretString,
+    pub ukey_part_b: SecretString,
     pub resource_id: i32,
 }
 
@@ -59,6 +69,8 @@ impl EncryptionKey {
             key_hash,
             ukey_info,
             created_at: now,
+            wrapped_key: None,
+            recovery_wrapped_key: None,
         }
     }
     
@@ -68,8 +80,8 @@ impl EncryptionKey {
         self.ukey_info = update_req.ukey_info;
     }
     
-    /// 验证密钥哈希
+    /// 验证密钥哈希，使用常量时间比较
     pub fn verify_key_hash(&self, key_hash: &str) -> bool {
-        self.key_hash == key_hash
+        crate::crypto::key_management::constant_time_eq(self.key_hash.as_bytes(), key_hash.as_bytes())
     }
 }
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+/// 签名清单头部
+#[derive(Debug, Clone, sqlx::FromRow)]
+#[allow(dead_code)]
+pub struct ResourceManifest {
+    pub id: i32,
+    /// 每次签名加一
+    pub sequence: i64,
+    /// 清单摘要，见 `crypto::manifest::manifest_digest`
+    pub digest: Vec<u8>,
+    /// 对清单摘要的Ed25519签名
+    pub signature: Vec<u8>,
+    /// 签名公钥，Base64编码
+    pub public_key: String,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// 签名清单验证结果
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ManifestReport {
+    /// 清单序号，还没有建立清单时为空
+    pub sequence: Option<i64>,
+    /// 清单签名是否有效，无效时清单记录本身已被篡改
+    pub signature_valid: bool,
+    /// 清单中有记录但已不存在的资源
+    pub missing: Vec<i32>,
+    /// 内容与清单记录不一致的资源，被回滚到旧版本或被篡改
+    pub rolled_back: Vec<i32>,
+    /// 不在清单中的资源，没有经过资源服务写入
+    pub injected: Vec<i32>,
+}
+
+impl ManifestReport {
+    /// 清单签名有效且所有资源与清单一致
+    pub fn is_intact(&self) -> bool {
+        self.signature_valid && self.missing.is_empty() && self.rolled_back.is_empty() && self.injected.is_empty()
+    }
+}
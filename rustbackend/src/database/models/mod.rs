@@ -7,5 +7,20 @@ pub mod user;
 /// 加密密钥模型
 pub mod encryption_key;
 
+/// 密钥更换任务模型
+pub mod rekey_job;
+
+/// 加密参数升级模型
+pub mod upgrade;
+
+/// 恢复密钥模型
+pub mod recovery_key;
+
+/// 签名清单模型
+pub mod manifest;
+
+/// UKey登记模型
+pub mod ukey;
+
 /// 重新导出模型
 pub use resource::{CreateResourceRequest, UpdateResourceRequest, ResourceQueryParams, ResourceStats, ResourceResponse};
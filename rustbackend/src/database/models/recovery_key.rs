@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::crypto::SecretString;
+use crate::ukey::HardwareAssertion;
+
+/// 恢复密钥模型
+///
+/// 只保存X25519公钥和指纹，恢复码本身不落库。
+#[derive(FromRow, Debug, Serialize, Clone)]
+pub struct RecoveryKey {
+    pub id: i32,
+    pub public_key: String,
+    /// 公钥指纹，用于核对打印的恢复码
+    pub fingerprint: String,
+    pub created_at: chrono::NaiveDateTime,
+    /// 撤销时间，有效的恢复密钥为空
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    /// 重建恢复码需要的分片数，以单个恢复码发放时为空
+    pub share_threshold: Option<i16>,
+    /// 发放的分片数，以单个恢复码发放时为空
+    pub share_count: Option<i16>,
+}
+
+/// 恢复密钥状态
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct RecoveryKeyStatus {
+    /// 当前有效的恢复密钥
+    pub recovery_key: Option<RecoveryKey>,
+    /// 可以用恢复码解包数据密钥的资源数
+    pub covered_resources: i32,
+    /// 资源总数
+    pub total_resources: i32,
+}
+
+/// 生成恢复密钥请求模型
+///
+/// 需要当前的密钥A和硬件码B解出已有资源的数据密钥，硬件码B由UKey应答挑战得到。
+#[derive(Debug, Deserialize)]
+pub struct RegenerateRecoveryKeyRequest {
+    /// 密钥A
+    pub key_part_a: SecretString,
+    /// UKey或FIDO2安全密钥的应答，为空时由服务器上的UKey应答
+    pub hardware_assertion: Option<HardwareAssertion>,
+}
+
+/// 撤销恢复密钥请求模型
+///
+/// 与生成恢复密钥一样需要当前的密钥A和UKey应答。
+#[derive(Debug, Deserialize)]
+pub struct RevokeRecoveryKeyRequest {
+    /// 密钥A
+    pub key_part_a: SecretString,
+    /// UKey或FIDO2安全密钥的应答，为空时由服务器上的UKey应答
+    pub hardware_assertion: Option<HardwareAssertion>,
+}
+
+/// 生成托管恢复密钥请求模型
+///
+/// 恢复码拆分为 `share_count` 份分片交给不同的保管人，任意 `share_threshold` 份可以重建。
+#[derive(Debug, Deserialize)]
+pub struct EscrowRecoveryKeyRequest {
+    /// 密钥A
+    pub key_part_a: SecretString,
+    /// UKey或FIDO2安全密钥的应答，为空时由服务器上的UKey应答
+    pub hardware_assertion: Option<HardwareAssertion>,
+    /// 重建需要的分片数
+    pub share_threshold: u8,
+    /// 分片数
+    pub share_count: u8,
+}
+
+/// 使用恢复码恢复请求模型
+///
+/// 用恢复码解包所有资源的数据密钥，并换上新的密钥A和新UKey的硬件码B。新UKey需要先登记。
+#[derive(Debug, Deserialize)]
+pub struct RecoverRequest {
+    /// 恢复码
+    pub recovery_code: SecretString,
+    /// 新密钥A
+    pub new_key_part_a: SecretString,
+    /// 新UKey的应答，为空时由服务器上的UKey应答
+    pub new_hardware_assertion: Option<HardwareAssertion>,
+}
+
+/// 使用保管人分片恢复请求模型
+#[derive(Debug, Deserialize)]
+pub struct UnlockWithSharesRequest {
+    /// 保管人提供的分片，数量不少于门限
+    pub shares: Vec<SecretString>,
+    /// 新密钥A
+    pub new_key_part_a: SecretString,
+    /// 新UKey的应答，为空时由服务器上的UKey应答
+    pub new_hardware_assertion: Option<HardwareAssertion>,
+}
+
+/// 生成恢复密钥的结果
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct RecoveryCoverage {
+    /// 已生成恢复包装的资源数
+    pub covered: i32,
+    /// 不属于该密钥或仍为旧版格式、未生成恢复包装的资源数
+    pub skipped: i32,
+}
+
+/// 使用恢复码恢复的结果
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct RecoverySummary {
+    /// 已换上新密钥的资源数
+    pub recovered: i32,
+    /// 恢复包装无法解开或恢复失败的资源数
+    pub failed: i32,
+}
@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::crypto::SecretString;
+use crate::ukey::HardwareAssertion;
+
+/// 密钥更换任务模型
+#[derive(FromRow, Debug, Serialize, Clone)]
+pub struct RekeyJob {
+    pub id: i32,
+    pub status: String,
+    /// 任务创建时的资源总数
+    pub total_resources: i32,
+    /// 已更换密钥的资源数
+    pub processed_resources: i32,
+    /// 不属于旧密钥而被跳过的资源数
+    pub skipped_resources: i32,
+    /// 被跳过的资源ID，这些资源仍使用其他密钥，需要单独处理
+    pub skipped_resource_ids: Vec<i32>,
+    /// 最后处理的资源ID，续跑时从下一个资源开始
+    pub last_resource_id: i32,
+    pub batch_size: i32,
+    /// 新密钥的密钥信息JSON，只用于续跑时确认新密钥一致
+    #[serde(skip_serializing)]
+    pub new_key_check: String,
+    pub error: Option<String>,
+    pub created_at: chrono::NaiveDateTime,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// 密钥更换请求模型
+///
+/// 旧硬件码B和新硬件码B分别由旧UKey和新UKey应答挑战得到，为空时由服务器上的UKey应答。
+/// 只更换密钥A时两者来自同一个UKey，但需要应答两次挑战。
+#[derive(Debug, Deserialize)]
+pub struct RekeyRequest {
+    /// 旧密钥A
+    pub old_key_part_a: SecretString,
+    /// 旧UKey的应答
+    pub old_hardware_assertion: Option<HardwareAssertion>,
+    /// 新密钥A
+    pub new_key_part_a: SecretString,
+    /// 新UKey的应答
+    pub new_hardware_assertion: Option<HardwareAssertion>,
+    /// 每批处理的资源数，为空时使用默认值
+    pub batch_size: Option<u32>,
+}
+
+/// 密钥更换任务使用的密钥
+///
+/// 硬件码B取自验证通过的硬件应答，任务执行期间保存在内存中，续跑时需要重新应答。
+#[derive(Debug)]
+pub struct RekeyKeys {
+    /// 旧密钥A
+    pub old_key_part_a: SecretString,
+    /// 旧硬件码B
+    pub old_ukey_part_b: SecretString,
+    /// 新密钥A
+    pub new_key_part_a: SecretString,
+    /// 新硬件码B
+    pub new_ukey_part_b: SecretString,
+    /// 新UKey的指纹，写入加密密钥记录
+    pub new_ukey_fingerprint: String,
+    /// 每批处理的资源数，为空时使用默认值
+    pub batch_size: Option<u32>,
+}
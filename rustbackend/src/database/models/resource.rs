@@ -2,8 +2,12 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 
+use crate::crypto::ResourceMetadata;
 use crate::database::schema::resource_status;
 
+/// 元数据加密的资源在解锁前显示的占位符
+pub const ENCRYPTED_METADATA_PLACEHOLDER: &str = "[已加密]";
+
 /// 资源模型
 #[derive(FromRow, Debug, Serialize, Deserialize, Clone)]
 pub struct Resource {
@@ -12,6 +16,7 @@ pub struct Resource {
     pub title_en: Option<String>,
     pub description: Option<String>,
     pub resource_type: String,
+    /// 加密容器保存在 `resource_chunks` 表中，只有按需读取容器时才填入，否则为空
     pub media_data: Vec<u8>,
     pub media_type: String,
     pub is_local: bool,
@@ -21,6 +26,10 @@ pub struct Resource {
     pub updated_at: chrono::NaiveDateTime,
     pub total_count: Option<i32>,
     pub has_pending_supplement: Option<bool>,
+    /// 资源的稳定标识，作为附加认证数据绑定到密文
+    pub uuid: uuid::Uuid,
+    /// 用资源的数据密钥加密的标题和描述，为空表示元数据以明文保存
+    pub encrypted_metadata: Option<Vec<u8>>,
 }
 
 /// 创建资源请求模型
@@ -38,7 +47,7 @@ pub struct CreateResourceRequest {
 }
 
 /// 更新资源请求模型
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct UpdateResourceRequest {
     pub title: Option<String>,
     pub title_en: Option<String>,
@@ -66,6 +75,8 @@ pub struct ResourceResponse {
     pub updated_at: chrono::NaiveDateTime,
     pub total_count: Option<i32>,
     pub has_pending_supplement: Option<bool>,
+    /// 标题和描述是否已加密，未解锁时为占位符
+    pub metadata_encrypted: bool,
 }
 
 /// 资源列表查询参数
@@ -118,6 +129,8 @@ impl Resource {
             updated_at: now,
             total_count: None,
             has_pending_supplement: None,
+            uuid: uuid::Uuid::new_v4(),
+            encrypted_metadata: None,
         }
     }
     
@@ -163,20 +176,52 @@ impl Resource {
     }
     
     /// 转换为响应模型（不包含媒体数据）
+    ///
+    /// 元数据已加密时标题和描述为占位符。
     pub fn to_response(&self) -> ResourceResponse {
+        if self.encrypted_metadata.is_some() {
+            return self.response_with_metadata(
+                ENCRYPTED_METADATA_PLACEHOLDER.to_string(),
+                ENCRYPTED_METADATA_PLACEHOLDER.to_string(),
+                ENCRYPTED_METADATA_PLACEHOLDER.to_string(),
+            );
+        }
+        
+        self.response_with_metadata(
+            self.title.clone(),
+            self.title_en.clone().unwrap_or_default(),
+            self.description.clone().unwrap_or_default(),
+        )
+    }
+    
+    /// 转换为解锁后的响应模型，使用解密后的元数据
+    pub fn to_unlocked_response(&self, metadata: Option<&ResourceMetadata>) -> ResourceResponse {
+        match metadata {
+            Some(metadata) => self.response_with_metadata(
+                metadata.title.clone(),
+                metadata.title_en.clone().unwrap_or_default(),
+                metadata.description.clone().unwrap_or_default(),
+            ),
+            None => self.to_response(),
+        }
+    }
+    
+    /// 用指定的标题和描述构建响应模型
+    fn response_with_metadata(&self, title: String, title_en: String, description: String) -> ResourceResponse {
         ResourceResponse {
             id: self.id,
-            title: self.title.clone(),
-            title_en: self.title_en.clone().unwrap_or_default(),
-            description: self.description.clone().unwrap_or_default(),
+            title,
+            title_en,
+            description,
             resource_type: self.resource_type.clone(),
             media_type: self.media_type.clone(),
             is_local: self.is_local,
             status: self.status.clone(),
-            created_at: self.created_at.clone(),
-            updated_at: self.updated_at.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
             total_count: self.total_count,
             has_pending_supplement: self.has_pending_supplement,
+            metadata_encrypted: self.encrypted_metadata.is_some(),
         }
     }
     
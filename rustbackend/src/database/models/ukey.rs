@@ -0,0 +1,75 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::crypto::SecretString;
+use crate::ukey::{Fido2Registration, HardwareAssertion, UKeyAssertion};
+
+/// UKey登记模型
+///
+/// 只保存UKey的P-256公钥和指纹，硬件码B不落库。FIDO2安全密钥的驱动为 `fido2`，序列号为凭据ID。
+#[derive(FromRow, Debug, Serialize, Clone)]
+pub struct UKey {
+    pub id: i32,
+    /// UKey序列号
+    pub serial: String,
+    /// UKey驱动
+    pub vendor: String,
+    /// 持有人
+    pub owner: String,
+    /// P-256公钥，Base64编码的未压缩点
+    pub public_key: String,
+    /// 公钥的SHA-256指纹，十六进制
+    pub fingerprint: String,
+    pub enrolled_at: chrono::NaiveDateTime,
+    /// 撤销时间，有效的UKey为空
+    pub revoked_at: Option<chrono::NaiveDateTime>,
+    /// FIDO2安全密钥最近一次断言的签名计数器，UKey始终为0
+    pub sign_count: i64,
+}
+
+/// 登记或撤销硬件的授权
+///
+/// 当前的密钥A和一个已登记、未撤销的UKey或FIDO2安全密钥的应答，证明请求来自现有硬件的持有人。
+/// 也可以改为在 `X-UKey-Admin-Token` 请求头中提供 `UKEY_ADMIN_TOKEN`，还没有可以验证密钥的资源时只接受管理令牌。
+#[derive(Debug, Deserialize)]
+pub struct UKeyAuthorization {
+    /// 当前的密钥A
+    pub key_part_a: SecretString,
+    /// 已登记硬件的应答
+    pub hardware_assertion: HardwareAssertion,
+}
+
+/// 登记UKey请求模型
+///
+/// UKey需要应答 `POST /api/ukey/challenges` 签发的挑战，证明持有对应的私钥。
+#[derive(Debug, Deserialize)]
+pub struct EnrollUKeyRequest {
+    /// 持有人
+    pub owner: String,
+    /// UKey驱动，只能是 `http` 或 `pkcs11`，为空时使用应答UKey所用的驱动
+    pub vendor: Option<String>,
+    /// UKey对挑战的应答，为空时由服务器上的UKey应答
+    pub ukey_assertion: Option<UKeyAssertion>,
+    /// 已登记硬件的授权，使用管理令牌时为空
+    pub authorization: Option<UKeyAuthorization>,
+}
+
+/// 撤销UKey或FIDO2安全密钥请求模型
+#[derive(Debug, Deserialize)]
+pub struct RevokeUKeyRequest {
+    /// 已登记硬件的授权，使用管理令牌时为空
+    pub authorization: Option<UKeyAuthorization>,
+}
+
+/// 登记FIDO2安全密钥请求模型
+///
+/// 安全密钥需要用 `POST /api/fido2/registrations/options` 返回的参数创建凭据。
+#[derive(Debug, Deserialize)]
+pub struct EnrollFido2Request {
+    /// 持有人
+    pub owner: String,
+    /// 安全密钥的注册结果
+    pub registration: Fido2Registration,
+    /// 已登记硬件的授权，使用管理令牌时可以省略
+    pub authorization: Option<UKeyAuthorization>,
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::SecretString;
+use crate::ukey::HardwareAssertion;
+
+/// 加密参数升级统计信息
+///
+/// 按 `crypto::policy::UpgradeReason` 分类统计仍低于当前配置的资源，每个资源只计入第一个原因。
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct EncryptionUpgradeStats {
+    /// 资源总数
+    pub total: i32,
+    /// 已符合当前配置的资源数
+    pub up_to_date: i32,
+    /// 旧版加密信息JSON格式的资源数
+    pub legacy_format: i32,
+    /// 旧版本容器格式的资源数
+    pub container_version: i32,
+    /// 旧版密钥组合方案的资源数
+    pub key_combiner: i32,
+    /// 加密算法与配置不一致的资源数
+    pub algorithm: i32,
+    /// 密钥派生参数低于配置的资源数
+    pub kdf_params: i32,
+    /// 加密信息或容器头部无法解析的资源数
+    pub unreadable: i32,
+}
+
+impl EncryptionUpgradeStats {
+    /// 仍需升级的资源数
+    pub fn pending(&self) -> i32 {
+        self.legacy_format + self.container_version + self.key_combiner + self.algorithm + self.kdf_params
+    }
+}
+
+/// 批量升级请求模型
+#[derive(Debug, Deserialize)]
+pub struct UpgradeRequest {
+    /// 密钥A
+    pub key_part_a: SecretString,
+    /// UKey或FIDO2安全密钥的应答，为空时由服务器上的UKey应答
+    pub hardware_assertion: Option<HardwareAssertion>,
+}
+
+/// 批量升级结果
+#[derive(Debug, Serialize, Clone, Default)]
+pub struct UpgradeSummary {
+    /// 已升级的资源数
+    pub upgraded: i32,
+    /// 已符合当前配置的资源数
+    pub up_to_date: i32,
+    /// 不属于该密钥而被跳过的资源数
+    pub skipped: i32,
+    /// 读取后被修改、留待下次升级的资源数
+    pub conflicted: i32,
+    /// 无法解析或升级失败的资源数
+    pub failed: i32,
+}
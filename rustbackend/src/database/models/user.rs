@@ -1,9 +1,12 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::fmt;
+
+use crate::logging::REDACTED;
 
 /// 用户模型
-#[derive(FromRow, Debug, Serialize, Deserialize, Clone)]
+#[derive(FromRow, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct User {
     pub id: i32,
@@ -14,8 +17,21 @@ pub struct User {
     pub updated_at: DateTime<Utc>,
 }
 
+impl fmt::Debug for User {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("User")
+            .field("id", &self.id)
+            .field("username", &self.username)
+            .field("hashed_password", &format_args!("{}", REDACTED))
+            .field("is_admin", &self.is_admin)
+            .field("created_at", &self.created_at)
+            .field("updated_at", &self.updated_at)
+            .finish()
+    }
+}
+
 /// 创建用户请求模型
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct CreateUserRequest {
     pub username: String,
@@ -23,8 +39,18 @@ pub struct CreateUserRequest {
     pub is_admin: Option<bool>,
 }
 
+impl fmt::Debug for CreateUserRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreateUserRequest")
+            .field("username", &self.username)
+            .field("password", &format_args!("{}", REDACTED))
+            .field("is_admin", &self.is_admin)
+            .finish()
+    }
+}
+
 /// 更新用户请求模型
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct UpdateUserRequest {
     pub username: Option<String>,
@@ -32,6 +58,16 @@ pub struct UpdateUserRequest {
     pub is_admin: Option<bool>,
 }
 
+impl fmt::Debug for UpdateUserRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UpdateUserRequest")
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| REDACTED))
+            .field("is_admin", &self.is_admin)
+            .finish()
+    }
+}
+
 /// 用户响应模型（不包含密码哈希）
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
@@ -44,13 +80,22 @@ pub struct UserResponse {
 }
 
 /// 登录请求模型
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
 }
 
+impl fmt::Debug for LoginRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginRequest")
+            .field("username", &self.username)
+            .field("password", &format_args!("{}", REDACTED))
+            .finish()
+    }
+}
+
 /// 登录响应模型
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
@@ -61,13 +106,22 @@ pub struct LoginResponse {
 }
 
 /// 密码更新请求模型
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 #[allow(dead_code)]
 pub struct PasswordUpdateRequest {
     pub current_password: String,
     pub new_password: String,
 }
 
+impl fmt::Debug for PasswordUpdateRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PasswordUpdateRequest")
+            .field("current_password", &format_args!("{}", REDACTED))
+            .field("new_password", &format_args!("{}", REDACTED))
+            .finish()
+    }
+}
+
 #[allow(dead_code)]
 impl User {
     /// 创建新用户
@@ -107,8 +161,8 @@ impl User {
             id: self.id,
             username: self.username.clone(),
             is_admin: self.is_admin,
-            created_at: self.created_at.clone(),
-            updated_at: self.updated_at.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
         }
     }
     
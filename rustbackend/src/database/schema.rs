@@ -15,6 +15,8 @@ pub mod table_name {
     pub const LIKES: &str = "likes";
     pub const STICKERS: &str = "stickers";
     pub const SUPPLEMENTS: &str = "supplements";
+    pub const REKEY_JOBS: &str = "rekey_jobs";
+    pub const RECOVERY_KEYS: &str = "recovery_keys";
 }
 
 /// 资源状态枚举
@@ -24,6 +26,13 @@ pub mod resource_status {
     pub const REJECTED: &str = "REJECTED";
 }
 
+/// 密钥更换任务状态枚举
+pub mod rekey_job_status {
+    pub const RUNNING: &str = "RUNNING";
+    pub const COMPLETED: &str = "COMPLETED";
+    pub const FAILED: &str = "FAILED";
+}
+
 /// 媒体类型枚举
 #[allow(dead_code)]
 pub mod media_type {
@@ -36,6 +45,8 @@ pub mod media_type {
 pub mod encryption_algorithm {
     pub const AES256GCM: &str = "AES256GCM";
     pub const CHACHA20POLY1305: &str = "CHACHA20POLY1305";
+    pub const XCHACHA20POLY1305: &str = "XCHACHA20POLY1305";
+    pub const AES256GCMSIV: &str = "AES256GCMSIV";
 }
 
 /// SQL查询常量
@@ -2,6 +2,7 @@ use std::net::SocketAddr;
 use std::sync::Arc;
 
 use axum::{serve};
+use clap::Parser;
 use tracing::info;
 use dotenvy::dotenv;
 
@@ -12,19 +13,28 @@ mod database;
 mod crypto;
 mod service;
 mod api;
+mod logging;
+mod cli;
+mod ukey;
 
 #[tokio::main]
 async fn main() {
     // 加载环境变量
     dotenv().ok();
     
-    // 初始化日志
-    tracing_subscriber::fmt::init();
+    // 解析命令行参数
+    let cli = cli::Cli::parse();
+    
+    // 初始化日志，输出前对秘密脱敏
+    logging::init();
     
     // 加载配置
     let config = config::AppConfig::from_env().expect("无法加载配置");
     config.validate().expect("配置验证失败");
     
+    // 按配置锁定秘密所在的内存页
+    crypto::secret::set_memory_locking(config.encryption.lock_memory);
+    
     // 初始化数据库连接池
     let db_pool = database::init_database_pool(&config)
         .await
@@ -33,9 +43,23 @@ async fn main() {
     // 创建服务实例
     let resource_service = Arc::new(ResourceService::new(db_pool.clone(), config.clone()));
     
+    // 指定子命令时执行后退出，不启动HTTP服务
+    if let Some(command) = cli.command {
+        if let Err(err) = cli::run(command, resource_service).await {
+            eprintln!("命令执行失败: {:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    
+    // 按UKEY_VENDOR创建UKey驱动
+    let ukey_provider = ukey::create_provider(&config.ukey).expect("无法创建UKey驱动");
+    info!("UKey驱动: {}", ukey_provider.vendor());
+    
     // 构建路由
     let app = api::routes::create_router(
         resource_service,
+        ukey_provider,
         config.clone()
     );
     
@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use sqlx::{query, query_as, query_scalar, PgExecutor, Postgres, Transaction};
+use tracing::{info, warn};
+
+use crate::crypto::manifest::{manifest_digest, parse_public_key, verify_signature, ManifestEntry, ManifestSigner};
+use crate::database::DatabaseError;
+use crate::database::models::manifest::{ManifestReport, ResourceManifest};
+use crate::service::resource::{ResourceService, ResourceServiceError};
+use crate::service::storage;
+
+/// 签名清单使用的PostgreSQL事务级咨询锁编号，并发写入按顺序重新签名
+const MANIFEST_ADVISORY_LOCK: i64 = 0x0053_474d_414e_4946;
+
+/// 验证和重建时每批读取的资源数
+const MANIFEST_BATCH_SIZE: i64 = 50;
+
+/// 将数据库错误转换为资源服务错误
+fn db_error(e: sqlx::Error) -> ResourceServiceError {
+    ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e))
+}
+
+/// 在调用方的事务中更新单个资源的清单记录并重新签名
+///
+/// 资源存在时记录当前的容器哈希，哈希变化时版本加一，资源已删除时移除记录。
+/// 每次签名都要读取全部清单记录，写入耗时随资源数线性增长。
+pub(crate) async fn record_resource(
+    transaction: &mut Transaction<'_, Postgres>,
+    signer: &ManifestSigner,
+    id: i32
+) -> Result<(), ResourceServiceError> {
+    lock_manifest(transaction).await?;
+    
+    let resource = query!("SELECT uuid, encrypted_metadata FROM resources WHERE id = $1", id)
+        .fetch_optional(&mut **transaction)
+        .await
+        .map_err(db_error)?;
+    match resource {
+        Some(resource) => {
+            let hash = storage::container_hash(&mut **transaction, id, resource.encrypted_metadata.as_deref()).await?;
+            upsert_entry(transaction, id, resource.uuid, &hash).await?;
+        }
+        None => {
+            query!("DELETE FROM resource_manifest_entries WHERE resource_id = $1", id)
+                .execute(&mut **transaction)
+                .await
+                .map_err(db_error)?;
+        }
+    }
+    
+    sign_manifest(transaction, signer).await
+}
+
+/// 获取清单锁，事务结束时自动释放
+async fn lock_manifest(transaction: &mut Transaction<'_, Postgres>) -> Result<(), ResourceServiceError> {
+    query("SELECT pg_advisory_xact_lock($1)")
+        .bind(MANIFEST_ADVISORY_LOCK)
+        .execute(&mut **transaction)
+        .await
+        .map_err(db_error)?;
+    
+    Ok(())
+}
+
+/// 写入资源的清单记录，容器哈希变化时版本加一
+async fn upsert_entry(
+    transaction: &mut Transaction<'_, Postgres>,
+    id: i32,
+    uuid: uuid::Uuid,
+    hash: &[u8]
+) -> Result<(), ResourceServiceError> {
+    query!(
+        r#"INSERT INTO resource_manifest_entries (resource_id, uuid, container_hash, version) VALUES ($1, $2, $3, 1)
+        ON CONFLICT (resource_id) DO UPDATE SET
+        uuid = EXCLUDED.uuid, container_hash = EXCLUDED.container_hash, version = resource_manifest_entries.version + 1
+        WHERE resource_manifest_entries.uuid <> EXCLUDED.uuid OR resource_manifest_entries.container_hash <> EXCLUDED.container_hash"#,
+        id,
+        uuid,
+        hash
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(db_error)?;
+    
+    Ok(())
+}
+
+/// 按资源ID顺序读取全部清单记录
+async fn fetch_entries<'e>(executor: impl PgExecutor<'e>) -> Result<Vec<ManifestEntry>, ResourceServiceError> {
+    query_as!(ManifestEntry, "SELECT resource_id, uuid, container_hash, version FROM resource_manifest_entries ORDER BY resource_id")
+        .fetch_all(executor)
+        .await
+        .map_err(db_error)
+}
+
+/// 用全部清单记录计算新的摘要并签名，序号加一
+async fn sign_manifest(transaction: &mut Transaction<'_, Postgres>, signer: &ManifestSigner) -> Result<(), ResourceServiceError> {
+    let sequence = query_scalar!("SELECT sequence FROM resource_manifest WHERE id = 1")
+        .fetch_optional(&mut **transaction)
+        .await
+        .map_err(db_error)?
+        .unwrap_or(0) + 1;
+    let entries = fetch_entries(&mut **transaction).await?;
+    let digest = manifest_digest(sequence, &entries);
+    
+    query!(
+        r#"INSERT INTO resource_manifest (id, sequence, digest, signature, public_key, updated_at) VALUES (1, $1, $2, $3, $4, $5)
+        ON CONFLICT (id) DO UPDATE SET
+        sequence = EXCLUDED.sequence, digest = EXCLUDED.digest, signature = EXCLUDED.signature,
+        public_key = EXCLUDED.public_key, updated_at = EXCLUDED.updated_at"#,
+        sequence,
+        digest,
+        signer.sign(&digest),
+        signer.public_key(),
+        chrono::Utc::now().naive_utc()
+    )
+    .execute(&mut **transaction)
+    .await
+    .map_err(db_error)?;
+    
+    Ok(())
+}
+
+/// 签名清单服务
+///
+/// 资源服务每次写入资源都会更新清单并用 `MANIFEST_SIGNING_KEY` 重新签名。
+/// 拿到数据库的攻击者可以删除、回滚或插入资源行，但无法生成有效的清单签名，验证时会被发现。
+/// 整个数据库被回滚到旧快照时清单本身仍然有效，需要与上次记录的清单序号比较。
+pub struct ManifestService {
+    resource_service: Arc<ResourceService>,
+}
+
+impl ManifestService {
+    /// 创建签名清单服务实例
+    pub fn new(resource_service: Arc<ResourceService>) -> Self {
+        Self { resource_service }
+    }
+    
+    /// 验证清单签名，并逐个比较资源与清单记录
+    ///
+    /// `public_key` 为Base64编码的Ed25519公钥，为空时使用配置的签名密钥对应的公钥。
+    pub async fn verify(&self, public_key: Option<&str>) -> Result<ManifestReport, ResourceServiceError> {
+        info!("验证签名清单");
+        
+        let public_key = match (public_key, self.resource_service.manifest_signer.as_ref()) {
+            (Some(public_key), _) => public_key.to_string(),
+            (None, Some(signer)) => signer.public_key(),
+            (None, None) => return Err(ResourceServiceError::ParameterError("未配置清单签名密钥，需要提供验证公钥".to_string())),
+        };
+        let public_key = parse_public_key(&public_key)
+            .map_err(|e| ResourceServiceError::ParameterError(e.to_string()))?;
+        
+        let db = &self.resource_service.db;
+        let mut report = ManifestReport::default();
+        let manifest = query_as!(ResourceManifest, "SELECT * FROM resource_manifest WHERE id = 1")
+            .fetch_optional(db)
+            .await
+            .map_err(db_error)?;
+        let entries = fetch_entries(db).await?;
+        if let Some(manifest) = &manifest {
+            let digest = manifest_digest(manifest.sequence, &entries);
+            report.sequence = Some(manifest.sequence);
+            report.signature_valid = digest == manifest.digest && verify_signature(&public_key, &digest, &manifest.signature);
+        }
+        
+        // 逐批计算资源的容器哈希，与清单记录比较
+        let mut expected: BTreeMap<i32, ManifestEntry> = entries.into_iter()
+            .map(|entry| (entry.resource_id, entry))
+            .collect();
+        let mut last_id = 0;
+        loop {
+            let resources = query!(
+                "SELECT id, uuid, encrypted_metadata FROM resources WHERE id > $1 ORDER BY id LIMIT $2",
+                last_id,
+                MANIFEST_BATCH_SIZE
+            )
+            .fetch_all(db)
+            .await
+            .map_err(db_error)?;
+            let Some(last) = resources.last() else {
+                break;
+            };
+            last_id = last.id;
+            
+            for resource in &resources {
+                match expected.remove(&resource.id) {
+                    Some(entry) => {
+                        let hash = storage::container_hash(db, resource.id, resource.encrypted_metadata.as_deref()).await?;
+                        if entry.uuid != resource.uuid || entry.container_hash != hash {
+                            report.rolled_back.push(resource.id);
+                        }
+                    }
+                    None => report.injected.push(resource.id),
+                }
+            }
+        }
+        report.missing = expected.into_keys().collect();
+        
+        if report.is_intact() {
+            info!("签名清单验证通过, 序号: {:?}", report.sequence);
+        } else {
+            warn!("签名清单验证失败: {:?}", report);
+        }
+        
+        Ok(report)
+    }
+    
+    /// 按资源表的当前内容重建清单并签名，返回清单中的资源数
+    ///
+    /// 用于首次启用签名清单，重建前应确认数据库未被篡改，已有的异常会被当作正常状态记录下来。
+    pub async fn rebuild(&self) -> Result<i64, ResourceServiceError> {
+        info!("重建签名清单");
+        
+        let signer = self.resource_service.manifest_signer.as_ref()
+            .ok_or_else(|| ResourceServiceError::ParameterError("未配置清单签名密钥".to_string()))?;
+        
+        let mut transaction = self.resource_service.db.begin().await.map_err(db_error)?;
+        lock_manifest(&mut transaction).await?;
+        
+        query!("DELETE FROM resource_manifest_entries WHERE resource_id NOT IN (SELECT id FROM resources)")
+            .execute(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+        
+        let mut count = 0;
+        let mut last_id = 0;
+        loop {
+            let resources = query!(
+                "SELECT id, uuid, encrypted_metadata FROM resources WHERE id > $1 ORDER BY id LIMIT $2",
+                last_id,
+                MANIFEST_BATCH_SIZE
+            )
+            .fetch_all(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+            let Some(last) = resources.last() else {
+                break;
+            };
+            last_id = last.id;
+            
+            for resource in &resources {
+                let hash = storage::container_hash(&mut *transaction, resource.id, resource.encrypted_metadata.as_deref()).await?;
+                upsert_entry(&mut transaction, resource.id, resource.uuid, &hash).await?;
+            }
+            count += resources.len() as i64;
+        }
+        
+        sign_manifest(&mut transaction, signer).await?;
+        transaction.commit().await.map_err(db_error)?;
+        
+        info!("签名清单重建完成, 资源数: {}", count);
+        
+        Ok(count)
+    }
+}
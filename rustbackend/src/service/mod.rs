@@ -1,2 +1,14 @@
 /// 资源服务
 pub mod resource;
+/// 密钥更换服务
+pub mod rekey;
+/// 加密参数升级服务
+pub mod upgrade;
+/// 恢复密钥服务
+pub mod recovery;
+/// 签名清单服务
+pub mod manifest;
+/// 加密容器的分块存储
+pub(crate) mod storage;
+/// UKey挑战服务
+pub mod ukey;
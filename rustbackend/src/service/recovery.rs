@@ -0,0 +1,508 @@
+use std::sync::Arc;
+
+use sqlx::{query, query_as, query_scalar, Postgres, Transaction};
+use tracing::{info, warn};
+
+use crate::crypto::policy::MAX_HEADER_PROBE_LEN;
+use crate::crypto::metadata::seal_metadata;
+use crate::crypto::recovery::{self, unwrap_with_recovery, RecoveryWrappedKey};
+use crate::crypto::{combine_key_shares, decode_metadata, split_master_key, KeyShare, RecoveryCode, RecoveryError, RecoveryPublicKey, ResourceContext, SecretString};
+use crate::database::DatabaseError;
+use crate::database::models::recovery_key::{RecoveryCoverage, RecoveryKey, RecoveryKeyStatus, RecoverySummary};
+use crate::service::resource::{RekeyedContainer, ResourceService, ResourceServiceError};
+
+/// 每批处理的资源数
+const RECOVERY_BATCH_SIZE: i64 = 50;
+
+/// 恢复密钥使用的PostgreSQL事务级咨询锁编号
+///
+/// 生成和撤销恢复密钥时持有排他锁，写入恢复包装的事务持有共享锁，恢复包装总是对应当前恢复密钥。
+const RECOVERY_KEY_ADVISORY_LOCK: i64 = 0x0053_4752_4543_4f56;
+
+/// 将sqlx错误转换为资源服务错误
+fn db_error(e: sqlx::Error) -> ResourceServiceError {
+    ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e))
+}
+
+/// 获取恢复密钥的排他锁，等待正在写入恢复包装的事务结束，事务结束时自动释放
+async fn lock_recovery_key(transaction: &mut Transaction<'_, Postgres>) -> Result<(), ResourceServiceError> {
+    query("SELECT pg_advisory_xact_lock($1)")
+        .bind(RECOVERY_KEY_ADVISORY_LOCK)
+        .execute(&mut **transaction)
+        .await
+        .map_err(db_error)?;
+    
+    Ok(())
+}
+
+/// 获取恢复密钥的共享锁，事务结束时自动释放
+///
+/// 写入恢复包装的事务需要在锁定资源行之前获取，之后读取的当前恢复密钥在事务结束前不会被替换或撤销。
+pub(crate) async fn lock_recovery_key_shared(transaction: &mut Transaction<'_, Postgres>) -> Result<(), ResourceServiceError> {
+    query("SELECT pg_advisory_xact_lock_shared($1)")
+        .bind(RECOVERY_KEY_ADVISORY_LOCK)
+        .execute(&mut **transaction)
+        .await
+        .map_err(db_error)?;
+    
+    Ok(())
+}
+
+/// 确认恢复包装属于当前恢复密钥，恢复密钥已被重新生成或撤销时返回错误
+pub(crate) async fn check_recovery_wrap(
+    transaction: &mut Transaction<'_, Postgres>,
+    recovery_wrapped_key: &str
+) -> Result<(), ResourceServiceError> {
+    let wrapped = RecoveryWrappedKey::from_json(recovery_wrapped_key).map_err(recovery_error)?;
+    let active_id = query_scalar!("SELECT id FROM recovery_keys WHERE revoked_at IS NULL")
+        .fetch_optional(&mut **transaction)
+        .await
+        .map_err(db_error)?;
+    if active_id != Some(wrapped.key_id) {
+        return Err(ResourceServiceError::ResourceStatusError("恢复密钥已更换，请重试".to_string()));
+    }
+    
+    Ok(())
+}
+
+/// 恢复密钥服务
+///
+/// 恢复密钥是可选的：生成后新资源的数据密钥同时用恢复公钥包装，UKey丢失时可以用恢复码
+/// 解包数据密钥并换上新的密钥A和硬件码B。恢复码只在生成时返回一次，重新生成或撤销后旧恢复码失效。
+pub struct RecoveryService {
+    resource_service: Arc<ResourceService>,
+}
+
+impl RecoveryService {
+    /// 创建恢复密钥服务实例
+    pub fn new(resource_service: Arc<ResourceService>) -> Self {
+        Self { resource_service }
+    }
+    
+    /// 获取当前恢复密钥和覆盖的资源数
+    pub async fn get_status(&self) -> Result<RecoveryKeyStatus, ResourceServiceError> {
+        let recovery_key = query_as!(RecoveryKey, "SELECT * FROM recovery_keys WHERE revoked_at IS NULL")
+            .fetch_optional(&self.resource_service.db)
+            .await
+            .map_err(db_error)?;
+        let covered_resources: i64 = query_scalar("SELECT COUNT(*) FROM encryption_keys WHERE recovery_wrapped_key IS NOT NULL")
+            .fetch_one(&self.resource_service.db)
+            .await
+            .map_err(db_error)?;
+        let total_resources: i64 = query_scalar("SELECT COUNT(*) FROM resources")
+            .fetch_one(&self.resource_service.db)
+            .await
+            .map_err(db_error)?;
+        
+        Ok(RecoveryKeyStatus {
+            recovery_key,
+            covered_resources: covered_resources as i32,
+            total_resources: total_resources as i32,
+        })
+    }
+    
+    /// 生成新的恢复密钥，旧恢复密钥同时撤销
+    ///
+    /// 在同一事务中替换恢复密钥，并用密钥A和硬件码B逐个解出已有资源的数据密钥、用新恢复公钥包装。
+    /// 期间持有恢复密钥的排他锁，新建、升级和更换密钥的写入等待完成。不属于该密钥的资源和旧版格式的资源不生成恢复包装，
+    /// 旧版格式的资源升级后需要重新生成。返回只展示一次的恢复码。
+    pub async fn regenerate(
+        &self,
+        key_part_a: &str,
+        ukey_part_b: &str
+    ) -> Result<(SecretString, RecoveryKey, RecoveryCoverage), ResourceServiceError> {
+        info!("生成恢复密钥");
+        
+        let code = RecoveryCode::generate().map_err(recovery_error)?;
+        let (recovery_key, coverage) = self.install(&code, key_part_a, ukey_part_b, None).await?;
+        
+        Ok((code.to_display(), recovery_key, coverage))
+    }
+    
+    /// 生成新的恢复密钥并把恢复码拆分为保管人分片，旧恢复密钥同时撤销
+    ///
+    /// 恢复码本身不返回，任意 `share_threshold` 份分片可以通过 `unlock` 重建恢复码。
+    /// 门限或分片数不合法时返回 `ParameterError`，不做任何修改。
+    pub async fn escrow(
+        &self,
+        key_part_a: &str,
+        ukey_part_b: &str,
+        share_threshold: u8,
+        share_count: u8
+    ) -> Result<(Vec<SecretString>, RecoveryKey, RecoveryCoverage), ResourceServiceError> {
+        info!("生成托管恢复密钥: {}/{}", share_threshold, share_count);
+        
+        let code = RecoveryCode::generate().map_err(recovery_error)?;
+        let shares = split_master_key(code.secret(), share_threshold, share_count)
+            .map_err(|e| ResourceServiceError::ParameterError(e.to_string()))?;
+        let (recovery_key, coverage) = self.install(&code, key_part_a, ukey_part_b, Some((share_threshold, share_count))).await?;
+        
+        Ok((shares.iter().map(KeyShare::to_display).collect(), recovery_key, coverage))
+    }
+    
+    /// 用恢复码对应的公钥替换当前恢复密钥，并为已有资源生成恢复包装
+    async fn install(
+        &self,
+        code: &RecoveryCode,
+        key_part_a: &str,
+        ukey_part_b: &str,
+        shares: Option<(u8, u8)>
+    ) -> Result<(RecoveryKey, RecoveryCoverage), ResourceServiceError> {
+        // 密钥错误时不做任何修改
+        self.resource_service.verify_current_key(key_part_a, ukey_part_b).await?;
+        
+        // 预留记录ID，恢复包装中记录该ID
+        let id: i64 = query_scalar("SELECT nextval(pg_get_serial_sequence('recovery_keys', 'id'))")
+            .fetch_one(&self.resource_service.db)
+            .await
+            .map_err(db_error)?;
+        let public_key = RecoveryPublicKey { id: id as i32, public_key: code.public_key() };
+        
+        // 持有排他锁期间其他事务不会写入恢复包装，恢复包装按锁定后读取的容器生成
+        let now = chrono::Utc::now().naive_utc();
+        let mut transaction = self.resource_service.db.begin().await.map_err(db_error)?;
+        lock_recovery_key(&mut transaction).await?;
+        
+        query!("UPDATE recovery_keys SET revoked_at = $1 WHERE revoked_at IS NULL", now)
+            .execute(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+        let recovery_key = query_as!(RecoveryKey,
+            r#"INSERT INTO recovery_keys (id, public_key, fingerprint, created_at, share_threshold, share_count)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *"#,
+            public_key.id,
+            public_key.to_base64(),
+            recovery::fingerprint(&public_key.public_key),
+            now,
+            shares.map(|(threshold, _)| threshold as i16),
+            shares.map(|(_, count)| count as i16)
+        )
+        .fetch_one(&mut *transaction)
+        .await
+        .map_err(db_error)?;
+        
+        // 逐批锁定加密密钥记录后生成恢复包装，旧恢复密钥的包装一并替换，避免旧恢复码仍能解包
+        let mut coverage = RecoveryCoverage::default();
+        let mut last_id = 0;
+        loop {
+            let records = query!(
+                r#"SELECT k.resource_id, r.encryption_info, substring(c.data from 1 for $2) AS header
+                FROM encryption_keys k JOIN resources r ON r.id = k.resource_id
+                LEFT JOIN resource_chunks c ON c.resource_id = k.resource_id AND c.chunk_index = 0
+                WHERE k.resource_id > $1 ORDER BY k.resource_id LIMIT $3
+                FOR UPDATE OF k, r"#,
+                last_id,
+                MAX_HEADER_PROBE_LEN,
+                RECOVERY_BATCH_SIZE
+            )
+            .fetch_all(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+            let Some(last) = records.last() else {
+                break;
+            };
+            last_id = last.resource_id;
+            
+            for record in records {
+                let recovery_wrapped_key = if record.encryption_info.is_empty() {
+                    let header = record.header.unwrap_or_default();
+                    match self.resource_service.encryption_service.wrap_resource_for_recovery(&header, key_part_a, ukey_part_b, &public_key) {
+                        Ok(recovery_wrapped_key) => Some(recovery_wrapped_key),
+                        Err(e) => {
+                            if !e.is_key_verification_failure() {
+                                warn!("生成资源恢复包装失败: {}, 错误: {}", record.resource_id, e);
+                            }
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+                
+                match recovery_wrapped_key {
+                    Some(_) => coverage.covered += 1,
+                    None => coverage.skipped += 1,
+                }
+                query!("UPDATE encryption_keys SET recovery_wrapped_key = $1 WHERE resource_id = $2", recovery_wrapped_key, record.resource_id)
+                    .execute(&mut *transaction)
+                    .await
+                    .map_err(db_error)?;
+            }
+        }
+        
+        transaction.commit().await.map_err(db_error)?;
+        
+        info!("恢复密钥生成成功: {}, 指纹: {}, 覆盖资源: {}, 跳过资源: {}",
+            recovery_key.id, recovery_key.fingerprint, coverage.covered, coverage.skipped);
+        
+        Ok((recovery_key, coverage))
+    }
+    
+    /// 撤销当前恢复密钥并清除所有恢复包装，没有有效的恢复密钥时返回 `ResourceNotFound`
+    ///
+    /// 与生成恢复密钥一样需要当前的密钥A和硬件码B，密钥错误时返回 `KeyVerificationFailed`，不做任何修改。
+    pub async fn revoke(&self, key_part_a: &str, ukey_part_b: &str) -> Result<RecoveryKey, ResourceServiceError> {
+        info!("撤销恢复密钥");
+        
+        self.resource_service.verify_current_key(key_part_a, ukey_part_b).await?;
+        
+        let mut transaction = self.resource_service.db.begin().await.map_err(db_error)?;
+        lock_recovery_key(&mut transaction).await?;
+        
+        let recovery_key = query_as!(RecoveryKey,
+            "UPDATE recovery_keys SET revoked_at = $1 WHERE revoked_at IS NULL RETURNING *",
+            chrono::Utc::now().naive_utc()
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .map_err(db_error)?
+        .ok_or(ResourceServiceError::ResourceNotFound)?;
+        
+        query!("UPDATE encryption_keys SET recovery_wrapped_key = NULL WHERE recovery_wrapped_key IS NOT NULL")
+            .execute(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+        
+        transaction.commit().await.map_err(db_error)?;
+        
+        info!("恢复密钥已撤销: {}", recovery_key.id);
+        
+        Ok(recovery_key)
+    }
+    
+    /// 用恢复码解包所有资源的数据密钥，并换上新的密钥A和硬件码B，`new_ukey_fingerprint` 为新UKey的指纹
+    ///
+    /// 恢复码与当前恢复密钥不匹配时返回 `KeyVerificationFailed`，格式错误时返回 `ParameterError`。
+    /// 数据密钥不变，恢复包装继续有效，恢复后原恢复码仍可使用，建议随后重新生成。
+    pub async fn recover(
+        &self,
+        recovery_code: &str,
+        new_key_part_a: &str,
+        new_ukey_part_b: &str,
+        new_ukey_fingerprint: &str
+    ) -> Result<RecoverySummary, ResourceServiceError> {
+        info!("使用恢复码恢复资源");
+        
+        let code = RecoveryCode::parse(recovery_code)
+            .map_err(|e| ResourceServiceError::ParameterError(e.to_string()))?;
+        
+        self.recover_with_code(&code, new_key_part_a, new_ukey_part_b, new_ukey_fingerprint).await
+    }
+    
+    /// 用保管人分片重建恢复码，再按 `recover` 恢复所有资源
+    ///
+    /// 分片格式错误、数量不足或来自不同批次时返回 `ParameterError`，重建结果与当前恢复密钥不匹配时返回 `KeyVerificationFailed`。
+    pub async fn unlock(
+        &self,
+        shares: &[SecretString],
+        new_key_part_a: &str,
+        new_ukey_part_b: &str,
+        new_ukey_fingerprint: &str
+    ) -> Result<RecoverySummary, ResourceServiceError> {
+        info!("使用{}份保管人分片恢复资源", shares.len());
+        
+        let shares = shares.iter()
+            .enumerate()
+            .map(|(i, share)| KeyShare::parse(share.expose_secret())
+                .map_err(|e| ResourceServiceError::ParameterError(format!("第{}份分片: {}", i + 1, e))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let secret = combine_key_shares(&shares)
+            .map_err(|e| ResourceServiceError::ParameterError(e.to_string()))?;
+        let code = RecoveryCode::from_secret(&secret)
+            .map_err(|_| ResourceServiceError::KeyVerificationFailed)?;
+        
+        self.recover_with_code(&code, new_key_part_a, new_ukey_part_b, new_ukey_fingerprint).await
+    }
+    
+    /// 用已解析的恢复码恢复所有资源
+    async fn recover_with_code(
+        &self,
+        code: &RecoveryCode,
+        new_key_part_a: &str,
+        new_ukey_part_b: &str,
+        new_ukey_fingerprint: &str
+    ) -> Result<RecoverySummary, ResourceServiceError> {
+        let recovery_key = self.resource_service.get_active_recovery_key()
+            .await?
+            .ok_or_else(|| ResourceServiceError::ResourceStatusError("没有有效的恢复密钥".to_string()))?;
+        if code.public_key() != recovery_key.public_key {
+            return Err(ResourceServiceError::KeyVerificationFailed);
+        }
+        
+        let mut summary = RecoverySummary::default();
+        let mut last_id = 0;
+        loop {
+            // 每批资源的加密密钥记录在事务中锁定，生成新容器期间不会被其他写入修改
+            let mut transaction = self.resource_service.db.begin().await.map_err(db_error)?;
+            lock_recovery_key_shared(&mut transaction).await?;
+            let records = query!(
+                r#"SELECT resource_id, recovery_wrapped_key AS "recovery_wrapped_key!" FROM encryption_keys
+                WHERE recovery_wrapped_key IS NOT NULL AND resource_id > $1
+                ORDER BY resource_id LIMIT $2
+                FOR UPDATE"#,
+                last_id,
+                RECOVERY_BATCH_SIZE
+            )
+            .fetch_all(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+            let Some(last) = records.last() else {
+                break;
+            };
+            last_id = last.resource_id;
+            
+            for record in &records {
+                let rekeyed = match self.recover_one(code, &recovery_key, record.resource_id, &record.recovery_wrapped_key, new_key_part_a, new_ukey_part_b).await {
+                    Ok(Some(rekeyed)) => rekeyed,
+                    Ok(None) => continue,
+                    Err(ResourceServiceError::DatabaseError(e)) => return Err(ResourceServiceError::DatabaseError(e)),
+                    Err(e) => {
+                        warn!("资源恢复失败: {}, 错误: {}", record.resource_id, e);
+                        summary.failed += 1;
+                        continue;
+                    }
+                };
+                if self.resource_service.write_rekeyed(&mut transaction, record.resource_id, &rekeyed, new_ukey_fingerprint).await? {
+                    summary.recovered += 1;
+                } else {
+                    warn!("资源恢复失败: {}, 错误: 资源在恢复期间被修改", record.resource_id);
+                    summary.failed += 1;
+                }
+            }
+            transaction.commit().await.map_err(db_error)?;
+            
+            info!("资源恢复进度: 已恢复: {}, 失败: {}", summary.recovered, summary.failed);
+        }
+        
+        info!("使用恢复码恢复资源完成: {:?}", summary);
+        
+        Ok(summary)
+    }
+    
+    /// 用恢复码为单个资源生成新容器，资源已被删除时返回None
+    ///
+    /// 数据密钥不变，原恢复包装仍然有效，元数据已加密时用同一数据密钥重新加密并用新密钥重建盲索引。
+    async fn recover_one(
+        &self,
+        code: &RecoveryCode,
+        recovery_key: &RecoveryPublicKey,
+        resource_id: i32,
+        recovery_wrapped_key: &str,
+        new_key_part_a: &str,
+        new_ukey_part_b: &str
+    ) -> Result<Option<RekeyedContainer>, ResourceServiceError> {
+        let resource = match self.resource_service.get_resource_with_container(resource_id).await {
+            Ok(resource) => resource,
+            Err(ResourceServiceError::ResourceNotFound) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        
+        let wrapped = RecoveryWrappedKey::from_json(recovery_wrapped_key).map_err(recovery_error)?;
+        let data_key = unwrap_with_recovery(code, recovery_key.id, &wrapped).map_err(recovery_error)?;
+        let (container, wrapped_key) = self.resource_service.encryption_service.recover_resource(
+            &resource.media_data,
+            &data_key,
+            new_key_part_a,
+            new_ukey_part_b,
+            &self.resource_service.config
+        )?;
+        let (encrypted_metadata, search_tokens) = match &resource.encrypted_metadata {
+            Some(encrypted_metadata) => {
+                let context = ResourceContext::new(resource.uuid, &resource.media_type);
+                let metadata = decode_metadata(&data_key, &context, encrypted_metadata)?;
+                let resealed = seal_metadata(&data_key, &context, &metadata).map_err(|e| ResourceServiceError::OtherError(e.into()))?;
+                (Some(resealed), Some(self.resource_service.search_tokens(&metadata, new_key_part_a, new_ukey_part_b)?))
+            }
+            None => (None, None),
+        };
+        
+        Ok(Some(RekeyedContainer {
+            container,
+            wrapped_key,
+            recovery_wrapped_key: Some(recovery_wrapped_key.to_string()),
+            encrypted_metadata,
+            search_tokens,
+            updated_at: resource.updated_at,
+        }))
+    }
+}
+
+/// 将恢复密钥错误转换为资源服务错误，恢复码错误单独返回
+fn recovery_error(err: RecoveryError) -> ResourceServiceError {
+    match err {
+        RecoveryError::UnwrapError | RecoveryError::KeyMismatch => ResourceServiceError::KeyVerificationFailed,
+        other => ResourceServiceError::OtherError(other.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::database::DatabasePool;
+    use crate::service::ukey::UKeyService;
+    use crate::service::ukey::tests::{create_resource, enroll, test_resource_service};
+    use crate::ukey::challenge::tests::test_key_pair;
+    
+    #[sqlx::test]
+    async fn test_install_waits_for_writers(db: DatabasePool) {
+        let resource_service = test_resource_service(db.clone());
+        let service = UKeyService::new(resource_service.clone());
+        let key_pair = test_key_pair();
+        enroll(&service, &key_pair, "UK-0001").await;
+        let id = create_resource(&service, &key_pair, "UK-0001", "key-part-a", "part-b").await;
+        create_resource(&service, &key_pair, "UK-0001", "other-key-part-a", "part-b").await;
+        let recovery_service = Arc::new(RecoveryService::new(resource_service.clone()));
+        
+        let (_, first_key, coverage) = recovery_service.regenerate("key-part-a", "part-b").await.unwrap();
+        assert_eq!((coverage.covered, coverage.skipped), (1, 1));
+        let first_wrap = query_scalar!("SELECT recovery_wrapped_key FROM encryption_keys WHERE resource_id = $1", id)
+            .fetch_one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        
+        // 写入恢复包装的事务持有共享锁期间，重新生成等待该事务结束
+        let mut writer = db.begin().await.unwrap();
+        lock_recovery_key_shared(&mut writer).await.unwrap();
+        let regenerate = tokio::spawn({
+            let recovery_service = recovery_service.clone();
+            async move { recovery_service.regenerate("key-part-a", "part-b").await }
+        });
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert!(!regenerate.is_finished());
+        check_recovery_wrap(&mut writer, &first_wrap).await.unwrap();
+        writer.commit().await.unwrap();
+        let (_, second_key, coverage) = regenerate.await.unwrap().unwrap();
+        assert_eq!((coverage.covered, coverage.skipped), (1, 1));
+        
+        // 恢复包装按锁定后的容器重新生成，旧恢复密钥的包装不能再写入
+        let second_wrap = query_scalar!("SELECT recovery_wrapped_key FROM encryption_keys WHERE resource_id = $1", id)
+            .fetch_one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(RecoveryWrappedKey::from_json(&second_wrap).unwrap().key_id, second_key.id);
+        let mut writer = db.begin().await.unwrap();
+        lock_recovery_key_shared(&mut writer).await.unwrap();
+        assert_ne!(first_key.id, second_key.id);
+        assert!(matches!(
+            check_recovery_wrap(&mut writer, &first_wrap).await,
+            Err(ResourceServiceError::ResourceStatusError(_))
+        ));
+        check_recovery_wrap(&mut writer, &second_wrap).await.unwrap();
+        drop(writer);
+        
+        // 撤销后任何恢复包装都不能再写入
+        recovery_service.revoke("key-part-a", "part-b").await.unwrap();
+        let mut writer = db.begin().await.unwrap();
+        lock_recovery_key_shared(&mut writer).await.unwrap();
+        assert!(check_recovery_wrap(&mut writer, &second_wrap).await.is_err());
+        let covered = query_scalar!("SELECT COUNT(*) FROM encryption_keys WHERE recovery_wrapped_key IS NOT NULL")
+            .fetch_one(&db)
+            .await
+            .unwrap();
+        assert_eq!(covered, Some(0));
+    }
+}
@@ -0,0 +1,498 @@
+use std::sync::Arc;
+
+use sqlx::{query, query_as, query_scalar, Connection, PgConnection};
+use tracing::{info, warn};
+
+use crate::crypto::key_management::{self, KeyInfo, KeyManagementError};
+use crate::database::DatabaseError;
+use crate::database::models::rekey_job::{RekeyJob, RekeyKeys};
+use crate::database::schema::rekey_job_status;
+use crate::service::recovery;
+use crate::service::resource::{RekeyedContainer, ResourceService, ResourceServiceError};
+
+/// 默认每批处理的资源数
+pub const DEFAULT_REKEY_BATCH_SIZE: u32 = 50;
+
+/// 每批处理的资源数上限，一批资源在处理期间保持行锁
+const MAX_REKEY_BATCH_SIZE: u32 = 1000;
+
+/// 密钥更换任务使用的PostgreSQL咨询锁编号
+///
+/// 锁随数据库会话释放，服务和命令行同一时间只能运行一个任务，进程崩溃后锁自动失效。
+const REKEY_ADVISORY_LOCK: i64 = 0x0053_4752_454b_4559;
+
+/// 将sqlx错误转换为资源服务错误
+fn db_error(e: sqlx::Error) -> ResourceServiceError {
+    ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e))
+}
+
+/// 检查每批处理的资源数
+fn check_batch_size(batch_size: Option<u32>) -> Result<u32, ResourceServiceError> {
+    let batch_size = batch_size.unwrap_or(DEFAULT_REKEY_BATCH_SIZE);
+    if batch_size == 0 || batch_size > MAX_REKEY_BATCH_SIZE {
+        return Err(ResourceServiceError::ParameterError(
+            format!("每批资源数必须在1到{}之间", MAX_REKEY_BATCH_SIZE)
+        ));
+    }
+    
+    Ok(batch_size)
+}
+
+/// 已通过密钥验证并持有任务锁、可以执行的密钥更换任务
+pub struct PreparedRekeyJob {
+    pub job: RekeyJob,
+    /// 持有咨询锁的独立连接，不归还连接池，断开时锁随之释放
+    lock_connection: PgConnection,
+}
+
+/// 单个资源的处理结果
+enum RekeyOutcome {
+    /// 新的加密容器、包装后的数据密钥、恢复包装和盲索引词元
+    Rekeyed(RekeyedContainer),
+    /// 资源不是用旧密钥加密的
+    Skipped,
+}
+
+/// 密钥更换服务
+///
+/// 用户更换密钥A（或硬件码B）时，按资源ID顺序分批处理所有资源：信封加密的资源只重新包装数据密钥，
+/// 旧资源解密后整体重新加密。每批资源和任务进度在同一事务中提交，崩溃后用相同的密钥重新发起即可续跑。
+pub struct RekeyService {
+    resource_service: Arc<ResourceService>,
+}
+
+impl RekeyService {
+    /// 创建密钥更换服务实例
+    pub fn new(resource_service: Arc<ResourceService>) -> Self {
+        Self { resource_service }
+    }
+    
+    /// 准备密钥更换任务
+    ///
+    /// 获取任务锁，存在未完成的任务时续跑该任务，否则新建任务。续跑前用任务中保存的校验块确认新密钥一致，
+    /// 并用第一个待处理的资源验证旧密钥，任何一项验证失败都返回 `KeyVerificationFailed`，不修改数据。
+    pub async fn prepare_job(&self, keys: &RekeyKeys) -> Result<PreparedRekeyJob, ResourceServiceError> {
+        let batch_size = check_batch_size(keys.batch_size)?;
+        
+        // 使用独立连接获取咨询锁
+        let mut lock_connection = self.resource_service.db.acquire()
+            .await
+            .map_err(db_error)?
+            .detach();
+        let locked: bool = query_scalar("SELECT pg_try_advisory_lock($1)")
+            .bind(REKEY_ADVISORY_LOCK)
+            .fetch_one(&mut lock_connection)
+            .await
+            .map_err(db_error)?;
+        if !locked {
+            return Err(ResourceServiceError::ResourceStatusError("已有密钥更换任务正在运行".to_string()));
+        }
+        
+        let job = match self.find_unfinished_job().await? {
+            Some(job) => {
+                info!("续跑密钥更换任务: {}, 已处理资源: {}", job.id, job.processed_resources);
+                self.verify_new_key(&job, keys)?;
+                self.verify_old_key(job.last_resource_id, keys).await?;
+                job
+            }
+            None => {
+                self.verify_old_key(0, keys).await?;
+                self.create_job(keys, batch_size).await?
+            }
+        };
+        
+        Ok(PreparedRekeyJob { job, lock_connection })
+    }
+    
+    /// 执行密钥更换任务直到完成，每提交一批资源调用一次 `progress`
+    ///
+    /// 出错时任务标记为失败并保留进度，可以用相同的密钥重新发起续跑。旧密钥无法解开的资源不更换密钥，
+    /// 记录在任务的 `skipped_resource_ids` 中，任务仍然完成。
+    pub async fn run_job<F>(
+        &self,
+        prepared: PreparedRekeyJob,
+        keys: &RekeyKeys,
+        mut progress: F
+    ) -> Result<RekeyJob, ResourceServiceError>
+    where
+        F: FnMut(&RekeyJob),
+    {
+        let PreparedRekeyJob { mut job, mut lock_connection } = prepared;
+        
+        let result = self.run_batches(&mut job, keys, &mut progress).await;
+        if let Err(e) = &result {
+            warn!("密钥更换任务失败: {}, 错误: {}", job.id, e);
+            if let Err(update_error) = self.mark_failed(job.id, &e.to_string()).await {
+                warn!("更新密钥更换任务状态失败: {}, 错误: {}", job.id, update_error);
+            }
+        }
+        
+        // 释放任务锁，释放失败时关闭连接也会释放
+        if let Err(e) = query("SELECT pg_advisory_unlock($1)").bind(REKEY_ADVISORY_LOCK).execute(&mut lock_connection).await {
+            warn!("释放密钥更换任务锁失败: {}", e);
+        }
+        let _ = lock_connection.close().await;
+        
+        result.map(|_| job)
+    }
+    
+    /// 获取密钥更换任务
+    pub async fn get_job(&self, id: i32) -> Result<RekeyJob, ResourceServiceError> {
+        query_as!(RekeyJob, "SELECT * FROM rekey_jobs WHERE id = $1", id)
+            .fetch_optional(&self.resource_service.db)
+            .await
+            .map_err(db_error)?
+            .ok_or(ResourceServiceError::ResourceNotFound)
+    }
+    
+    /// 查找最近一个未完成的任务
+    async fn find_unfinished_job(&self) -> Result<Option<RekeyJob>, ResourceServiceError> {
+        query_as!(RekeyJob,
+            "SELECT * FROM rekey_jobs WHERE status <> $1 ORDER BY id DESC LIMIT 1",
+            rekey_job_status::COMPLETED
+        )
+        .fetch_optional(&self.resource_service.db)
+        .await
+        .map_err(db_error)
+    }
+    
+    /// 新建任务，保存新密钥的校验块
+    async fn create_job(&self, keys: &RekeyKeys, batch_size: u32) -> Result<RekeyJob, ResourceServiceError> {
+        let config = &self.resource_service.config;
+        let kdf_params = key_management::get_kdf_params(config).map_err(to_service_error)?;
+        let (key_info, _) = key_management::generate_key_info(
+            key_management::get_encryption_algorithm(config),
+            &key_management::generate_salt(),
+            &kdf_params,
+            keys.new_key_part_a.expose_secret(),
+            keys.new_ukey_part_b.expose_secret()
+        ).map_err(to_service_error)?;
+        let new_key_check = serde_json::to_string(&key_info)
+            .map_err(|e| ResourceServiceError::OtherError(e.into()))?;
+        
+        let total_resources: i64 = query_scalar("SELECT COUNT(*) FROM resources")
+            .fetch_one(&self.resource_service.db)
+            .await
+            .map_err(db_error)?;
+        let now = chrono::Utc::now().naive_utc();
+        
+        let job = query_as!(RekeyJob,
+            r#"INSERT INTO rekey_jobs
+            (status, total_resources, batch_size, new_key_check, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *"#,
+            rekey_job_status::RUNNING,
+            total_resources as i32,
+            batch_size as i32,
+            new_key_check,
+            now,
+            now
+        )
+        .fetch_one(&self.resource_service.db)
+        .await
+        .map_err(db_error)?;
+        
+        info!("创建密钥更换任务: {}, 资源总数: {}", job.id, job.total_resources);
+        
+        Ok(job)
+    }
+    
+    /// 用任务中保存的校验块确认新密钥与发起任务时一致，避免资源分散在两套新密钥下
+    fn verify_new_key(&self, job: &RekeyJob, keys: &RekeyKeys) -> Result<(), ResourceServiceError> {
+        let key_info: KeyInfo = serde_json::from_str(&job.new_key_check)
+            .map_err(|e| ResourceServiceError::OtherError(e.into()))?;
+        key_management::get_key_from_info(&key_info, keys.new_key_part_a.expose_secret(), keys.new_ukey_part_b.expose_secret())
+            .map_err(to_service_error)?;
+        
+        Ok(())
+    }
+    
+    /// 用第一个待处理的资源验证旧密钥，没有待处理的资源时无需验证
+    async fn verify_old_key(&self, after_id: i32, keys: &RekeyKeys) -> Result<(), ResourceServiceError> {
+        let first_id = query_scalar!("SELECT id FROM resources WHERE id > $1 ORDER BY id LIMIT 1", after_id)
+            .fetch_optional(&self.resource_service.db)
+            .await
+            .map_err(db_error)?;
+        
+        if let Some(id) = first_id {
+            let resource = self.resource_service.get_resource_with_header(id).await?;
+            self.resource_service.verify_resource_key(
+                &resource,
+                keys.old_key_part_a.expose_secret(),
+                keys.old_ukey_part_b.expose_secret()
+            ).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// 逐批处理剩余的资源
+    async fn run_batches<F>(
+        &self,
+        job: &mut RekeyJob,
+        keys: &RekeyKeys,
+        progress: &mut F
+    ) -> Result<(), ResourceServiceError>
+    where
+        F: FnMut(&RekeyJob),
+    {
+        loop {
+            // 每批资源在事务中锁定后再生成新容器，处理期间资源不会被其他写入修改
+            let mut transaction = self.resource_service.db.begin().await.map_err(db_error)?;
+            recovery::lock_recovery_key_shared(&mut transaction).await?;
+            let ids = query_scalar!(
+                "SELECT id FROM resources WHERE id > $1 ORDER BY id LIMIT $2 FOR UPDATE",
+                job.last_resource_id,
+                job.batch_size as i64
+            )
+            .fetch_all(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+            
+            if ids.is_empty() {
+                break;
+            }
+            
+            let mut processed = 0;
+            let mut skipped = Vec::new();
+            for id in &ids {
+                match self.rekey_one(*id, keys).await? {
+                    RekeyOutcome::Rekeyed(rekeyed) => {
+                        if !self.resource_service.write_rekeyed(&mut transaction, *id, &rekeyed, &keys.new_ukey_fingerprint).await? {
+                            return Err(ResourceServiceError::ResourceStatusError(format!("资源在更换密钥期间被修改: {}", id)));
+                        }
+                        processed += 1;
+                    }
+                    RekeyOutcome::Skipped => skipped.push(*id),
+                }
+            }
+            
+            let last_resource_id = *ids.last().unwrap_or(&job.last_resource_id);
+            *job = query_as!(RekeyJob,
+                r#"UPDATE rekey_jobs SET
+                status = $1, processed_resources = processed_resources + $2, skipped_resources = skipped_resources + $3,
+                skipped_resource_ids = skipped_resource_ids || $4::integer[], last_resource_id = $5, error = NULL, updated_at = $6
+                WHERE id = $7
+                RETURNING *"#,
+                rekey_job_status::RUNNING,
+                processed,
+                skipped.len() as i32,
+                &skipped,
+                last_resource_id,
+                chrono::Utc::now().naive_utc(),
+                job.id
+            )
+            .fetch_one(&mut *transaction)
+            .await
+            .map_err(db_error)?;
+            
+            transaction.commit().await.map_err(db_error)?;
+            
+            info!(
+                "密钥更换任务进度: {}, 已处理: {}, 已跳过: {}, 资源总数: {}",
+                job.id, job.processed_resources, job.skipped_resources, job.total_resources
+            );
+            progress(job);
+        }
+        
+        *job = query_as!(RekeyJob,
+            "UPDATE rekey_jobs SET status = $1, error = NULL, updated_at = $2 WHERE id = $3 RETURNING *",
+            rekey_job_status::COMPLETED,
+            chrono::Utc::now().naive_utc(),
+            job.id
+        )
+        .fetch_one(&self.resource_service.db)
+        .await
+        .map_err(db_error)?;
+        
+        if job.skipped_resource_ids.is_empty() {
+            info!("密钥更换任务完成: {}", job.id);
+        } else {
+            warn!("密钥更换任务完成: {}, 旧密钥无法解开的资源未更换密钥: {:?}", job.id, job.skipped_resource_ids);
+        }
+        progress(job);
+        
+        Ok(())
+    }
+    
+    /// 生成单个资源的新容器，旧密钥无法解开的资源跳过
+    async fn rekey_one(&self, id: i32, keys: &RekeyKeys) -> Result<RekeyOutcome, ResourceServiceError> {
+        let resource = self.resource_service.get_resource_with_container(id).await?;
+        
+        let rekeyed = self.resource_service.rekeyed_container(
+            &resource,
+            keys.old_key_part_a.expose_secret(),
+            keys.old_ukey_part_b.expose_secret(),
+            keys.new_key_part_a.expose_secret(),
+            keys.new_ukey_part_b.expose_secret()
+        ).await;
+        
+        match rekeyed {
+            Ok(rekeyed) => Ok(RekeyOutcome::Rekeyed(rekeyed)),
+            Err(ResourceServiceError::KeyVerificationFailed) => {
+                // 已经用新密钥加密的资源（例如单独更换过密钥）和使用其他密钥的资源都不处理
+                info!("资源不属于旧密钥，跳过: {}", id);
+                Ok(RekeyOutcome::Skipped)
+            }
+            Err(e) => Err(e),
+        }
+    }
+    
+    /// 标记任务失败
+    async fn mark_failed(&self, id: i32, error: &str) -> Result<(), ResourceServiceError> {
+        query!(
+            "UPDATE rekey_jobs SET status = $1, error = $2, updated_at = $3 WHERE id = $4",
+            rekey_job_status::FAILED,
+            error,
+            chrono::Utc::now().naive_utc(),
+            id
+        )
+        .execute(&self.resource_service.db)
+        .await
+        .map_err(db_error)?;
+        
+        Ok(())
+    }
+}
+
+/// 将密钥管理错误转换为资源服务错误，密钥错误单独返回
+fn to_service_error(err: KeyManagementError) -> ResourceServiceError {
+    ResourceServiceError::from(crate::crypto::CryptoError::KeyManagement(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::SecretString;
+    use crate::crypto::decode::tests::test_config;
+    use crate::database::DatabasePool;
+    use crate::service::ukey::UKeyService;
+    use crate::service::ukey::tests::{create_resource, enroll, test_resource_service, ADMIN_TOKEN};
+    use crate::ukey::challenge::tests::test_key_pair;
+    
+    fn rekey_keys(new_key_part_a: &str, batch_size: u32) -> RekeyKeys {
+        RekeyKeys {
+            old_key_part_a: SecretString::from("old-key-part-a"),
+            old_ukey_part_b: SecretString::from("old-part-b"),
+            new_key_part_a: SecretString::from(new_key_part_a),
+            new_ukey_part_b: SecretString::from("new-part-b"),
+            new_ukey_fingerprint: "new-fingerprint".to_string(),
+            batch_size: Some(batch_size),
+        }
+    }
+    
+    #[sqlx::test]
+    async fn test_advisory_lock(db: DatabasePool) {
+        let resource_service = test_resource_service(db);
+        let rekey_service = RekeyService::new(resource_service);
+        let keys = rekey_keys("new-key-part-a", 10);
+        
+        let prepared = rekey_service.prepare_job(&keys).await.unwrap();
+        let result = rekey_service.prepare_job(&keys).await;
+        assert!(matches!(result, Err(ResourceServiceError::ResourceStatusError(_))), "{:?}", result.map(|prepared| prepared.job));
+        
+        // 任务结束后释放锁
+        let job = rekey_service.run_job(prepared, &keys, |_| {}).await.unwrap();
+        assert_eq!(job.status, rekey_job_status::COMPLETED);
+        let prepared = rekey_service.prepare_job(&keys).await.unwrap();
+        assert_ne!(prepared.job.id, job.id);
+        drop(prepared);
+        
+        // 持有锁的连接断开后锁随之释放
+        assert!(rekey_service.prepare_job(&keys).await.is_ok());
+    }
+    
+    #[sqlx::test]
+    async fn test_resume_after_interruption(db: DatabasePool) {
+        let resource_service = test_resource_service(db.clone());
+        let service = UKeyService::new(resource_service.clone());
+        let key_pair = test_key_pair();
+        enroll(&service, &key_pair, "UK-0001").await;
+        let mut ids = Vec::new();
+        for _ in 0..4 {
+            ids.push(create_resource(&service, &key_pair, "UK-0001", "old-key-part-a", "old-part-b").await);
+        }
+        let other = create_resource(&service, &key_pair, "UK-0001", "other-key-part-a", "old-part-b").await;
+        let rekey_service = RekeyService::new(resource_service.clone());
+        let keys = rekey_keys("new-key-part-a", 1);
+        
+        // 第三个资源无法解析，任务在提交两批后失败
+        let original = query_scalar!("SELECT data FROM resource_chunks WHERE resource_id = $1 AND chunk_index = 0", ids[2]).fetch_one(&db).await.unwrap();
+        query!("UPDATE resource_chunks SET data = $1 WHERE resource_id = $2 AND chunk_index = 0", b"corrupted".as_slice(), ids[2]).execute(&db).await.unwrap();
+        let prepared = rekey_service.prepare_job(&keys).await.unwrap();
+        let job_id = prepared.job.id;
+        assert!(rekey_service.run_job(prepared, &keys, |_| {}).await.is_err());
+        let job = rekey_service.get_job(job_id).await.unwrap();
+        assert_eq!(job.status, rekey_job_status::FAILED);
+        assert_eq!((job.processed_resources, job.last_resource_id), (2, ids[1]));
+        
+        // 续跑时新密钥必须与发起任务时一致
+        query!("UPDATE resource_chunks SET data = $1 WHERE resource_id = $2 AND chunk_index = 0", original, ids[2]).execute(&db).await.unwrap();
+        let result = rekey_service.prepare_job(&rekey_keys("another-key-part-a", 1)).await;
+        assert!(matches!(result, Err(ResourceServiceError::KeyVerificationFailed)), "{:?}", result.map(|prepared| prepared.job));
+        
+        let prepared = rekey_service.prepare_job(&keys).await.unwrap();
+        assert_eq!(prepared.job.id, job_id);
+        let job = rekey_service.run_job(prepared, &keys, |_| {}).await.unwrap();
+        assert_eq!(job.status, rekey_job_status::COMPLETED);
+        assert_eq!(job.processed_resources, 4);
+        assert_eq!(job.skipped_resource_ids, vec![other]);
+        
+        for id in &ids {
+            let resource = resource_service.get_resource_with_container(*id).await.unwrap();
+            resource_service.verify_resource_key(&resource, "new-key-part-a", "new-part-b").await.unwrap();
+            let ukey_info = query_scalar!("SELECT ukey_info FROM encryption_keys WHERE resource_id = $1", id).fetch_one(&db).await.unwrap();
+            assert_eq!(ukey_info, "new-fingerprint");
+        }
+        let resource = resource_service.get_resource_with_container(other).await.unwrap();
+        resource_service.verify_resource_key(&resource, "other-key-part-a", "old-part-b").await.unwrap();
+    }
+    
+    #[sqlx::test]
+    async fn test_write_rekeyed_rejects_modified_resource(db: DatabasePool) {
+        let mut config = test_config();
+        config.encryption.encrypt_metadata = true;
+        config.ukey.admin_token = Some(SecretString::from(ADMIN_TOKEN));
+        let resource_service = Arc::new(ResourceService::new(db.clone(), config));
+        let service = UKeyService::new(resource_service.clone());
+        let key_pair = test_key_pair();
+        enroll(&service, &key_pair, "UK-0001").await;
+        let id = create_resource(&service, &key_pair, "UK-0001", "old-key-part-a", "old-part-b").await;
+        
+        let resource = resource_service.get_resource_with_container(id).await.unwrap();
+        let rekeyed = resource_service.rekeyed_container(&resource, "old-key-part-a", "old-part-b", "new-key-part-a", "new-part-b")
+            .await
+            .unwrap();
+        query!("UPDATE resources SET updated_at = updated_at + INTERVAL '1 second' WHERE id = $1", id).execute(&db).await.unwrap();
+        
+        let mut transaction = db.begin().await.unwrap();
+        assert!(!resource_service.write_rekeyed(&mut transaction, id, &rekeyed, "new-fingerprint").await.unwrap());
+        transaction.commit().await.unwrap();
+        let resource = resource_service.get_resource_with_container(id).await.unwrap();
+        resource_service.verify_resource_key(&resource, "old-key-part-a", "old-part-b").await.unwrap();
+        
+        // 重新生成后写入，元数据用同一数据密钥重新加密
+        let rekeyed = resource_service.rekeyed_container(&resource, "old-key-part-a", "old-part-b", "new-key-part-a", "new-part-b")
+            .await
+            .unwrap();
+        assert!(rekeyed.encrypted_metadata.is_some());
+        assert_ne!(rekeyed.encrypted_metadata, resource.encrypted_metadata);
+        let mut transaction = db.begin().await.unwrap();
+        assert!(resource_service.write_rekeyed(&mut transaction, id, &rekeyed, "new-fingerprint").await.unwrap());
+        transaction.commit().await.unwrap();
+        let resource = resource_service.get_resource_with_container(id).await.unwrap();
+        assert_eq!(resource.encrypted_metadata, rekeyed.encrypted_metadata);
+        let (data, metadata) = resource_service.decrypt_loaded_resource(&resource, "new-key-part-a", "new-part-b").await.unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        assert!(metadata.is_some());
+    }
+    
+    #[test]
+    fn test_check_batch_size() {
+        assert_eq!(check_batch_size(None).unwrap(), DEFAULT_REKEY_BATCH_SIZE);
+        assert_eq!(check_batch_size(Some(1)).unwrap(), 1);
+        assert_eq!(check_batch_size(Some(MAX_REKEY_BATCH_SIZE)).unwrap(), MAX_REKEY_BATCH_SIZE);
+        assert!(matches!(check_batch_size(Some(0)), Err(ResourceServiceError::ParameterError(_))));
+        assert!(matches!(check_batch_size(Some(MAX_REKEY_BATCH_SIZE + 1)), Err(ResourceServiceError::ParameterError(_))));
+    }
+}
@@ -1,12 +1,22 @@
-use sqlx::{query, query_as};
-use tracing::{info, error};
+use sqlx::{query, query_as, query_scalar, Postgres, QueryBuilder, Transaction};
+use futures_util::TryStreamExt;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::io::StreamReader;
+use tracing::{info, warn};
 use anyhow::Result;
 
 use crate::database::{DatabasePool, DatabaseError};
 use crate::database::models::resource::{Resource, CreateResourceRequest, UpdateResourceRequest, ResourceQueryParams, ResourceStats, ResourceResponse};
 use crate::database::models::encryption_key::{EncryptionKey, CreateEncryptionKeyRequest, UpdateEncryptionKeyRequest};
-use crate::crypto::{encode_resource, decode_resource, verify_key, generate_key_hash, EncryptionService, EncodeError, DecodeError};
+use crate::crypto::policy::check_upgrade;
+use crate::crypto::{CryptoError, RecoveryPublicKey, EncryptionService, EncodeError, EncodedResource, DecodeError, ResourceContext, ResourceMetadata, SearchKey, SecretString, MAX_QUERY_TOKENS, SEARCH_TOKEN_LEN};
+use crate::crypto::manifest::ManifestSigner;
 use crate::config::AppConfig;
+use crate::service::{manifest, recovery, storage, ukey};
+use crate::ukey::HardwareAssertion;
+
+/// 上传时加密输出和分块写入之间的管道缓冲区大小
+const CONTAINER_PIPE_SIZE: usize = 64 * 1024;
 
 /// 资源服务错误类型
 #[derive(thiserror::Error, Debug)]
@@ -16,7 +26,7 @@ pub enum ResourceServiceError {
     DatabaseError(#[from] DatabaseError),
     
     #[error("加密服务错误: {0}")]
-    EncryptionError(#[from] crate::crypto::CryptoError),
+    EncryptionError(crate::crypto::CryptoError),
     
     #[error("编码错误: {0}")]
     EncodeError(#[from] EncodeError),
@@ -45,25 +55,68 @@ pub enum ResourceServiceError {
     #[error("密钥验证失败")]
     KeyVerificationFailed,
     
+    #[error("UKey验证失败: {0}")]
+    UKeyVerificationFailed(String),
+    
     #[error("其他错误: {0}")]
     OtherError(#[from] anyhow::Error),
 }
 
+impl From<crate::crypto::CryptoError> for ResourceServiceError {
+    fn from(err: crate::crypto::CryptoError) -> Self {
+        // 密钥错误单独返回，便于接口层返回401
+        if err.is_key_verification_failure() {
+            ResourceServiceError::KeyVerificationFailed
+        } else {
+            ResourceServiceError::EncryptionError(err)
+        }
+    }
+}
+
+/// 更换密钥A和硬件码B后的资源容器
+pub(crate) struct RekeyedContainer {
+    /// 新的加密容器
+    pub container: Vec<u8>,
+    /// 用新密钥包装的数据密钥
+    pub wrapped_key: String,
+    /// 恢复包装
+    pub recovery_wrapped_key: Option<String>,
+    /// 重新加密的元数据，元数据未加密时为 `None`
+    pub encrypted_metadata: Option<Vec<u8>>,
+    /// 用新密钥生成的盲索引词元，元数据未加密时为 `None`
+    pub search_tokens: Option<Vec<Vec<u8>>>,
+    /// 生成容器时读取的资源更新时间，写入时资源已被修改则放弃
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+/// 已验证密钥、等待流式解密的资源，容器格式的资源在 `resource.media_data` 中只有第一个分块
+pub struct ResourceDownload {
+    pub resource: Resource,
+    wrapped_key: Option<String>,
+    key_part_a: SecretString,
+    ukey_part_b: SecretString,
+}
+
 /// 资源服务
 pub struct ResourceService {
   pub db: DatabasePool,
     pub config: AppConfig,
-    #[allow(dead_code)]
-    encryption_service: EncryptionService,
+    pub(crate) encryption_service: EncryptionService,
+    /// 签名清单的签名密钥，未配置时不维护签名清单
+    pub(crate) manifest_signer: Option<ManifestSigner>,
 }
 
 impl ResourceService {
     /// 创建资源服务实例
     pub fn new(db: DatabasePool, config: AppConfig) -> Self {
+        // 签名密钥已在配置校验时检查过格式
+        let manifest_signer = config.encryption.manifest_signing_key.as_ref()
+            .and_then(|key| ManifestSigner::from_base64(key.expose_secret()).ok());
         Self {
             db,
             config,
             encryption_service: EncryptionService::new(),
+            manifest_signer,
         }
     }
     
@@ -71,75 +124,70 @@ impl ResourceService {
     pub async fn get_resources(&self, params: ResourceQueryParams) -> Result<(Vec<ResourceResponse>, Option<i32>), ResourceServiceError> {
         info!("获取资源列表，参数: {:?}", params);
         
-        // 构建查询语句
-        let mut base_query = String::from("SELECT * FROM resources");
-        let mut count_query = String::from("SELECT COUNT(*) FROM resources");
-        let mut conditions = Vec::new();
-        let mut query_params = Vec::new();
-        
-        // 添加状态过滤
-        if let Some(status) = params.status {
-            conditions.push(format!("status = ?"));
-            query_params.push(status);
-        }
+        self.list_resources(params, None).await
+    }
+    
+    /// 按客户端计算的盲索引词元搜索元数据已加密的资源，资源需要包含每个词元
+    ///
+    /// 词元由客户端用密钥A和硬件码B派生的盲索引密钥计算，服务器不接触查询词和密钥，也不对明文列做模糊匹配。
+    /// 词元为空、过多或长度不对时返回 `ParameterError`。
+    pub async fn search_resources(
+        &self,
+        search_tokens: &[Vec<u8>],
+        params: ResourceQueryParams
+    ) -> Result<(Vec<ResourceResponse>, Option<i32>), ResourceServiceError> {
+        info!("搜索资源, 词元数: {}, 媒体类型: {:?}, 状态: {:?}", search_tokens.len(), params.media_type, params.status);
         
-        // 添加媒体类型过滤
-        if let Some(media_type) = params.media_type {
-            conditions.push(format!("media_type = ?"));
-            query_params.push(media_type);
+        if search_tokens.is_empty() || search_tokens.len() > MAX_QUERY_TOKENS {
+            return Err(ResourceServiceError::ParameterError(format!("搜索词元数必须在1到{}之间", MAX_QUERY_TOKENS)));
         }
-        
-        // 添加本地资源过滤
-        if let Some(is_local) = params.is_local {
-            conditions.push(format!("is_local = ?"));
-            query_params.push(is_local.to_string());
+        if search_tokens.iter().any(|token| token.len() != SEARCH_TOKEN_LEN) {
+            return Err(ResourceServiceError::ParameterError("搜索词元长度错误".to_string()));
         }
         
-        // 添加搜索条件
-        if let Some(search) = params.search {
-            conditions.push(format!("(title LIKE ? OR title_en LIKE ? OR description LIKE ?)",));
-            let search_pattern = format!("%{}%", search);
-            query_params.extend_from_slice(&[search_pattern.clone(), search_pattern.clone(), search_pattern]);
-        }
+        // 重复的词元只计一次，否则无法满足包含全部词元的条件
+        let mut search_tokens = search_tokens.to_vec();
+        search_tokens.sort();
+        search_tokens.dedup();
         
-        // 构建完整查询
-        if !conditions.is_empty() {
-            let where_clause = format!(" WHERE {}", conditions.join(" AND "));
-            base_query += &where_clause;
-            count_query += &where_clause;
-        }
+        let params = ResourceQueryParams { search: None, ..params };
+        self.list_resources(params, Some(&search_tokens)).await
+    }
+    
+    /// 按过滤条件查询资源列表，提供盲索引词元时按盲索引搜索
+    async fn list_resources(
+        &self,
+        params: ResourceQueryParams,
+        search_tokens: Option<&[Vec<u8>]>
+    ) -> Result<(Vec<ResourceResponse>, Option<i32>), ResourceServiceError> {
+        // 构建查询语句
+        let mut base_query = QueryBuilder::<Postgres>::new("SELECT * FROM resources");
+        Self::push_filters(&mut base_query, &params, search_tokens);
         
-        // 添加排序
-        let sort_by = params.sort_by.unwrap_or("created_at".to_string());
-        let sort_order = params.sort_order.unwrap_or("desc".to_string());
-        base_query += &format!(" ORDER BY {} {}", sort_by, sort_order);
+        // 添加排序，列名和方向只能取白名单中的值
+        let (sort_by, sort_order) = Self::order_by(&params)?;
+        base_query.push(" ORDER BY ").push(sort_by).push(" ").push(sort_order);
         
         // 添加分页
         let skip = params.skip.unwrap_or(0);
         let limit = params.limit.unwrap_or(100);
-        base_query += &format!(" LIMIT ? OFFSET ?");
-        query_params.push(limit.to_string());
-        query_params.push(skip.to_string());
+        base_query.push(" LIMIT ").push_bind(limit as i64).push(" OFFSET ").push_bind(skip as i64);
         
         // 执行查询
-        let mut query = query_as(&base_query);
-        for param in query_params.clone() {
-            query = query.bind(param);
-        }
-        let resources: Vec<Resource> = query.fetch_all(&self.db)
+        let resources: Vec<Resource> = base_query.build_query_as()
+            .fetch_all(&self.db)
             .await
             .map_err(crate::database::DatabaseError::ConnectionError)?;
         
         // 获取总数（如果需要）
         let total_count = if params.count_only.unwrap_or(false) {
-            let mut count_query = sqlx::query_scalar(&count_query);
-            for param in query_params {
-                count_query = count_query.bind(param);
-            }
-            let count: i32 = count_query.fetch_one(&self.db)
+            let mut count_query = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM resources");
+            Self::push_filters(&mut count_query, &params, search_tokens);
+            let count: i64 = count_query.build_query_scalar()
+                .fetch_one(&self.db)
                 .await
                 .map_err(crate::database::DatabaseError::ConnectionError)?;
-            Some(count)
+            Some(count as i32)
         } else {
             None
         };
@@ -153,6 +201,63 @@ impl ResourceService {
         Ok((resource_responses, total_count))
     }
     
+    /// 把排序参数映射为固定的列名和方向，不在白名单中的值返回 `ParameterError`
+    fn order_by(params: &ResourceQueryParams) -> Result<(&'static str, &'static str), ResourceServiceError> {
+        let sort_by = match params.sort_by.as_deref().unwrap_or("created_at") {
+            "id" => "id",
+            "title" => "title",
+            "media_type" => "media_type",
+            "status" => "status",
+            "created_at" => "created_at",
+            "updated_at" => "updated_at",
+            other => return Err(ResourceServiceError::ParameterError(format!("不支持的排序字段: {}", other))),
+        };
+        let sort_order = match params.sort_order.as_deref().unwrap_or("desc") {
+            order if order.eq_ignore_ascii_case("asc") => "ASC",
+            order if order.eq_ignore_ascii_case("desc") => "DESC",
+            other => return Err(ResourceServiceError::ParameterError(format!("不支持的排序方向: {}", other))),
+        };
+        
+        Ok((sort_by, sort_order))
+    }
+    
+    /// 添加资源列表的过滤条件
+    ///
+    /// 提供盲索引词元时只匹配包含全部词元的资源，否则按 `search` 模糊匹配明文的标题和描述。
+    fn push_filters(builder: &mut QueryBuilder<'_, Postgres>, params: &ResourceQueryParams, search_tokens: Option<&[Vec<u8>]>) {
+        builder.push(" WHERE TRUE");
+        
+        // 添加状态过滤
+        if let Some(status) = &params.status {
+            builder.push(" AND status = ").push_bind(status.clone());
+        }
+        
+        // 添加媒体类型过滤
+        if let Some(media_type) = &params.media_type {
+            builder.push(" AND media_type = ").push_bind(media_type.clone());
+        }
+        
+        // 添加本地资源过滤
+        if let Some(is_local) = params.is_local {
+            builder.push(" AND is_local = ").push_bind(is_local);
+        }
+        
+        // 添加搜索条件
+        if let Some(search_tokens) = search_tokens {
+            builder.push(" AND id IN (SELECT resource_id FROM resource_search_index WHERE token = ANY(")
+                .push_bind(search_tokens.to_vec())
+                .push(") GROUP BY resource_id HAVING COUNT(*) = ")
+                .push_bind(search_tokens.len() as i64)
+                .push(")");
+        } else if let Some(search) = &params.search {
+            let search_pattern = format!("%{}%", search);
+            builder.push(" AND (title LIKE ").push_bind(search_pattern.clone())
+                .push(" OR title_en LIKE ").push_bind(search_pattern.clone())
+                .push(" OR description LIKE ").push_bind(search_pattern)
+                .push(")");
+        }
+    }
+    
     /// 根据ID获取资源
     pub async fn get_resource_by_id(&self, id: i32, is_admin_view: bool) -> Result<Resource, ResourceServiceError> {
         info!("根据ID获取资源: {}, 管理员视图: {}", id, is_admin_view);
@@ -173,7 +278,9 @@ impl ResourceService {
                 created_at, 
                 updated_at, 
                 total_count, 
-                has_pending_supplement 
+                has_pending_supplement, 
+                uuid, 
+                encrypted_metadata 
             FROM resources 
             WHERE id = $1
         "#, id)
@@ -198,6 +305,8 @@ impl ResourceService {
             updated_at: resource_row.updated_at,
             total_count: resource_row.total_count,
             has_pending_supplement: resource_row.has_pending_supplement,
+            uuid: resource_row.uuid,
+            encrypted_metadata: resource_row.encrypted_metadata,
         };
         
         // 如果不是管理员视图，只返回已批准的资源
@@ -208,14 +317,56 @@ impl ResourceService {
         Ok(resource)
     }
     
+    /// 根据ID获取资源和完整的加密容器，用于需要整体处理容器的解密、更换密钥、升级和恢复
+    pub(crate) async fn get_resource_with_container(&self, id: i32) -> Result<Resource, ResourceServiceError> {
+        let mut resource = self.get_resource_by_id(id, true).await?;
+        resource.media_data = storage::read_container(&self.db, id).await?;
+        
+        Ok(resource)
+    }
+    
+    /// 根据ID获取资源和容器头部，用于验证密钥
+    ///
+    /// 容器格式的资源只读取包含头部的第一个分块，没有容器头部的旧格式资源读取完整数据。
+    pub(crate) async fn get_resource_with_header(&self, id: i32) -> Result<Resource, ResourceServiceError> {
+        let mut resource = self.get_resource_by_id(id, true).await?;
+        resource.media_data = if resource.encryption_info.is_empty() {
+            storage::read_first_chunk(&self.db, id).await?
+        } else {
+            storage::read_container(&self.db, id).await?
+        };
+        
+        Ok(resource)
+    }
+    
     /// 创建资源
+    ///
+    /// 硬件码B取自UKey或FIDO2安全密钥对一次性挑战的应答，应答验证通过后才加密资源。
     pub async fn create_resource(
         &self,
-        create_req: CreateResourceRequest,
+        mut create_req: CreateResourceRequest,
         key_part_a: &str,
-        ukey_part_b: &str
+        assertion: &HardwareAssertion
     ) -> Result<Resource, ResourceServiceError> {
-        info!("创建资源: {}, 媒体类型: {}, 本地资源: {}", create_req.title, create_req.media_type, create_req.is_local);
+        let media_data = std::mem::take(&mut create_req.media_data);
+        self.create_resource_stream(create_req, &mut media_data.as_slice(), key_part_a, assertion).await
+    }
+    
+    /// 从 `reader` 读取媒体数据并创建资源，`create_req` 中的 `media_data` 不使用
+    ///
+    /// 明文逐段加密，加密容器边生成边分块写入数据库，内存中只保留正在处理的分段和分块。
+    /// 与 `create_resource` 相同，应答验证通过后才读取媒体数据。
+    pub async fn create_resource_stream<R>(
+        &self,
+        create_req: CreateResourceRequest,
+        reader: &mut R,
+        key_part_a: &str,
+        assertion: &HardwareAssertion
+    ) -> Result<Resource, ResourceServiceError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        info!("创建资源, 媒体类型: {}, 本地资源: {}, 加密元数据: {}", create_req.media_type, create_req.is_local, self.config.encryption.encrypt_metadata);
         
         // 验证必填字段
         if create_req.title.is_empty() {
@@ -234,58 +385,113 @@ impl ResourceService {
             return Err(ResourceServiceError::ParameterError("媒体类型不能为空".to_string()));
         }
         
-        // 编码资源
-        let encryption_info_json = encode_resource(
-            &create_req.media_data,
-            &create_req.media_type,
-            create_req.is_local,
-            key_part_a,
-            ukey_part_b,
-            &self.config
-        )?;
+        let (ukey, ukey_part_b) = ukey::verify_hardware_assertion(&self.db, &self.config, assertion).await?;
+        let ukey_part_b = ukey_part_b.expose_secret();
+        
+        // 加密资源，数据库中只保存加密容器和包装后的数据密钥
+        // 加密参数保存在容器头部，encryption_info为空；资源UUID和媒体类型绑定到密文
+        // 设置了恢复密钥时，数据密钥同时用恢复公钥包装
+        // 启用元数据加密时，标题和描述用数据密钥加密，明文列留空，另外建立盲索引用于搜索
+        // 资源、加密密钥、盲索引和签名清单在同一事务中写入，任何一步失败都不会留下不完整的记录
+        // 事务先持有恢复密钥的共享锁，加密期间恢复密钥不会被替换或撤销
+        let mut transaction = self.db.begin()
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        recovery::lock_recovery_key_shared(&mut transaction).await?;
         
-        // 生成密钥哈希
-        let actual_key = format!("{}{}", key_part_a, ukey_part_b);
-        let key_hash = generate_key_hash(&actual_key);
+        let uuid = uuid::Uuid::new_v4();
+        let recovery_key = self.get_active_recovery_key().await?;
+        let metadata = self.config.encryption.encrypt_metadata.then(|| ResourceMetadata {
+            title: create_req.title.clone(),
+            title_en: create_req.title_en.clone(),
+            description: Some(create_req.description.clone()),
+        });
+        let (title, title_en, description) = match &metadata {
+            Some(_) => (String::new(), None, None),
+            None => (create_req.title, Some(create_req.title_en.unwrap_or_default()), Some(create_req.description)),
+        };
         
-        // 创建资源记录
+        // 先创建资源记录，加密容器写入分块表，加密元数据在加密完成后写入
         let now = chrono::Utc::now().naive_utc();
         let status = create_req.status.unwrap_or("PENDING".to_string());
         
         let resource_id = query!(r#"INSERT INTO resources 
-            (title, title_en, description, resource_type, media_data, media_type, is_local, encryption_info, status, created_at, updated_at) 
+            (title, title_en, description, resource_type, media_type, is_local, encryption_info, status, created_at, updated_at, uuid) 
             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) 
             RETURNING id"#, 
-            create_req.title, 
-            create_req.title_en.unwrap_or_default(), 
-            create_req.description, 
+            title, 
+            title_en, 
+            description, 
             create_req.resource_type, 
-            create_req.media_data, 
             create_req.media_type, 
             create_req.is_local, 
-            encryption_info_json, 
+            "", 
             status, 
             now, 
-            now
+            now, 
+            uuid
         )
-        .fetch_one(&self.db)
+        .fetch_one(&mut *transaction)
         .await
         .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?
         .id;
         
+        // 加密结果经管道交给分块写入，两端同时进行，任何一端出错都放弃整个事务
+        let (mut container_reader, mut container_writer) = tokio::io::duplex(CONTAINER_PIPE_SIZE);
+        let context = ResourceContext::new(uuid, &create_req.media_type);
+        let (metadata_ref, recovery_key_ref) = (metadata.as_ref(), recovery_key.as_ref());
+        let encode = async move {
+            let encoded = self.encryption_service.encrypt_resource_stream(
+                reader,
+                &mut container_writer,
+                &context,
+                key_part_a,
+                ukey_part_b,
+                recovery_key_ref,
+                metadata_ref,
+                &self.config
+            ).await;
+            // 关闭写入端，分块写入读到结尾后结束
+            drop(container_writer);
+            encoded.map_err(ResourceServiceError::from)
+        };
+        let store = storage::write_container(&mut transaction, resource_id, &mut container_reader, self.config.upload.storage_chunk_size);
+        let (encoded, container_len) = tokio::try_join!(encode, store)?;
+        
+        query!("UPDATE resources SET encrypted_metadata = $1 WHERE id = $2", encoded.encrypted_metadata, resource_id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        let search_tokens = match &metadata {
+            Some(metadata) => Some(self.search_tokens(metadata, key_part_a, ukey_part_b)?),
+            None => None,
+        };
+        
         // 创建加密密钥记录
+        // 不再保存密钥哈希和硬件码B，密钥由容器头部中的校验块验证；ukey_info记录加密时使用的UKey指纹
         let encryption_key = CreateEncryptionKeyRequest {
             resource_id,
-            key_hash,
-            ukey_info: "".to_string(), // 这里应该存储UKey的相关信息
+            key_hash: String::new(),
+            ukey_info: ukey.fingerprint,
+            wrapped_key: Some(encoded.wrapped_key),
+            recovery_wrapped_key: encoded.recovery_wrapped_key,
         };
         
-        self.create_encryption_key(&encryption_key).await?;
+        Self::create_encryption_key(&mut transaction, &encryption_key).await?;
+        
+        // 写入盲索引和签名清单
+        if let Some(search_tokens) = search_tokens {
+            Self::write_search_index(&mut transaction, resource_id, &search_tokens).await?;
+        }
+        self.update_manifest(&mut transaction, resource_id).await?;
+        transaction.commit()
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
         
         // 获取创建的资源
         let resource = self.get_resource_by_id(resource_id, true).await?;
         
-        info!("资源创建成功: {}, ID: {}", create_req.title, resource_id);
+        info!("资源创建成功, ID: {}, 容器长度: {} 字节", resource_id, container_len);
         
         Ok(resource)
     }
@@ -298,33 +504,63 @@ impl ResourceService {
     ) -> Result<Resource, ResourceServiceError> {
         info!("更新资源: {}", id);
         
+        // 媒体数据和加密信息只能由加密流程写入，避免明文落库
+        // 媒体类型已绑定到密文，修改后资源将无法解密
+        if update_req.media_data.is_some() || update_req.encryption_info.is_some() || update_req.media_type.is_some() {
+            return Err(ResourceServiceError::ParameterError("不允许直接更新媒体数据、媒体类型或加密信息".to_string()));
+        }
+        
         // 检查资源是否存在
-        let mut resource = self.get_resource_by_id(id, true).await?;
+        let resource = self.get_resource_by_id(id, true).await?;
+        
+        let resource = self.write_update(resource, update_req).await?;
+        
+        info!("资源更新成功: {}", id);
+        
+        Ok(resource)
+    }
+    
+    /// 把更新写入读取时的资源记录，只写入可以直接修改的字段
+    ///
+    /// 容器、加密信息和媒体类型只由加密流程写入。资源在读取后被更换密钥、升级或恢复时 `updated_at` 已改变，
+    /// 不写入并返回错误，避免用旧的记录覆盖。
+    async fn write_update(
+        &self,
+        mut resource: Resource,
+        update_req: UpdateResourceRequest
+    ) -> Result<Resource, ResourceServiceError> {
+        // 加密的元数据需要数据密钥才能修改，明文列保持为空
+        if resource.encrypted_metadata.is_some()
+            && (update_req.title.is_some() || update_req.title_en.is_some() || update_req.description.is_some()) {
+            return Err(ResourceServiceError::ParameterError("资源的标题和描述已加密，不允许直接更新".to_string()));
+        }
+        
+        let read_at = resource.updated_at;
         
         // 更新资源字段
         resource.update(update_req);
         
         // 执行更新
-        query!(r#"UPDATE resources SET 
-            title = $1, title_en = $2, description = $3, resource_type = $4, media_data = $5, media_type = $6, is_local = $7, encryption_info = $8, status = $9, updated_at = $10 
-            WHERE id = $11"#, 
+        let result = query!(r#"UPDATE resources SET 
+            title = $1, title_en = $2, description = $3, resource_type = $4, is_local = $5, status = $6, updated_at = $7 
+            WHERE id = $8 AND updated_at = $9"#, 
             resource.title, 
             resource.title_en, 
             resource.description, 
             resource.resource_type, 
-            resource.media_data, 
-            resource.media_type, 
             resource.is_local, 
-            resource.encryption_info, 
             resource.status, 
             resource.updated_at, 
-            id
+            resource.id, 
+            read_at
         )
         .execute(&self.db)
         .await
         .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
         
-        info!("资源更新成功: {}", id);
+        if result.rows_affected() == 0 {
+            return Err(ResourceServiceError::ResourceStatusError("资源在更新期间被修改，请重试".to_string()));
+        }
         
         Ok(resource)
     }
@@ -347,12 +583,21 @@ impl ResourceService {
             .await
             .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
         
+        // 删除盲索引
+        sqlx::query!("DELETE FROM resource_search_index WHERE resource_id = $1", id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        
         // 删除资源
         sqlx::query!("DELETE FROM resources WHERE id = $1", id)
             .execute(&mut *transaction)
             .await
             .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
         
+        // 从签名清单中移除
+        self.update_manifest(&mut transaction, id).await?;
+        
         // 提交事务
         transaction.commit()
             .await
@@ -364,33 +609,426 @@ impl ResourceService {
     }
     
     /// 解密资源
+    ///
+    /// 返回解密后的数据和资源响应，元数据已加密的资源在响应中返回解密后的标题和描述。
+    /// 硬件码B只通过UKey或FIDO2安全密钥对一次性挑战的应答接受，挑战无效或签名错误时返回 `UKeyVerificationFailed`。
     pub async fn decrypt_resource(
         &self,
         id: i32,
         key_part_a: &str,
-        ukey_part_b: &str
-    ) -> Result<Vec<u8>, ResourceServiceError> {
-        info!("解密资源: {}", id);
+        assertion: &HardwareAssertion
+    ) -> Result<(Vec<u8>, ResourceResponse), ResourceServiceError> {
+        info!("解密资源: {}, UKey序列号: {}", id, assertion.serial());
         
         // 获取资源
-        let resource = self.get_resource_by_id(id, true).await?;
+        let resource = self.get_resource_with_container(id).await?;
+        
+        // 资源存在时才消耗挑战
+        let (_, ukey_part_b) = ukey::verify_hardware_assertion(&self.db, &self.config, assertion).await?;
+        let ukey_part_b = ukey_part_b.expose_secret();
         
-        // 验证密钥
-        if !verify_key(key_part_a, ukey_part_b, &resource.encryption_info)? {
-            return Err(ResourceServiceError::KeyVerificationFailed);
+        let (decrypted_data, metadata) = self.decrypt_loaded_resource(&resource, key_part_a, ukey_part_b).await?;
+        
+        info!("资源解密成功: {}", id);
+        
+        // 加密参数低于当前配置的资源（包括旧版密钥组合方案）在成功解锁后重新加密，升级失败不影响本次解密
+        match check_upgrade(&resource.media_data, &resource.encryption_info, &self.config) {
+            Ok(Some(reason)) => match self.upgrade_resource(&resource, &decrypted_data, metadata.as_ref(), key_part_a, ukey_part_b).await {
+                Ok(true) => info!("资源加密参数升级成功: {}, 原因: {:?}", id, reason),
+                Ok(false) => info!("资源已被修改，跳过加密参数升级: {}", id),
+                Err(e) => warn!("资源加密参数升级失败: {}, 错误: {}", id, e),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("检查资源加密参数失败: {}, 错误: {}", id, e),
         }
         
-        // 解密资源
-        let decrypted_data = decode_resource(
+        let response = resource.to_unlocked_response(metadata.as_ref());
+        
+        Ok((decrypted_data, response))
+    }
+    
+    /// 准备流式下载资源：验证硬件应答和密钥，但不解密媒体数据
+    ///
+    /// 与 `decrypt_resource` 不同，下载时不升级资源的加密参数。密钥错误返回 `KeyVerificationFailed`。
+    pub async fn prepare_download(
+        &self,
+        id: i32,
+        key_part_a: &str,
+        assertion: &HardwareAssertion
+    ) -> Result<ResourceDownload, ResourceServiceError> {
+        info!("下载资源: {}, UKey序列号: {}", id, assertion.serial());
+        
+        let resource = self.get_resource_with_header(id).await?;
+        
+        // 资源存在时才消耗挑战
+        let (_, ukey_part_b) = ukey::verify_hardware_assertion(&self.db, &self.config, assertion).await?;
+        self.verify_resource_key(&resource, key_part_a, ukey_part_b.expose_secret()).await?;
+        let wrapped_key = self.get_wrapped_key(resource.id).await?;
+        
+        Ok(ResourceDownload { resource, wrapped_key, key_part_a: SecretString::from(key_part_a), ukey_part_b })
+    }
+    
+    /// 从分块表逐块读取已准备下载的资源，逐段解密后写入 `writer`，返回明文长度
+    ///
+    /// 数据损坏时在出错的分段返回 `KeyVerificationFailed`，此前的分段已经写出。
+    pub async fn write_download<W>(&self, download: &ResourceDownload, writer: &mut W) -> Result<u64, ResourceServiceError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let resource = &download.resource;
+        let mut reader = StreamReader::new(storage::container_chunks(&self.db, resource.id).map_err(std::io::Error::other));
+        let written = self.encryption_service.decrypt_resource_stream(
+            &mut reader,
+            writer,
+            &resource.encryption_info,
+            download.wrapped_key.as_deref(),
+            &ResourceContext::new(resource.uuid, &resource.media_type),
+            download.key_part_a.expose_secret(),
+            download.ukey_part_b.expose_secret()
+        ).await?;
+        
+        info!("资源下载完成: {}, 明文长度: {} 字节", resource.id, written);
+        
+        Ok(written)
+    }
+    
+    /// 解密已读取的资源
+    ///
+    /// 使用加密信息和包装的数据密钥解密，密钥错误和数据损坏都返回 `KeyVerificationFailed`。
+    /// 元数据已加密时同时返回解密后的元数据。
+    pub(crate) async fn decrypt_loaded_resource(
+        &self,
+        resource: &Resource,
+        key_part_a: &str,
+        ukey_part_b: &str
+    ) -> Result<(Vec<u8>, Option<ResourceMetadata>), ResourceServiceError> {
+        let wrapped_key = self.get_wrapped_key(resource.id).await?;
+        let decrypted = self.encryption_service.decrypt_resource_with_metadata(
             &resource.media_data,
             &resource.encryption_info,
+            wrapped_key.as_deref(),
+            &ResourceContext::new(resource.uuid, &resource.media_type),
+            resource.encrypted_metadata.as_deref(),
             key_part_a,
             ukey_part_b
         )?;
         
-        info!("资源解密成功: {}", id);
+        Ok(decrypted)
+    }
+    
+    /// 生成用新的密钥A和硬件码B保护的加密容器，不写入数据库
+    ///
+    /// 信封加密的资源只重新包装数据密钥，恢复包装保持不变，元数据用同一数据密钥重新加密；旧资源需要解密后
+    /// 用新密钥整体重新加密，同时生成新的恢复包装。
+    pub(crate) async fn rekeyed_container(
+        &self,
+        resource: &Resource,
+        old_key_part_a: &str,
+        old_ukey_part_b: &str,
+        new_key_part_a: &str,
+        new_ukey_part_b: &str
+    ) -> Result<RekeyedContainer, ResourceServiceError> {
+        let wrapped_key = self.get_wrapped_key(resource.id).await?;
+        let rekeyed = self.encryption_service.rekey_resource(
+            &resource.media_data,
+            &resource.encryption_info,
+            wrapped_key.as_deref(),
+            old_key_part_a,
+            old_ukey_part_b,
+            new_key_part_a,
+            new_ukey_part_b,
+            &self.config
+        );
+        
+        match rekeyed {
+            Ok((container, wrapped_key)) => {
+                // 数据密钥不变，原恢复包装仍然有效，盲索引需要用新密钥重建
+                let recovery_wrapped_key = self.get_recovery_wrapped_key(resource.id).await?;
+                let (encrypted_metadata, search_tokens) = match &resource.encrypted_metadata {
+                    Some(encrypted_metadata) => {
+                        let (metadata, resealed) = self.encryption_service.reseal_resource_metadata(
+                            &resource.media_data,
+                            &ResourceContext::new(resource.uuid, &resource.media_type),
+                            encrypted_metadata,
+                            old_key_part_a,
+                            old_ukey_part_b
+                        )?;
+                        (Some(resealed), Some(self.search_tokens(&metadata, new_key_part_a, new_ukey_part_b)?))
+                    }
+                    None => (None, None),
+                };
+                Ok(RekeyedContainer {
+                    container,
+                    wrapped_key,
+                    recovery_wrapped_key,
+                    encrypted_metadata,
+                    search_tokens,
+                    updated_at: resource.updated_at,
+                })
+            }
+            Err(CryptoError::Encode(EncodeError::NotEnvelopeEncrypted)) => {
+                // 旧资源没有数据密钥，只能整体重新加密，元数据随新数据密钥重新加密
+                let (decrypted_data, metadata) = self.decrypt_loaded_resource(resource, old_key_part_a, old_ukey_part_b).await?;
+                let recovery_key = self.get_active_recovery_key().await?;
+                let reencrypted = self.encryption_service.encrypt_resource(
+                    &decrypted_data,
+                    &ResourceContext::new(resource.uuid, &resource.media_type),
+                    new_key_part_a,
+                    new_ukey_part_b,
+                    recovery_key.as_ref(),
+                    metadata.as_ref(),
+                    &self.config
+                )?;
+                let search_tokens = metadata
+                    .map(|metadata| self.search_tokens(&metadata, new_key_part_a, new_ukey_part_b))
+                    .transpose()?;
+                
+                info!("资源重新加密完成: {}", resource.id);
+                Ok(RekeyedContainer {
+                    container: reencrypted.container,
+                    wrapped_key: reencrypted.wrapped_key,
+                    recovery_wrapped_key: reencrypted.recovery_wrapped_key,
+                    encrypted_metadata: reencrypted.encrypted_metadata,
+                    search_tokens,
+                    updated_at: resource.updated_at,
+                })
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+    
+    /// 用第一个容器格式的资源验证当前的密钥A和硬件码B，错误时返回 `KeyVerificationFailed`
+    ///
+    /// 返回是否有资源参与验证，没有资源时无法验证，返回 `false`。
+    pub(crate) async fn verify_current_key(&self, key_part_a: &str, ukey_part_b: &str) -> Result<bool, ResourceServiceError> {
+        let first_id = query_scalar!("SELECT id FROM resources WHERE encryption_info = '' ORDER BY id LIMIT 1")
+            .fetch_optional(&self.db)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        
+        if let Some(id) = first_id {
+            let resource = self.get_resource_with_header(id).await?;
+            self.verify_resource_key(&resource, key_part_a, ukey_part_b).await?;
+        }
+        
+        Ok(first_id.is_some())
+    }
+    
+    /// 验证资源的密钥A和硬件码B，错误时返回 `KeyVerificationFailed`
+    ///
+    /// 有密钥校验信息时只执行一次密钥派生，没有校验信息的旧资源通过解密验证。
+    pub(crate) async fn verify_resource_key(
+        &self,
+        resource: &Resource,
+        key_part_a: &str,
+        ukey_part_b: &str
+    ) -> Result<(), ResourceServiceError> {
+        match self.encryption_service.verify_resource_key(key_part_a, ukey_part_b, &resource.media_data, &resource.encryption_info) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(ResourceServiceError::KeyVerificationFailed),
+            Err(_) => {
+                let wrapped_key = self.get_wrapped_key(resource.id).await?;
+                self.encryption_service.decrypt_resource(
+                    &resource.media_data,
+                    &resource.encryption_info,
+                    wrapped_key.as_deref(),
+                    &ResourceContext::new(resource.uuid, &resource.media_type),
+                    key_part_a,
+                    ukey_part_b
+                )?;
+                Ok(())
+            }
+        }
+    }
+    
+    /// 使用当前加密配置重新加密已解密的资源
+    ///
+    /// 只有资源自读取后未被修改时才写入，避免覆盖同时进行的密钥更换或更新。返回是否写入。
+    pub(crate) async fn upgrade_resource(
+        &self,
+        resource: &Resource,
+        decrypted_data: &[u8],
+        metadata: Option<&ResourceMetadata>,
+        key_part_a: &str,
+        ukey_part_b: &str
+    ) -> Result<bool, ResourceServiceError> {
+        let mut transaction = self.db.begin()
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        recovery::lock_recovery_key_shared(&mut transaction).await?;
+        
+        // 锁定资源行并确认未被修改，已修改时回滚
+        let updated_at = query_scalar!("SELECT updated_at FROM resources WHERE id = $1 FOR UPDATE", resource.id)
+            .fetch_optional(&mut *transaction)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        if updated_at != Some(resource.updated_at) {
+            return Ok(false);
+        }
+        
+        // 持有恢复密钥的共享锁后读取当前恢复密钥，写入前不会被替换
+        let recovery_key = self.get_active_recovery_key().await?;
+        let encoded = self.encryption_service.encrypt_resource(
+            decrypted_data,
+            &ResourceContext::new(resource.uuid, &resource.media_type),
+            key_part_a,
+            ukey_part_b,
+            recovery_key.as_ref(),
+            metadata,
+            &self.config
+        )?;
+        
+        self.write_encoded(&mut transaction, resource.id, &encoded).await?;
+        
+        transaction.commit()
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        
+        Ok(true)
+    }
+    
+    /// 在调用方的事务中写入重新加密的结果，包括用新数据密钥加密的元数据
+    async fn write_encoded(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        id: i32,
+        encoded: &EncodedResource
+    ) -> Result<(), ResourceServiceError> {
+        // 先写入元数据，签名清单在写入容器时按两者一起更新
+        query!("UPDATE resources SET encrypted_metadata = $1 WHERE id = $2", encoded.encrypted_metadata, id)
+            .execute(&mut **transaction)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        
+        self.write_container(transaction, id, &encoded.container, &encoded.wrapped_key, encoded.recovery_wrapped_key.as_deref(), None).await
+    }
+    
+    /// 在调用方的事务中写入更换密钥后的容器、元数据和新UKey的指纹，元数据已加密时同时重建盲索引
+    ///
+    /// 与 `upgrade_resource` 相同，锁定资源行并确认生成容器后资源未被修改，已修改或已删除时不写入。返回是否写入。
+    pub(crate) async fn write_rekeyed(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        id: i32,
+        rekeyed: &RekeyedContainer,
+        ukey_fingerprint: &str
+    ) -> Result<bool, ResourceServiceError> {
+        let updated_at = query_scalar!("SELECT updated_at FROM resources WHERE id = $1 FOR UPDATE", id)
+            .fetch_optional(&mut **transaction)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        if updated_at != Some(rekeyed.updated_at) {
+            return Ok(false);
+        }
         
-        Ok(decrypted_data)
+        query!("UPDATE resources SET encrypted_metadata = $1 WHERE id = $2", rekeyed.encrypted_metadata, id)
+            .execute(&mut **transaction)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        
+        self.write_container(
+            transaction,
+            id,
+            &rekeyed.container,
+            &rekeyed.wrapped_key,
+            rekeyed.recovery_wrapped_key.as_deref(),
+            Some(ukey_fingerprint)
+        ).await?;
+        
+        if let Some(search_tokens) = &rekeyed.search_tokens {
+            Self::write_search_index(transaction, id, search_tokens).await?;
+        }
+        
+        Ok(true)
+    }
+    
+    /// 在调用方的事务中替换资源的盲索引词元
+    async fn write_search_index(
+        transaction: &mut Transaction<'_, Postgres>,
+        id: i32,
+        search_tokens: &[Vec<u8>]
+    ) -> Result<(), ResourceServiceError> {
+        query!("DELETE FROM resource_search_index WHERE resource_id = $1", id)
+            .execute(&mut **transaction)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        
+        query!(
+            "INSERT INTO resource_search_index (resource_id, token) SELECT $1, UNNEST($2::bytea[])",
+            id,
+            search_tokens
+        )
+        .execute(&mut **transaction)
+        .await
+        .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        
+        Ok(())
+    }
+    
+    /// 用密钥A和硬件码B派生的盲索引密钥生成元数据的索引词元
+    pub(crate) fn search_tokens(
+        &self,
+        metadata: &ResourceMetadata,
+        key_part_a: &str,
+        ukey_part_b: &str
+    ) -> Result<Vec<Vec<u8>>, ResourceServiceError> {
+        let search_key = SearchKey::derive(key_part_a, ukey_part_b).map_err(CryptoError::from)?;
+        Ok(search_key.index_tokens(metadata))
+    }
+    
+    /// 在调用方的事务中写入加密容器、包装后的数据密钥和恢复包装，并更新签名清单
+    ///
+    /// 提供 `ukey_fingerprint` 时把 `ukey_info` 更新为新UKey的指纹，否则保留加密时记录的指纹。
+    /// 调用方需要先持有恢复密钥的共享锁，恢复包装不属于当前恢复密钥时返回错误。
+    async fn write_container(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        id: i32,
+        encrypted_data: &[u8],
+        wrapped_key: &str,
+        recovery_wrapped_key: Option<&str>,
+        ukey_fingerprint: Option<&str>
+    ) -> Result<(), ResourceServiceError> {
+        if let Some(recovery_wrapped_key) = recovery_wrapped_key {
+            recovery::check_recovery_wrap(transaction, recovery_wrapped_key).await?;
+        }
+        
+        let mut reader = encrypted_data;
+        storage::write_container(transaction, id, &mut reader, self.config.upload.storage_chunk_size).await?;
+        
+        let now = chrono::Utc::now().naive_utc();
+        
+        query!(r#"UPDATE resources SET 
+            encryption_info = '', updated_at = $1 
+            WHERE id = $2"#, 
+            now, 
+            id
+        )
+        .execute(&mut **transaction)
+        .await
+        .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        
+        query!(
+            r#"UPDATE encryption_keys SET key_hash = '', ukey_info = COALESCE($3, ukey_info), wrapped_key = $1, recovery_wrapped_key = $2
+            WHERE resource_id = $4"#,
+            wrapped_key,
+            recovery_wrapped_key,
+            ukey_fingerprint,
+            id
+        )
+            .execute(&mut **transaction)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        
+        self.update_manifest(transaction, id).await
+    }
+    
+    /// 在调用方的事务中更新资源在签名清单中的记录，未配置签名密钥时不处理
+    async fn update_manifest(&self, transaction: &mut Transaction<'_, Postgres>, id: i32) -> Result<(), ResourceServiceError> {
+        match &self.manifest_signer {
+            Some(signer) => manifest::record_resource(transaction, signer, id).await,
+            None => Ok(()),
+        }
     }
     
     /// 获取资源统计信息
@@ -468,32 +1106,31 @@ impl ResourceService {
         Ok(stats)
     }
     
-    /// 创建加密密钥
+    /// 在调用方的事务中创建加密密钥记录，恢复包装需要属于当前恢复密钥
     async fn create_encryption_key(
-        &self,
+        transaction: &mut Transaction<'_, Postgres>,
         create_req: &CreateEncryptionKeyRequest
     ) -> Result<EncryptionKey, ResourceServiceError> {
+        if let Some(recovery_wrapped_key) = &create_req.recovery_wrapped_key {
+            recovery::check_recovery_wrap(transaction, recovery_wrapped_key).await?;
+        }
+        
         let now = chrono::Utc::now().naive_utc();
         
-        let encryption_key_id = query!(r#"INSERT INTO encryption_keys 
-            (resource_id, key_hash, ukey_info, created_at) 
-            VALUES ($1, $2, $3, $4) 
-            RETURNING id"#, 
+        let encryption_key = query_as!(EncryptionKey, r#"INSERT INTO encryption_keys 
+            (resource_id, key_hash, ukey_info, wrapped_key, recovery_wrapped_key, created_at) 
+            VALUES ($1, $2, $3, $4, $5, $6) 
+            RETURNING *"#, 
             create_req.resource_id, 
             create_req.key_hash, 
             create_req.ukey_info, 
+            create_req.wrapped_key, 
+            create_req.recovery_wrapped_key, 
             now
         )
-        .fetch_one(&self.db)
+        .fetch_one(&mut **transaction)
         .await
-        .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?
-        .id;
-        
-        // 获取创建的加密密钥
-        let encryption_key = query_as!(EncryptionKey, "SELECT * FROM encryption_keys WHERE id = $1", encryption_key_id)
-            .fetch_one(&self.db)
-            .await
-            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
         
         Ok(encryption_key)
     }
@@ -531,6 +1168,47 @@ impl ResourceService {
         Ok(updated_encryption_key)
     }
     
+    /// 获取资源包装后的数据密钥，旧资源没有数据密钥时返回None
+    pub(crate) async fn get_wrapped_key(
+        &self,
+        resource_id: i32
+    ) -> Result<Option<String>, ResourceServiceError> {
+        let wrapped_key = query!("SELECT wrapped_key FROM encryption_keys WHERE resource_id = $1", resource_id)
+            .fetch_optional(&self.db)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?
+            .and_then(|record| record.wrapped_key);
+        
+        Ok(wrapped_key)
+    }
+    
+    /// 获取资源用恢复公钥包装的数据密钥
+    pub(crate) async fn get_recovery_wrapped_key(
+        &self,
+        resource_id: i32
+    ) -> Result<Option<String>, ResourceServiceError> {
+        let recovery_wrapped_key = query_scalar!("SELECT recovery_wrapped_key FROM encryption_keys WHERE resource_id = $1", resource_id)
+            .fetch_optional(&self.db)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?
+            .flatten();
+        
+        Ok(recovery_wrapped_key)
+    }
+    
+    /// 获取当前有效的恢复公钥，没有设置恢复密钥时返回None
+    pub(crate) async fn get_active_recovery_key(&self) -> Result<Option<RecoveryPublicKey>, ResourceServiceError> {
+        let record = query!("SELECT id, public_key FROM recovery_keys WHERE revoked_at IS NULL")
+            .fetch_optional(&self.db)
+            .await
+            .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        
+        record
+            .map(|record| RecoveryPublicKey::from_base64(record.id, &record.public_key))
+            .transpose()
+            .map_err(|e| ResourceServiceError::OtherError(e.into()))
+    }
+    
     /// 获取加密密钥
     #[allow(dead_code)]
     async fn get_encryption_key(
@@ -546,3 +1224,235 @@ impl ResourceService {
         Ok(encryption_key)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use crate::service::ukey::UKeyService;
+    use crate::service::ukey::tests::{create_resource, enroll, respond, test_resource_service};
+    use crate::ukey::challenge::tests::test_key_pair;
+    
+    #[sqlx::test]
+    async fn test_upload_and_download_stream(db: DatabasePool) {
+        let mut config = test_resource_service(db.clone()).config.clone();
+        // 多个分段和多个存储分块，最后一段和最后一块都不满
+        config.encryption.chunk_size = 64;
+        config.upload.storage_chunk_size = 4096;
+        let resource_service = Arc::new(ResourceService::new(db.clone(), config));
+        let service = UKeyService::new(resource_service.clone());
+        let key_pair = test_key_pair();
+        enroll(&service, &key_pair, "UK-0001").await;
+        
+        let media_data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+        let request = CreateResourceRequest {
+            title: "title".to_string(),
+            title_en: None,
+            description: "description".to_string(),
+            resource_type: "video".to_string(),
+            media_data: Vec::new(),
+            media_type: "video/mp4".to_string(),
+            is_local: true,
+            encryption_info: String::new(),
+            status: None,
+        };
+        let assertion = HardwareAssertion::UKey(respond(&service, &key_pair, "UK-0001", "part-b").await);
+        let resource = resource_service.create_resource_stream(request, &mut media_data.as_slice(), "key-part-a", &assertion)
+            .await
+            .unwrap();
+        
+        // 容器只保存在分块表中
+        let chunk_lens = query_scalar!(
+            r#"SELECT length(data) AS "len!" FROM resource_chunks WHERE resource_id = $1 ORDER BY chunk_index"#,
+            resource.id
+        )
+        .fetch_all(&db)
+        .await
+        .unwrap();
+        assert!(chunk_lens.len() > 2, "{:?}", chunk_lens);
+        assert!(chunk_lens[..chunk_lens.len() - 1].iter().all(|len| *len == 4096));
+        assert!(resource.media_data.is_empty());
+        
+        let assertion = HardwareAssertion::UKey(respond(&service, &key_pair, "UK-0001", "part-b").await);
+        let download = resource_service.prepare_download(resource.id, "key-part-a", &assertion).await.unwrap();
+        // 验证密钥只读取第一个分块
+        assert_eq!(download.resource.media_data.len(), 4096);
+        let mut downloaded = Vec::new();
+        assert_eq!(resource_service.write_download(&download, &mut downloaded).await.unwrap(), media_data.len() as u64);
+        assert_eq!(downloaded, media_data);
+        
+        // 流式写入的容器与一次性解密兼容
+        let assertion = HardwareAssertion::UKey(respond(&service, &key_pair, "UK-0001", "part-b").await);
+        assert_eq!(resource_service.decrypt_resource(resource.id, "key-part-a", &assertion).await.unwrap().0, media_data);
+        
+        // 密钥错误时不开始下载
+        let assertion = HardwareAssertion::UKey(respond(&service, &key_pair, "UK-0001", "part-b").await);
+        let result = resource_service.prepare_download(resource.id, "wrong-key-part-a", &assertion).await;
+        assert!(matches!(result, Err(ResourceServiceError::KeyVerificationFailed)));
+        
+        // 数据损坏时在出错的分段中断
+        query!(
+            r#"UPDATE resource_chunks SET data = set_byte(data, length(data) - 1, get_byte(data, length(data) - 1) # 1)
+            WHERE resource_id = $1 AND chunk_index = (SELECT MAX(chunk_index) FROM resource_chunks WHERE resource_id = $1)"#,
+            resource.id
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        let mut downloaded = Vec::new();
+        let result = resource_service.write_download(&download, &mut downloaded).await;
+        assert!(matches!(result, Err(ResourceServiceError::KeyVerificationFailed)), "{:?}", result);
+        assert!(downloaded.len() < media_data.len());
+    }
+    
+    #[sqlx::test]
+    async fn test_create_resource_is_atomic(db: DatabasePool) {
+        let mut config = test_resource_service(db.clone()).config.clone();
+        config.encryption.encrypt_metadata = true;
+        let resource_service = Arc::new(ResourceService::new(db.clone(), config));
+        let service = UKeyService::new(resource_service.clone());
+        let key_pair = test_key_pair();
+        enroll(&service, &key_pair, "UK-0001").await;
+        let request = || CreateResourceRequest {
+            title: "title".to_string(),
+            title_en: None,
+            description: "description".to_string(),
+            resource_type: "video".to_string(),
+            media_data: Vec::new(),
+            media_type: "video/mp4".to_string(),
+            is_local: true,
+            encryption_info: String::new(),
+            status: None,
+        };
+        
+        query("CREATE FUNCTION reject_insert() RETURNS trigger AS $$ BEGIN RAISE EXCEPTION 'rejected'; END $$ LANGUAGE plpgsql")
+            .execute(&db)
+            .await
+            .unwrap();
+        
+        // 加密密钥或盲索引写入失败时，资源记录一并回滚
+        for table in ["encryption_keys", "resource_search_index"] {
+            query(&format!("CREATE TRIGGER reject_insert BEFORE INSERT ON {} FOR EACH ROW EXECUTE FUNCTION reject_insert()", table))
+                .execute(&db)
+                .await
+                .unwrap();
+            let assertion = HardwareAssertion::UKey(respond(&service, &key_pair, "UK-0001", "part-b").await);
+            let result = resource_service.create_resource_stream(request(), &mut b"media".as_slice(), "key-part-a", &assertion).await;
+            assert!(matches!(result, Err(ResourceServiceError::DatabaseError(_))), "{:?}", result.map(|resource| resource.id));
+            query(&format!("DROP TRIGGER reject_insert ON {}", table)).execute(&db).await.unwrap();
+            
+            let resources: i64 = query_scalar("SELECT COUNT(*) FROM resources").fetch_one(&db).await.unwrap();
+            let encryption_keys: i64 = query_scalar("SELECT COUNT(*) FROM encryption_keys").fetch_one(&db).await.unwrap();
+            assert_eq!((resources, encryption_keys), (0, 0));
+        }
+        
+        let assertion = HardwareAssertion::UKey(respond(&service, &key_pair, "UK-0001", "part-b").await);
+        resource_service.create_resource_stream(request(), &mut b"media".as_slice(), "key-part-a", &assertion).await.unwrap();
+    }
+    
+    #[sqlx::test]
+    async fn test_update_resource_keeps_container(db: DatabasePool) {
+        let resource_service = test_resource_service(db.clone());
+        let service = UKeyService::new(resource_service.clone());
+        let key_pair = test_key_pair();
+        enroll(&service, &key_pair, "UK-0001").await;
+        
+        let request = CreateResourceRequest {
+            title: "title".to_string(),
+            title_en: None,
+            description: "description".to_string(),
+            resource_type: "video".to_string(),
+            media_data: Vec::new(),
+            media_type: "video/mp4".to_string(),
+            is_local: true,
+            encryption_info: String::new(),
+            status: None,
+        };
+        let assertion = HardwareAssertion::UKey(respond(&service, &key_pair, "UK-0001", "part-b").await);
+        let resource = resource_service.create_resource_stream(request, &mut b"media".as_slice(), "key-part-a", &assertion)
+            .await
+            .unwrap();
+        
+        // 读取后资源被其他流程重写（更换密钥、升级或恢复）时不覆盖
+        let stale = resource_service.get_resource_by_id(resource.id, true).await.unwrap();
+        query!("UPDATE resources SET updated_at = updated_at + INTERVAL '1 second' WHERE id = $1", resource.id)
+            .execute(&db)
+            .await
+            .unwrap();
+        let update = || UpdateResourceRequest {
+            title: Some("new title".to_string()),
+            ..Default::default()
+        };
+        let result = resource_service.write_update(stale, update()).await;
+        assert!(matches!(result, Err(ResourceServiceError::ResourceStatusError(_))), "{:?}", result);
+        assert_eq!(resource_service.get_resource_by_id(resource.id, true).await.unwrap().title, "title");
+        
+        // 正常更新只修改可编辑字段，资源仍可解密
+        let updated = resource_service.update_resource(resource.id, update()).await.unwrap();
+        assert_eq!(updated.title, "new title");
+        let assertion = HardwareAssertion::UKey(respond(&service, &key_pair, "UK-0001", "part-b").await);
+        assert_eq!(resource_service.decrypt_resource(resource.id, "key-part-a", &assertion).await.unwrap().0, b"media");
+    }
+    
+    #[sqlx::test]
+    async fn test_search_resources_by_tokens(db: DatabasePool) {
+        let plain_service = test_resource_service(db.clone());
+        let mut config = plain_service.config.clone();
+        config.encryption.encrypt_metadata = true;
+        let sealed_service = Arc::new(ResourceService::new(db, config));
+        let service = UKeyService::new(sealed_service.clone());
+        let key_pair = test_key_pair();
+        enroll(&service, &key_pair, "UK-0001").await;
+        let sealed = create_resource(&service, &key_pair, "UK-0001", "key-part-a", "part-b").await;
+        create_resource(&UKeyService::new(plain_service), &key_pair, "UK-0001", "key-part-a", "part-b").await;
+        
+        // 只按词元匹配元数据已加密的资源，同名的明文资源不参与模糊匹配
+        let search_key = SearchKey::derive("key-part-a", "part-b").unwrap();
+        let mut search_tokens = search_key.query_tokens("Title descr");
+        let (resources, _) = sealed_service.search_resources(&search_tokens, ResourceQueryParams::default()).await.unwrap();
+        assert_eq!(resources.iter().map(|resource| resource.id).collect::<Vec<_>>(), vec![sealed]);
+        
+        // 重复的词元不影响匹配
+        search_tokens.push(search_tokens[0].clone());
+        let (resources, _) = sealed_service.search_resources(&search_tokens, ResourceQueryParams::default()).await.unwrap();
+        assert_eq!(resources.len(), 1);
+        
+        // 其他密钥计算的词元匹配不到
+        let other_tokens = SearchKey::derive("other-key-part-a", "part-b").unwrap().query_tokens("title");
+        let (resources, _) = sealed_service.search_resources(&other_tokens, ResourceQueryParams::default()).await.unwrap();
+        assert!(resources.is_empty());
+        
+        for invalid in [Vec::new(), vec![b"title".to_vec()], vec![vec![0u8; SEARCH_TOKEN_LEN]; MAX_QUERY_TOKENS + 1]] {
+            assert!(matches!(
+                sealed_service.search_resources(&invalid, ResourceQueryParams::default()).await,
+                Err(ResourceServiceError::ParameterError(_))
+            ));
+        }
+    }
+    
+    #[test]
+    fn test_order_by_whitelist() {
+        let params = |sort_by: &str, sort_order: &str| ResourceQueryParams {
+            sort_by: Some(sort_by.to_string()),
+            sort_order: Some(sort_order.to_string()),
+            ..Default::default()
+        };
+        
+        assert_eq!(ResourceService::order_by(&ResourceQueryParams::default()).unwrap(), ("created_at", "DESC"));
+        assert_eq!(ResourceService::order_by(&params("title", "asc")).unwrap(), ("title", "ASC"));
+        assert_eq!(ResourceService::order_by(&params("updated_at", "DESC")).unwrap(), ("updated_at", "DESC"));
+        
+        // 注入尝试和未知字段都被拒绝
+        for (sort_by, sort_order) in [
+            ("created_at; DROP TABLE resources", "desc"),
+            ("media_data", "desc"),
+            ("id", "desc, (SELECT 1)"),
+            ("id", ""),
+        ] {
+            assert!(matches!(
+                ResourceService::order_by(&params(sort_by, sort_order)),
+                Err(ResourceServiceError::ParameterError(_))
+            ));
+        }
+    }
+}
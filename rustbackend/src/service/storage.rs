@@ -0,0 +1,129 @@
+use std::io::Cursor;
+
+use futures_util::{Stream, TryStreamExt};
+use sqlx::{query, query_scalar, PgExecutor, Postgres, Transaction};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::crypto::manifest::ContainerHasher;
+use crate::database::DatabaseError;
+use crate::service::resource::ResourceServiceError;
+
+/// 将sqlx错误转换为资源服务错误
+fn db_error(e: sqlx::Error) -> ResourceServiceError {
+    ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e))
+}
+
+/// 在调用方的事务中从 `reader` 读取加密容器，按 `chunk_size` 分块写入，替换资源原有的容器，返回容器长度
+///
+/// 每次只在内存中保留一个分块，`reader` 可以是边加密边输出的管道。
+pub(crate) async fn write_container<R>(
+    transaction: &mut Transaction<'_, Postgres>,
+    id: i32,
+    reader: &mut R,
+    chunk_size: usize
+) -> Result<u64, ResourceServiceError>
+where
+    R: AsyncRead + Unpin,
+{
+    query!("DELETE FROM resource_chunks WHERE resource_id = $1", id)
+        .execute(&mut **transaction)
+        .await
+        .map_err(db_error)?;
+    
+    let mut chunk = Vec::with_capacity(chunk_size);
+    let mut chunk_index = 0;
+    let mut written = 0;
+    loop {
+        chunk.clear();
+        (&mut *reader).take(chunk_size as u64)
+            .read_to_end(&mut chunk)
+            .await
+            .map_err(|e| ResourceServiceError::OtherError(anyhow::anyhow!("读取加密容器失败: {}", e)))?;
+        if chunk.is_empty() {
+            break;
+        }
+        
+        query!(
+            "INSERT INTO resource_chunks (resource_id, chunk_index, data) VALUES ($1, $2, $3)",
+            id,
+            chunk_index,
+            chunk
+        )
+        .execute(&mut **transaction)
+        .await
+        .map_err(db_error)?;
+        chunk_index += 1;
+        written += chunk.len() as u64;
+        
+        if chunk.len() < chunk_size {
+            break;
+        }
+    }
+    
+    Ok(written)
+}
+
+/// 按顺序逐块读取资源的加密容器
+pub(crate) fn container_chunks<'e, E>(executor: E, id: i32) -> impl Stream<Item = Result<Cursor<Vec<u8>>, sqlx::Error>> + 'e
+where
+    E: PgExecutor<'e> + 'e,
+{
+    query_scalar!("SELECT data FROM resource_chunks WHERE resource_id = $1 ORDER BY chunk_index", id)
+        .fetch(executor)
+        .map_ok(Cursor::new)
+}
+
+/// 读取资源的完整加密容器，用于需要整体处理容器的更换密钥、升级和恢复
+pub(crate) async fn read_container<'e, E>(executor: E, id: i32) -> Result<Vec<u8>, ResourceServiceError>
+where
+    E: PgExecutor<'e> + 'e,
+{
+    container_chunks(executor, id)
+        .try_fold(Vec::new(), |mut container, chunk| async move {
+            container.extend_from_slice(chunk.get_ref());
+            Ok(container)
+        })
+        .await
+        .map_err(db_error)
+}
+
+/// 读取资源的第一个分块，其中包含完整的容器头部
+///
+/// 存储分块不小于 `MAX_HEADER_PROBE_LEN`，验证密钥和检查加密参数时只需要这一块。
+pub(crate) async fn read_first_chunk<'e, E>(executor: E, id: i32) -> Result<Vec<u8>, ResourceServiceError>
+where
+    E: PgExecutor<'e>,
+{
+    let chunk = query_scalar!("SELECT data FROM resource_chunks WHERE resource_id = $1 AND chunk_index = 0", id)
+        .fetch_optional(executor)
+        .await
+        .map_err(db_error)?;
+    
+    Ok(chunk.unwrap_or_default())
+}
+
+/// 逐块计算资源当前容器的清单哈希，内存中只保留一个分块
+pub(crate) async fn container_hash<'e, E>(
+    executor: E,
+    id: i32,
+    encrypted_metadata: Option<&[u8]>
+) -> Result<Vec<u8>, ResourceServiceError>
+where
+    E: PgExecutor<'e> + 'e,
+{
+    // 容器长度在开头参与哈希，每一行都带上总长度
+    let mut chunks = query!(
+        r#"SELECT data, SUM(length(data)) OVER () AS "container_len!"
+        FROM resource_chunks WHERE resource_id = $1 ORDER BY chunk_index"#,
+        id
+    )
+    .fetch(executor);
+    
+    let mut hasher = None;
+    while let Some(chunk) = chunks.try_next().await.map_err(db_error)? {
+        hasher.get_or_insert_with(|| ContainerHasher::new(chunk.container_len as u64))
+            .update(&chunk.data);
+    }
+    
+    Ok(hasher.unwrap_or_else(|| ContainerHasher::new(0)).finish(encrypted_metadata))
+}
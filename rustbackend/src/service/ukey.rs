@@ -0,0 +1,676 @@
+use std::sync::Arc;
+
+use base64::{Engine as _, engine::general_purpose};
+use ring::rand::{SecureRandom, SystemRandom};
+use sqlx::{query, query_as, query_scalar};
+use tracing::{info, warn};
+
+use crate::config::{AppConfig, Fido2Config};
+use crate::crypto::key_management::constant_time_eq;
+use crate::crypto::SecretString;
+use crate::database::{DatabaseError, DatabasePool};
+use crate::database::models::ukey::{UKey, UKeyAuthorization};
+use crate::service::resource::{ResourceService, ResourceServiceError};
+use crate::ukey::challenge::{self, CHALLENGE_NONCE_LEN};
+use crate::ukey::fido2;
+use crate::ukey::{
+    Fido2Assertion, Fido2Registration, HardwareAssertion, UKeyAssertion, UKeyChallenge,
+    VENDOR_DEFAULT, VENDOR_FIDO2, VENDOR_HTTP, VENDOR_PKCS11
+};
+
+/// 将sqlx错误转换为资源服务错误
+fn db_error(e: sqlx::Error) -> ResourceServiceError {
+    ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e))
+}
+
+/// 消耗挑战，返回挑战的随机数
+///
+/// 挑战在同一条语句中检查有效期并标记为已使用，并发提交同一个应答时只有一个能通过。
+/// 后续验证失败时挑战同样作废，需要重新申请。
+async fn take_challenge(db: &DatabasePool, challenge_id: uuid::Uuid) -> Result<Vec<u8>, ResourceServiceError> {
+    let now = chrono::Utc::now().naive_utc();
+    query_scalar!(
+        "UPDATE ukey_challenges SET used_at = $2 WHERE id = $1 AND used_at IS NULL AND expires_at > $2 RETURNING nonce",
+        challenge_id,
+        now
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| ResourceServiceError::UKeyVerificationFailed("挑战不存在、已过期或已使用".to_string()))
+}
+
+/// 消耗挑战并验证应答的签名，不检查UKey是否登记
+async fn consume_challenge(db: &DatabasePool, assertion: &UKeyAssertion) -> Result<(), ResourceServiceError> {
+    let nonce = take_challenge(db, assertion.challenge_id).await?;
+    
+    challenge::verify_assertion(assertion, &nonce).map_err(|e| {
+        warn!("UKey应答验证失败, 序列号: {}, 错误: {}", assertion.serial, e);
+        ResourceServiceError::UKeyVerificationFailed(e.to_string())
+    })
+}
+
+/// 消耗挑战并验证UKey的应答，返回应答的UKey
+///
+/// 签名有效之外，UKey的公钥必须已经登记、未被撤销，且序列号与登记时一致。
+pub(crate) async fn verify_assertion(db: &DatabasePool, assertion: &UKeyAssertion) -> Result<UKey, ResourceServiceError> {
+    consume_challenge(db, assertion).await?;
+    
+    // 签名已验证，公钥格式有效
+    let public_key = challenge::parse_public_key(&assertion.public_key)
+        .map_err(|e| ResourceServiceError::UKeyVerificationFailed(e.to_string()))?;
+    let fingerprint = challenge::fingerprint(&public_key);
+    let ukey = query_as!(UKey, "SELECT * FROM ukeys WHERE fingerprint = $1", fingerprint)
+        .fetch_optional(db)
+        .await
+        .map_err(db_error)?;
+    
+    let ukey = match ukey {
+        Some(ukey) if ukey.revoked_at.is_some() => Err("UKey已撤销"),
+        Some(ukey) if ukey.vendor == VENDOR_FIDO2 => Err("FIDO2安全密钥只接受WebAuthn断言"),
+        Some(ukey) if ukey.serial != assertion.serial => Err("UKey序列号与登记时不一致"),
+        Some(ukey) => Ok(ukey),
+        None => Err("UKey未登记"),
+    };
+    ukey.map_err(|reason| {
+        warn!("拒绝UKey应答, 序列号: {}, 指纹: {}, 原因: {}", assertion.serial, fingerprint, reason);
+        ResourceServiceError::UKeyVerificationFailed(reason.to_string())
+    })
+}
+
+/// 消耗挑战并验证FIDO2安全密钥的断言，返回安全密钥和由PRF输出得到的硬件码B
+///
+/// 凭据必须已经登记且未被撤销。签名计数器在同一条语句中检查并更新，计数器不增加时拒绝断言。
+pub(crate) async fn verify_fido2_assertion(
+    db: &DatabasePool,
+    config: &Fido2Config,
+    assertion: &Fido2Assertion
+) -> Result<(UKey, SecretString), ResourceServiceError> {
+    let nonce = take_challenge(db, assertion.challenge_id).await?;
+    let reject = |reason: String| {
+        warn!("拒绝FIDO2断言, 凭据: {}, 原因: {}", assertion.credential_id, reason);
+        ResourceServiceError::UKeyVerificationFailed(reason)
+    };
+    
+    let ukey = query_as!(UKey, "SELECT * FROM ukeys WHERE vendor = $1 AND serial = $2", VENDOR_FIDO2, assertion.credential_id.trim())
+        .fetch_optional(db)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| reject("安全密钥未登记".to_string()))?;
+    if ukey.revoked_at.is_some() {
+        return Err(reject("安全密钥已撤销".to_string()));
+    }
+    
+    let public_key = challenge::parse_public_key(&ukey.public_key).map_err(|e| reject(e.to_string()))?;
+    let sign_count = fido2::verify_assertion(config, &nonce, &public_key, assertion).map_err(|e| reject(e.to_string()))?;
+    fido2::check_sign_count(ukey.sign_count as u32, sign_count).map_err(|e| reject(e.to_string()))?;
+    let part_b = fido2::part_b(assertion).map_err(|e| reject(e.to_string()))?;
+    
+    // 并发提交的断言可能已经更新了计数器，更新时再检查一次
+    let ukey = query_as!(UKey,
+        "UPDATE ukeys SET sign_count = $2 WHERE id = $1 AND (sign_count < $2 OR (sign_count = 0 AND $2 = 0)) RETURNING *",
+        ukey.id,
+        sign_count as i64
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| reject("签名计数器回退，安全密钥可能被克隆".to_string()))?;
+    
+    Ok((ukey, part_b))
+}
+
+/// 验证硬件应答，返回应答的UKey或安全密钥和硬件码B
+pub(crate) async fn verify_hardware_assertion(
+    db: &DatabasePool,
+    config: &AppConfig,
+    assertion: &HardwareAssertion
+) -> Result<(UKey, SecretString), ResourceServiceError> {
+    match assertion {
+        HardwareAssertion::UKey(assertion) => {
+            let ukey = verify_assertion(db, assertion).await?;
+            Ok((ukey, assertion.part_b.clone()))
+        }
+        HardwareAssertion::Fido2(assertion) => verify_fido2_assertion(db, &config.fido2, assertion).await,
+    }
+}
+
+/// 登记或撤销硬件的凭据
+#[derive(Debug)]
+pub enum EnrollmentCredential<'a> {
+    /// `X-UKey-Admin-Token` 请求头中的管理令牌，与 `UKEY_ADMIN_TOKEN` 比较
+    AdminToken(&'a str),
+    /// 当前的密钥A和已登记硬件的应答
+    Hardware(&'a UKeyAuthorization),
+}
+
+/// 检查UKey驱动名称，只接受服务器端驱动，`default` 视为 `http`
+fn check_vendor(vendor: &str) -> Result<&'static str, ResourceServiceError> {
+    match vendor.trim().to_lowercase().as_str() {
+        VENDOR_HTTP | VENDOR_DEFAULT => Ok(VENDOR_HTTP),
+        VENDOR_PKCS11 => Ok(VENDOR_PKCS11),
+        VENDOR_FIDO2 => Err(ResourceServiceError::ParameterError("FIDO2安全密钥需要通过WebAuthn注册".to_string())),
+        other => Err(ResourceServiceError::ParameterError(format!("不支持的UKey驱动: {}", other))),
+    }
+}
+
+/// UKey挑战和登记服务
+///
+/// 硬件码B只通过挑战应答接受：服务器签发一次性随机数，UKey用不可导出的私钥对随机数和硬件码B签名，
+/// 截获的请求无法重放。挑战的有效期由 `UKEY_CHALLENGE_TTL_SECS` 配置。
+/// 只有登记过且未撤销的UKey的应答才被接受，UKey丢失后撤销登记即可停用。
+/// 登记和撤销需要管理令牌，或者当前的密钥A加上一个已登记硬件的应答，见 `authorize`。
+/// FIDO2安全密钥使用同一张挑战表和登记表，挑战作为WebAuthn的challenge，硬件码B取自PRF扩展。
+pub struct UKeyService {
+    resource_service: Arc<ResourceService>,
+}
+
+impl UKeyService {
+    /// 创建UKey挑战服务实例
+    pub fn new(resource_service: Arc<ResourceService>) -> Self {
+        Self { resource_service }
+    }
+    
+    /// 签发一次性挑战，同时清理已过期的挑战
+    pub async fn issue_challenge(&self) -> Result<UKeyChallenge, ResourceServiceError> {
+        let mut nonce = vec![0u8; CHALLENGE_NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_| ResourceServiceError::OtherError(anyhow::anyhow!("生成挑战随机数失败")))?;
+        
+        let now = chrono::Utc::now().naive_utc();
+        let challenge = UKeyChallenge {
+            id: uuid::Uuid::new_v4(),
+            nonce,
+            expires_at: now + chrono::Duration::seconds(self.resource_service.config.ukey.challenge_ttl_secs as i64),
+        };
+        
+        let db = &self.resource_service.db;
+        let expired = query!("DELETE FROM ukey_challenges WHERE expires_at <= $1", now)
+            .execute(db)
+            .await
+            .map_err(db_error)?
+            .rows_affected();
+        if expired > 0 {
+            info!("清理过期的UKey挑战: {}", expired);
+        }
+        
+        query!(
+            "INSERT INTO ukey_challenges (id, nonce, created_at, expires_at) VALUES ($1, $2, $3, $4)",
+            challenge.id,
+            challenge.nonce,
+            now,
+            challenge.expires_at
+        )
+        .execute(db)
+        .await
+        .map_err(db_error)?;
+        
+        Ok(challenge)
+    }
+    
+    /// 验证硬件应答，返回应答的UKey或安全密钥和硬件码B
+    ///
+    /// 只接受已登记且未撤销的硬件对有效挑战的应答，挑战验证后即作废。
+    pub async fn verify(&self, assertion: &HardwareAssertion) -> Result<(UKey, SecretString), ResourceServiceError> {
+        verify_hardware_assertion(&self.resource_service.db, &self.resource_service.config, assertion).await
+    }
+    
+    /// 检查登记或撤销硬件的凭据
+    ///
+    /// 管理令牌需要与 `UKEY_ADMIN_TOKEN` 一致；硬件授权需要已登记且未撤销的硬件的应答，
+    /// 且应答得到的硬件码B和密钥A能解开现有资源。登记表为空或还没有可以验证密钥的资源时（例如新部署），
+    /// 硬件授权无法证明持有当前密钥，只接受管理令牌。没有凭据、令牌错误或无法验证时返回 `PermissionError`。
+    pub async fn authorize(&self, credential: Option<EnrollmentCredential<'_>>) -> Result<(), ResourceServiceError> {
+        match credential {
+            Some(EnrollmentCredential::AdminToken(token)) => {
+                let admin_token = self.resource_service.config.ukey.admin_token.as_ref()
+                    .ok_or_else(|| ResourceServiceError::PermissionError("未配置管理令牌".to_string()))?;
+                if !constant_time_eq(token.as_bytes(), admin_token.expose_secret().as_bytes()) {
+                    warn!("UKey管理令牌错误");
+                    return Err(ResourceServiceError::PermissionError("管理令牌错误".to_string()));
+                }
+            }
+            Some(EnrollmentCredential::Hardware(authorization)) => {
+                let (ukey, ukey_part_b) = self.verify(&authorization.hardware_assertion).await?;
+                if !self.resource_service.verify_current_key(authorization.key_part_a.expose_secret(), ukey_part_b.expose_secret()).await? {
+                    warn!("没有可以验证密钥的资源，拒绝硬件授权: {}", ukey.id);
+                    return Err(ResourceServiceError::PermissionError("还没有可以验证密钥的资源，需要管理令牌".to_string()));
+                }
+                info!("硬件授权通过: {}, 序列号: {}", ukey.id, ukey.serial);
+            }
+            None => {
+                return Err(ResourceServiceError::PermissionError("需要管理令牌或已登记硬件的授权".to_string()));
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// 列出所有登记的UKey，包括已撤销的
+    pub async fn list(&self) -> Result<Vec<UKey>, ResourceServiceError> {
+        query_as!(UKey, "SELECT * FROM ukeys ORDER BY id")
+            .fetch_all(&self.resource_service.db)
+            .await
+            .map_err(db_error)
+    }
+    
+    /// 登记UKey
+    ///
+    /// 先检查凭据，见 `authorize`。UKey需要应答一次有效的挑战，证明持有公钥对应的私钥，驱动只能是 `http` 或 `pkcs11`。
+    /// 同一公钥只能登记一次，撤销后也不能重新登记。
+    pub async fn enroll(
+        &self,
+        owner: &str,
+        vendor: &str,
+        assertion: &UKeyAssertion,
+        credential: Option<EnrollmentCredential<'_>>
+    ) -> Result<UKey, ResourceServiceError> {
+        info!("登记UKey, 序列号: {}, 驱动: {}, 持有人: {}", assertion.serial, vendor, owner);
+        
+        if owner.trim().is_empty() {
+            return Err(ResourceServiceError::ParameterError("持有人不能为空".to_string()));
+        }
+        if assertion.serial.trim().is_empty() {
+            return Err(ResourceServiceError::ParameterError("UKey序列号不能为空".to_string()));
+        }
+        let vendor = check_vendor(vendor)?;
+        self.authorize(credential).await?;
+        
+        let db = &self.resource_service.db;
+        consume_challenge(db, assertion).await?;
+        let public_key = challenge::parse_public_key(&assertion.public_key)
+            .map_err(|e| ResourceServiceError::UKeyVerificationFailed(e.to_string()))?;
+        
+        let ukey = query_as!(UKey,
+            r#"INSERT INTO ukeys (serial, vendor, owner, public_key, fingerprint, enrolled_at) VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (fingerprint) DO NOTHING RETURNING *"#,
+            assertion.serial,
+            vendor,
+            owner.trim(),
+            assertion.public_key.trim(),
+            challenge::fingerprint(&public_key),
+            chrono::Utc::now().naive_utc()
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(db_error)?
+        .ok_or(ResourceServiceError::ResourceExists)?;
+        
+        info!("UKey已登记: {}, 指纹: {}", ukey.id, ukey.fingerprint);
+        
+        Ok(ukey)
+    }
+    
+    /// 列出所有FIDO2安全密钥，包括已撤销的，注册时用于排除已登记的凭据
+    pub async fn fido2_credentials(&self) -> Result<Vec<UKey>, ResourceServiceError> {
+        query_as!(UKey, "SELECT * FROM ukeys WHERE vendor = $1 ORDER BY id", VENDOR_FIDO2)
+            .fetch_all(&self.resource_service.db)
+            .await
+            .map_err(db_error)
+    }
+    
+    /// 查找加密资源时使用的FIDO2安全密钥，资源不存在时返回 `ResourceNotFound`
+    ///
+    /// 资源由UKey加密或安全密钥已撤销时返回None。
+    pub async fn resource_fido2_credential(&self, resource_id: i32) -> Result<Option<UKey>, ResourceServiceError> {
+        let fingerprint = query_scalar!("SELECT ukey_info FROM encryption_keys WHERE resource_id = $1", resource_id)
+            .fetch_optional(&self.resource_service.db)
+            .await
+            .map_err(db_error)?
+            .ok_or(ResourceServiceError::ResourceNotFound)?;
+        
+        query_as!(UKey,
+            "SELECT * FROM ukeys WHERE fingerprint = $1 AND vendor = $2 AND revoked_at IS NULL",
+            fingerprint,
+            VENDOR_FIDO2
+        )
+        .fetch_optional(&self.resource_service.db)
+        .await
+        .map_err(db_error)
+    }
+    
+    /// 按凭据ID查找未撤销的FIDO2安全密钥
+    pub async fn fido2_credential(&self, credential_id: &str) -> Result<Option<UKey>, ResourceServiceError> {
+        query_as!(UKey,
+            "SELECT * FROM ukeys WHERE serial = $1 AND vendor = $2 AND revoked_at IS NULL",
+            credential_id.trim(),
+            VENDOR_FIDO2
+        )
+        .fetch_optional(&self.resource_service.db)
+        .await
+        .map_err(db_error)
+    }
+    
+    /// 登记FIDO2安全密钥
+    ///
+    /// 与UKey一样先检查凭据，见 `authorize`。注册结果需要对应一次有效的挑战，且安全密钥支持hmac-secret/PRF扩展。
+    /// 同一凭据只能登记一次。
+    pub async fn enroll_fido2(
+        &self,
+        owner: &str,
+        registration: &Fido2Registration,
+        credential: Option<EnrollmentCredential<'_>>
+    ) -> Result<UKey, ResourceServiceError> {
+        info!("登记FIDO2安全密钥, 持有人: {}", owner);
+        
+        if owner.trim().is_empty() {
+            return Err(ResourceServiceError::ParameterError("持有人不能为空".to_string()));
+        }
+        self.authorize(credential).await?;
+        
+        let db = &self.resource_service.db;
+        let nonce = take_challenge(db, registration.challenge_id).await?;
+        let credential = fido2::verify_registration(&self.resource_service.config.fido2, &nonce, registration).map_err(|e| {
+            warn!("FIDO2注册验证失败, 持有人: {}, 错误: {}", owner, e);
+            ResourceServiceError::UKeyVerificationFailed(e.to_string())
+        })?;
+        
+        // 凭据ID和公钥指纹都不能重复
+        let ukey = query_as!(UKey,
+            r#"INSERT INTO ukeys (serial, vendor, owner, public_key, fingerprint, enrolled_at, sign_count) VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT DO NOTHING RETURNING *"#,
+            credential.credential_id,
+            VENDOR_FIDO2,
+            owner.trim(),
+            general_purpose::STANDARD.encode(&credential.public_key),
+            challenge::fingerprint(&credential.public_key),
+            chrono::Utc::now().naive_utc(),
+            credential.sign_count as i64
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(db_error)?
+        .ok_or(ResourceServiceError::ResourceExists)?;
+        
+        info!("FIDO2安全密钥已登记: {}, 凭据: {}", ukey.id, ukey.serial);
+        
+        Ok(ukey)
+    }
+    
+    /// 撤销UKey，已撤销的UKey保持原撤销时间
+    ///
+    /// 与登记一样先检查凭据，见 `authorize`。
+    pub async fn revoke(&self, id: i32, credential: Option<EnrollmentCredential<'_>>) -> Result<UKey, ResourceServiceError> {
+        info!("撤销UKey: {}", id);
+        
+        self.authorize(credential).await?;
+        
+        let ukey = query_as!(UKey,
+            "UPDATE ukeys SET revoked_at = COALESCE(revoked_at, $2) WHERE id = $1 RETURNING *",
+            id,
+            chrono::Utc::now().naive_utc()
+        )
+        .fetch_optional(&self.resource_service.db)
+        .await
+        .map_err(db_error)?
+        .ok_or(ResourceServiceError::ResourceNotFound)?;
+        
+        info!("UKey已撤销: {}, 序列号: {}", ukey.id, ukey.serial);
+        
+        Ok(ukey)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ring::signature::EcdsaKeyPair;
+    
+    use crate::crypto::decode::tests::test_config;
+    use crate::database::models::CreateResourceRequest;
+    use crate::ukey::challenge::tests::{test_assertion, test_key_pair};
+    use crate::ukey::fido2::tests::SoftAuthenticator;
+    
+    /// 测试用的管理令牌
+    pub(crate) const ADMIN_TOKEN: &str = "test-admin-token-0123456789abcdef";
+    
+    /// 使用测试数据库和管理令牌的资源服务
+    pub(crate) fn test_resource_service(db: DatabasePool) -> Arc<ResourceService> {
+        let mut config = test_config();
+        config.ukey.admin_token = Some(SecretString::from(ADMIN_TOKEN));
+        Arc::new(ResourceService::new(db, config))
+    }
+    
+    /// 使用测试数据库和管理令牌的UKey服务
+    pub(crate) fn test_service(db: DatabasePool) -> UKeyService {
+        UKeyService::new(test_resource_service(db))
+    }
+    
+    /// 签发挑战并用软件密钥应答
+    pub(crate) async fn respond(service: &UKeyService, key_pair: &EcdsaKeyPair, serial: &str, part_b: &str) -> UKeyAssertion {
+        let challenge = service.issue_challenge().await.unwrap();
+        test_assertion(key_pair, &challenge, serial, part_b)
+    }
+    
+    /// 用管理令牌登记软件密钥
+    pub(crate) async fn enroll(service: &UKeyService, key_pair: &EcdsaKeyPair, serial: &str) -> UKey {
+        let assertion = respond(service, key_pair, serial, "enroll-part-b").await;
+        service.enroll("tester", VENDOR_HTTP, &assertion, Some(EnrollmentCredential::AdminToken(ADMIN_TOKEN))).await.unwrap()
+    }
+    
+    /// 用软件密钥应答创建资源
+    pub(crate) async fn create_resource(service: &UKeyService, key_pair: &EcdsaKeyPair, serial: &str, key_part_a: &str, part_b: &str) -> i32 {
+        let assertion = respond(service, key_pair, serial, part_b).await;
+        let request = CreateResourceRequest {
+            title: "title".to_string(),
+            title_en: None,
+            description: "description".to_string(),
+            resource_type: "image".to_string(),
+            media_data: vec![1, 2, 3],
+            media_type: "image/png".to_string(),
+            is_local: true,
+            encryption_info: String::new(),
+            status: None,
+        };
+        service.resource_service.create_resource(request, key_part_a, &HardwareAssertion::UKey(assertion)).await.unwrap().id
+    }
+    
+    fn rejected(result: Result<UKey, ResourceServiceError>) -> String {
+        match result {
+            Err(ResourceServiceError::UKeyVerificationFailed(reason)) => reason,
+            other => panic!("应答应被拒绝: {:?}", other),
+        }
+    }
+    
+    #[sqlx::test]
+    async fn test_verify_assertion_registry(db: DatabasePool) {
+        let service = test_service(db.clone());
+        let key_pair = test_key_pair();
+        let ukey = enroll(&service, &key_pair, "UK-0001").await;
+        
+        let assertion = respond(&service, &key_pair, "UK-0001", "part-b").await;
+        assert_eq!(verify_assertion(&db, &assertion).await.unwrap().id, ukey.id);
+        // 挑战只能使用一次
+        assert!(rejected(verify_assertion(&db, &assertion).await).contains("挑战"));
+        
+        // 未登记的公钥
+        let assertion = respond(&service, &test_key_pair(), "UK-0001", "part-b").await;
+        assert_eq!(rejected(verify_assertion(&db, &assertion).await), "UKey未登记");
+        
+        // 序列号与登记时不一致
+        let assertion = respond(&service, &key_pair, "UK-0002", "part-b").await;
+        assert_eq!(rejected(verify_assertion(&db, &assertion).await), "UKey序列号与登记时不一致");
+        
+        // 登记为FIDO2安全密钥的公钥不接受UKey应答
+        let fido2_key_pair = test_key_pair();
+        let public_key = ring::signature::KeyPair::public_key(&fido2_key_pair).as_ref().to_vec();
+        query!(
+            "INSERT INTO ukeys (serial, vendor, owner, public_key, fingerprint, enrolled_at) VALUES ($1, $2, $3, $4, $5, $6)",
+            "credential-id",
+            VENDOR_FIDO2,
+            "tester",
+            general_purpose::STANDARD.encode(&public_key),
+            challenge::fingerprint(&public_key),
+            chrono::Utc::now().naive_utc()
+        )
+        .execute(&db)
+        .await
+        .unwrap();
+        let assertion = respond(&service, &fido2_key_pair, "credential-id", "part-b").await;
+        assert_eq!(rejected(verify_assertion(&db, &assertion).await), "FIDO2安全密钥只接受WebAuthn断言");
+        
+        // 撤销后不再接受
+        service.revoke(ukey.id, Some(EnrollmentCredential::AdminToken(ADMIN_TOKEN))).await.unwrap();
+        let assertion = respond(&service, &key_pair, "UK-0001", "part-b").await;
+        assert_eq!(rejected(verify_assertion(&db, &assertion).await), "UKey已撤销");
+    }
+    
+    #[sqlx::test]
+    async fn test_enroll_and_revoke_require_authorization(db: DatabasePool) {
+        let service = test_service(db.clone());
+        let key_pair = test_key_pair();
+        let ukey = enroll(&service, &key_pair, "UK-0001").await;
+        
+        let candidate = test_key_pair();
+        let permission_denied = |result: Result<UKey, ResourceServiceError>| {
+            assert!(matches!(result, Err(ResourceServiceError::PermissionError(_))), "{:?}", result);
+        };
+        
+        // 还没有资源时无法验证密钥A，硬件授权不被接受
+        let authorization = UKeyAuthorization {
+            key_part_a: SecretString::from("any-key-part-a"),
+            hardware_assertion: HardwareAssertion::UKey(respond(&service, &key_pair, "UK-0001", "part-b").await),
+        };
+        let assertion = respond(&service, &candidate, "UK-0002", "part-b-2").await;
+        permission_denied(service.enroll("mallory", VENDOR_HTTP, &assertion, Some(EnrollmentCredential::Hardware(&authorization))).await);
+        create_resource(&service, &key_pair, "UK-0001", "key-part-a", "part-b").await;
+        
+        // 没有凭据或管理令牌错误
+        let assertion = respond(&service, &candidate, "UK-0002", "part-b-2").await;
+        permission_denied(service.enroll("mallory", VENDOR_HTTP, &assertion, None).await);
+        let assertion = respond(&service, &candidate, "UK-0002", "part-b-2").await;
+        permission_denied(service.enroll("mallory", VENDOR_HTTP, &assertion, Some(EnrollmentCredential::AdminToken("wrong"))).await);
+        permission_denied(service.revoke(ukey.id, None).await);
+        permission_denied(service.revoke(ukey.id, Some(EnrollmentCredential::AdminToken("wrong"))).await);
+        
+        // 未配置管理令牌时不接受任何令牌
+        let mut config = test_config();
+        config.ukey.admin_token = None;
+        let no_token_service = UKeyService::new(Arc::new(ResourceService::new(db.clone(), config)));
+        permission_denied(no_token_service.revoke(ukey.id, Some(EnrollmentCredential::AdminToken(""))).await);
+        
+        // 只接受服务器端驱动
+        for vendor in [VENDOR_FIDO2, "acme"] {
+            let assertion = respond(&service, &candidate, "UK-0002", "part-b-2").await;
+            let result = service.enroll("tester", vendor, &assertion, Some(EnrollmentCredential::AdminToken(ADMIN_TOKEN))).await;
+            assert!(matches!(result, Err(ResourceServiceError::ParameterError(_))), "{:?}", result);
+        }
+        
+        // 已登记UKey的授权需要正确的密钥A
+        let authorization = UKeyAuthorization {
+            key_part_a: SecretString::from("wrong-key-part-a"),
+            hardware_assertion: HardwareAssertion::UKey(respond(&service, &key_pair, "UK-0001", "part-b").await),
+        };
+        let assertion = respond(&service, &candidate, "UK-0002", "part-b-2").await;
+        let result = service.enroll("tester", VENDOR_HTTP, &assertion, Some(EnrollmentCredential::Hardware(&authorization))).await;
+        assert!(matches!(result, Err(ResourceServiceError::KeyVerificationFailed)), "{:?}", result);
+        
+        let authorization = UKeyAuthorization {
+            key_part_a: SecretString::from("key-part-a"),
+            hardware_assertion: HardwareAssertion::UKey(respond(&service, &key_pair, "UK-0001", "part-b").await),
+        };
+        let assertion = respond(&service, &candidate, "UK-0002", "part-b-2").await;
+        let enrolled = service.enroll("tester", "PKCS11", &assertion, Some(EnrollmentCredential::Hardware(&authorization))).await.unwrap();
+        assert_eq!(enrolled.vendor, VENDOR_PKCS11);
+        
+        // 未登记的UKey不能授权
+        let authorization = UKeyAuthorization {
+            key_part_a: SecretString::from("key-part-a"),
+            hardware_assertion: HardwareAssertion::UKey(respond(&service, &test_key_pair(), "UK-0003", "part-b").await),
+        };
+        let result = service.revoke(ukey.id, Some(EnrollmentCredential::Hardware(&authorization))).await;
+        assert!(matches!(result, Err(ResourceServiceError::UKeyVerificationFailed(_))), "{:?}", result);
+        
+        let ukeys = service.list().await.unwrap();
+        assert_eq!(ukeys.len(), 2);
+        assert!(ukeys.iter().all(|ukey| ukey.revoked_at.is_none()));
+    }
+    
+    /// 签发挑战并用软件安全密钥断言
+    async fn fido2_assert(service: &UKeyService, authenticator: &mut SoftAuthenticator) -> Fido2Assertion {
+        let challenge = service.issue_challenge().await.unwrap();
+        let mut assertion = authenticator.get_assertion(&service.resource_service.config.fido2, &challenge.nonce);
+        assertion.challenge_id = challenge.id;
+        assertion
+    }
+    
+    #[sqlx::test]
+    async fn test_verify_fido2_assertion(db: DatabasePool) {
+        let service = test_service(db.clone());
+        let config = service.resource_service.config.fido2.clone();
+        let mut authenticator = SoftAuthenticator::new();
+        
+        // 登记需要授权
+        let challenge = service.issue_challenge().await.unwrap();
+        let mut registration = authenticator.make_credential(&config, &challenge.nonce, true);
+        registration.challenge_id = challenge.id;
+        let result = service.enroll_fido2("tester", &registration, None).await;
+        assert!(matches!(result, Err(ResourceServiceError::PermissionError(_))), "{:?}", result);
+        let challenge = service.issue_challenge().await.unwrap();
+        let mut registration = authenticator.make_credential(&config, &challenge.nonce, true);
+        registration.challenge_id = challenge.id;
+        let ukey = service.enroll_fido2("tester", &registration, Some(EnrollmentCredential::AdminToken(ADMIN_TOKEN))).await.unwrap();
+        assert_eq!(ukey.sign_count, 0);
+        
+        // 验证后计数器写回数据库
+        let assertion = fido2_assert(&service, &mut authenticator).await;
+        let (verified, part_b) = verify_fido2_assertion(&db, &config, &assertion).await.unwrap();
+        assert_eq!(verified.id, ukey.id);
+        assert_eq!(verified.sign_count, 1);
+        assert_eq!(query_scalar!("SELECT sign_count FROM ukeys WHERE id = $1", ukey.id).fetch_one(&db).await.unwrap(), 1);
+        
+        // 挑战只能使用一次
+        assert!(rejected(verify_fido2_assertion(&db, &config, &assertion).await.map(|(ukey, _)| ukey)).contains("挑战"));
+        
+        // 计数器回退
+        let stale = fido2_assert(&service, &mut authenticator).await;
+        let assertion = fido2_assert(&service, &mut authenticator).await;
+        assert_eq!(verify_fido2_assertion(&db, &config, &assertion).await.unwrap().0.sign_count, 3);
+        assert!(rejected(verify_fido2_assertion(&db, &config, &stale).await.map(|(ukey, _)| ukey)).contains("计数器"));
+        
+        // 计数器相同的断言并发提交时只接受一个
+        let first = fido2_assert(&service, &mut authenticator).await;
+        authenticator.sign_count -= 1;
+        let second = fido2_assert(&service, &mut authenticator).await;
+        let (first, second) = tokio::join!(
+            verify_fido2_assertion(&db, &config, &first),
+            verify_fido2_assertion(&db, &config, &second)
+        );
+        assert_eq!([&first, &second].iter().filter(|result| result.is_ok()).count(), 1, "{:?} {:?}", first, second);
+        assert_eq!(query_scalar!("SELECT sign_count FROM ukeys WHERE id = $1", ukey.id).fetch_one(&db).await.unwrap(), 4);
+        
+        // 同一安全密钥每次得到相同的硬件码B
+        let assertion = fido2_assert(&service, &mut authenticator).await;
+        let (_, again) = verify_fido2_assertion(&db, &config, &assertion).await.unwrap();
+        assert_eq!(again.expose_secret(), part_b.expose_secret());
+        
+        // 断言参数只给出加密资源的安全密钥
+        let assertion = fido2_assert(&service, &mut authenticator).await;
+        let request = CreateResourceRequest {
+            title: "title".to_string(),
+            title_en: None,
+            description: "description".to_string(),
+            resource_type: "image".to_string(),
+            media_data: vec![1, 2, 3],
+            media_type: "image/png".to_string(),
+            is_local: true,
+            encryption_info: String::new(),
+            status: None,
+        };
+        let resource = service.resource_service.create_resource(request, "key-part-a", &HardwareAssertion::Fido2(assertion)).await.unwrap();
+        assert_eq!(service.resource_fido2_credential(resource.id).await.unwrap().unwrap().id, ukey.id);
+        let key_pair = test_key_pair();
+        enroll(&service, &key_pair, "UK-0001").await;
+        let other = create_resource(&service, &key_pair, "UK-0001", "key-part-a", "part-b").await;
+        assert!(service.resource_fido2_credential(other).await.unwrap().is_none());
+        assert!(matches!(service.resource_fido2_credential(-1).await, Err(ResourceServiceError::ResourceNotFound)));
+        
+        // 撤销后不再接受，也不再出现在断言参数中
+        service.revoke(ukey.id, Some(EnrollmentCredential::AdminToken(ADMIN_TOKEN))).await.unwrap();
+        let assertion = fido2_assert(&service, &mut authenticator).await;
+        assert_eq!(rejected(verify_fido2_assertion(&db, &config, &assertion).await.map(|(ukey, _)| ukey)), "安全密钥已撤销");
+        assert!(service.resource_fido2_credential(resource.id).await.unwrap().is_none());
+        assert!(service.fido2_credential(&ukey.serial).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use sqlx::query;
+use tracing::{info, warn};
+
+use crate::crypto::policy::{check_upgrade, UpgradeReason, MAX_HEADER_PROBE_LEN};
+use crate::database::DatabaseError;
+use crate::database::models::upgrade::{EncryptionUpgradeStats, UpgradeSummary};
+use crate::service::resource::{ResourceService, ResourceServiceError};
+
+/// 每批检查的资源数
+const UPGRADE_BATCH_SIZE: i64 = 50;
+
+/// 只包含容器头部的资源记录
+struct ResourceHeader {
+    id: i32,
+    encryption_info: String,
+    header: Vec<u8>,
+}
+
+/// 加密参数升级服务
+///
+/// 提高 `KEY_DERIVATION_ITERATIONS`、Argon2id参数或更换 `ENCRYPTION_ALGORITHM` 后，已有资源仍保留旧参数。
+/// 资源在下次成功解锁时自动升级，也可以提供密钥批量升级；统计只读取资源头部，不需要密钥。
+pub struct UpgradeService {
+    resource_service: Arc<ResourceService>,
+}
+
+impl UpgradeService {
+    /// 创建加密参数升级服务实例
+    pub fn new(resource_service: Arc<ResourceService>) -> Self {
+        Self { resource_service }
+    }
+    
+    /// 统计仍低于当前配置的资源
+    pub async fn get_stats(&self) -> Result<EncryptionUpgradeStats, ResourceServiceError> {
+        info!("统计加密参数升级情况");
+        
+        let mut stats = EncryptionUpgradeStats::default();
+        let mut last_id = 0;
+        loop {
+            let headers = self.fetch_headers(last_id).await?;
+            let Some(last) = headers.last() else {
+                break;
+            };
+            last_id = last.id;
+            
+            for resource in &headers {
+                stats.total += 1;
+                match check_upgrade(&resource.header, &resource.encryption_info, &self.resource_service.config) {
+                    Ok(None) => stats.up_to_date += 1,
+                    Ok(Some(UpgradeReason::LegacyFormat)) => stats.legacy_format += 1,
+                    Ok(Some(UpgradeReason::ContainerVersion)) => stats.container_version += 1,
+                    Ok(Some(UpgradeReason::KeyCombiner)) => stats.key_combiner += 1,
+                    Ok(Some(UpgradeReason::Algorithm)) => stats.algorithm += 1,
+                    Ok(Some(UpgradeReason::KdfParams)) => stats.kdf_params += 1,
+                    Err(e) => {
+                        warn!("检查资源加密参数失败: {}, 错误: {}", resource.id, e);
+                        stats.unreadable += 1;
+                    }
+                }
+            }
+        }
+        
+        info!("加密参数升级统计: {:?}", stats);
+        
+        Ok(stats)
+    }
+    
+    /// 用提供的密钥升级所有低于当前配置的资源，每处理完一批调用一次 `progress`
+    ///
+    /// 不属于该密钥的资源跳过，单个资源失败只计数不中断，可以重复执行直到没有待升级的资源。
+    pub async fn upgrade_all<F>(
+        &self,
+        key_part_a: &str,
+        ukey_part_b: &str,
+        mut progress: F
+    ) -> Result<UpgradeSummary, ResourceServiceError>
+    where
+        F: FnMut(&UpgradeSummary),
+    {
+        info!("批量升级资源加密参数");
+        
+        let mut summary = UpgradeSummary::default();
+        let mut last_id = 0;
+        loop {
+            let headers = self.fetch_headers(last_id).await?;
+            let Some(last) = headers.last() else {
+                break;
+            };
+            last_id = last.id;
+            
+            for resource in &headers {
+                match check_upgrade(&resource.header, &resource.encryption_info, &self.resource_service.config) {
+                    Ok(None) => summary.up_to_date += 1,
+                    Ok(Some(reason)) => self.upgrade_one(resource.id, reason, key_part_a, ukey_part_b, &mut summary).await?,
+                    Err(e) => {
+                        warn!("检查资源加密参数失败: {}, 错误: {}", resource.id, e);
+                        summary.failed += 1;
+                    }
+                }
+            }
+            
+            progress(&summary);
+        }
+        
+        info!("批量升级资源加密参数完成: {:?}", summary);
+        
+        Ok(summary)
+    }
+    
+    /// 升级单个资源并计数，只有数据库错误会中断批量升级
+    async fn upgrade_one(
+        &self,
+        id: i32,
+        reason: UpgradeReason,
+        key_part_a: &str,
+        ukey_part_b: &str,
+        summary: &mut UpgradeSummary
+    ) -> Result<(), ResourceServiceError> {
+        let resource = match self.resource_service.get_resource_with_container(id).await {
+            Ok(resource) => resource,
+            // 检查之后被删除
+            Err(ResourceServiceError::ResourceNotFound) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        
+        let result = match self.resource_service.decrypt_loaded_resource(&resource, key_part_a, ukey_part_b).await {
+            Ok((decrypted_data, metadata)) => self.resource_service.upgrade_resource(&resource, &decrypted_data, metadata.as_ref(), key_part_a, ukey_part_b).await,
+            Err(e) => Err(e),
+        };
+        
+        match result {
+            Ok(true) => {
+                info!("资源加密参数升级成功: {}, 原因: {:?}", id, reason);
+                summary.upgraded += 1;
+            }
+            Ok(false) => {
+                info!("资源已被修改，跳过加密参数升级: {}", id);
+                summary.conflicted += 1;
+            }
+            Err(ResourceServiceError::KeyVerificationFailed) => summary.skipped += 1,
+            Err(ResourceServiceError::DatabaseError(e)) => return Err(ResourceServiceError::DatabaseError(e)),
+            Err(e) => {
+                warn!("资源加密参数升级失败: {}, 错误: {}", id, e);
+                summary.failed += 1;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// 读取一批资源的加密信息和容器头部
+    async fn fetch_headers(&self, after_id: i32) -> Result<Vec<ResourceHeader>, ResourceServiceError> {
+        let records = query!(
+            r#"SELECT r.id, r.encryption_info, substring(c.data from 1 for $2) AS header
+            FROM resources r LEFT JOIN resource_chunks c ON c.resource_id = r.id AND c.chunk_index = 0
+            WHERE r.id > $1 ORDER BY r.id LIMIT $3"#,
+            after_id,
+            MAX_HEADER_PROBE_LEN,
+            UPGRADE_BATCH_SIZE
+        )
+        .fetch_all(&self.resource_service.db)
+        .await
+        .map_err(|e| ResourceServiceError::DatabaseError(DatabaseError::ConnectionError(e)))?;
+        
+        Ok(records
+            .into_iter()
+            .map(|record| ResourceHeader {
+                id: record.id,
+                encryption_info: record.encryption_info,
+                header: record.header.unwrap_or_default(),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,153 @@
+use base64::{Engine as _, engine::general_purpose};
+use ring::{digest, signature};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::UKeyError;
+use crate::crypto::SecretString;
+
+/// 挑战签名消息的域分隔标签
+const CHALLENGE_LABEL: &[u8] = b"SecretGallery/ukey-challenge/v1";
+
+/// 服务器随机数长度
+pub const CHALLENGE_NONCE_LEN: usize = 32;
+
+/// 未压缩的P-256公钥长度（0x04 ‖ X ‖ Y）
+pub const P256_PUBLIC_KEY_LEN: usize = 65;
+
+/// 服务器签发的一次性挑战
+#[derive(Debug, Clone)]
+pub struct UKeyChallenge {
+    pub id: Uuid,
+    pub nonce: Vec<u8>,
+    pub expires_at: chrono::NaiveDateTime,
+}
+
+/// UKey对挑战的应答
+///
+/// UKey用不可导出的P-256私钥对 `challenge_message` 签名（ECDSA-SHA256，r ‖ s 共64字节），
+/// 同时返回由令牌派生的固定硬件码B。签名覆盖硬件码B的哈希，硬件码B不能与其他应答拼接。
+#[derive(Deserialize, Debug)]
+pub struct UKeyAssertion {
+    /// 挑战ID
+    pub challenge_id: Uuid,
+    /// UKey序列号
+    pub serial: String,
+    /// P-256公钥，Base64编码的未压缩点
+    pub public_key: String,
+    /// 硬件码B
+    pub part_b: SecretString,
+    /// 签名，Base64编码
+    pub signature: String,
+}
+
+/// 计算UKey需要签名的消息
+///
+/// 标签 ‖ 挑战ID(16) ‖ 随机数 ‖ 序列号长度(4) ‖ 序列号 ‖ SHA-256(硬件码B)
+pub fn challenge_message(challenge_id: Uuid, nonce: &[u8], serial: &str, part_b: &str) -> Vec<u8> {
+    let mut message = Vec::with_capacity(CHALLENGE_LABEL.len() + 16 + nonce.len() + 4 + serial.len() + 32);
+    message.extend_from_slice(CHALLENGE_LABEL);
+    message.extend_from_slice(challenge_id.as_bytes());
+    message.extend_from_slice(nonce);
+    message.extend_from_slice(&(serial.len() as u32).to_be_bytes());
+    message.extend_from_slice(serial.as_bytes());
+    message.extend_from_slice(digest::digest(&digest::SHA256, part_b.as_bytes()).as_ref());
+    message
+}
+
+/// 解析Base64编码的P-256公钥
+pub fn parse_public_key(public_key: &str) -> Result<Vec<u8>, UKeyError> {
+    let public_key = general_purpose::STANDARD.decode(public_key.trim())
+        .map_err(|_| UKeyError::InvalidAssertion("公钥不是有效的Base64".to_string()))?;
+    if public_key.len() != P256_PUBLIC_KEY_LEN || public_key[0] != 0x04 {
+        return Err(UKeyError::InvalidAssertion("公钥不是未压缩的P-256点".to_string()));
+    }
+    Ok(public_key)
+}
+
+/// 计算UKey公钥的指纹：未压缩点的SHA-256，十六进制
+pub fn fingerprint(public_key: &[u8]) -> String {
+    digest::digest(&digest::SHA256, public_key)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// 验证应答的签名，`nonce` 为服务器保存的随机数
+///
+/// 只验证应答是用所给公钥针对这次挑战生成的，挑战是否有效、公钥是否可信由调用方检查。
+pub fn verify_assertion(assertion: &UKeyAssertion, nonce: &[u8]) -> Result<(), UKeyError> {
+    if assertion.part_b.expose_secret().is_empty() {
+        return Err(UKeyError::InvalidAssertion("硬件码B为空".to_string()));
+    }
+    
+    let public_key = parse_public_key(&assertion.public_key)?;
+    let signature = general_purpose::STANDARD.decode(assertion.signature.trim())
+        .map_err(|_| UKeyError::InvalidAssertion("签名不是有效的Base64".to_string()))?;
+    let message = challenge_message(assertion.challenge_id, nonce, &assertion.serial, assertion.part_b.expose_secret());
+    
+    signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, &public_key)
+        .verify(&message, &signature)
+        .map_err(|_| UKeyError::InvalidAssertion("签名验证失败".to_string()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::KeyPair;
+    
+    /// 软件模拟的UKey签名密钥
+    pub(crate) fn test_key_pair() -> signature::EcdsaKeyPair {
+        let rng = SystemRandom::new();
+        let pkcs8 = signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        signature::EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap()
+    }
+    
+    /// 用软件密钥生成应答
+    pub(crate) fn test_assertion(key_pair: &signature::EcdsaKeyPair, challenge: &UKeyChallenge, serial: &str, part_b: &str) -> UKeyAssertion {
+        let message = challenge_message(challenge.id, &challenge.nonce, serial, part_b);
+        let signature = key_pair.sign(&SystemRandom::new(), &message).unwrap();
+        UKeyAssertion {
+            challenge_id: challenge.id,
+            serial: serial.to_string(),
+            public_key: general_purpose::STANDARD.encode(key_pair.public_key().as_ref()),
+            part_b: SecretString::from(part_b),
+            signature: general_purpose::STANDARD.encode(signature.as_ref()),
+        }
+    }
+    
+    #[test]
+    fn test_verify_assertion() {
+        let key_pair = test_key_pair();
+        let challenge = UKeyChallenge {
+            id: Uuid::new_v4(),
+            nonce: vec![7u8; CHALLENGE_NONCE_LEN],
+            expires_at: chrono::Utc::now().naive_utc(),
+        };
+        let mut assertion = test_assertion(&key_pair, &challenge, "UK-0001", "hardware-code-b");
+        assert!(verify_assertion(&assertion, &challenge.nonce).is_ok());
+        
+        // 其他挑战的随机数、替换硬件码B或序列号后签名失效
+        assert!(verify_assertion(&assertion, &[8u8; CHALLENGE_NONCE_LEN]).is_err());
+        assertion.part_b = SecretString::from("other-code-b");
+        assert!(verify_assertion(&assertion, &challenge.nonce).is_err());
+        assertion.part_b = SecretString::from("hardware-code-b");
+        assertion.serial = "UK-0002".to_string();
+        assert!(verify_assertion(&assertion, &challenge.nonce).is_err());
+        
+        // 换成其他公钥后签名失效
+        let mut assertion = test_assertion(&key_pair, &challenge, "UK-0001", "hardware-code-b");
+        assertion.public_key = general_purpose::STANDARD.encode(test_key_pair().public_key().as_ref());
+        assert!(verify_assertion(&assertion, &challenge.nonce).is_err());
+        assertion.public_key = general_purpose::STANDARD.encode([4u8; 33]);
+        assert!(matches!(verify_assertion(&assertion, &challenge.nonce), Err(UKeyError::InvalidAssertion(_))));
+        
+        // 指纹只取决于公钥
+        let public_key = key_pair.public_key().as_ref();
+        assert_eq!(fingerprint(public_key).len(), 64);
+        assert_eq!(fingerprint(public_key), fingerprint(&parse_public_key(&general_purpose::STANDARD.encode(public_key)).unwrap()));
+        assert_ne!(fingerprint(public_key), fingerprint(test_key_pair().public_key().as_ref()));
+    }
+}
@@ -0,0 +1,483 @@
+use base64::{Engine as _, engine::general_purpose};
+use ciborium::value::Value;
+use ring::{digest, signature};
+use serde::Deserialize;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use super::UKeyError;
+use crate::config::Fido2Config;
+use crate::crypto::SecretString;
+
+/// PRF扩展盐值的域分隔标签，盐值为标签的SHA-256
+const PRF_SALT_LABEL: &[u8] = b"SecretGallery/fido2-prf/v1";
+
+/// PRF输出长度
+pub const PRF_OUTPUT_LEN: usize = 32;
+
+/// COSE算法ES256（ECDSA P-256 SHA-256）
+pub const COSE_ALG_ES256: i64 = -7;
+
+/// 认证器数据标志：用户在场
+const FLAG_UP: u8 = 0x01;
+/// 认证器数据标志：包含凭据数据
+const FLAG_AT: u8 = 0x40;
+/// 认证器数据标志：包含扩展数据
+const FLAG_ED: u8 = 0x80;
+
+/// 认证器数据的固定部分：rpIdHash(32) ‖ 标志(1) ‖ 签名计数器(4)
+const AUTH_DATA_MIN_LEN: usize = 37;
+
+/// PRF扩展的盐值
+///
+/// 盐值固定，同一凭据每次求值得到相同的输出，作为硬件码B。
+pub fn prf_salt() -> Vec<u8> {
+    digest::digest(&digest::SHA256, PRF_SALT_LABEL).as_ref().to_vec()
+}
+
+/// WebAuthn使用的Base64url编码，不带填充
+pub fn encode(bytes: &[u8]) -> String {
+    general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// 解码Base64url字段，允许带填充
+fn decode(value: &str, field: &str) -> Result<Vec<u8>, UKeyError> {
+    general_purpose::URL_SAFE_NO_PAD.decode(value.trim().trim_end_matches('='))
+        .map_err(|_| UKeyError::InvalidAssertion(format!("{}不是有效的Base64url", field)))
+}
+
+/// 安全密钥的注册结果
+///
+/// 字段取自 `navigator.credentials.create` 返回的 `PublicKeyCredential`，均为Base64url编码。
+#[derive(Deserialize, Debug)]
+pub struct Fido2Registration {
+    /// 挑战ID
+    pub challenge_id: Uuid,
+    /// response.clientDataJSON
+    pub client_data_json: String,
+    /// response.attestationObject
+    pub attestation_object: String,
+    /// 客户端扩展结果 prf.enabled，浏览器不一定在认证器数据中返回hmac-secret
+    pub prf_enabled: Option<bool>,
+}
+
+/// 安全密钥的断言
+///
+/// 字段取自 `navigator.credentials.get` 返回的 `PublicKeyCredential`，均为Base64url编码。
+/// PRF输出不在签名范围内，只有签名验证通过后才作为硬件码B使用。
+#[derive(Deserialize, Debug)]
+pub struct Fido2Assertion {
+    /// 挑战ID
+    pub challenge_id: Uuid,
+    /// 凭据ID，即 rawId
+    pub credential_id: String,
+    /// response.clientDataJSON
+    pub client_data_json: String,
+    /// response.authenticatorData
+    pub authenticator_data: String,
+    /// response.signature，DER编码的ECDSA签名
+    pub signature: String,
+    /// 客户端扩展结果 prf.results.first
+    pub prf_output: SecretString,
+}
+
+/// 注册验证通过的凭据
+#[derive(Debug, Clone)]
+pub struct Fido2Credential {
+    /// 凭据ID，Base64url编码
+    pub credential_id: String,
+    /// 未压缩的P-256公钥
+    pub public_key: Vec<u8>,
+    /// 注册时的签名计数器
+    pub sign_count: u32,
+}
+
+/// 客户端数据
+#[derive(Deserialize, Debug)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+/// 解析后的认证器数据
+#[derive(Debug)]
+struct AuthenticatorData {
+    sign_count: u32,
+    /// 凭据ID和公钥，只在注册时出现
+    credential: Option<(Vec<u8>, Vec<u8>)>,
+    extensions: Option<Value>,
+}
+
+/// 验证客户端数据的类型、挑战和来源
+fn verify_client_data(config: &Fido2Config, client_data_json: &[u8], kind: &str, nonce: &[u8]) -> Result<(), UKeyError> {
+    let client_data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|e| UKeyError::InvalidAssertion(format!("客户端数据格式错误: {}", e)))?;
+    
+    if client_data.kind != kind {
+        return Err(UKeyError::InvalidAssertion(format!("客户端数据类型错误: {}", client_data.kind)));
+    }
+    if decode(&client_data.challenge, "挑战")? != nonce {
+        return Err(UKeyError::InvalidAssertion("挑战不匹配".to_string()));
+    }
+    if client_data.origin != config.origin {
+        return Err(UKeyError::InvalidAssertion(format!("来源不匹配: {}", client_data.origin)));
+    }
+    Ok(())
+}
+
+/// 按整数标签查找CBOR映射中的值
+fn map_get(entries: &[(Value, Value)], label: i64) -> Option<&Value> {
+    entries.iter()
+        .find(|(key, _)| key.as_integer().map(i128::from) == Some(label as i128))
+        .map(|(_, value)| value)
+}
+
+/// 把COSE格式的EC2公钥转换为未压缩的P-256点，只接受ES256
+fn cose_public_key(key: &Value) -> Result<Vec<u8>, UKeyError> {
+    let invalid = |reason: &str| UKeyError::InvalidAssertion(format!("凭据公钥无效: {}", reason));
+    let entries = key.as_map().ok_or_else(|| invalid("不是CBOR映射"))?;
+    let integer = |label| map_get(entries, label).and_then(Value::as_integer).map(i128::from);
+    
+    // kty=2(EC2), alg=-7(ES256), crv=1(P-256)
+    if integer(1) != Some(2) || integer(3) != Some(COSE_ALG_ES256 as i128) || integer(-1) != Some(1) {
+        return Err(invalid("只支持ES256"));
+    }
+    let x = map_get(entries, -2).and_then(Value::as_bytes).ok_or_else(|| invalid("缺少x坐标"))?;
+    let y = map_get(entries, -3).and_then(Value::as_bytes).ok_or_else(|| invalid("缺少y坐标"))?;
+    if x.len() != 32 || y.len() != 32 {
+        return Err(invalid("坐标长度错误"));
+    }
+    
+    let mut public_key = Vec::with_capacity(super::challenge::P256_PUBLIC_KEY_LEN);
+    public_key.push(0x04);
+    public_key.extend_from_slice(x);
+    public_key.extend_from_slice(y);
+    Ok(public_key)
+}
+
+/// 解析认证器数据，检查依赖方ID和用户在场标志
+fn parse_authenticator_data(config: &Fido2Config, data: &[u8]) -> Result<AuthenticatorData, UKeyError> {
+    let invalid = |reason: &str| UKeyError::InvalidAssertion(format!("认证器数据无效: {}", reason));
+    if data.len() < AUTH_DATA_MIN_LEN {
+        return Err(invalid("长度不足"));
+    }
+    if data[..32] != *digest::digest(&digest::SHA256, config.rp_id.as_bytes()).as_ref() {
+        return Err(UKeyError::InvalidAssertion("依赖方ID不匹配".to_string()));
+    }
+    let flags = data[32];
+    if flags & FLAG_UP == 0 {
+        return Err(UKeyError::InvalidAssertion("用户未确认在场".to_string()));
+    }
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+    
+    // 凭据数据：AAGUID(16) ‖ 凭据ID长度(2) ‖ 凭据ID ‖ COSE公钥
+    let mut rest = &data[AUTH_DATA_MIN_LEN..];
+    let credential = if flags & FLAG_AT != 0 {
+        if rest.len() < 18 {
+            return Err(invalid("凭据数据长度不足"));
+        }
+        let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        rest = &rest[18..];
+        if rest.len() < id_len {
+            return Err(invalid("凭据ID长度不足"));
+        }
+        let (credential_id, tail) = rest.split_at(id_len);
+        rest = tail;
+        let key: Value = ciborium::de::from_reader(&mut rest).map_err(|_| invalid("凭据公钥不是有效的CBOR"))?;
+        Some((credential_id.to_vec(), cose_public_key(&key)?))
+    } else {
+        None
+    };
+    let extensions = if flags & FLAG_ED != 0 {
+        Some(ciborium::de::from_reader::<Value, _>(&mut rest).map_err(|_| invalid("扩展数据不是有效的CBOR"))?)
+    } else {
+        None
+    };
+    if !rest.is_empty() {
+        return Err(invalid("存在多余的数据"));
+    }
+    
+    Ok(AuthenticatorData { sign_count, credential, extensions })
+}
+
+/// 验证安全密钥的注册结果，`nonce` 为服务器保存的随机数
+///
+/// 安全密钥必须支持hmac-secret（WebAuthn中的PRF扩展），只接受ES256凭据。
+/// 不验证认证声明，任何厂商的安全密钥都可以登记，登记请求的授权由 `UKeyService::enroll_fido2` 检查。
+pub fn verify_registration(config: &Fido2Config, nonce: &[u8], registration: &Fido2Registration) -> Result<Fido2Credential, UKeyError> {
+    let client_data_json = decode(&registration.client_data_json, "客户端数据")?;
+    verify_client_data(config, &client_data_json, "webauthn.create", nonce)?;
+    
+    let attestation_object = decode(&registration.attestation_object, "认证对象")?;
+    let attestation: Value = ciborium::de::from_reader(attestation_object.as_slice())
+        .map_err(|_| UKeyError::InvalidAssertion("认证对象不是有效的CBOR".to_string()))?;
+    let auth_data = attestation.as_map()
+        .and_then(|entries| entries.iter().find(|(key, _)| key.as_text() == Some("authData")))
+        .and_then(|(_, value)| value.as_bytes())
+        .ok_or_else(|| UKeyError::InvalidAssertion("认证对象缺少authData".to_string()))?;
+    let auth_data = parse_authenticator_data(config, auth_data)?;
+    
+    let (credential_id, public_key) = auth_data.credential
+        .ok_or_else(|| UKeyError::InvalidAssertion("认证器数据缺少凭据".to_string()))?;
+    let hmac_secret = auth_data.extensions.as_ref()
+        .and_then(Value::as_map)
+        .and_then(|entries| entries.iter().find(|(key, _)| key.as_text() == Some("hmac-secret")))
+        .and_then(|(_, value)| value.as_bool())
+        .unwrap_or(false);
+    if !hmac_secret && registration.prf_enabled != Some(true) {
+        return Err(UKeyError::InvalidAssertion("安全密钥不支持hmac-secret/PRF扩展".to_string()));
+    }
+    
+    Ok(Fido2Credential {
+        credential_id: encode(&credential_id),
+        public_key,
+        sign_count: auth_data.sign_count,
+    })
+}
+
+/// 验证安全密钥的断言，返回断言中的签名计数器
+///
+/// 签名覆盖 authenticatorData ‖ SHA-256(clientDataJSON)，用登记时保存的公钥验证。
+/// 凭据是否登记、计数器是否回退由调用方检查。
+pub fn verify_assertion(config: &Fido2Config, nonce: &[u8], public_key: &[u8], assertion: &Fido2Assertion) -> Result<u32, UKeyError> {
+    let client_data_json = decode(&assertion.client_data_json, "客户端数据")?;
+    verify_client_data(config, &client_data_json, "webauthn.get", nonce)?;
+    
+    let auth_data = decode(&assertion.authenticator_data, "认证器数据")?;
+    let parsed = parse_authenticator_data(config, &auth_data)?;
+    let signature = decode(&assertion.signature, "签名")?;
+    
+    let mut message = auth_data;
+    message.extend_from_slice(digest::digest(&digest::SHA256, &client_data_json).as_ref());
+    signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_ASN1, public_key)
+        .verify(&message, &signature)
+        .map_err(|_| UKeyError::InvalidAssertion("签名验证失败".to_string()))?;
+    
+    Ok(parsed.sign_count)
+}
+
+/// 检查签名计数器
+///
+/// 支持计数器的安全密钥每次断言计数器都会增加，计数器不增加说明凭据可能被克隆。
+/// 不支持计数器的安全密钥始终返回0。
+pub fn check_sign_count(stored: u32, sign_count: u32) -> Result<(), UKeyError> {
+    if sign_count > stored || (stored == 0 && sign_count == 0) {
+        Ok(())
+    } else {
+        Err(UKeyError::InvalidAssertion(format!("签名计数器回退: {} -> {}，安全密钥可能被克隆", stored, sign_count)))
+    }
+}
+
+/// 从断言的PRF输出得到硬件码B：32字节PRF输出的十六进制
+pub fn part_b(assertion: &Fido2Assertion) -> Result<SecretString, UKeyError> {
+    let output = Zeroizing::new(decode(assertion.prf_output.expose_secret(), "PRF输出")?);
+    if output.len() != PRF_OUTPUT_LEN {
+        return Err(UKeyError::InvalidAssertion("PRF输出长度错误".to_string()));
+    }
+    
+    let mut part_b = String::with_capacity(output.len() * 2);
+    for byte in output.iter() {
+        part_b.push(char::from_digit((byte >> 4) as u32, 16).unwrap_or('0'));
+        part_b.push(char::from_digit((byte & 0x0f) as u32, 16).unwrap_or('0'));
+    }
+    Ok(SecretString::new(part_b))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use ring::hmac;
+    use ring::rand::{SecureRandom, SystemRandom};
+    use ring::signature::KeyPair;
+    
+    fn test_config() -> Fido2Config {
+        Fido2Config {
+            rp_id: "gallery.example".to_string(),
+            rp_name: "SecretGallery".to_string(),
+            origin: "https://gallery.example".to_string(),
+        }
+    }
+    
+    /// 软件模拟的FIDO2安全密钥，支持hmac-secret
+    pub(crate) struct SoftAuthenticator {
+        pub(crate) key_pair: signature::EcdsaKeyPair,
+        pub(crate) credential_id: Vec<u8>,
+        cred_random: [u8; 32],
+        pub(crate) sign_count: u32,
+    }
+    
+    impl SoftAuthenticator {
+        pub(crate) fn new() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 = signature::EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+            let key_pair = signature::EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+            let mut credential_id = vec![0u8; 48];
+            let mut cred_random = [0u8; 32];
+            rng.fill(&mut credential_id).unwrap();
+            rng.fill(&mut cred_random).unwrap();
+            Self { key_pair, credential_id, cred_random, sign_count: 0 }
+        }
+        
+        fn client_data(kind: &str, nonce: &[u8], origin: &str) -> Vec<u8> {
+            serde_json::to_vec(&serde_json::json!({
+                "type": kind,
+                "challenge": encode(nonce),
+                "origin": origin,
+                "crossOrigin": false,
+            })).unwrap()
+        }
+        
+        fn cose_key(&self) -> Value {
+            let public_key = self.key_pair.public_key().as_ref();
+            Value::Map(vec![
+                (Value::from(1), Value::from(2)),
+                (Value::from(3), Value::from(COSE_ALG_ES256)),
+                (Value::from(-1), Value::from(1)),
+                (Value::from(-2), Value::Bytes(public_key[1..33].to_vec())),
+                (Value::from(-3), Value::Bytes(public_key[33..].to_vec())),
+            ])
+        }
+        
+        fn auth_data(&self, rp_id: &str, flags: u8, hmac_secret: bool, attested: bool) -> Vec<u8> {
+            let mut data = digest::digest(&digest::SHA256, rp_id.as_bytes()).as_ref().to_vec();
+            data.push(flags | if attested { FLAG_AT } else { 0 } | if hmac_secret { FLAG_ED } else { 0 });
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+            if attested {
+                data.extend_from_slice(&[0u8; 16]);
+                data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+                data.extend_from_slice(&self.credential_id);
+                ciborium::ser::into_writer(&self.cose_key(), &mut data).unwrap();
+            }
+            if hmac_secret {
+                let extensions = Value::Map(vec![(Value::from("hmac-secret"), Value::from(true))]);
+                ciborium::ser::into_writer(&extensions, &mut data).unwrap();
+            }
+            data
+        }
+        
+        pub(crate) fn make_credential(&self, config: &Fido2Config, nonce: &[u8], hmac_secret: bool) -> Fido2Registration {
+            let attestation = Value::Map(vec![
+                (Value::from("fmt"), Value::from("none")),
+                (Value::from("attStmt"), Value::Map(Vec::new())),
+                (Value::from("authData"), Value::Bytes(self.auth_data(&config.rp_id, FLAG_UP, hmac_secret, true))),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::ser::into_writer(&attestation, &mut attestation_object).unwrap();
+            Fido2Registration {
+                challenge_id: Uuid::new_v4(),
+                client_data_json: encode(&Self::client_data("webauthn.create", nonce, &config.origin)),
+                attestation_object: encode(&attestation_object),
+                prf_enabled: None,
+            }
+        }
+        
+        /// 按WebAuthn PRF扩展的定义，hmac-secret的输入为 SHA-256("WebAuthn PRF" ‖ 0x00 ‖ 盐值)
+        pub(crate) fn get_assertion(&mut self, config: &Fido2Config, nonce: &[u8]) -> Fido2Assertion {
+            self.sign_count += 1;
+            let auth_data = self.auth_data(&config.rp_id, FLAG_UP, false, false);
+            let client_data_json = Self::client_data("webauthn.get", nonce, &config.origin);
+            let mut message = auth_data.clone();
+            message.extend_from_slice(digest::digest(&digest::SHA256, &client_data_json).as_ref());
+            let signature = self.key_pair.sign(&SystemRandom::new(), &message).unwrap();
+            
+            let mut prf_input = b"WebAuthn PRF\x00".to_vec();
+            prf_input.extend_from_slice(&prf_salt());
+            let salt = digest::digest(&digest::SHA256, &prf_input);
+            let prf_output = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &self.cred_random), salt.as_ref());
+            
+            Fido2Assertion {
+                challenge_id: Uuid::new_v4(),
+                credential_id: encode(&self.credential_id),
+                client_data_json: encode(&client_data_json),
+                authenticator_data: encode(&auth_data),
+                signature: encode(signature.as_ref()),
+                prf_output: SecretString::new(encode(prf_output.as_ref())),
+            }
+        }
+    }
+    
+    #[test]
+    fn test_verify_registration() {
+        let config = test_config();
+        let nonce = [7u8; 32];
+        let authenticator = SoftAuthenticator::new();
+        
+        let credential = verify_registration(&config, &nonce, &authenticator.make_credential(&config, &nonce, true)).unwrap();
+        assert_eq!(credential.credential_id, encode(&authenticator.credential_id));
+        assert_eq!(credential.public_key, authenticator.key_pair.public_key().as_ref());
+        assert_eq!(credential.sign_count, 0);
+        
+        // 其他挑战、来源、依赖方或类型
+        assert!(verify_registration(&config, &[8u8; 32], &authenticator.make_credential(&config, &nonce, true)).is_err());
+        let mut other = test_config();
+        other.origin = "https://evil.example".to_string();
+        assert!(verify_registration(&config, &nonce, &authenticator.make_credential(&other, &nonce, true)).is_err());
+        other = test_config();
+        other.rp_id = "evil.example".to_string();
+        assert!(verify_registration(&config, &nonce, &authenticator.make_credential(&other, &nonce, true)).is_err());
+        let mut registration = authenticator.make_credential(&config, &nonce, true);
+        registration.client_data_json = encode(&SoftAuthenticator::client_data("webauthn.get", &nonce, &config.origin));
+        assert!(verify_registration(&config, &nonce, &registration).is_err());
+        
+        // 没有hmac-secret时需要客户端确认启用了PRF
+        let mut registration = authenticator.make_credential(&config, &nonce, false);
+        assert!(verify_registration(&config, &nonce, &registration).is_err());
+        registration.prf_enabled = Some(true);
+        assert!(verify_registration(&config, &nonce, &registration).is_ok());
+    }
+    
+    #[test]
+    fn test_verify_assertion() {
+        let config = test_config();
+        let nonce = [7u8; 32];
+        let mut authenticator = SoftAuthenticator::new();
+        let public_key = authenticator.key_pair.public_key().as_ref().to_vec();
+        
+        let assertion = authenticator.get_assertion(&config, &nonce);
+        assert_eq!(verify_assertion(&config, &nonce, &public_key, &assertion).unwrap(), 1);
+        let part_b_first = part_b(&assertion).unwrap();
+        assert_eq!(part_b_first.expose_secret().len(), PRF_OUTPUT_LEN * 2);
+        
+        // 同一凭据每次得到相同的硬件码B，其他安全密钥不同
+        let assertion = authenticator.get_assertion(&config, &nonce);
+        assert_eq!(verify_assertion(&config, &nonce, &public_key, &assertion).unwrap(), 2);
+        assert_eq!(part_b(&assertion).unwrap().expose_secret(), part_b_first.expose_secret());
+        let mut other = SoftAuthenticator::new();
+        assert_ne!(part_b(&other.get_assertion(&config, &nonce)).unwrap().expose_secret(), part_b_first.expose_secret());
+        
+        // 其他挑战、其他公钥
+        assert!(verify_assertion(&config, &[8u8; 32], &public_key, &assertion).is_err());
+        assert!(verify_assertion(&config, &nonce, other.key_pair.public_key().as_ref(), &assertion).is_err());
+        
+        // 篡改认证器数据或客户端数据后签名失效
+        let mut tampered = authenticator.get_assertion(&config, &nonce);
+        let mut auth_data = decode(&tampered.authenticator_data, "").unwrap();
+        auth_data[36] = auth_data[36].wrapping_add(1);
+        tampered.authenticator_data = encode(&auth_data);
+        assert!(matches!(verify_assertion(&config, &nonce, &public_key, &tampered), Err(UKeyError::InvalidAssertion(_))));
+        let mut tampered = authenticator.get_assertion(&config, &nonce);
+        tampered.client_data_json = encode(&SoftAuthenticator::client_data("webauthn.get", &nonce, "https://gallery.example:8443"));
+        assert!(verify_assertion(&config, &nonce, &public_key, &tampered).is_err());
+        
+        // 没有用户在场标志
+        let mut absent = authenticator.get_assertion(&config, &nonce);
+        absent.authenticator_data = encode(&authenticator.auth_data(&config.rp_id, 0, false, false));
+        assert!(verify_assertion(&config, &nonce, &public_key, &absent).is_err());
+        
+        // PRF输出长度错误
+        let mut short = authenticator.get_assertion(&config, &nonce);
+        short.prf_output = SecretString::new(encode(&[1u8; 16]));
+        assert!(part_b(&short).is_err());
+    }
+    
+    #[test]
+    fn test_check_sign_count() {
+        assert!(check_sign_count(0, 0).is_ok());
+        assert!(check_sign_count(0, 1).is_ok());
+        assert!(check_sign_count(5, 6).is_ok());
+        assert!(check_sign_count(5, 5).is_err());
+        assert!(check_sign_count(5, 0).is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use std::time::Duration;
+
+use base64::{Engine as _, engine::general_purpose};
+use reqwest::{Client, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use uuid::Uuid;
+
+use super::{UKeyAssertion, UKeyChallenge, UKeyError, UKeyFuture, UKeyProvider, VENDOR_HTTP};
+use crate::crypto::SecretString;
+
+/// 错误响应中保留的最大字符数，避免把大段响应写入错误信息
+const MAX_ERROR_MESSAGE_CHARS: usize = 200;
+
+/// 发给UKey代理的挑战
+#[derive(Serialize)]
+struct ChallengeRequest {
+    challenge_id: Uuid,
+    /// 随机数，Base64编码
+    nonce: String,
+}
+
+/// UKey代理对挑战的应答
+#[derive(Deserialize)]
+struct ChallengeResponse {
+    serial: String,
+    public_key: String,
+    part_b: SecretString,
+    signature: String,
+}
+
+/// 通过本机UKey代理的HTTP接口应答挑战
+///
+/// 代理接口：`POST {UKEY_API_URL}/challenge`，请求 `{"challenge_id": "...", "nonce": "..."}`，
+/// 成功时返回 `{"serial": "...", "public_key": "...", "part_b": "...", "signature": "..."}`，
+/// 签名方式见 `challenge::challenge_message`，没有插入UKey时返回404。连接和整个请求都受 `UKEY_TIMEOUT_MS` 限制。
+pub struct HttpUKeyProvider {
+    client: Client,
+    api_url: Url,
+}
+
+impl HttpUKeyProvider {
+    /// 创建HTTP驱动，`api_url` 不是有效的HTTP地址时返回 `InvalidConfig`
+    pub fn new(api_url: &str, timeout: Duration) -> Result<Self, UKeyError> {
+        let api_url = Url::parse(api_url)
+            .map_err(|e| UKeyError::InvalidConfig(format!("UKEY_API_URL: {}", e)))?;
+        if !matches!(api_url.scheme(), "http" | "https") {
+            return Err(UKeyError::InvalidConfig("UKEY_API_URL只支持http和https".to_string()));
+        }
+        
+        let client = Client::builder()
+            .connect_timeout(timeout)
+            .timeout(timeout)
+            .build()
+            .map_err(|e| UKeyError::InvalidConfig(e.to_string()))?;
+        
+        Ok(Self { client, api_url })
+    }
+    
+    /// 拼接代理接口地址
+    fn endpoint(&self, path: &str) -> String {
+        format!("{}/{}", self.api_url.as_str().trim_end_matches('/'), path)
+    }
+}
+
+/// 将请求错误转换为UKey错误
+fn request_error(err: reqwest::Error) -> UKeyError {
+    if err.is_timeout() {
+        UKeyError::Timeout
+    } else {
+        UKeyError::Unavailable(err.without_url().to_string())
+    }
+}
+
+impl UKeyProvider for HttpUKeyProvider {
+    fn vendor(&self) -> &'static str {
+        VENDOR_HTTP
+    }
+    
+    fn respond<'a>(&'a self, challenge: &'a UKeyChallenge) -> UKeyFuture<'a, UKeyAssertion> {
+        Box::pin(async move {
+            let request = ChallengeRequest {
+                challenge_id: challenge.id,
+                nonce: general_purpose::STANDARD.encode(&challenge.nonce),
+            };
+            let response = self.client.post(self.endpoint("challenge"))
+                .json(&request)
+                .send()
+                .await
+                .map_err(request_error)?;
+            
+            let status = response.status();
+            if status == StatusCode::NOT_FOUND {
+                return Err(UKeyError::NoToken);
+            }
+            if !status.is_success() {
+                let message = response.text().await.unwrap_or_default();
+                return Err(UKeyError::AgentError {
+                    status: status.as_u16(),
+                    message: message.chars().take(MAX_ERROR_MESSAGE_CHARS).collect(),
+                });
+            }
+            
+            // 响应体中包含硬件码B，解析错误不输出响应内容
+            let body = response.bytes().await.map_err(request_error)?;
+            let parsed: ChallengeResponse = serde_json::from_slice(&body)
+                .map_err(|e| UKeyError::InvalidResponse(format!("第{}行第{}列", e.line(), e.column())))?;
+            if parsed.part_b.expose_secret().is_empty() {
+                return Err(UKeyError::InvalidResponse("硬件码B为空".to_string()));
+            }
+            debug!("UKey已应答挑战, 序列号: {}", parsed.serial);
+            
+            Ok(UKeyAssertion {
+                challenge_id: challenge.id,
+                serial: parsed.serial,
+                public_key: parsed.public_key,
+                part_b: parsed.part_b,
+                signature: parsed.signature,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ukey::challenge::tests::test_key_pair;
+    use crate::ukey::challenge::{challenge_message, verify_assertion, CHALLENGE_NONCE_LEN};
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+    
+    /// 软件模拟的UKey代理，用P-256密钥签名收到的挑战
+    struct SoftwareAgent {
+        key_pair: EcdsaKeyPair,
+    }
+    
+    impl Respond for SoftwareAgent {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            let challenge_id: Uuid = body["challenge_id"].as_str().unwrap().parse().unwrap();
+            let nonce = general_purpose::STANDARD.decode(body["nonce"].as_str().unwrap()).unwrap();
+            let message = challenge_message(challenge_id, &nonce, "UK-0001", "hardware-code-b");
+            let signature = self.key_pair.sign(&SystemRandom::new(), &message).unwrap();
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "serial": "UK-0001",
+                "public_key": general_purpose::STANDARD.encode(self.key_pair.public_key().as_ref()),
+                "part_b": "hardware-code-b",
+                "signature": general_purpose::STANDARD.encode(signature.as_ref()),
+            }))
+        }
+    }
+    
+    fn test_challenge() -> UKeyChallenge {
+        UKeyChallenge {
+            id: Uuid::new_v4(),
+            nonce: vec![9u8; CHALLENGE_NONCE_LEN],
+            expires_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+    
+    /// 启动UKey代理替身，返回指向它的驱动
+    async fn provider_for<R: Respond + 'static>(response: R) -> (MockServer, HttpUKeyProvider) {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/ukey/challenge"))
+            .respond_with(response)
+            .mount(&server)
+            .await;
+        let provider = HttpUKeyProvider::new(&format!("{}/ukey/", server.uri()), Duration::from_millis(500)).unwrap();
+        (server, provider)
+    }
+    
+    #[tokio::test]
+    async fn test_respond() {
+        let (_server, provider) = provider_for(SoftwareAgent { key_pair: test_key_pair() }).await;
+        let challenge = test_challenge();
+        
+        let assertion = provider.respond(&challenge).await.unwrap();
+        assert_eq!(assertion.challenge_id, challenge.id);
+        assert_eq!(assertion.serial, "UK-0001");
+        assert_eq!(assertion.part_b.expose_secret(), "hardware-code-b");
+        assert!(verify_assertion(&assertion, &challenge.nonce).is_ok());
+        assert!(!format!("{:?}", assertion).contains("hardware-code-b"));
+    }
+    
+    #[tokio::test]
+    async fn test_agent_errors() {
+        let challenge = test_challenge();
+        let (_server, provider) = provider_for(ResponseTemplate::new(404)).await;
+        assert!(matches!(provider.respond(&challenge).await, Err(UKeyError::NoToken)));
+        
+        let (_server, provider) = provider_for(ResponseTemplate::new(423).set_body_string("PIN已锁定")).await;
+        assert!(matches!(provider.respond(&challenge).await, Err(UKeyError::AgentError { status: 423, message }) if message == "PIN已锁定"));
+        
+        let body = serde_json::json!({"serial": "UK-0001", "public_key": "", "part_b": "", "signature": ""});
+        let (_server, provider) = provider_for(ResponseTemplate::new(200).set_body_json(body)).await;
+        assert!(matches!(provider.respond(&challenge).await, Err(UKeyError::InvalidResponse(_))));
+        
+        // 格式错误的响应不把内容写入错误信息
+        let (_server, provider) = provider_for(ResponseTemplate::new(200).set_body_string("{\"part_b\": \"leaked-secret\"")).await;
+        let err = provider.respond(&challenge).await.unwrap_err();
+        assert!(matches!(err, UKeyError::InvalidResponse(_)));
+        assert!(!err.to_string().contains("leaked-secret"));
+    }
+    
+    #[tokio::test]
+    async fn test_timeout_and_unavailable() {
+        let challenge = test_challenge();
+        let (_server, provider) = provider_for(ResponseTemplate::new(200).set_delay(Duration::from_secs(2))).await;
+        assert!(matches!(provider.respond(&challenge).await, Err(UKeyError::Timeout)));
+        
+        // 代理已停止，MockServer释放后会回到复用池，改用刚释放的端口
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+        let provider = HttpUKeyProvider::new(&format!("http://{}", address), Duration::from_millis(500)).unwrap();
+        assert!(matches!(provider.respond(&challenge).await, Err(UKeyError::Unavailable(_))));
+    }
+}
@@ -0,0 +1,156 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::config::UKeyConfig;
+
+// 挑战应答协议
+pub mod challenge;
+// FIDO2安全密钥
+pub mod fido2;
+// HTTP代理驱动
+pub mod http;
+// PKCS#11驱动
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+
+pub use challenge::{UKeyAssertion, UKeyChallenge};
+pub use fido2::{Fido2Assertion, Fido2Registration};
+pub use http::HttpUKeyProvider;
+#[cfg(feature = "pkcs11")]
+pub use pkcs11::Pkcs11UKeyProvider;
+
+/// 通过本机UKey代理的HTTP接口读取硬件码B的驱动名称
+pub const VENDOR_HTTP: &str = "http";
+
+/// 通过PKCS#11模块从USB令牌派生硬件码B的驱动名称，需要启用 `pkcs11` 功能
+pub const VENDOR_PKCS11: &str = "pkcs11";
+
+/// 通过WebAuthn登记的FIDO2安全密钥，硬件码B取自hmac-secret/PRF扩展，不能用作 `UKEY_VENDOR`
+pub const VENDOR_FIDO2: &str = "fido2";
+
+/// 未配置 `UKEY_VENDOR` 时使用的驱动名称，等同于 `http`
+pub const VENDOR_DEFAULT: &str = "default";
+
+/// UKey错误类型
+#[derive(Error, Debug)]
+pub enum UKeyError {
+    #[error("不支持的UKey驱动: {0}")]
+    UnsupportedVendor(String),
+    
+    #[error("UKey配置错误: {0}")]
+    InvalidConfig(String),
+    
+    #[error("UKey代理请求超时")]
+    Timeout,
+    
+    #[error("无法连接UKey代理: {0}")]
+    Unavailable(String),
+    
+    #[error("未检测到UKey")]
+    NoToken,
+    
+    #[error("UKey代理返回错误: {status} {message}")]
+    AgentError { status: u16, message: String },
+    
+    #[error("UKey代理响应格式错误: {0}")]
+    InvalidResponse(String),
+    
+    #[error("UKey应答无效: {0}")]
+    InvalidAssertion(String),
+    
+    #[cfg(feature = "pkcs11")]
+    #[error("PKCS#11错误: {0}")]
+    Pkcs11(String),
+}
+
+/// 硬件码B的来源
+///
+/// UKey对挑战签名并直接给出硬件码B；FIDO2安全密钥签署WebAuthn断言，硬件码B由PRF扩展的输出得到。
+/// 请求中写作 `{"ukey": {...}}` 或 `{"fido2": {...}}`。
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HardwareAssertion {
+    /// UKey对挑战的应答
+    UKey(UKeyAssertion),
+    /// FIDO2安全密钥的断言
+    Fido2(Fido2Assertion),
+}
+
+impl HardwareAssertion {
+    /// 用于日志的硬件标识：UKey序列号或FIDO2凭据ID
+    pub fn serial(&self) -> &str {
+        match self {
+            HardwareAssertion::UKey(assertion) => &assertion.serial,
+            HardwareAssertion::Fido2(assertion) => &assertion.credential_id,
+        }
+    }
+}
+
+/// UKey驱动返回的异步结果
+pub type UKeyFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, UKeyError>> + Send + 'a>>;
+
+/// UKey驱动
+///
+/// 插在服务器上的UKey通过驱动应答服务器签发的挑战，应答中带有硬件码B和对挑战的签名。
+/// 驱动按 `UKEY_VENDOR` 选择，见 `create_provider`。
+pub trait UKeyProvider: Send + Sync {
+    /// 驱动名称
+    fn vendor(&self) -> &'static str;
+    
+    /// 用当前插入的UKey应答挑战
+    fn respond<'a>(&'a self, challenge: &'a UKeyChallenge) -> UKeyFuture<'a, UKeyAssertion>;
+}
+
+/// 按配置创建UKey驱动
+pub fn create_provider(config: &UKeyConfig) -> Result<Arc<dyn UKeyProvider>, UKeyError> {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    
+    match config.vendor.to_lowercase().as_str() {
+        VENDOR_HTTP | VENDOR_DEFAULT => Ok(Arc::new(HttpUKeyProvider::new(&config.api_url, timeout)?)),
+        #[cfg(feature = "pkcs11")]
+        VENDOR_PKCS11 => Ok(Arc::new(Pkcs11UKeyProvider::new(config, timeout)?)),
+        #[cfg(not(feature = "pkcs11"))]
+        VENDOR_PKCS11 => Err(UKeyError::InvalidConfig("未启用pkcs11功能，需要使用 --features pkcs11 编译".to_string())),
+        VENDOR_FIDO2 => Err(UKeyError::InvalidConfig("FIDO2安全密钥由浏览器通过WebAuthn应答，不能作为服务器端驱动".to_string())),
+        other => Err(UKeyError::UnsupportedVendor(other.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn test_config(vendor: &str) -> UKeyConfig {
+        UKeyConfig {
+            vendor: vendor.to_string(),
+            api_url: "http://127.0.0.1:8080/ukey".to_string(),
+            timeout_ms: 1000,
+            challenge_ttl_secs: 60,
+            pkcs11_module: None,
+            pkcs11_token_label: None,
+            pkcs11_pin: None,
+            pkcs11_key_label: "secretgallery-part-b".to_string(),
+            admin_token: None,
+        }
+    }
+    
+    #[test]
+    fn test_create_provider() {
+        assert_eq!(create_provider(&test_config("HTTP")).unwrap().vendor(), VENDOR_HTTP);
+        assert_eq!(create_provider(&test_config("default")).unwrap().vendor(), VENDOR_HTTP);
+        assert!(matches!(create_provider(&test_config("unknown")), Err(UKeyError::UnsupportedVendor(vendor)) if vendor == "unknown"));
+        
+        // 没有配置PKCS#11模块，或未启用pkcs11功能
+        assert!(matches!(create_provider(&test_config("pkcs11")), Err(UKeyError::InvalidConfig(_))));
+        assert!(matches!(create_provider(&test_config("fido2")), Err(UKeyError::InvalidConfig(_))));
+        
+        let mut config = test_config("http");
+        config.api_url = "not a url".to_string();
+        assert!(matches!(create_provider(&config), Err(UKeyError::InvalidConfig(_))));
+    }
+}
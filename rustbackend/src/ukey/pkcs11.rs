@@ -0,0 +1,345 @@
+use std::time::Duration;
+
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::error::{Error as CryptokiError, RvError};
+use base64::{Engine as _, engine::general_purpose};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, AttributeType, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::slot::Slot;
+use cryptoki::types::AuthPin;
+use ring::digest;
+use tracing::debug;
+
+use super::challenge::{challenge_message, P256_PUBLIC_KEY_LEN};
+use super::{UKeyAssertion, UKeyChallenge, UKeyError, UKeyFuture, UKeyProvider, VENDOR_PKCS11};
+use crate::config::UKeyConfig;
+use crate::crypto::SecretString;
+
+/// 派生硬件码B时交给令牌计算HMAC的挑战，带域分隔和版本
+///
+/// 硬件码B参与组合密钥，必须对同一令牌保持不变，因此挑战是固定值。
+const PART_B_CHALLENGE: &[u8] = b"SecretGallery/ukey-part-b/v1";
+
+/// 将PKCS#11错误转换为UKey错误
+fn pkcs11_error(err: CryptokiError) -> UKeyError {
+    match err {
+        CryptokiError::Pkcs11(RvError::TokenNotPresent | RvError::DeviceRemoved | RvError::SlotIdInvalid, _) => UKeyError::NoToken,
+        CryptokiError::Pkcs11(rv, function) => UKeyError::Pkcs11(format!("{:?} ({:?})", rv, function)),
+        other => UKeyError::Pkcs11(format!("{:?}", other)),
+    }
+}
+
+/// 通过PKCS#11模块用USB令牌应答挑战
+///
+/// 令牌上保存同一标签的两组不可导出的密钥（`CKA_SENSITIVE=true`，`CKA_EXTRACTABLE=false`）：
+/// 通用密钥（`CKK_GENERIC_SECRET`）对固定消息计算的HMAC-SHA256（十六进制）作为硬件码B，
+/// P-256密钥对用于签名服务器的挑战。密钥不离开令牌，拿到PIN但没有令牌无法得到硬件码B，也无法应答新的挑战。
+/// PKCS#11调用是阻塞的，在 `spawn_blocking` 中执行，整个应答过程受 `UKEY_TIMEOUT_MS` 限制。
+pub struct Pkcs11UKeyProvider {
+    pkcs11: Pkcs11,
+    token_label: Option<String>,
+    pin: Option<SecretString>,
+    key_label: String,
+    timeout: Duration,
+}
+
+impl Pkcs11UKeyProvider {
+    /// 加载并初始化 `UKEY_PKCS11_MODULE` 指定的PKCS#11模块
+    pub fn new(config: &UKeyConfig, timeout: Duration) -> Result<Self, UKeyError> {
+        let module = config.pkcs11_module.as_deref()
+            .ok_or_else(|| UKeyError::InvalidConfig("UKEY_VENDOR=pkcs11时需要设置UKEY_PKCS11_MODULE".to_string()))?;
+        let pkcs11 = Pkcs11::new(module)
+            .map_err(|e| UKeyError::InvalidConfig(format!("无法加载PKCS#11模块 {}: {}", module, e)))?;
+        match pkcs11.initialize(CInitializeArgs::OsThreads) {
+            Ok(()) | Err(CryptokiError::AlreadyInitialized) => {}
+            Err(CryptokiError::Pkcs11(RvError::CryptokiAlreadyInitialized, _)) => {}
+            Err(e) => return Err(pkcs11_error(e)),
+        }
+        
+        Ok(Self::with_context(pkcs11, config, timeout))
+    }
+    
+    /// 使用已初始化的PKCS#11上下文创建驱动
+    ///
+    /// PKCS#11模块在同一进程中只能初始化一次，需要与其他代码共用上下文时使用。
+    pub fn with_context(pkcs11: Pkcs11, config: &UKeyConfig, timeout: Duration) -> Self {
+        Self {
+            pkcs11,
+            token_label: config.pkcs11_token_label.clone(),
+            pin: config.pkcs11_pin.clone(),
+            key_label: config.pkcs11_key_label.clone(),
+            timeout,
+        }
+    }
+}
+
+/// 查找配置的令牌，返回槽位和令牌序列号
+fn find_token(pkcs11: &Pkcs11, token_label: Option<&str>) -> Result<(Slot, String), UKeyError> {
+    for slot in pkcs11.get_slots_with_token().map_err(pkcs11_error)? {
+        let info = pkcs11.get_token_info(slot).map_err(pkcs11_error)?;
+        if !info.token_initialized() {
+            continue;
+        }
+        if token_label.is_none_or(|label| info.label().trim() == label) {
+            return Ok((slot, info.serial_number().trim().to_string()));
+        }
+    }
+    
+    Err(UKeyError::NoToken)
+}
+
+/// 以用户身份登录，同一应用的其他会话已经登录时直接使用
+fn login(session: &Session, pin: Option<&SecretString>) -> Result<(), UKeyError> {
+    let Some(pin) = pin else {
+        return Ok(());
+    };
+    
+    let pin = AuthPin::new(pin.expose_secret().to_string());
+    match session.login(UserType::User, Some(&pin)) {
+        Ok(()) | Err(CryptokiError::Pkcs11(RvError::UserAlreadyLoggedIn, _)) => Ok(()),
+        Err(e) => Err(pkcs11_error(e)),
+    }
+}
+
+/// 按类型和标签查找令牌上的对象
+fn find_key(session: &Session, class: ObjectClass, key_label: &str, serial: &str) -> Result<ObjectHandle, UKeyError> {
+    session.find_objects(&[
+        Attribute::Class(class),
+        Attribute::Label(key_label.as_bytes().to_vec()),
+    ])
+    .map_err(pkcs11_error)?
+    .into_iter()
+    .next()
+    .ok_or_else(|| UKeyError::InvalidConfig(format!("令牌 {} 上没有标签为 {} 的{}", serial, key_label, class)))
+}
+
+/// 读取P-256公钥的未压缩点
+///
+/// `CKA_EC_POINT` 按规范是DER编码的OCTET STRING，部分模块直接返回未压缩点，两种都接受。
+fn read_public_key(session: &Session, key: ObjectHandle) -> Result<Vec<u8>, UKeyError> {
+    let attributes = session.get_attributes(key, &[AttributeType::EcPoint]).map_err(pkcs11_error)?;
+    let Some(Attribute::EcPoint(point)) = attributes.into_iter().next() else {
+        return Err(UKeyError::InvalidConfig("令牌上的公钥没有CKA_EC_POINT".to_string()));
+    };
+    
+    match point.as_slice() {
+        [0x04, len, rest @ ..] if *len as usize == P256_PUBLIC_KEY_LEN && rest.len() == P256_PUBLIC_KEY_LEN => Ok(rest.to_vec()),
+        [0x04, ..] if point.len() == P256_PUBLIC_KEY_LEN => Ok(point),
+        _ => Err(UKeyError::InvalidConfig("令牌上的公钥不是P-256".to_string())),
+    }
+}
+
+/// 在令牌上计算硬件码B并签名挑战
+fn respond_on_token(
+    pkcs11: &Pkcs11,
+    token_label: Option<&str>,
+    pin: Option<&SecretString>,
+    key_label: &str,
+    challenge: &UKeyChallenge
+) -> Result<UKeyAssertion, UKeyError> {
+    let (slot, serial) = find_token(pkcs11, token_label)?;
+    let session = pkcs11.open_ro_session(slot).map_err(pkcs11_error)?;
+    login(&session, pin)?;
+    
+    let secret_key = find_key(&session, ObjectClass::SECRET_KEY, key_label, &serial)?;
+    let mac = zeroize::Zeroizing::new(session.sign(&Mechanism::Sha256Hmac, secret_key, PART_B_CHALLENGE).map_err(pkcs11_error)?);
+    let mut part_b = String::with_capacity(mac.len() * 2);
+    for byte in mac.iter() {
+        part_b.push(char::from_digit((byte >> 4) as u32, 16).unwrap_or('0'));
+        part_b.push(char::from_digit((byte & 0x0f) as u32, 16).unwrap_or('0'));
+    }
+    let part_b = SecretString::new(part_b);
+    
+    // CKM_ECDSA只对摘要签名，由调用方计算SHA-256，签名格式为 r ‖ s
+    let private_key = find_key(&session, ObjectClass::PRIVATE_KEY, key_label, &serial)?;
+    let public_key = read_public_key(&session, find_key(&session, ObjectClass::PUBLIC_KEY, key_label, &serial)?)?;
+    let message = challenge_message(challenge.id, &challenge.nonce, &serial, part_b.expose_secret());
+    let signature = session.sign(&Mechanism::Ecdsa, private_key, digest::digest(&digest::SHA256, &message).as_ref())
+        .map_err(pkcs11_error)?;
+    debug!("PKCS#11令牌已应答挑战, 序列号: {}", serial);
+    
+    Ok(UKeyAssertion {
+        challenge_id: challenge.id,
+        serial,
+        public_key: general_purpose::STANDARD.encode(public_key),
+        part_b,
+        signature: general_purpose::STANDARD.encode(signature),
+    })
+}
+
+impl UKeyProvider for Pkcs11UKeyProvider {
+    fn vendor(&self) -> &'static str {
+        VENDOR_PKCS11
+    }
+    
+    fn respond<'a>(&'a self, challenge: &'a UKeyChallenge) -> UKeyFuture<'a, UKeyAssertion> {
+        Box::pin(async move {
+            let pkcs11 = self.pkcs11.clone();
+            let token_label = self.token_label.clone();
+            let pin = self.pin.clone();
+            let key_label = self.key_label.clone();
+            let challenge = challenge.clone();
+            let task = tokio::task::spawn_blocking(move || {
+                respond_on_token(&pkcs11, token_label.as_deref(), pin.as_ref(), &key_label, &challenge)
+            });
+            
+            // 超时后阻塞任务仍会执行完，只是不再等待结果
+            match tokio::time::timeout(self.timeout, task).await {
+                Ok(Ok(result)) => result,
+                Ok(Err(e)) => Err(UKeyError::Pkcs11(format!("PKCS#11任务失败: {}", e))),
+                Err(_) => Err(UKeyError::Timeout),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ukey::challenge::{verify_assertion, CHALLENGE_NONCE_LEN};
+    use cryptoki::object::KeyType;
+    
+    /// P-256曲线的DER编码OID
+    const P256_EC_PARAMS: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+    
+    /// 常见的SoftHSM2模块路径，可用 `SOFTHSM2_MODULE` 指定
+    const SOFTHSM2_MODULES: &[&str] = &[
+        "/usr/lib/softhsm/libsofthsm2.so",
+        "/usr/lib/x86_64-linux-gnu/softhsm/libsofthsm2.so",
+        "/usr/lib/aarch64-linux-gnu/softhsm/libsofthsm2.so",
+        "/usr/lib64/pkcs11/libsofthsm2.so",
+        "/usr/local/lib/softhsm/libsofthsm2.so",
+        "/opt/homebrew/lib/softhsm/libsofthsm2.so",
+    ];
+    
+    const SO_PIN: &str = "12345678";
+    const USER_PIN: &str = "87654321";
+    const TOKEN_LABEL: &str = "SecretGallery";
+    const KEY_LABEL: &str = "secretgallery-part-b";
+    
+    fn softhsm2_module() -> Option<String> {
+        std::env::var("SOFTHSM2_MODULE").ok()
+            .or_else(|| SOFTHSM2_MODULES.iter().find(|path| std::path::Path::new(path).exists()).map(|path| path.to_string()))
+    }
+    
+    fn test_config(token_label: Option<&str>, pin: Option<&str>, key_label: &str) -> UKeyConfig {
+        UKeyConfig {
+            vendor: VENDOR_PKCS11.to_string(),
+            api_url: String::new(),
+            timeout_ms: 5000,
+            challenge_ttl_secs: 60,
+            pkcs11_module: None,
+            pkcs11_token_label: token_label.map(str::to_string),
+            pkcs11_pin: pin.map(SecretString::from),
+            pkcs11_key_label: key_label.to_string(),
+            admin_token: None,
+        }
+    }
+    
+    fn test_challenge() -> UKeyChallenge {
+        UKeyChallenge {
+            id: uuid::Uuid::new_v4(),
+            nonce: (0..CHALLENGE_NONCE_LEN as u8).collect(),
+            expires_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+    
+    /// 在临时目录中创建SoftHSM2令牌，生成不可导出的HMAC密钥和P-256密钥对
+    fn provision_token(pkcs11: &Pkcs11) {
+        let slot = pkcs11.get_slots_with_token().unwrap()[0];
+        pkcs11.init_token(slot, &AuthPin::new(SO_PIN.to_string()), TOKEN_LABEL).unwrap();
+        
+        // 初始化令牌后槽位编号会变化，按标签重新查找
+        let (slot, _) = find_token(pkcs11, Some(TOKEN_LABEL)).unwrap();
+        let session = pkcs11.open_rw_session(slot).unwrap();
+        session.login(UserType::So, Some(&AuthPin::new(SO_PIN.to_string()))).unwrap();
+        session.init_pin(&AuthPin::new(USER_PIN.to_string())).unwrap();
+        session.logout().unwrap();
+        
+        session.login(UserType::User, Some(&AuthPin::new(USER_PIN.to_string()))).unwrap();
+        session.generate_key(&Mechanism::GenericSecretKeyGen, &[
+            Attribute::Class(ObjectClass::SECRET_KEY),
+            Attribute::KeyType(KeyType::GENERIC_SECRET),
+            Attribute::ValueLen(32.into()),
+            Attribute::Token(true),
+            Attribute::Private(true),
+            Attribute::Sensitive(true),
+            Attribute::Extractable(false),
+            Attribute::Sign(true),
+            Attribute::Label(KEY_LABEL.as_bytes().to_vec()),
+        ]).unwrap();
+        session.generate_key_pair(&Mechanism::EccKeyPairGen, &[
+            Attribute::Token(true),
+            Attribute::Verify(true),
+            Attribute::EcParams(P256_EC_PARAMS.to_vec()),
+            Attribute::Label(KEY_LABEL.as_bytes().to_vec()),
+        ], &[
+            Attribute::Token(true),
+            Attribute::Private(true),
+            Attribute::Sensitive(true),
+            Attribute::Extractable(false),
+            Attribute::Sign(true),
+            Attribute::Label(KEY_LABEL.as_bytes().to_vec()),
+        ]).unwrap();
+    }
+    
+    /// 需要SoftHSM2，默认不运行，安装后用 `cargo test --features pkcs11 -- --ignored` 运行
+    ///
+    /// PKCS#11模块在同一进程中只能初始化一次，全部场景放在一个测试中，共用一个上下文。
+    #[tokio::test]
+    #[ignore = "需要SoftHSM2"]
+    async fn test_softhsm2_respond() {
+        let module = softhsm2_module().expect("未找到SoftHSM2模块，请安装softhsm2或设置SOFTHSM2_MODULE");
+        
+        // SoftHSM2的令牌目录放在临时目录中，不影响系统中已有的令牌
+        let dir = std::env::temp_dir().join(format!("secretgallery-softhsm2-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("tokens")).unwrap();
+        let conf = dir.join("softhsm2.conf");
+        std::fs::write(&conf, format!("directories.tokendir = {}\nobjectstore.backend = file\nlog.level = ERROR\n", dir.join("tokens").display())).unwrap();
+        std::env::set_var("SOFTHSM2_CONF", &conf);
+        
+        let mut config = test_config(Some(TOKEN_LABEL), Some(USER_PIN), KEY_LABEL);
+        config.pkcs11_module = Some(module);
+        let provider = Pkcs11UKeyProvider::new(&config, Duration::from_secs(5)).unwrap();
+        provision_token(&provider.pkcs11);
+        let pkcs11 = provider.pkcs11.clone();
+        
+        // 每个挑战的签名都能验证，同一令牌派生的硬件码B和公钥不变
+        let (first_challenge, second_challenge) = (test_challenge(), test_challenge());
+        let first = provider.respond(&first_challenge).await.unwrap();
+        let second = provider.respond(&second_challenge).await.unwrap();
+        assert!(verify_assertion(&first, &first_challenge.nonce).is_ok());
+        assert!(verify_assertion(&second, &second_challenge.nonce).is_ok());
+        assert!(verify_assertion(&second, &first_challenge.nonce).is_err());
+        assert!(!first.serial.is_empty());
+        assert_eq!(first.part_b.expose_secret().len(), 64);
+        assert_eq!(first.part_b.expose_secret(), second.part_b.expose_secret());
+        assert_eq!(first.public_key, second.public_key);
+        assert_eq!(provider.vendor(), VENDOR_PKCS11);
+        
+        // 密钥不可导出
+        let (slot, _) = find_token(&pkcs11, Some(TOKEN_LABEL)).unwrap();
+        let session = pkcs11.open_ro_session(slot).unwrap();
+        session.login(UserType::User, Some(&AuthPin::new(USER_PIN.to_string()))).unwrap();
+        let key = find_key(&session, ObjectClass::SECRET_KEY, KEY_LABEL, "").unwrap();
+        let attributes = session.get_attributes(key, &[AttributeType::Value]).unwrap();
+        assert!(!attributes.iter().any(|attribute| matches!(attribute, Attribute::Value(_))));
+        let key = find_key(&session, ObjectClass::PRIVATE_KEY, KEY_LABEL, "").unwrap();
+        let attributes = session.get_attributes(key, &[AttributeType::Value]).unwrap();
+        assert!(!attributes.iter().any(|attribute| matches!(attribute, Attribute::Value(_))));
+        drop(session);
+        
+        // PIN错误、令牌不存在或密钥不存在
+        let provider = Pkcs11UKeyProvider::with_context(pkcs11.clone(), &test_config(Some(TOKEN_LABEL), Some("00000000"), KEY_LABEL), Duration::from_secs(5));
+        assert!(matches!(provider.respond(&first_challenge).await, Err(UKeyError::Pkcs11(message)) if message.contains("PinIncorrect")));
+        
+        let provider = Pkcs11UKeyProvider::with_context(pkcs11.clone(), &test_config(Some("Other"), Some(USER_PIN), KEY_LABEL), Duration::from_secs(5));
+        assert!(matches!(provider.respond(&first_challenge).await, Err(UKeyError::NoToken)));
+        
+        let provider = Pkcs11UKeyProvider::with_context(pkcs11.clone(), &test_config(None, Some(USER_PIN), "other-key"), Duration::from_secs(5));
+        assert!(matches!(provider.respond(&first_challenge).await, Err(UKeyError::InvalidConfig(_))));
+        
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}